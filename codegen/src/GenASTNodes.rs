@@ -48,7 +48,7 @@ fn generateClassEnum(
 ) {
     output.push_str("#[derive(Clone, Copy)]\n");
 
-    output.push_str("#[allow(clippy::enum_variant_names)]\n");
+    output.push_str("#[allow(clippy::enum_variant_names, missing_docs)]\n");
     output.push_str("#[enum_dispatch(");
     for implementTrait in getAllTraitsToImplement(parents, class) {
         output.push_str(&format!("{implementTrait}, "));
@@ -240,7 +240,7 @@ fn generateFroms(
 
             output.push_str(&format!("impl {name} {{\n"));
             output.push_str("    #[inline]");
-            output.push_str("    #[allow(clippy::trivially_copy_pass_by_ref)]");
+            output.push_str("    #[allow(clippy::trivially_copy_pass_by_ref, missing_docs)]");
             output.push_str(&format!(
                 "    pub const fn getStatic(&self) -> &'static {name}StructNode {{\n"
             ));
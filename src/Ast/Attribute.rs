@@ -35,7 +35,7 @@ impl ToString for Kind {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct AstAttributeStruct {
     /// CXX11, alignas, etc.
     pub kind: Kind,
@@ -68,8 +68,8 @@ impl AstAttributeStructNode {
         self.base.kind
     }
 
-    pub const fn getSourceRange(&self) -> SourceRange {
-        self.base.sourceRange
+    pub fn getSourceRange(&self) -> SourceRange {
+        self.base.sourceRange.clone()
     }
 
     pub fn new(kind: Kind, sourceRange: SourceRange) -> Self {
@@ -15,11 +15,11 @@ use crate::{
     Lex::Token::Token,
     Utils::{
         StringRef::ToStringRef,
-        Structs::{CompileError, CompileMsgImpl, FileTokPos},
+        Structs::{CompileError, CompileMsgImpl, FileTokPos, SourceRange},
     },
 };
 
-#[derive(Clone, Copy, CommonAst)]
+#[derive(Clone, CommonAst)]
 pub struct AstAttributeCXXRustyCppCheckSymbolMatchTagStruct {
     pub numberOrFound: FileTokPos<Token>,
     pub qualifiedNameSpecifier: Option<&'static [AstNestedNameSpecifier]>,
@@ -101,6 +101,20 @@ impl CXXAttribute for AstAttributeCXXRustyCppCheckSymbolMatchTagStruct {
             },
         );
         if decls.is_empty() {
+            // If the nested name specifier failed, it was already reported.
+            let qualifScope = self
+                .qualifiedNameSpecifier
+                .map(|qualified| qualified.last().unwrap().scope.borrow().clone());
+            if !matches!(qualifScope, Some(None))
+                && parser.diagnoseNotVisible(
+                    name,
+                    qualifScope.flatten().as_ref(),
+                    |_| true,
+                    &SourceRange::newSingleTok(&self.name),
+                )
+            {
+                return;
+            }
             if self.numberOrFound.tokPos.tok != Token::BoolLiteral(false) {
                 parser.addError(CompileError::fromPreTo(format!("While trying to resolve name {name} we found nothing, but we were expecting something"), &self.numberOrFound));
             }
@@ -12,7 +12,7 @@ use crate::{
     Utils::{StringRef::ToStringRef, Structs::FileTokPos},
 };
 
-#[derive(Clone, Copy, CommonAst)]
+#[derive(Clone, CommonAst)]
 pub struct AstAttributeCXXRustyCppTagDeclStruct {
     pub number: FileTokPos<Token>,
 }
@@ -38,8 +38,8 @@ impl CXXAttribute for &AstAttributeCXXRustyCppTagDeclStructNode {}
 
 #[RustycppInheritanceConstructors]
 impl AstAttributeCXXRustyCppTagDeclStructNode {
-    pub const fn getNumber(&self) -> FileTokPos<Token> {
-        self.base.number
+    pub fn getNumber(&self) -> FileTokPos<Token> {
+        self.base.number.clone()
     }
 
     pub fn new(number: FileTokPos<Token>) -> Self {
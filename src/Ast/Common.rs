@@ -44,7 +44,9 @@ impl AstNodeStructNode {
 }
 
 #[enum_dispatch]
+/// Operations of every node of the AST
 pub trait CommonAst {
+    /// Printable tree of the node
     fn getDebugNode(&self) -> DebugNode;
 }
 
@@ -89,8 +89,8 @@ impl AstDeclStructNode {
         self.base.scope.clone()
     }
 
-    pub const fn getSourceRange(&self) -> SourceRange {
-        self.base.sourceRange
+    pub fn getSourceRange(&self) -> SourceRange {
+        self.base.sourceRange.clone()
     }
 
     pub const fn getFlags(&self) -> MyFlags {
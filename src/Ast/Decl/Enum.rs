@@ -19,6 +19,10 @@ impl AstDeclCustomRustyCppEnumStruct {
     pub const fn new(name: StringRef) -> Self {
         Self { name }
     }
+
+    pub const fn getName(&self) -> StringRef {
+        self.name
+    }
 }
 
 #[RustycppInheritanceConstructors]
@@ -34,4 +38,8 @@ impl AstDeclCustomRustyCppEnumStructNode {
             base: <Base!()>::new(name),
         }
     }
+
+    pub const fn getName(&self) -> StringRef {
+        self.base.getName()
+    }
 }
@@ -61,10 +61,18 @@ impl AstDeclNamespaceStruct {
         *contents = newContents;
     }
 
+    pub const fn getName(&self) -> StringRef {
+        self.name
+    }
+
     pub const fn isInline(&self) -> bool {
         self.isInline
     }
 
+    pub fn getContents(&self) -> &'static [AstDecl] {
+        *self.contents.borrow()
+    }
+
     pub const fn parentScope(&self) -> &ScopeRef {
         &self.parentScope
     }
@@ -94,10 +102,18 @@ impl AstDeclNamespaceStructNode {
         self.base.setContents(newContents);
     }
 
+    pub const fn getName(&self) -> StringRef {
+        self.base.getName()
+    }
+
     pub const fn isInline(&self) -> bool {
         self.base.isInline()
     }
 
+    pub fn getContents(&self) -> &'static [AstDecl] {
+        self.base.getContents()
+    }
+
     pub const fn parentScope(&self) -> &ScopeRef {
         self.base.parentScope()
     }
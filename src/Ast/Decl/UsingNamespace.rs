@@ -13,6 +13,8 @@ use deriveMacros::RustycppInheritanceConstructors;
 pub struct AstDeclUsingNamespaceStruct {
     #[AstToString]
     name: StringRef,
+    /// Range of the namespace name, without the nested name specifier
+    nameRange: SourceRange,
     #[AstChildSlice]
     nestedNameSpecifier: &'static [AstNestedNameSpecifier],
 }
@@ -20,13 +22,27 @@ pub struct AstDeclUsingNamespaceStruct {
 impl AstDeclUsingNamespaceStruct {
     pub const fn new(
         name: StringRef,
+        nameRange: SourceRange,
         nestedNameSpecifier: &'static [AstNestedNameSpecifier],
     ) -> Self {
         Self {
             name,
+            nameRange,
             nestedNameSpecifier,
         }
     }
+
+    pub const fn getName(&self) -> StringRef {
+        self.name
+    }
+
+    pub fn getNameRange(&self) -> SourceRange {
+        self.nameRange.clone()
+    }
+
+    pub const fn getNestedNameSpecifier(&self) -> &'static [AstNestedNameSpecifier] {
+        self.nestedNameSpecifier
+    }
 }
 
 #[RustycppInheritanceConstructors]
@@ -36,11 +52,25 @@ impl AstDeclUsingNamespaceStructNode {
         scope: ScopeRef,
         attrs: &'static [AstAttribute],
         name: StringRef,
+        nameRange: SourceRange,
         nestedNameSpecifier: &'static [AstNestedNameSpecifier],
     ) -> Self {
         Self {
             parent: <Parent!()>::new(sourceRange, scope, attrs),
-            base: <Base!()>::new(name, nestedNameSpecifier),
+            base: <Base!()>::new(name, nameRange, nestedNameSpecifier),
         }
     }
+
+    pub const fn getName(&self) -> StringRef {
+        self.base.getName()
+    }
+
+    /// Range of the namespace name, without the nested name specifier
+    pub fn getNameRange(&self) -> SourceRange {
+        self.base.getNameRange()
+    }
+
+    pub const fn getNestedNameSpecifier(&self) -> &'static [AstNestedNameSpecifier] {
+        self.base.getNestedNameSpecifier()
+    }
 }
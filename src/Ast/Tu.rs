@@ -24,14 +24,24 @@ impl AstTuStruct {
             astContext,
         }
     }
+
+    pub const fn getGlobalDecl(&self) -> &'static [Common::AstDecl] {
+        self.globalDecl
+    }
 }
 
 #[RustycppInheritanceConstructors]
 impl AstTuStructNode {
+    /// Translation unit of the `global` declarations
     pub fn new(astContext: AstContext, global: &[Common::AstDecl]) -> Self {
         Self {
             parent: <Parent!()>::new(),
             base: <Base!()>::new(astContext, global),
         }
     }
+
+    /// Declarations at the global scope of the translation unit
+    pub const fn getGlobalDecl(&self) -> &'static [Common::AstDecl] {
+        self.base.getGlobalDecl()
+    }
 }
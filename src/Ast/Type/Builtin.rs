@@ -1,7 +1,7 @@
 use std::fmt::Display;
 
 use deriveMacros::{CommonAst, RustycppInheritanceConstructors};
-use strum_macros::EnumIter;
+use strum_macros::{EnumIter, EnumString, IntoStaticStr};
 
 use crate::{
     Ast::Common::AstTypeBuiltin,
@@ -16,7 +16,7 @@ use crate::{
     Utils::FoldingContainer::Foldable,
 };
 
-#[derive(EnumIter, Copy, Clone)]
+#[derive(EnumIter, EnumString, IntoStaticStr, Copy, Clone)]
 pub enum BuiltinTypeKind {
     Void,
     Bool,
@@ -119,6 +119,10 @@ impl AstTypeBuiltinStructNode {
             parent: <Parent!()>::new(),
         }
     }
+
+    pub const fn getKind(&self) -> BuiltinTypeKind {
+        self.base.kindType
+    }
 }
 
 impl TypeAst for &AstTypeBuiltinStructNode {
@@ -36,6 +36,10 @@ impl AstTypePointerStructNode {
             parent: <Parent!()>::new(),
         }
     }
+
+    pub const fn getPointee(&self) -> &QualType {
+        &self.base.base
+    }
 }
 
 impl TypeAst for &AstTypePointerStructNode {
@@ -35,6 +35,10 @@ impl AstTypeReferenceStructNode {
             parent: <Parent!()>::new(),
         }
     }
+
+    pub const fn getReferenced(&self) -> &QualType {
+        &self.base.base
+    }
 }
 
 impl TypeAst for &AstTypeReferenceStructNode {
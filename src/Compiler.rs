@@ -1,10 +1,16 @@
 //! Main compiler driver. It pushes the machinery to do its thing!
 #![warn(missing_docs)]
 
+use std::cmp::Reverse;
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::thread;
+use std::time::{Duration, Instant};
 use std::{
     collections::HashSet,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, PoisonError},
 };
 use std::{
     collections::{HashMap, VecDeque},
@@ -14,21 +20,49 @@ use std::{num::NonZeroUsize, sync::atomic::AtomicBool};
 
 use threadpool::ThreadPool;
 
+use crate::Ast::Common::{AstTu, CommonAst};
+
+use crate::ModuleTree::CriticalPath::{
+    CostModel, CriticalPathReport, CriticalPathStep, TimingCache, UnitTiming,
+};
 use crate::ModuleTree::Generate::generateDependencyTree;
-use crate::ModuleTree::Structs::ModuleTree;
+use crate::ModuleTree::ModuleMap::{findModuleInterface, moduleRequirements, ModuleRequest};
+use crate::ModuleTree::RebuildPlan::{
+    planRebuild, FilesEntered, RebuildPlan, RebuildState, UnitState,
+};
+use crate::ModuleTree::Structs::{ModuleDeclaration, ModuleOperator, ModuleTree};
 use crate::ModuleTree::{
     DependencyIterator::DependencyIterator, DependencyParser::parseModuleMacroOp,
 };
 use crate::Parse::Parser::Parser;
+use crate::Preprocessor::Amalgamate::Amalgamator;
+use crate::Preprocessor::Callbacks::PreprocessorCallbacks;
+use crate::Preprocessor::ConditionalReport::ConditionalReport;
+use crate::Preprocessor::IncludeTree::IncludeTree;
+use crate::Preprocessor::MacroIndex::MacroIndex;
+use crate::Preprocessor::MacroTrace::MacroTracer;
 use crate::Preprocessor::Preprocessor;
+use crate::Preprocessor::TokenCache::TokenCache;
+use crate::Preprocessor::Unifdef::{Unifdef, UnifdefConfig};
 use crate::Utils::CompilerState::CompilerState;
 use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::{FileSystem, RealFileSystem};
 use crate::Utils::Parameters::Parameters;
 use crate::Utils::StateCompileUnit::StageCompileUnit;
 use crate::Utils::StateCompileUnit::StateCompileUnit;
-use crate::Utils::Structs::{CompileMsg, CompileMsgKind};
+use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl};
+use crate::Utils::TimeTrace::{TimeTrace, TraceStage};
 use crate::{Lex::Lexer::Lexer, Utils::ModuleHeaderAtomicLexingList::ModuleHeaderAtomicLexingList};
 
+mod Caches;
+mod Output;
+mod Pipeline;
+
+pub(crate) use Caches::CompilerCaches;
+
+use Output::Outputs;
+use Pipeline::{Scheduler, STEP_LEXED, STEP_PARSED};
+
 /// Path to a translation unit
 pub type TranslationUnit = u64;
 
@@ -39,13 +73,42 @@ pub struct Compiler {
     compilerState: CompilerState,
     /// Threadpool
     pool: ThreadPool,
+    /// Minimized files of the dependency scanning
+    scanCache: Arc<TokenCache>,
+    /// Have the unlisted module interfaces been added to the translation
+    /// units?
+    modulesDiscovered: bool,
+    /// Directory where the result of each translation unit is written,
+    /// instead of printing it
+    outputDir: Option<PathBuf>,
+    /// Translation units whose results of a previous compilation are still
+    /// valid. They are not compiled again, unless they depend on one that is.
+    upToDate: HashSet<TranslationUnit>,
+    /// Translation units not compiled by the last parse, as they were up to
+    /// date
+    skipped: HashSet<TranslationUnit>,
 }
 
 impl Compiler {
-    /// Creates a new compiler with the given parameters
+    /// Creates a new compiler with the given parameters, of the files on disk
     pub fn new(parameters: Parameters) -> Self {
+        Self::new_with_fs(parameters, Arc::new(RealFileSystem))
+    }
+
+    /// Creates a new compiler with the given parameters, of the files of
+    /// `fileSystem`. The files are read once, so to see the changes to them,
+    /// create a new compiler.
+    pub fn new_with_fs(parameters: Parameters, fileSystem: Arc<dyn FileSystem>) -> Self {
         let parameters = Arc::new(parameters);
-        let mut compileFiles = FileMap::new(parameters.clone());
+        let caches = CompilerCaches::new(parameters.clone(), fileSystem);
+        Self::new_with_caches(parameters, &caches)
+    }
+
+    /// Creates a new compiler with the given parameters, reusing the files
+    /// and the results of a previous compilation of the same project in
+    /// `caches`.
+    pub(crate) fn new_with_caches(parameters: Arc<Parameters>, caches: &CompilerCaches) -> Self {
+        let mut compileFiles = caches.fileMap.lock().unwrap();
         let mut translationUnits = HashSet::new();
         for file in &parameters.translationUnits {
             translationUnits.insert(compileFiles.getAddFile(file));
@@ -54,105 +117,391 @@ impl Compiler {
         for file in &parameters.moduleHeaderUnits {
             moduleHeaderUnits.insert(compileFiles.getAddFile(file));
         }
+        drop(compileFiles);
 
-        let mut compileUnits = HashMap::new();
-        for tu in translationUnits.iter().chain(moduleHeaderUnits.iter()) {
-            compileUnits.insert(*tu, StateCompileUnit::new());
-        }
+        let compileUnits = Self::newCompileUnits(&translationUnits, &moduleHeaderUnits);
         let threadNum = parameters.threadNum;
         Self {
             compilerState: CompilerState {
                 parameters,
-                compileFiles: Arc::new(Mutex::new(compileFiles)),
+                compileFiles: caches.fileMap.clone(),
                 compileUnits: Arc::new(compileUnits),
                 translationUnitsFiles: Arc::new(translationUnits),
                 moduleHeaderUnitsFiles: Arc::new(moduleHeaderUnits),
                 foundErrors: Arc::new(AtomicBool::new(false)),
+                tokenCache: caches.tokenCache.clone(),
+                bmiCache: caches.bmiCache.clone(),
+                preprocessorCallbacks: vec![],
+                macroTracer: None,
+                timeTrace: None,
+                conditionalReports: None,
             },
             pool: ThreadPool::new(threadNum.unwrap_or_else(|| {
                 thread::available_parallelism()
                     .unwrap_or(NonZeroUsize::new(1).unwrap())
                     .get()
             })),
+            scanCache: caches.scanCache.clone(),
+            modulesDiscovered: false,
+            outputDir: None,
+            upToDate: HashSet::new(),
+            skipped: HashSet::new(),
         }
     }
 
+    /// The files of the compilation. Needed to print the diagnostics.
+    pub const fn fileMap(&self) -> &Arc<Mutex<FileMap>> {
+        &self.compilerState.compileFiles
+    }
+
+    /// Writes the result of each translation unit of the printing modes to a
+    /// file in `dir`, instead of printing them
+    pub fn setOutputDir(&mut self, dir: Option<PathBuf>) {
+        self.outputDir = dir;
+    }
+
+    /// The translation units in the order they were given, then the module
+    /// header units, then the discovered module interfaces by path
+    fn inputOrder(&self) -> Vec<TranslationUnit> {
+        let parameters = &self.compilerState.parameters;
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        let mut order = parameters
+            .translationUnits
+            .iter()
+            .chain(parameters.moduleHeaderUnits.iter())
+            .map(|path| compileFiles.getAddFile(path))
+            .collect::<Vec<_>>();
+        let mut rest = self
+            .compilerState
+            .compileUnits
+            .keys()
+            .filter(|tu| !order.contains(tu))
+            .map(|tu| (compileFiles.getOpenedFile(*tu).path().clone(), *tu))
+            .collect::<Vec<_>>();
+        drop(compileFiles);
+        rest.sort_unstable();
+        order.extend(rest.into_iter().map(|(_, tu)| tu));
+        order.dedup();
+        order
+    }
+
+    /// Prints the results of a mode in the input order, or writes them to
+    /// the output directory with `extension`
+    fn writeOutputs(
+        &self,
+        outputs: &Outputs,
+        extension: &str,
+    ) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        outputs
+            .write(
+                &self.inputOrder(),
+                &self.compilerState.compileFiles,
+                self.outputDir.as_deref(),
+                extension,
+            )
+            .map_err(|err| (self.compilerState.clone(), err))
+    }
+
+    /// Should the printed trees be colored? Only on a terminal
+    fn coloredOutput(&self) -> bool {
+        self.outputDir.is_none() && std::io::stdout().is_terminal()
+    }
+
+    /// Records how long each stage takes to each translation unit and
+    /// header, from now on. Must be called before compiling.
+    pub fn enableTimeTrace(&mut self) -> Arc<TimeTrace> {
+        let timeTrace = Arc::new(TimeTrace::new());
+        self.compilerState.timeTrace = Some(timeTrace.clone());
+        self.compilerState
+            .preprocessorCallbacks
+            .push(timeTrace.clone());
+        timeTrace
+    }
+
+    /// Fresh state for each of the files to compile
+    fn newCompileUnits(
+        translationUnits: &HashSet<TranslationUnit>,
+        moduleHeaderUnits: &HashSet<TranslationUnit>,
+    ) -> HashMap<TranslationUnit, StateCompileUnit> {
+        translationUnits
+            .iter()
+            .chain(moduleHeaderUnits.iter())
+            .map(|tu| (*tu, StateCompileUnit::new()))
+            .collect()
+    }
+
+    /// Records the conditional structure of each translation unit while it's
+    /// preprocessed. Must be called before compiling. See
+    /// [`Compiler::takeConditionalReports`]
+    pub(crate) fn recordConditionalReports(&mut self) {
+        self.compilerState.conditionalReports = Some(Arc::default());
+    }
+
+    /// The conditional structure of the translation units preprocessed since
+    /// [`Compiler::recordConditionalReports`], sorted by translation unit
+    pub(crate) fn takeConditionalReports(&self) -> Vec<(TranslationUnit, ConditionalReport)> {
+        let Some(reports) = &self.compilerState.conditionalReports else {
+            return vec![];
+        };
+        let mut reports = std::mem::take(&mut *reports.lock().unwrap())
+            .into_iter()
+            .collect::<Vec<_>>();
+        reports.sort_unstable_by_key(|(tu, _)| *tu);
+        reports
+    }
+
+    /// Registers callbacks that observe the preprocessing of every
+    /// translation unit. Must be called before compiling.
+    pub fn addPreprocessorCallbacks(&mut self, callbacks: Arc<dyn PreprocessorCallbacks>) {
+        self.compilerState.preprocessorCallbacks.push(callbacks);
+    }
+
+    /// Errors found while lexing the module header units and the translation
+    /// units
+    fn takeErrors(&self) -> Vec<CompileMsg> {
+        let mut err = Vec::new();
+        for tu in self.compilerState.moduleHeaderUnitsFiles.iter() {
+            err.extend(
+                self.compilerState
+                    .compileUnits
+                    .get(tu)
+                    .unwrap()
+                    .errors
+                    .lock()
+                    .unwrap()
+                    .drain(..),
+            );
+        }
+        for tu in self.compilerState.translationUnitsFiles.iter() {
+            err.extend(
+                self.compilerState
+                    .compileUnits
+                    .get(tu)
+                    .unwrap()
+                    .errors
+                    .lock()
+                    .unwrap()
+                    .drain(..),
+            );
+        }
+        err
+    }
+
     fn genDependencyTreeAndAggregateErrors(&mut self) -> Result<ModuleTree, Vec<CompileMsg>> {
         if self.compilerState.foundErrors.load(Ordering::Relaxed) {
-            let mut err = Vec::new();
-            for tu in self.compilerState.moduleHeaderUnitsFiles.iter() {
-                err.extend(
-                    self.compilerState
-                        .compileUnits
-                        .get(tu)
-                        .unwrap()
-                        .errors
-                        .lock()
-                        .unwrap()
-                        .drain(..),
-                );
+            return Err(self.takeErrors());
+        }
+        generateDependencyTree(&self.compilerState)
+    }
+
+    /// Adds to the translation units the interfaces of the imported modules
+    /// that are not listed, found through the `moduleMap` and the
+    /// `moduleSearchDirs` parameters. The added files are scanned in turn, until
+    /// every import is provided by some translation unit.
+    fn discoverModules(&mut self) -> Result<(), Vec<CompileMsg>> {
+        self.discoverModulesWith(&HashMap::new())
+    }
+
+    /// Like [`Compiler::discoverModules`], but the translation units in
+    /// `known` are not scanned: their module operations are taken from it.
+    fn discoverModulesWith(
+        &mut self,
+        known: &HashMap<TranslationUnit, Vec<ModuleOperator>>,
+    ) -> Result<(), Vec<CompileMsg>> {
+        let parameters = self.compilerState.parameters.clone();
+        if self.modulesDiscovered
+            || (parameters.moduleMap.is_empty() && parameters.moduleSearchDirs.is_empty())
+        {
+            return Ok(());
+        }
+        let mut translationUnits = (*self.compilerState.translationUnitsFiles).clone();
+        let mut pending = translationUnits.iter().copied().collect::<Vec<_>>();
+        let mut provided = HashSet::new();
+        let mut requests: Vec<ModuleRequest> = vec![];
+        while !pending.is_empty() {
+            let compilerState = CompilerState {
+                compileUnits: Arc::new(Self::newCompileUnits(
+                    &translationUnits,
+                    &self.compilerState.moduleHeaderUnitsFiles,
+                )),
+                translationUnitsFiles: Arc::new(translationUnits.clone()),
+                foundErrors: Arc::default(),
+                tokenCache: self.scanCache.clone(),
+                ..self.compilerState.clone()
+            };
+            let toScan = pending
+                .iter()
+                .copied()
+                .filter(|tu| !known.contains_key(tu))
+                .collect::<Vec<_>>();
+            if !toScan.is_empty() {
+                self.lexCompileUnits(&compilerState, &toScan);
             }
-            for tu in self.compilerState.translationUnitsFiles.iter() {
-                err.extend(
-                    self.compilerState
-                        .compileUnits
-                        .get(tu)
-                        .unwrap()
-                        .errors
+            for tu in std::mem::take(&mut pending) {
+                // Files that can't be scanned report their errors once compiled
+                let ops = known.get(&tu).cloned().unwrap_or_else(|| {
+                    compilerState.compileUnits[&tu]
+                        .moduleOperations
                         .lock()
                         .unwrap()
-                        .drain(..),
-                );
+                        .take()
+                        .unwrap_or_default()
+                });
+                let (declared, imports) = moduleRequirements(tu, &ops);
+                provided.extend(declared);
+                requests.extend(imports);
+            }
+            requests.retain(|request| !provided.contains(&request.name));
+            let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+            for request in &requests {
+                if let Some(tu) = findModuleInterface(&parameters, &mut compileFiles, &request.name)
+                {
+                    if translationUnits.insert(tu) {
+                        pending.push(tu);
+                    }
+                }
             }
-            return Err(err);
+            drop(compileFiles);
         }
-        generateDependencyTree(&self.compilerState)
+        if !requests.is_empty() {
+            return Err(requests.iter().map(ModuleRequest::notFound).collect());
+        }
+        self.compilerState.compileUnits = Arc::new(Self::newCompileUnits(
+            &translationUnits,
+            &self.compilerState.moduleHeaderUnitsFiles,
+        ));
+        self.compilerState.translationUnitsFiles = Arc::new(translationUnits);
+        self.modulesDiscovered = true;
+        Ok(())
     }
 
-    // TODO: we are repeating the same function with a minnor difference (new_module_header vs new) in anoying different contexts... Can we mix them? I don't like repeating code...
-    fn lexAllCompileModule(&mut self) -> Result<ModuleTree, std::vec::Vec<CompileMsg>> {
+    /// Lexes the module header units and the translation units once, through
+    /// the scheduler, and finds their module dependencies in the tokens
+    /// lexed. `observe` adds to the compiler state of the lexing what the
+    /// mode wants to know about it: preprocessor callbacks, a macro tracer...
+    fn lexAllCompileModule(
+        &mut self,
+        observe: impl FnOnce(&mut CompilerState),
+    ) -> Result<ModuleTree, std::vec::Vec<CompileMsg>> {
+        self.discoverModules()?;
+        let mut compilerState = self.compilerState.clone();
+        observe(&mut compilerState);
+        self.lexAllCompileModuleWith(&compilerState)
+    }
+
+    /// Finds the module dependencies of the files, without preprocessing them
+    /// completely: only their directives are evaluated, and the module
+    /// directives lexed. The files are [minimized](crate::Preprocessor::Minimize)
+    /// once, and cached for later scans. The tokens of the files are not
+    /// usable for the next stages.
+    pub fn scanDependencies(&mut self) -> Result<ModuleTree, std::vec::Vec<CompileMsg>> {
+        self.discoverModules()?;
+        let compilerState = CompilerState {
+            tokenCache: self.scanCache.clone(),
+            ..self.compilerState.clone()
+        };
+        self.lexAllCompileModuleWith(&compilerState)
+    }
+
+    fn lexAllCompileModuleWith(
+        &mut self,
+        compilerState: &CompilerState,
+    ) -> Result<ModuleTree, std::vec::Vec<CompileMsg>> {
+        let translationUnits = compilerState
+            .translationUnitsFiles
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        self.lexCompileUnits(compilerState, &translationUnits);
+        self.genDependencyTreeAndAggregateErrors()
+    }
+
+    /// Lexes the module header units and the given translation units, and
+    /// parses their module operations
+    fn lexCompileUnits(&self, compilerState: &CompilerState, translationUnits: &[TranslationUnit]) {
+        let moduleHeaderUnits = compilerState
+            .moduleHeaderUnitsFiles
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        self.spawnLexing(
+            compilerState,
+            &moduleHeaderUnits,
+            translationUnits,
+            &Arc::default(),
+            &Arc::new(|_, _, _| {}),
+        );
+        self.pool.join();
+        assert_eq!(self.pool.panic_count(), 0);
+    }
+
+    /// Schedules the lexing of the given module header units and translation
+    /// units, and the parsing of their module operations, without waiting for
+    /// them. `onLexed` is called after each one, with whether it was lexed
+    /// without errors and how long it took. The translation units start in
+    /// the order given. The translation units that import a module header
+    /// unit wait for it, so it must be lexed already or given too.
+    fn spawnLexing(
+        &self,
+        compilerState: &CompilerState,
+        moduleHeaderUnits: &[TranslationUnit],
+        translationUnits: &[TranslationUnit],
+        scheduler: &Arc<Scheduler>,
+        onLexed: &Arc<impl Fn(TranslationUnit, bool, Duration) + Send + Sync + 'static>,
+    ) {
+        for tu in moduleHeaderUnits.iter().chain(translationUnits) {
+            compilerState.compileUnits[tu]
+                .finishedStage
+                .store(StageCompileUnit::Start, Ordering::Relaxed);
+        }
+        if !moduleHeaderUnits.is_empty() {
+            self.spawnModuleHeaderLexing(compilerState, moduleHeaderUnits, onLexed);
+        }
+
+        for tu in translationUnits.iter().copied() {
+            let compilerState = compilerState.clone();
+            let onLexed = onLexed.clone();
+            scheduler.pushLex(&self.pool, move || {
+                let start = Instant::now();
+                let lexed = Self::guardCrash(&compilerState, tu, "lexing", || {
+                    Self::lexUnit(&compilerState, tu, || {
+                        Preprocessor::new((compilerState.clone(), tu))
+                    })
+                });
+                onLexed(tu, lexed == Some(true), start.elapsed());
+            });
+        }
+    }
+
+    /// Schedules the lexing of the module header units. They don't go
+    /// through the scheduler, as they need to go first: the translation units
+    /// wait for the ones they import. The pool runs the tasks in order, so
+    /// these start before any translation unit scheduled after them.
+    fn spawnModuleHeaderLexing(
+        &self,
+        compilerState: &CompilerState,
+        moduleHeaderUnits: &[TranslationUnit],
+        onLexed: &Arc<impl Fn(TranslationUnit, bool, Duration) + Send + Sync + 'static>,
+    ) {
         let moduleHeaderAtomicLexingList = Arc::new(ModuleHeaderAtomicLexingList::new(
-            self.pool
-                .max_count()
-                .min(self.compilerState.moduleHeaderUnitsFiles.len()),
+            self.pool.max_count().min(moduleHeaderUnits.len()),
         ));
         let mut executionFunction: Vec<Box<dyn Fn() + Send>> = vec![];
-        for tu in self.compilerState.moduleHeaderUnitsFiles.iter().copied() {
-            let compilerState = self.compilerState.clone();
+        for tu in moduleHeaderUnits.iter().copied() {
+            let compilerState = compilerState.clone();
             let moduleHeaderAtomicLexingList = moduleHeaderAtomicLexingList.clone();
+            let onLexed = onLexed.clone();
             executionFunction.push(Box::new(move || {
-                let compileUnit = compilerState.compileUnits.get(&tu).unwrap();
-                let (toks, mut err, mut moduleDirectives) = {
-                    compileUnit
-                        .processingStage
-                        .store(StageCompileUnit::Lexer, Ordering::Relaxed);
-
-                    let preprocessor = Preprocessor::new_module_header(
-                        (compilerState.clone(), tu),
-                        moduleHeaderAtomicLexingList.clone(),
-                    );
-                    let mut lexer = Lexer::new(preprocessor);
-                    let toks = (&mut lexer).collect::<Vec<_>>();
-                    let moduleDirectivesPos = lexer.moduleDirectives();
-
-                    // TODO: We are mixing parsing the module macros with lexing the file. This could be split.
-                    let moduleDirectives = parseModuleMacroOp(tu, &toks, moduleDirectivesPos);
-                    (toks, lexer.errors(), moduleDirectives)
-                };
-                if let Err(errModuleDirectives) = moduleDirectives.as_mut() {
-                    err.append(errModuleDirectives);
-                }
-
-                if err.is_empty() {
-                    *compileUnit.moduleOperations.lock().unwrap() = Some(moduleDirectives.unwrap());
-                } else {
-                    compileUnit.errors.lock().unwrap().extend(err);
-                    compilerState.foundErrors.store(true, Ordering::Relaxed);
-                }
-                *compileUnit.tokens.lock().unwrap() = Some(toks);
-                compileUnit
-                    .finishedStage
-                    .store(StageCompileUnit::Lexer, Ordering::Relaxed);
+                let start = Instant::now();
+                let lexed = Self::guardCrash(&compilerState, tu, "lexing", || {
+                    Self::lexUnit(&compilerState, tu, || {
+                        Preprocessor::new_module_header(
+                            (compilerState.clone(), tu),
+                            moduleHeaderAtomicLexingList.clone(),
+                        )
+                    })
+                });
+                onLexed(tu, lexed == Some(true), start.elapsed());
             }));
         }
         moduleHeaderAtomicLexingList.push(executionFunction);
@@ -164,53 +513,107 @@ impl Compiler {
                 }
             });
         }
+    }
 
-        while self.pool.queued_count() != 0 {} // Just in case. header modules need to go first.
+    /// Runs `job`, the `stage` of `tu`. If it crashes, the crash is reported
+    /// as an error of the translation unit and `None` is returned, so the
+    /// ones waiting for it are not left waiting forever.
+    fn guardCrash<T>(
+        compilerState: &CompilerState,
+        tu: TranslationUnit,
+        stage: &str,
+        job: impl FnOnce() -> T,
+    ) -> Option<T> {
+        let crash = match catch_unwind(AssertUnwindSafe(job)) {
+            Ok(result) => return Some(result),
+            Err(crash) => crash,
+        };
+        let reason = crash
+            .downcast_ref::<&str>()
+            .map(ToString::to_string)
+            .or_else(|| crash.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        let path = compilerState.compileFiles.lock().map_or_else(
+            |_| tu.to_string(),
+            |mut compileFiles| compileFiles.getOpenedFile(tu).path().clone(),
+        );
+        compilerState.compileUnits[&tu]
+            .errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(CompileError::unlocated(format!(
+                "The compiler crashed while {stage} {path}: {reason}"
+            )));
+        compilerState.foundErrors.store(true, Ordering::Relaxed);
+        None
+    }
 
-        for tu in self.compilerState.translationUnitsFiles.iter().copied() {
-            let compilerState = self.compilerState.clone();
-            self.pool.execute(move || {
-                let compileUnit = compilerState.compileUnits.get(&tu).unwrap();
-                let (toks, mut err, mut moduleDirectives) = {
-                    compileUnit
-                        .processingStage
-                        .store(StageCompileUnit::Lexer, Ordering::Relaxed);
-
-                    let preprocessor = Preprocessor::new((compilerState.clone(), tu));
-                    let mut lexer = Lexer::new(preprocessor);
-
-                    let toks = (&mut lexer).collect::<Vec<_>>();
-                    let moduleDirectivesPos = lexer.moduleDirectives();
-
-                    // TODO: We are mixing parsing the module macros with lexing the file. This could be split.
-                    let moduleDirectives = parseModuleMacroOp(tu, &toks, moduleDirectivesPos);
-                    (toks, lexer.errors(), moduleDirectives)
-                };
-                if let Err(errModuleDirectives) = moduleDirectives.as_mut() {
-                    err.append(errModuleDirectives);
-                }
+    /// Lexes a unit with the preprocessor given, and parses its module
+    /// operations. Returns if there were no errors.
+    fn lexUnit(
+        compilerState: &CompilerState,
+        tu: TranslationUnit,
+        preprocessor: impl FnOnce() -> Preprocessor,
+    ) -> bool {
+        let compileUnit = compilerState.compileUnits.get(&tu).unwrap();
+        let (toks, mut err, moduleDirectives) = {
+            compileUnit
+                .processingStage
+                .store(StageCompileUnit::Lexer, Ordering::Relaxed);
 
-                if err.is_empty() {
-                    *compileUnit.moduleOperations.lock().unwrap() = Some(moduleDirectives.unwrap());
-                } else {
-                    compileUnit.errors.lock().unwrap().extend(err);
-                    compilerState.foundErrors.store(true, Ordering::Relaxed);
-                }
-                *compileUnit.tokens.lock().unwrap() = Some(toks);
-                compileUnit
-                    .finishedStage
-                    .store(StageCompileUnit::Lexer, Ordering::Relaxed);
-            });
+            let stage = if compilerState.tokenCache.minimizes() {
+                TraceStage::Scan
+            } else {
+                TraceStage::Lex
+            };
+            let start = Instant::now();
+            if let Some(timeTrace) = &compilerState.timeTrace {
+                timeTrace.startLexing(tu, stage);
+            }
+            let mut lexer = Lexer::new(preprocessor());
+            if compilerState.timeTrace.is_some() {
+                lexer.measurePreprocessing();
+            }
+            let toks = (&mut lexer).collect::<Vec<_>>();
+            let moduleDirectivesPos = lexer.moduleDirectives();
+            if let Some(timeTrace) = &compilerState.timeTrace {
+                let preprocessing = lexer.preprocessingTime().unwrap_or_default();
+                timeTrace.recordLexing(stage, tu, start, preprocessing);
+            }
+
+            // TODO: We are mixing parsing the module macros with lexing the file. This could be split.
+            let start = Instant::now();
+            let moduleDirectives = parseModuleMacroOp(tu, &toks, moduleDirectivesPos);
+            if let Some(timeTrace) = &compilerState.timeTrace {
+                timeTrace.record(TraceStage::ModuleDirectives, tu, start);
+            }
+            (toks, lexer.errors(), moduleDirectives)
+        };
+        // The module operations are kept even if the file has other errors,
+        // so the ones that depend on it find it
+        match moduleDirectives {
+            Ok(moduleDirectives) => {
+                *compileUnit.moduleOperations.lock().unwrap() = Some(moduleDirectives);
+            }
+            Err(mut errModuleDirectives) => err.append(&mut errModuleDirectives),
         }
-        self.pool.join();
-        assert!(self.pool.panic_count() == 0);
-        self.genDependencyTreeAndAggregateErrors()
+
+        let lexed = err.is_empty();
+        if !lexed {
+            compileUnit.errors.lock().unwrap().extend(err);
+            compilerState.foundErrors.store(true, Ordering::Relaxed);
+        }
+        *compileUnit.tokens.lock().unwrap() = Some(toks);
+        compileUnit
+            .finishedStage
+            .store(StageCompileUnit::Lexer, Ordering::Relaxed);
+        lexed
     }
 
     /// Executes the preprocessing stage
     pub fn print_dependency_tree(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
         let tree = self
-            .lexAllCompileModule()
+            .scanDependencies()
             .map_err(|err| (self.compilerState.clone(), err))?;
         println!("Resulting module tree: {:?}", tree.roots);
         let dependencyIterator = DependencyIterator::new(&tree, 0);
@@ -234,172 +637,906 @@ impl Compiler {
         Ok(())
     }
 
+    /// Finds the translation units to recompile after the `changed` files
+    /// changed, and the ones that import them, in a valid build order. The
+    /// result of the dependency scan is kept in the `rebuildStateFile`, and
+    /// the translation units whose files did not change are restored from it
+    /// instead of being scanned again. If any translation unit is scanned,
+    /// the module header units are scanned too, as it may import them. The
+    /// translation units of the plan are planned again until
+    /// [`Compiler::confirmRebuild`] records that they were rebuilt.
+    pub fn rebuildPlan(&mut self, changed: &[String]) -> Result<RebuildPlan, Vec<CompileMsg>> {
+        let statePath = PathBuf::from(&self.compilerState.parameters.rebuildStateFile);
+        let mut previous = RebuildState::read(&statePath).unwrap_or_else(|err| {
+            log::info!("Scanning all the translation units: {err}");
+            RebuildState::default()
+        });
+
+        // The unlisted modules are found with the restored operations too
+        let mut upToDate = self.upToDateUnits(std::mem::take(&mut previous.units));
+        self.discoverModulesWith(
+            &upToDate
+                .iter()
+                .map(|(tu, (_, operations))| (*tu, operations.clone()))
+                .collect(),
+        )?;
+
+        let changed = {
+            let compileFiles = self.compilerState.compileFiles.lock().unwrap();
+            changed
+                .iter()
+                .map(|path| compileFiles.canonicalPath(path))
+                .collect::<HashSet<_>>()
+        };
+
+        let mut units = self
+            .compilerState
+            .compileUnits
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        units.sort_unstable();
+        let mut restored = HashMap::new();
+        let mut outOfDate = vec![];
+        for tu in units.iter().copied() {
+            if let Some((unit, operations)) = upToDate.remove(&tu) {
+                *self.compilerState.compileUnits[&tu]
+                    .moduleOperations
+                    .lock()
+                    .unwrap() = Some(operations);
+                restored.insert(tu, unit);
+            } else {
+                outOfDate.push(tu);
+            }
+        }
+
+        let filesEntered = Arc::new(FilesEntered::default());
+        if !outOfDate.is_empty() {
+            let mut compilerState = CompilerState {
+                tokenCache: self.scanCache.clone(),
+                ..self.compilerState.clone()
+            };
+            compilerState
+                .preprocessorCallbacks
+                .push(filesEntered.clone());
+            let translationUnits = outOfDate
+                .iter()
+                .copied()
+                .filter(|tu| !compilerState.moduleHeaderUnitsFiles.contains(tu))
+                .collect::<Vec<_>>();
+            self.lexCompileUnits(&compilerState, &translationUnits);
+            for tu in compilerState.moduleHeaderUnitsFiles.iter() {
+                restored.remove(tu);
+            }
+        }
+
+        let operations = self
+            .compilerState
+            .compileUnits
+            .iter()
+            .map(|(tu, unit)| {
+                let operations = unit.moduleOperations.lock().unwrap().clone();
+                (*tu, operations.unwrap_or_default())
+            })
+            .collect::<HashMap<_, _>>();
+        let tree = self.genDependencyTreeAndAggregateErrors()?;
+
+        let mut state = RebuildState::default();
+        let mut dirty = outOfDate.iter().copied().collect::<HashSet<_>>();
+        let mut rescanned = vec![];
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        for tu in units {
+            let unit = restored.remove(&tu).unwrap_or_else(|| {
+                rescanned.push(compileFiles.getOpenedFile(tu).path().clone());
+                UnitState::new(&filesEntered.take(tu), &operations[&tu], &mut compileFiles)
+            });
+            let path = compileFiles.getOpenedFile(tu).path().clone();
+            if unit.entersAny(&changed) || previous.pending.contains(&path) {
+                dirty.insert(tu);
+            }
+            state.units.insert(path, unit);
+        }
+        let rebuild = planRebuild(&tree, &dirty)
+            .into_iter()
+            .map(|tu| compileFiles.getOpenedFile(tu).path().clone())
+            .collect::<Vec<_>>();
+        drop(compileFiles);
+        state.pending = rebuild.iter().cloned().collect();
+        Self::writeRebuildState(&state, &statePath)?;
+
+        rescanned.sort_unstable();
+        Ok(RebuildPlan { rebuild, rescanned })
+    }
+
+    /// The translation units of a previous scan whose files did not change,
+    /// with their module operations
+    fn upToDateUnits(
+        &self,
+        units: HashMap<String, UnitState>,
+    ) -> HashMap<TranslationUnit, (UnitState, Vec<ModuleOperator>)> {
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        units
+            .into_iter()
+            .filter_map(|(path, unit)| {
+                if !unit.isUpToDate(&mut compileFiles) {
+                    return None;
+                }
+                let tu = compileFiles.getPath(&path).ok()?;
+                let operations = unit.operations(&mut compileFiles)?;
+                Some((tu, (unit, operations)))
+            })
+            .collect()
+    }
+
+    /// Records that the translation units of the last rebuild plan were
+    /// rebuilt, so the next plans only have them if they change again.
+    pub fn confirmRebuild(&self) -> Result<(), Vec<CompileMsg>> {
+        let statePath = PathBuf::from(&self.compilerState.parameters.rebuildStateFile);
+        let mut state = RebuildState::read(&statePath).map_err(|err| {
+            vec![CompileError::unlocated(format!(
+                "Can't read the rebuild state at {}: {err}",
+                statePath.display()
+            ))]
+        })?;
+        state.pending.clear();
+        Self::writeRebuildState(&state, &statePath)
+    }
+
+    /// Writes the state of the rebuild planner to `statePath`
+    fn writeRebuildState(state: &RebuildState, statePath: &Path) -> Result<(), Vec<CompileMsg>> {
+        state.write(statePath).map_err(|err| {
+            vec![CompileError::unlocated(format!(
+                "Can't write the rebuild state to {}: {err}",
+                statePath.display()
+            ))]
+        })
+    }
+
+    /// Prints the translation units to recompile after the `changed` files
+    /// changed, in a valid build order.
+    pub fn print_rebuild_plan(
+        &mut self,
+        changed: &[String],
+    ) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let plan = self
+            .rebuildPlan(changed)
+            .map_err(|err| (self.compilerState.clone(), err))?;
+        log::info!("Rescanned {} translation units", plan.rescanned.len());
+        for path in plan.rebuild {
+            println!("{path}");
+        }
+        Ok(())
+    }
+
     /// Executes the preprocessing stage
     pub fn print_preprocessor(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
-        let tree = self
-            .lexAllCompileModule()
-            .map_err(|err| (self.compilerState.clone(), err))?;
+        let outputs = Arc::new(Outputs::default());
+        self.lexAllCompileModule(|compilerState| {
+            compilerState.preprocessorCallbacks.push(outputs.clone());
+        })
+        .map_err(|err| (self.compilerState.clone(), err))?;
+        self.writeOutputs(&outputs, "i")
+    }
 
-        let dependencyIterator = Arc::new(DependencyIterator::new(&tree, 0));
+    /// Preprocesses the files, explaining step by step the macro expansions
+    /// requested. The request is either `path:line` or a macro name.
+    pub fn print_macro_explanation(
+        &mut self,
+        request: &str,
+        json: bool,
+    ) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let tracer =
+            MacroTracer::fromRequest(request, &self.compilerState.compileFiles).map_err(|err| {
+                (
+                    self.compilerState.clone(),
+                    vec![CompileError::unlocated(err)],
+                )
+            })?;
+        let tracer = Arc::new(tracer);
+        self.lexAllCompileModule(|compilerState| {
+            compilerState.macroTracer = Some(tracer.clone());
+        })
+        .map_err(|err| (self.compilerState.clone(), err))?;
 
-        loop {
-            let next = dependencyIterator.next();
-            let dependencyIterator = dependencyIterator.clone();
-            let compilerState = self.compilerState.clone();
-            match next {
-                Some(tu) => self.pool.execute(move || {
-                    let mut output = format!("// file: {}\n", &tu);
-                    for tok in Preprocessor::new((compilerState.clone(), tu)) {
-                        match tok {
-                            Ok(tok) => {
-                                output.push_str(tok.tokPos.tok.to_str());
-                            }
-                            Err(err) => {
-                                log::info!("{}", err.to_string(&compilerState.compileFiles));
-                                assert!(
-                                    err.severity() != CompileMsgKind::FatalError,
-                                    "Force stop. Unrecoverable error"
-                                );
-                            }
-                        }
-                    }
-                    print!("{output}");
-                    dependencyIterator.markDone(tu, 1);
-                }),
-                None => break,
+        let compilerState = &self.compilerState;
+        let mut tus = compilerState
+            .compileUnits
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        tus.sort_unstable();
+        let mut jsonOutput = json::JsonValue::new_array();
+        for tu in tus {
+            let entries = tracer.take(tu);
+            if entries.is_empty() {
+                continue;
+            }
+            let path = compilerState
+                .compileFiles
+                .lock()
+                .unwrap()
+                .getOpenedFile(tu)
+                .path()
+                .clone();
+            if json {
+                let _ = jsonOutput.push(json::object! {
+                    file: path,
+                    steps: MacroTracer::entriesToJson(&entries, &compilerState.compileFiles),
+                });
+            } else {
+                print!(
+                    "// file: {path}\n{}",
+                    MacroTracer::entriesToText(&entries, &compilerState.compileFiles)
+                );
             }
         }
-        self.pool.join();
-        assert!(self.pool.panic_count() == 0);
+        if json {
+            println!("{}", jsonOutput.pretty(2));
+        }
+        Ok(())
+    }
+
+    /// Preprocesses the files, and returns the conditional structure of each
+    /// translation unit, sorted by translation unit.
+    pub(crate) fn conditionalReports(
+        &mut self,
+    ) -> Result<Vec<(TranslationUnit, ConditionalReport)>, Vec<CompileMsg>> {
+        self.recordConditionalReports();
+        self.lexAllCompileModule(|_| {})?;
+        Ok(self.takeConditionalReports())
+    }
+
+    /// Preprocesses the files, and prints as JSON the conditional
+    /// structure of each translation unit, with the skipped regions.
+    pub fn print_conditional_report(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let reports = self
+            .conditionalReports()
+            .map_err(|err| (self.compilerState.clone(), err))?;
+        let mut jsonOutput = json::JsonValue::new_array();
+        for (tu, report) in reports {
+            let mut json = report.toJson(&self.compilerState.compileFiles);
+            json["tu"] = self
+                .compilerState
+                .compileFiles
+                .lock()
+                .unwrap()
+                .getOpenedFile(tu)
+                .path()
+                .as_str()
+                .into();
+            let _ = jsonOutput.push(json);
+        }
+        println!("{}", jsonOutput.pretty(2));
+        Ok(())
+    }
+
+    /// Preprocesses the files, and prints the include hierarchy of each
+    /// translation unit, followed by the cost of each header.
+    pub fn print_include_tree(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let includeTree = Arc::new(IncludeTree::default());
+        self.lexAllCompileModule(|compilerState| {
+            compilerState
+                .preprocessorCallbacks
+                .push(includeTree.clone());
+        })
+        .map_err(|err| (self.compilerState.clone(), err))?;
+
+        let compileFiles = &self.compilerState.compileFiles;
+        let mut tus = self
+            .compilerState
+            .compileUnits
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        tus.sort_unstable();
+        for tu in tus {
+            print!("{}", includeTree.treeToText(tu, compileFiles));
+        }
+        print!("// include cost\n{}", includeTree.costsToText(compileFiles));
         Ok(())
     }
 
+    /// Preprocesses the files, and prints as JSON the cross-reference index
+    /// of the macros of all the translation units.
+    pub fn print_macro_index(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let macroIndex = Arc::new(MacroIndex::default());
+        self.lexAllCompileModule(|compilerState| {
+            compilerState.preprocessorCallbacks.push(macroIndex.clone());
+        })
+        .map_err(|err| (self.compilerState.clone(), err))?;
+        println!(
+            "{}",
+            macroIndex
+                .toJson(&self.compilerState.compileFiles)
+                .pretty(2)
+        );
+        Ok(())
+    }
+
+    /// Partially preprocesses the files, resolving only the conditional groups
+    /// that depend on the known macros, and prints the resulting source.
+    pub fn print_unifdef(&self, config: &UnifdefConfig) {
+        let mut tus = self
+            .compilerState
+            .compileUnits
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        tus.sort_unstable();
+        for tu in tus {
+            let file = self
+                .compilerState
+                .compileFiles
+                .lock()
+                .unwrap()
+                .getOpenedFile(tu);
+            print!(
+                "// file: {}\n{}",
+                file.path(),
+                Unifdef::run(config, file.content())
+            );
+        }
+    }
+
+    /// Bundles each translation unit with the project headers it includes,
+    /// and prints the resulting self-contained source.
+    pub fn print_amalgamation(&self) {
+        let mut tus = self
+            .compilerState
+            .compileUnits
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        tus.sort_unstable();
+        for tu in tus {
+            let (output, messages) = Amalgamator::run(&self.compilerState.compileFiles, tu);
+            for msg in messages {
+                msg.print(&self.compilerState.compileFiles);
+            }
+            let path = self
+                .compilerState
+                .compileFiles
+                .lock()
+                .unwrap()
+                .getOpenedFile(tu)
+                .path()
+                .clone();
+            print!("// file: {path}\n{output}");
+        }
+    }
+
     /// Executes the preprocessing stage and parses the tokens to its final token form
     pub fn print_lexer(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
-        self.lexAllCompileModule()
+        self.lexAllCompileModule(|_| {})
             .map_err(|err| (self.compilerState.clone(), err))?;
 
-        #[allow(clippy::significant_drop_in_scrutinee)]
+        let outputs = Outputs::default();
         for (tu, compileUnit) in self.compilerState.compileUnits.iter() {
-            let mut output = format!("// file: {}\n", &tu);
+            let mut output = String::new();
             for tok in compileUnit.tokens.lock().unwrap().as_ref().unwrap().iter() {
-                output.push_str(&format!("{:?}\n", tok.tokPos.tok));
+                let _ = writeln!(output, "{:?}", tok.tokPos.tok);
             }
-            print!("{output}");
+            outputs.push(*tu, output);
         }
-        Ok(())
+        self.writeOutputs(&outputs, "tokens")
     }
 
-    /// Parses the resulting tokens to an AST and prints it
-    pub fn print_parsed_tree(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
-        let tree = self
-            .lexAllCompileModule()
-            .map_err(|err| (self.compilerState.clone(), err))?;
+    /// Lexes and parses all the translation units. Each one is lexed once,
+    /// and its dependencies are found in its own tokens. Then each one is
+    /// parsed as soon as its dependencies are parsed. When recompiling, the
+    /// translation units up to date are only scanned: the ones that depend on
+    /// one that changed are lexed while the others are parsed, pipelined. The
+    /// translation units on the critical path of the module tree go first
+    /// (see [`CostModel`]). The translation units that can't be lexed, and the
+    /// ones that depend on them, are not parsed: their errors are returned
+    /// once the rest are parsed. `onParsed` is called from the threads of the
+    /// pool with the result of parsing each translation unit. Returns the
+    /// predicted and the actual critical path.
+    fn parseAll(
+        &mut self,
+        onParsed: impl Fn(&CompilerState, TranslationUnit, AstTu, Vec<CompileMsg>)
+            + Send
+            + Sync
+            + 'static,
+    ) -> Result<CriticalPathReport, Vec<CompileMsg>> {
+        self.discoverModules()?;
+        let compilerState = self.compilerState.clone();
+        let scheduler = Arc::new(Scheduler::default());
+        let failed = Arc::new(Mutex::new(HashSet::new()));
 
-        let dependencyIterator = Arc::new(DependencyIterator::new(&tree, 0));
+        let (upToDate, outOfDate): (Vec<_>, Vec<_>) = compilerState
+            .translationUnitsFiles
+            .iter()
+            .copied()
+            .partition(|tu| self.upToDate.contains(tu));
+        let moduleHeaderUnits = compilerState
+            .moduleHeaderUnitsFiles
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        let lexedFirst = Arc::new(Mutex::new(HashMap::new()));
+        let onLexedFirst = {
+            let lexedFirst = lexedFirst.clone();
+            let failed = failed.clone();
+            Arc::new(move |tu, lexed: bool, took| {
+                lexedFirst.lock().unwrap().insert(tu, took);
+                if !lexed {
+                    failed.lock().unwrap().insert(tu);
+                }
+            })
+        };
+        self.spawnLexing(
+            &compilerState,
+            &moduleHeaderUnits,
+            &outOfDate,
+            &scheduler,
+            &onLexedFirst,
+        );
+        if !upToDate.is_empty() {
+            let scanState = CompilerState {
+                tokenCache: self.scanCache.clone(),
+                ..compilerState.clone()
+            };
+            self.spawnLexing(
+                &scanState,
+                &[],
+                &upToDate,
+                &scheduler,
+                &Arc::new(|_, _, _| {}),
+            );
+        }
+        self.pool.join();
+        assert_eq!(self.pool.panic_count(), 0);
 
-        loop {
-            let next = dependencyIterator.next();
+        let tree = generateDependencyTree(&compilerState).map_err(|err| {
+            // The lexing errors might be the cause, they go instead
+            if compilerState.foundErrors.load(Ordering::Relaxed) {
+                self.takeErrors()
+            } else {
+                err
+            }
+        })?;
+        let timingCache = self.readTimingCache();
+        let costs = Arc::new(CostModel::new(
+            &tree,
+            &timingCache,
+            &mut self.compilerState.compileFiles.lock().unwrap(),
+        ));
+        let estimated = self
+            .compilerState
+            .compileUnits
+            .keys()
+            .map(|tu| (*tu, costs.estimate(*tu)))
+            .collect::<HashMap<_, _>>();
+        let lexedFirst = std::mem::take(&mut *lexedFirst.lock().unwrap());
+        for (tu, took) in &lexedFirst {
+            if !failed.lock().unwrap().contains(tu) {
+                costs.lexed(*tu, Self::tokensLexed(&compilerState, *tu), *took);
+            }
+        }
+
+        let steps = tree
+            .roots
+            .values()
+            .chain(tree.childModules.values())
+            .map(|node| (node.module.1, node.stepsCompleted.clone()))
+            .collect::<HashMap<_, _>>();
+        self.skipped = self.unitsToSkip(&tree);
+        for tu in lexedFirst.keys().chain(&self.skipped) {
+            steps[tu].fetch_max(STEP_LEXED, Ordering::Relaxed);
+        }
+        let skipped = Arc::new(self.skipped.clone());
+        let dependencyIterator = Arc::new({
+            let costs = costs.clone();
+            DependencyIterator::new_prioritized(&tree, STEP_LEXED, move |root| {
+                costs.remainingCost(root.module.1)
+            })
+        });
+
+        // The translation units up to date that can't be skipped were only
+        // scanned
+        let onLexed = {
+            let compilerState = compilerState.clone();
             let dependencyIterator = dependencyIterator.clone();
-            let compilerState = self.compilerState.clone();
-            match next {
-                Some(tu) => self.pool.execute(move || {
-                    let compileUnit = compilerState.compileUnits.get(&tu).unwrap();
-                    compileUnit
-                        .processingStage
-                        .store(StageCompileUnit::Parser, Ordering::Relaxed);
-                    let mut output = format!("// file: {}\n", &tu);
-                    let mut parser = Parser::new(
-                        compileUnit.tokens.lock().unwrap().take().unwrap(),
-                        tu,
-                        compilerState.clone(),
-                    );
-                    let (ast, errors) = parser.parse();
+            let failed = failed.clone();
+            let costs = costs.clone();
+            Arc::new(move |tu, lexed: bool, took| {
+                if lexed {
+                    costs.lexed(tu, Self::tokensLexed(&compilerState, tu), took);
+                } else {
+                    failed.lock().unwrap().insert(tu);
+                }
+                steps[&tu].fetch_max(STEP_LEXED, Ordering::Relaxed);
+                dependencyIterator.notifyStageCompleted();
+            })
+        };
+        let mut toLex = upToDate
+            .into_iter()
+            .filter(|tu| !skipped.contains(tu))
+            .collect::<Vec<_>>();
+        toLex.sort_by_cached_key(|tu| (Reverse(costs.remainingCost(*tu)), *tu));
+        self.spawnLexing(&compilerState, &[], &toLex, &scheduler, &onLexed);
+
+        let onParsed = Arc::new(onParsed);
+        while let Some(tu) = dependencyIterator.next() {
+            let compilerState = compilerState.clone();
+            let dependencyIterator = dependencyIterator.clone();
+            let failed = failed.clone();
+            let onParsed = onParsed.clone();
+            let costs = costs.clone();
+            let skipped = skipped.clone();
+            scheduler.pushParse(&self.pool, costs.remainingCost(tu), move || {
+                let canParse =
+                    !skipped.contains(&tu) && Self::canParse(&compilerState, tu, &failed);
+                let crashed = canParse
+                    && Self::guardCrash(&compilerState, tu, "parsing", || {
+                        Self::parseUnit(&compilerState, tu, &costs, &*onParsed);
+                    })
+                    .is_none();
+                if crashed {
+                    // The ones that import it can't be parsed either
+                    failed.lock().unwrap().insert(tu);
+                }
+                dependencyIterator.markDone(tu, STEP_PARSED);
+            });
+        }
+        self.pool.join();
+        assert_eq!(self.pool.panic_count(), 0);
+
+        if self.compilerState.foundErrors.load(Ordering::Relaxed) {
+            return Err(self.takeErrors());
+        }
+
+        let measured = costs.measured();
+        let report = self.criticalPathReport(&costs, &estimated, &measured);
+        self.writeTimingCache(timingCache, measured)?;
+        Ok(report)
+    }
+
+    /// Number of tokens lexed of a translation unit
+    fn tokensLexed(compilerState: &CompilerState, tu: TranslationUnit) -> usize {
+        compilerState.compileUnits[&tu]
+            .tokens
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, Vec::len)
+    }
+
+    /// The costs of the previous compilations, from the `timingCacheFile`
+    fn readTimingCache(&self) -> TimingCache {
+        let Some(path) = &self.compilerState.parameters.timingCacheFile else {
+            return TimingCache::default();
+        };
+        TimingCache::read(Path::new(path)).unwrap_or_else(|err| {
+            log::info!("Estimating the costs without the timing cache: {err}");
+            TimingCache::default()
+        })
+    }
+
+    /// Adds the costs `measured` to the `timingCache`, and writes it to the
+    /// `timingCacheFile`, if any
+    fn writeTimingCache(
+        &self,
+        mut timingCache: TimingCache,
+        measured: HashMap<TranslationUnit, UnitTiming>,
+    ) -> Result<(), Vec<CompileMsg>> {
+        let Some(path) = &self.compilerState.parameters.timingCacheFile else {
+            return Ok(());
+        };
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        for (tu, timing) in measured {
+            let unitPath = compileFiles.getOpenedFile(tu).path().clone();
+            timingCache.units.insert(unitPath, timing);
+        }
+        drop(compileFiles);
+        timingCache.write(Path::new(path)).map_err(|err| {
+            vec![CompileError::unlocated(format!(
+                "Can't write the timing cache to {path}: {err}"
+            ))]
+        })
+    }
+
+    /// The critical path with the costs `estimated` before compiling, and the
+    /// one with the costs `measured`
+    fn criticalPathReport(
+        &self,
+        costs: &CostModel,
+        estimated: &HashMap<TranslationUnit, u64>,
+        measured: &HashMap<TranslationUnit, UnitTiming>,
+    ) -> CriticalPathReport {
+        let micros = |tu| {
+            measured
+                .get(&tu)
+                .map_or(0, |timing: &UnitTiming| timing.micros)
+        };
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        let mut report = |path: Vec<TranslationUnit>| {
+            path.into_iter()
+                .map(|tu| CriticalPathStep {
+                    path: compileFiles.getOpenedFile(tu).path().clone(),
+                    estimatedMicros: estimated[&tu],
+                    micros: micros(tu),
+                })
+                .collect::<Vec<_>>()
+        };
+        CriticalPathReport {
+            predicted: report(costs.criticalPath(|tu| estimated[&tu]).0),
+            actual: report(costs.criticalPath(micros).0),
+        }
+    }
+
+    /// Can the translation unit be parsed? Not if it, or any of the ones it
+    /// imports, `failed`. In that case it's added to the ones that failed.
+    fn canParse(
+        compilerState: &CompilerState,
+        tu: TranslationUnit,
+        failed: &Mutex<HashSet<TranslationUnit>>,
+    ) -> bool {
+        let mut failed = failed.lock().unwrap();
+        let dependencyFailed = compilerState.compileUnits[&tu]
+            .moduleKind
+            .lock()
+            .unwrap()
+            .dependsOn
+            .iter()
+            .any(|dependency| failed.contains(&dependency.1));
+        if dependencyFailed {
+            failed.insert(tu);
+        }
+        !failed.contains(&tu)
+    }
+
+    /// Parses a translation unit already lexed, and calls `onParsed` with
+    /// the result
+    fn parseUnit(
+        compilerState: &CompilerState,
+        tu: TranslationUnit,
+        costs: &CostModel,
+        onParsed: &impl Fn(&CompilerState, TranslationUnit, AstTu, Vec<CompileMsg>),
+    ) {
+        let compileUnit = compilerState.compileUnits.get(&tu).unwrap();
+        let start = Instant::now();
+        compileUnit
+            .processingStage
+            .store(StageCompileUnit::Parser, Ordering::Relaxed);
+        let mut parser = Parser::new(
+            compileUnit.tokens.lock().unwrap().take().unwrap(),
+            tu,
+            compilerState.clone(),
+        );
+        let (ast, errors) = parser.parse();
+        drop(parser);
+        costs.parsed(tu, start.elapsed());
+        if let Some(timeTrace) = &compilerState.timeTrace {
+            timeTrace.record(TraceStage::Parse, tu, start);
+        }
+        onParsed(compilerState, tu, ast, errors);
+        compileUnit
+            .finishedStage
+            .store(StageCompileUnit::Parser, Ordering::Relaxed);
+    }
+
+    /// The translation units up to date that don't depend on any that is not
+    fn unitsToSkip(&self, tree: &ModuleTree) -> HashSet<TranslationUnit> {
+        if self.upToDate.is_empty() {
+            return HashSet::new();
+        }
+        let units = self.compilerState.compileUnits.keys().copied();
+        let dirty = units
+            .clone()
+            .filter(|tu| !self.upToDate.contains(tu))
+            .collect();
+        let rebuild = planRebuild(tree, &dirty)
+            .into_iter()
+            .collect::<HashSet<_>>();
+        units.filter(|tu| !rebuild.contains(tu)).collect()
+    }
+
+    /// Parses the translation units again, except the ones at
+    /// `upToDatePaths` that don't depend on any other that changed: their
+    /// results of the previous compilation are still valid. `onParsed` is
+    /// called with the diagnostics of each translation unit parsed. Returns
+    /// the paths of the translation units skipped, and the errors of the ones
+    /// that could not be lexed.
+    pub(crate) fn recompile(
+        &mut self,
+        upToDatePaths: &HashSet<String>,
+        onParsed: impl Fn(&CompilerState, TranslationUnit, Vec<CompileMsg>) + Send + Sync + 'static,
+    ) -> (Vec<String>, Vec<CompileMsg>) {
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        self.upToDate = self
+            .compilerState
+            .compileUnits
+            .keys()
+            .copied()
+            .filter(|tu| upToDatePaths.contains(compileFiles.getOpenedFile(*tu).path()))
+            .collect();
+        drop(compileFiles);
+        let errors = self
+            .parseAll(move |compilerState, tu, _, errors| onParsed(compilerState, tu, errors))
+            .err()
+            .unwrap_or_default();
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        let mut skipped = self
+            .skipped
+            .iter()
+            .map(|tu| compileFiles.getOpenedFile(*tu).path().clone())
+            .collect::<Vec<_>>();
+        skipped.sort();
+        (skipped, errors)
+    }
 
+    /// Parses all the translation units, and returns the critical path of
+    /// the module tree: the one predicted before compiling, and the one found
+    /// with the costs measured.
+    pub fn criticalPath(&mut self) -> Result<CriticalPathReport, Vec<CompileMsg>> {
+        self.parseAll(|_, _, _, _| {})
+    }
+
+    /// Parses all the translation units, and prints the critical path of the
+    /// module tree.
+    pub fn print_critical_path(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let report = self
+            .criticalPath()
+            .map_err(|err| (self.compilerState.clone(), err))?;
+        print!("{report}");
+        Ok(())
+    }
+
+    /// Parses the resulting tokens to an AST and prints it
+    pub fn print_parsed_tree(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        self.print_ast(None)
+    }
+
+    /// Parses the translation units, and prints their diagnostics and their
+    /// AST. With `filter`, only the declarations with that name are printed.
+    pub fn print_ast(
+        &mut self,
+        filter: Option<&str>,
+    ) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let outputs = Arc::new(Outputs::default());
+        let colored = self.coloredOutput();
+        let filter = filter.map(ToString::to_string);
+        let parsed = self.parseAll({
+            let outputs = outputs.clone();
+            move |compilerState, tu, ast, errors| {
+                let mut output = String::new();
+                for err in errors {
+                    output.push_str(&err.to_string(&compilerState.compileFiles));
                     output.push('\n');
-                    for err in errors {
-                        output.push_str(&err.to_string(&compilerState.compileFiles));
-                        output.push('\n');
-                    }
-                    output.push_str(&Parser::printStringTree(ast));
+                }
+                let tree = ast.getDebugNode();
+                let nodes = filter
+                    .as_ref()
+                    .map_or_else(|| vec![&tree], |name| tree.findNamed(name));
+                for node in nodes {
+                    output.push_str(&if colored {
+                        node.to_string()
+                    } else {
+                        node.to_string_colorless()
+                    });
                     output.push('\n');
+                }
+                outputs.push(tu, output);
+            }
+        });
+        self.writeOutputs(&outputs, "ast")?;
+        parsed
+            .map(|_| ())
+            .map_err(|err| (self.compilerState.clone(), err))
+    }
 
-                    print!("{output}");
-                    dependencyIterator.markDone(tu, 1);
-                    compileUnit
-                        .finishedStage
-                        .store(StageCompileUnit::Parser, Ordering::Relaxed);
-                }),
-                None => break,
+    /// Parses and analyzes the translation units, and prints only their
+    /// diagnostics. The analysis is done while parsing.
+    pub fn print_diagnostics(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let outputs = Arc::new(Outputs::default());
+        let parsed = self.parseAll({
+            let outputs = outputs.clone();
+            move |compilerState, tu, _, errors| {
+                let output = errors
+                    .iter()
+                    .map(|err| err.to_string(&compilerState.compileFiles) + "\n")
+                    .collect();
+                outputs.push(tu, output);
             }
+        });
+        self.writeOutputs(&outputs, "diagnostics")?;
+        parsed
+            .map(|_| ())
+            .map_err(|err| (self.compilerState.clone(), err))
+    }
+
+    /// Scans the module dependencies, and prints the module of each
+    /// translation unit and the ones it depends on
+    pub fn print_scan(&mut self) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let tree = self
+            .scanDependencies()
+            .map_err(|err| (self.compilerState.clone(), err))?;
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        let mut describe = |module: &ModuleDeclaration| match module {
+            ModuleDeclaration::ModuleHeaderUnit(file) => {
+                format!("<{}>", compileFiles.getOpenedFile(*file).path())
+            }
+            ModuleDeclaration::Global(_) => "global module".to_string(),
+            module => module.to_string(),
+        };
+        let outputs = Outputs::default();
+        for node in tree.roots.values().chain(tree.childModules.values()) {
+            let mut dependencies = node
+                .dependsOn
+                .iter()
+                .map(|dependency| format!("depends on: {}\n", describe(&dependency.0)))
+                .collect::<Vec<_>>();
+            dependencies.sort_unstable();
+            let output = format!("{}\n{}", describe(&node.module.0), dependencies.concat());
+            outputs.push(node.module.1, output);
         }
-        self.pool.join();
-        assert!(self.pool.panic_count() == 0);
-        Ok(())
+        drop(compileFiles);
+        self.writeOutputs(&outputs, "deps")
+    }
+
+    /// Parses the translation units, and returns their AST by path, with the
+    /// diagnostics found.
+    pub fn parsedTrees(&mut self) -> (HashMap<String, AstTu>, Vec<CompileMsg>) {
+        let resultLoc = Arc::new(Mutex::new((HashMap::new(), vec![])));
+        let resultParsed = resultLoc.clone();
+        let parsed = self.parseAll(move |compilerState, tu, ast, errors| {
+            let path = compilerState
+                .compileFiles
+                .lock()
+                .unwrap()
+                .getOpenedFile(tu)
+                .path()
+                .clone();
+            let mut res = resultParsed.lock().unwrap();
+            res.0.insert(path, ast);
+            res.1.extend(errors);
+        });
+        let mut result = resultLoc.lock().unwrap().clone();
+        if let Err(err) = parsed {
+            result.1.extend(err);
+        }
+        result
     }
 
     /// Parses the resulting tokens to an AST and returns it
     #[cfg(test)]
     pub fn parsed_tree_test(
         &mut self,
-        result: &mut (HashMap<String, crate::Ast::Common::AstTu>, Vec<CompileMsg>),
+        result: &mut (HashMap<String, AstTu>, Vec<CompileMsg>),
     ) -> CompilerState {
-        let tree = match self.lexAllCompileModule() {
-            Ok(tree) => tree,
-            Err(err) => {
-                result.1.extend(err);
-                return self.compilerState.clone();
-            }
-        };
-
-        let dependencyIterator = Arc::new(DependencyIterator::new(&tree, 0));
-        let resultLoc = Arc::new(Mutex::new((HashMap::new(), vec![])));
+        *result = self.parsedTrees();
+        self.compilerState.clone()
+    }
 
-        loop {
-            let next = dependencyIterator.next();
-            let dependencyIterator = dependencyIterator.clone();
-            let compilerState = self.compilerState.clone();
-            let result = resultLoc.clone();
-            match next {
-                Some(tu) => self.pool.execute(move || {
-                    let compileUnit = compilerState.compileUnits.get(&tu).unwrap();
-                    compileUnit
-                        .processingStage
-                        .store(StageCompileUnit::Parser, Ordering::Relaxed);
-                    let mut parser = Parser::new(
-                        compileUnit.tokens.lock().unwrap().take().unwrap(),
-                        tu,
-                        compilerState.clone(),
-                    );
-                    let (ast, errors) = parser.parse();
-                    let mut res = result.lock().unwrap();
-                    res.0.insert(
-                        compilerState
-                            .compileFiles
-                            .lock()
-                            .unwrap()
-                            .getOpenedFile(tu)
-                            .path()
-                            .clone(),
-                        ast,
-                    );
-                    res.1.extend(errors);
-                    drop(parser);
-
-                    dependencyIterator.markDone(tu, 1);
-                    compileUnit
-                        .finishedStage
-                        .store(StageCompileUnit::Parser, Ordering::Relaxed);
-                }),
-                None => break,
-            }
+    /// Writes the stages timed since [`Compiler::enableTimeTrace`] as a Chrome
+    /// trace to `traceFile`, and prints to stderr the slowest translation
+    /// units and headers if `summary`.
+    pub fn print_time_trace(
+        &self,
+        traceFile: Option<&str>,
+        summary: bool,
+    ) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+        let Some(timeTrace) = &self.compilerState.timeTrace else {
+            return Ok(());
+        };
+        let compileFiles = &self.compilerState.compileFiles;
+        if summary {
+            eprint!("{}", timeTrace.summaryToText(compileFiles, 10));
         }
-        self.pool.join();
-        assert!(self.pool.panic_count() == 0);
-        let res = resultLoc.lock().unwrap().clone();
-        result.0 = res.0;
-        result.1 = res.1;
-        self.compilerState.clone()
+        if let Some(traceFile) = traceFile {
+            let trace = timeTrace.toChromeTrace(compileFiles);
+            std::fs::write(traceFile, trace.pretty(1)).map_err(|err| {
+                (
+                    self.compilerState.clone(),
+                    vec![CompileError::unlocated(format!(
+                        "Can't write the time trace to {traceFile}: {err}"
+                    ))],
+                )
+            })?;
+        }
+        Ok(())
     }
 
     /// Attempts to compile everything, until the last thing implemented.
@@ -0,0 +1,44 @@
+//! State that outlives a compilation, so compiling the same project again
+//! doesn't read, lex nor load again what didn't change. The strings are
+//! interned for the whole process anyway (see
+//! [`StringRef`](crate::Utils::StringRef::StringRef)).
+use std::sync::{Arc, Mutex};
+
+use crate::Preprocessor::TokenCache::TokenCache;
+use crate::Sema::Bmi::BmiCache;
+use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::FileSystem;
+use crate::Utils::Parameters::Parameters;
+
+/// Caches shared by the compilations of a project
+#[derive(Debug, Clone)]
+pub struct CompilerCaches {
+    /// Files read
+    pub fileMap: Arc<Mutex<FileMap>>,
+    /// Pre-lexed files of the compilation
+    pub tokenCache: Arc<TokenCache>,
+    /// Minimized files of the dependency scanning
+    pub scanCache: Arc<TokenCache>,
+    /// BMIs of the modules imported or compiled
+    pub bmiCache: Arc<BmiCache>,
+}
+
+impl CompilerCaches {
+    /// Empty caches, of the files of `fileSystem`
+    pub fn new(parameters: Arc<Parameters>, fileSystem: Arc<dyn FileSystem>) -> Self {
+        Self {
+            fileMap: Arc::new(Mutex::new(FileMap::new_with_fs(parameters, fileSystem))),
+            tokenCache: Arc::default(),
+            scanCache: Arc::new(TokenCache::new_minimizing()),
+            bmiCache: Arc::default(),
+        }
+    }
+
+    /// Reads again the files of the project. Returns the paths of the ones
+    /// that changed. The token caches check the contents of the files, and
+    /// the BMIs are written again when their interfaces are compiled, so
+    /// nothing needs to be cleared.
+    pub fn refresh(&self) -> Result<Vec<String>, String> {
+        self.fileMap.lock().unwrap().refresh()
+    }
+}
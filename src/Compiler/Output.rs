@@ -0,0 +1,107 @@
+//! Output of the modes that print a result per translation unit.
+//!
+//! The results are printed in the order the translation units were given, no
+//! matter the order they are compiled in, so the output is the same in every
+//! run. With an output directory, each result is written to its own file
+//! instead.
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::Preprocessor::Callbacks::PreprocessorCallbacks;
+use crate::Preprocessor::Pretoken::PreToken;
+use crate::Utils::FileMap::FileMap;
+use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl, FileTokPos};
+
+use super::TranslationUnit;
+
+/// Results of a mode, by translation unit
+#[derive(Debug, Default)]
+pub struct Outputs {
+    /// Result of each translation unit
+    results: Mutex<HashMap<TranslationUnit, String>>,
+}
+
+impl Outputs {
+    /// Sets the result of a translation unit
+    pub fn push(&self, tu: TranslationUnit, result: String) {
+        self.results.lock().unwrap().insert(tu, result);
+    }
+
+    /// Prints the results to stdout in `order`, each one after a
+    /// `// file: <path>` line. With `dir`, writes each one to
+    /// `<dir>/<file name>.<extension>` instead.
+    pub fn write(
+        &self,
+        order: &[TranslationUnit],
+        fileMap: &Arc<Mutex<FileMap>>,
+        dir: Option<&Path>,
+        extension: &str,
+    ) -> Result<(), Vec<CompileMsg>> {
+        let results = std::mem::take(&mut *self.results.lock().unwrap());
+        let mut fileMap = fileMap.lock().unwrap();
+        let results = order
+            .iter()
+            .filter_map(|tu| {
+                let path = fileMap.getOpenedFile(*tu).path().clone();
+                Some((path, results.get(tu)?))
+            })
+            .collect::<Vec<_>>();
+        drop(fileMap);
+
+        let Some(dir) = dir else {
+            for (path, result) in results {
+                print!("// file: {path}\n{result}");
+                if !result.ends_with('\n') {
+                    println!();
+                }
+            }
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir).map_err(|err| {
+            vec![CompileError::unlocated(format!(
+                "Can't create the output directory {}: {err}",
+                dir.display()
+            ))]
+        })?;
+        let mut written = HashSet::new();
+        let mut errors = vec![];
+        for (path, result) in results {
+            let fileName = Path::new(&path)
+                .file_name()
+                .map_or_else(|| path.clone(), |name| name.to_string_lossy().to_string());
+            let output = dir.join(format!("{fileName}.{extension}"));
+            if !written.insert(output.clone()) {
+                errors.push(CompileError::unlocated(format!(
+                    "Several translation units are named {fileName}, they would be written to the same file {}",
+                    output.display()
+                )));
+                continue;
+            }
+            if let Err(err) = std::fs::write(&output, result) {
+                errors.push(CompileError::unlocated(format!(
+                    "Can't write {}: {err}",
+                    output.display()
+                )));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// As preprocessor callbacks, the result of each translation unit is its
+/// preprocessed source
+impl PreprocessorCallbacks for Outputs {
+    fn tokenGenerated(&self, tu: TranslationUnit, tok: &FileTokPos<PreToken>) {
+        self.results
+            .lock()
+            .unwrap()
+            .entry(tu)
+            .or_default()
+            .push_str(tok.tokPos.tok.to_str());
+    }
+}
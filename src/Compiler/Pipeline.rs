@@ -0,0 +1,95 @@
+//! Jobs of the pipelined compilation.
+//!
+//! Each translation unit advances through the stages on its own: it's parsed
+//! as soon as it's lexed and its dependencies are parsed. The module tree is
+//! found in the tokens lexed, so the translation units are lexed before any
+//! is parsed, except when recompiling: the ones up to date are only scanned,
+//! and the ones that have to be parsed again are lexed while the others are
+//! parsed. The progress is stored in `Node::stepsCompleted`, which the
+//! [`DependencyIterator`](crate::ModuleTree::DependencyIterator::DependencyIterator)
+//! uses to hand out the translation units ready for the next stage.
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use threadpool::ThreadPool;
+
+/// `Node::stepsCompleted` once the translation unit is lexed
+pub const STEP_LEXED: usize = 1;
+/// `Node::stepsCompleted` once the translation unit is parsed
+pub const STEP_PARSED: usize = 2;
+
+/// A job of the pipeline
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Jobs waiting for a thread, by stage
+#[derive(Default)]
+struct Jobs {
+    /// Parsing jobs, with their priority. They go first, as they unlock their
+    /// dependents.
+    parse: VecDeque<(u64, Job)>,
+    /// Lexing jobs
+    lex: VecDeque<Job>,
+}
+
+/// Runs the jobs of the stages in a thread pool, prioritizing the later
+/// stages. Every job pushed schedules a task in the pool, and every task runs
+/// the job with the highest priority at that moment, so no thread waits for a
+/// stage to finish.
+#[derive(Default)]
+pub struct Scheduler {
+    /// Jobs waiting for a thread
+    jobs: Mutex<Jobs>,
+}
+
+impl Scheduler {
+    /// Schedules the lexing of a translation unit
+    pub fn pushLex(self: &Arc<Self>, pool: &ThreadPool, job: impl FnOnce() + Send + 'static) {
+        self.jobs.lock().unwrap().lex.push_back(Box::new(job));
+        self.schedule(pool);
+    }
+
+    /// Schedules the parsing of a translation unit. The ones with the highest
+    /// `priority` are parsed first, and the ones with the same priority in the
+    /// order they were pushed.
+    pub fn pushParse(
+        self: &Arc<Self>,
+        pool: &ThreadPool,
+        priority: u64,
+        job: impl FnOnce() + Send + 'static,
+    ) {
+        self.jobs
+            .lock()
+            .unwrap()
+            .parse
+            .push_back((priority, Box::new(job)));
+        self.schedule(pool);
+    }
+
+    /// Adds a task to the pool that runs the next job
+    fn schedule(self: &Arc<Self>, pool: &ThreadPool) {
+        let scheduler = self.clone();
+        pool.execute(move || {
+            if let Some(job) = scheduler.next() {
+                job();
+            }
+        });
+    }
+
+    /// Takes the job with the highest priority
+    fn next(&self) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let next = jobs
+            .parse
+            .iter()
+            .enumerate()
+            .max_by_key(|(pos, (priority, _))| (*priority, Reverse(*pos)))
+            .map(|(pos, _)| pos);
+        let job = next
+            .and_then(|pos| jobs.parse.remove(pos))
+            .map(|(_, job)| job)
+            .or_else(|| jobs.lex.pop_front());
+        drop(jobs);
+        job
+    }
+}
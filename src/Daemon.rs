@@ -0,0 +1,18 @@
+//! Compile server of the compiler.
+//!
+//! A long-running process that keeps each project it compiles in memory: the
+//! files read, the tokens of the headers, the interned strings and the BMIs
+//! of the modules. A compile request only reads the files again, and compiles
+//! the translation units that entered a file whose contents changed, and the
+//! ones that import them. The diagnostics of the rest are replayed from the
+//! previous compilation.
+//!
+//! Clients talk to it over a Unix socket, one request per connection: a line
+//! with a JSON object, answered with a JSON object per line. See
+//! [`serveCompiles`] and [`requestCompile`].
+
+pub mod Client;
+pub mod Server;
+
+pub use Client::{requestCompile, requestStop};
+pub use Server::{defaultSocket, serveCompiles};
@@ -0,0 +1,71 @@
+//! Requests to a running compile server.
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use json::{object, JsonValue};
+
+/// Sends `request` to the server at `socket`, and calls `onLine` with each
+/// line of the answer
+fn request(
+    socket: &Path,
+    request: &JsonValue,
+    mut onLine: impl FnMut(&JsonValue) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut stream = UnixStream::connect(socket).map_err(|err| {
+        format!(
+            "Can't connect to the compile server at {}: {err}",
+            socket.display()
+        )
+    })?;
+    writeln!(stream, "{}", request.dump()).map_err(|err| err.to_string())?;
+    for line in BufReader::new(stream).lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        let answer = json::parse(&line).map_err(|err| format!("Invalid answer {line}: {err}"))?;
+        if answer["kind"] == "error" {
+            return Err(answer["message"].to_string());
+        }
+        onLine(&answer)?;
+    }
+    Ok(())
+}
+
+/// Asks the compile server at `socket` to compile the project of the file
+/// list `files`. Returns if there were no errors.
+///
+/// The diagnostics are written to `output` as they arrive, followed by how
+/// many translation units were compiled.
+pub fn requestCompile(socket: &Path, files: &str, output: &mut impl Write) -> Result<bool, String> {
+    let cwd = std::env::current_dir().map_err(|err| err.to_string())?;
+    let mut success = None;
+    request(
+        socket,
+        &object! {
+            request: "compile",
+            files: files,
+            cwd: cwd.to_string_lossy().to_string(),
+        },
+        |answer| {
+            let written = match answer["kind"].as_str() {
+                Some("diagnostic") => write!(output, "{}", answer["message"]),
+                Some("done") => {
+                    success = Some(answer["errors"] == 0);
+                    writeln!(
+                        output,
+                        "{} translation units compiled, {} up to date",
+                        answer["compiled"].len(),
+                        answer["cached"].len()
+                    )
+                }
+                _ => Ok(()),
+            };
+            written.map_err(|err| err.to_string())
+        },
+    )?;
+    success.ok_or_else(|| "The compile server stopped before finishing".to_string())
+}
+
+/// Asks the compile server at `socket` to stop
+pub fn requestStop(socket: &Path) -> Result<(), String> {
+    request(socket, &object! {request: "stop"}, |_| Ok(()))
+}
@@ -0,0 +1,370 @@
+//! State of the compile server and the handling of each request.
+//!
+//! A request is `{"request": "compile", "files": <file list>, "cwd": <dir>}`
+//! or `{"request": "stop"}`. The relative paths of a compile request are
+//! relative to its `cwd`, the directory of the client. A compile is answered
+//! with a `diagnostic` line for each message (`cached` if it's replayed from a
+//! previous compilation), and a final `done` line with the translation units
+//! compiled and skipped. Anything that goes wrong is answered with an `error`
+//! line.
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use json::{object, JsonValue};
+
+use crate::Compiler::{Compiler, CompilerCaches, TranslationUnit};
+use crate::ModuleTree::RebuildPlan::FilesEntered;
+use crate::Sema::Bmi::sourceHash;
+use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::RealFileSystem;
+use crate::Utils::Parameters::Parameters;
+use crate::Utils::Structs::{CompileMsg, CompileMsgKind};
+
+/// Socket of the server when none is given: `rustycpp/daemon.sock` in the
+/// runtime directory of the user (`$XDG_RUNTIME_DIR`), which no other user
+/// can access. None if there's no runtime directory.
+pub fn defaultSocket() -> Option<PathBuf> {
+    let runtimeDir = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty())?;
+    Some(
+        PathBuf::from(runtimeDir)
+            .join("rustycpp")
+            .join("daemon.sock"),
+    )
+}
+
+/// A message of a compilation, as sent to the clients
+#[derive(Debug, Clone)]
+struct Diagnostic {
+    /// Is it an error?
+    error: bool,
+    /// Severity, as `error`, `warning` or `note`
+    severity: &'static str,
+    /// The message rendered, with its location
+    text: String,
+}
+
+impl Diagnostic {
+    /// Renders a message with the files of its compilation
+    fn new(msg: &CompileMsg, fileMap: &Arc<Mutex<FileMap>>) -> Self {
+        let severity = match msg.severity() {
+            CompileMsgKind::Error | CompileMsgKind::FatalError => "error",
+            CompileMsgKind::Warning => "warning",
+            CompileMsgKind::Notice => "note",
+        };
+        Self {
+            error: severity == "error",
+            severity,
+            text: msg.to_string(fileMap),
+        }
+    }
+
+    /// The line sent for it. Without `unit` if it's not of a single one.
+    fn json(&self, unit: Option<&str>, cached: bool) -> JsonValue {
+        object! {
+            kind: "diagnostic",
+            unit: unit,
+            severity: self.severity,
+            message: self.text.clone(),
+            cached: cached,
+        }
+    }
+}
+
+/// Result of compiling a translation unit, valid until one of the files it
+/// entered changes
+#[derive(Debug)]
+struct UnitResult {
+    /// Paths of the files entered, including itself
+    files: HashSet<String>,
+    /// Its diagnostics
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// A project kept in memory
+#[derive(Debug)]
+struct Project {
+    /// Hash of the file list. If it changes, the project starts over.
+    listHash: String,
+    /// Parameters of the file list
+    parameters: Arc<Parameters>,
+    /// Files, tokens and BMIs of the previous compilations
+    caches: CompilerCaches,
+    /// Result of each translation unit compiled, by path
+    units: HashMap<String, UnitResult>,
+}
+
+impl Project {
+    /// A project not compiled yet, of the file list at `list` with
+    /// `contents`, whose relative paths are relative to `cwd`
+    fn new(list: &Path, cwd: &Path, contents: &str) -> Result<Self, String> {
+        let listDir = list.parent().unwrap_or_else(|| Path::new(""));
+        let mut parameters = Parameters::new_str(contents, listDir)?;
+        parameters.relativeTo(cwd);
+        let parameters = Arc::new(parameters);
+        Ok(Self {
+            listHash: sourceHash(contents),
+            caches: CompilerCaches::new(parameters.clone(), Arc::new(RealFileSystem)),
+            parameters,
+            units: HashMap::new(),
+        })
+    }
+}
+
+/// What a compilation did
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompileSummary {
+    /// Paths of the translation units compiled, sorted
+    pub compiled: Vec<String>,
+    /// Paths of the translation units skipped, as they were up to date
+    pub cached: Vec<String>,
+    /// Errors found, including the replayed ones
+    pub errors: usize,
+}
+
+/// The compile server, with the projects compiled so far, by the canonical
+/// path of their file lists and the directory their relative paths are
+/// relative to
+#[derive(Debug, Default)]
+pub struct CompileServer {
+    /// The projects
+    projects: HashMap<(String, PathBuf), Project>,
+}
+
+impl CompileServer {
+    /// Compiles the project of the file list at `files`, writing its
+    /// diagnostics to `output` as they are found. Its relative paths, and
+    /// `files`, are relative to `cwd`. Only what changed since the previous
+    /// compilation of the project is compiled again.
+    pub fn compile(
+        &mut self,
+        files: &str,
+        cwd: &Path,
+        output: &mut impl Write,
+    ) -> Result<CompileSummary, String> {
+        let list = std::fs::canonicalize(cwd.join(files))
+            .map_err(|err| format!("Can't find the file list {files}: {err}"))?;
+        let contents = std::fs::read_to_string(&list)
+            .map_err(|err| format!("Can't read the file list {files}: {err}"))?;
+        let key = (list.to_string_lossy().to_string(), cwd.to_path_buf());
+
+        let mut changed = HashSet::new();
+        let mut project = match self.projects.remove(&key) {
+            Some(project) if project.listHash == sourceHash(&contents) => {
+                match project.caches.refresh() {
+                    Ok(paths) => {
+                        changed.extend(paths);
+                        project
+                    }
+                    Err(err) => {
+                        log::info!("Compiling {files} from scratch: {err}");
+                        Project::new(&list, cwd, &contents)?
+                    }
+                }
+            }
+            _ => Project::new(&list, cwd, &contents)?,
+        };
+        project
+            .units
+            .retain(|_, unit| unit.files.is_disjoint(&changed));
+
+        let compiled = catch_unwind(AssertUnwindSafe(|| {
+            Self::compileProject(&mut project, output)
+        }));
+        match compiled {
+            Ok(summary) => {
+                self.projects.insert(key, project);
+                summary
+            }
+            Err(_) => Err(format!("The compiler crashed compiling {files}")),
+        }
+    }
+
+    /// Compiles the translation units of `project` that are not up to date,
+    /// and replays the diagnostics of the rest
+    fn compileProject(
+        project: &mut Project,
+        output: &mut impl Write,
+    ) -> Result<CompileSummary, String> {
+        let fileMap = project.caches.fileMap.clone();
+        let upToDate = project.units.keys().cloned().collect::<HashSet<_>>();
+        let mut compiler = Compiler::new_with_caches(project.parameters.clone(), &project.caches);
+        let filesEntered = Arc::new(FilesEntered::default());
+        compiler.addPreprocessorCallbacks(filesEntered.clone());
+
+        let write = |output: &mut dyn Write, line: &JsonValue| {
+            writeln!(output, "{}", line.dump())
+                .and_then(|()| output.flush())
+                .map_err(|err| err.to_string())
+        };
+        let mut summary = CompileSummary::default();
+        let mut parsed = HashMap::<TranslationUnit, Vec<Diagnostic>>::new();
+        let (skipped, errors) = thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            let compiling = scope.spawn(|| {
+                compiler.recompile(&upToDate, move |compilerState, tu, errors| {
+                    let diagnostics = errors
+                        .iter()
+                        .map(|msg| Diagnostic::new(msg, &compilerState.compileFiles))
+                        .collect::<Vec<_>>();
+                    let _ = sender.send((tu, diagnostics));
+                })
+            });
+            // Stream the diagnostics while the rest is compiled
+            for (tu, diagnostics) in receiver {
+                let path = fileMap.lock().unwrap().getOpenedFile(tu).path().clone();
+                for diagnostic in &diagnostics {
+                    write(output, &diagnostic.json(Some(&path), false))?;
+                }
+                parsed.insert(tu, diagnostics);
+            }
+            compiling
+                .join()
+                .map_err(|_| "The compiler crashed".to_string())
+        })?;
+
+        for msg in &errors {
+            let diagnostic = Diagnostic::new(msg, &fileMap);
+            summary.errors += usize::from(diagnostic.error);
+            write(output, &diagnostic.json(None, false))?;
+        }
+        let mut fileMap = fileMap.lock().unwrap();
+        for (tu, diagnostics) in parsed {
+            let path = fileMap.getOpenedFile(tu).path().clone();
+            let mut files = filesEntered
+                .take(tu)
+                .into_iter()
+                .map(|file| fileMap.getOpenedFile(file).path().clone())
+                .collect::<HashSet<_>>();
+            files.insert(path.clone());
+            summary.errors += diagnostics.iter().filter(|diag| diag.error).count();
+            summary.compiled.push(path.clone());
+            project
+                .units
+                .insert(path, UnitResult { files, diagnostics });
+        }
+        drop(fileMap);
+        summary.compiled.sort();
+
+        for path in &skipped {
+            let unit = &project.units[path];
+            for diagnostic in &unit.diagnostics {
+                summary.errors += usize::from(diagnostic.error);
+                write(output, &diagnostic.json(Some(path), true))?;
+            }
+        }
+        // The units not compiled nor skipped failed, and are tried again the
+        // next time
+        let kept = summary
+            .compiled
+            .iter()
+            .chain(&skipped)
+            .cloned()
+            .collect::<HashSet<_>>();
+        project.units.retain(|path, _| kept.contains(path));
+        summary.cached = skipped;
+
+        write(
+            output,
+            &object! {
+                kind: "done",
+                compiled: summary.compiled.clone(),
+                cached: summary.cached.clone(),
+                errors: summary.errors,
+            },
+        )?;
+        Ok(summary)
+    }
+
+    /// Answers the request of a client. Returns false if the server must
+    /// stop.
+    fn handle(&mut self, stream: &UnixStream) -> std::io::Result<bool> {
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        if line.trim().is_empty() {
+            // Someone checking if the server is running
+            return Ok(true);
+        }
+        let mut output = stream;
+        let fail = |output: &mut &UnixStream, message: &str| {
+            writeln!(
+                output,
+                "{}",
+                object! {kind: "error", message: message}.dump()
+            )
+        };
+        let request = match json::parse(&line) {
+            Ok(request) => request,
+            Err(err) => {
+                fail(
+                    &mut output,
+                    &format!("Invalid request {}: {err}", line.trim()),
+                )?;
+                return Ok(true);
+            }
+        };
+        match request["request"].as_str() {
+            Some("stop") => {
+                writeln!(output, "{}", object! {kind: "done"}.dump())?;
+                return Ok(false);
+            }
+            Some("compile") => {}
+            _ => {
+                fail(&mut output, &format!("Unknown request {}", line.trim()))?;
+                return Ok(true);
+            }
+        }
+        let Some(files) = request["files"].as_str() else {
+            fail(&mut output, "Missing file list")?;
+            return Ok(true);
+        };
+        // The paths of the file list are relative to the client
+        let cwd = match request["cwd"].as_str() {
+            Some(cwd) => PathBuf::from(cwd),
+            None => std::env::current_dir()?,
+        };
+        if let Err(err) = self.compile(files, &cwd, &mut output) {
+            fail(&mut output, &err)?;
+        }
+        Ok(true)
+    }
+}
+
+/// Runs a compile server listening on `socket`, until a client asks it to
+/// stop. Fails if another server is already listening there.
+pub fn serveCompiles(socket: &Path) -> Result<(), String> {
+    if UnixStream::connect(socket).is_ok() {
+        return Err(format!(
+            "A compile server is already listening on {}",
+            socket.display()
+        ));
+    }
+    // Left by a server that didn't stop properly
+    let _ = std::fs::remove_file(socket);
+    if let Some(dir) = socket.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    let listener = UnixListener::bind(socket)
+        .map_err(|err| format!("Can't listen on {}: {err}", socket.display()))?;
+    let mut server = CompileServer::default();
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::error!("Can't accept a client: {err}");
+                continue;
+            }
+        };
+        match server.handle(&stream) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => log::error!("Can't answer a client: {err}"),
+        }
+    }
+    let _ = std::fs::remove_file(socket);
+    Ok(())
+}
@@ -6,7 +6,7 @@ use std::marker::Send;
 
 use crate::Preprocessor::Pretoken::PreToken;
 use crate::Preprocessor::Structs::ExpandData;
-use crate::Utils::Structs::CompileMsg;
+use crate::Utils::Structs::{CompileMsg, SourceRange};
 
 use crate::Utils::Structs::FileTokPos;
 
@@ -82,6 +82,8 @@ pub struct DefineAst {
     pub replacement: Vec<PreTokenDefine>,
     /// Function used for expansion. Intended to aid in the implementation of custom macros
     pub expandFunc: &'static DefineExpansionFunc,
+    /// Location of the macro name in the `#define`. None for builtin macros
+    pub definedAt: Option<SourceRange>,
 }
 
 impl Debug for DefineAst {
@@ -91,6 +93,7 @@ impl Debug for DefineAst {
             .field("param", &self.param)
             .field("variadic", &self.variadic)
             .field("replacement", &self.replacement)
+            .field("definedAt", &self.definedAt)
             .finish_non_exhaustive()
     }
 }
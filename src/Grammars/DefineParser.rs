@@ -219,7 +219,6 @@ fn MaybeHashHash(input: In) -> ParseRes {
 }
 
 fn NoWhiteSp(input: In) -> ParseRes {
-    println!("{input:?}");
     let onHashPrecondition = wrap!(
         In,
         p_alt!(In, MaybeHashHash, |input: In| {
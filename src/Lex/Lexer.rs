@@ -1,5 +1,6 @@
 //! Transforms `PreToken`s into `Token`s.
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 use crate::Preprocessor::Preprocessor;
 use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl, FileTokPos, TokPos};
@@ -21,6 +22,8 @@ pub struct Lexer {
     moduleDirectives: Vec<usize>,
     /// current index
     currIndex: usize,
+    /// Time spent in the preprocessor, if measured
+    preprocessingTime: Option<Duration>,
 }
 
 impl Lexer {
@@ -33,9 +36,20 @@ impl Lexer {
             greaterInLastToken: false,
             moduleDirectives: vec![],
             currIndex: 0,
+            preprocessingTime: None,
         }
     }
 
+    /// Measure the time spent in the preprocessor from now on
+    pub const fn measurePreprocessing(&mut self) {
+        self.preprocessingTime = Some(Duration::ZERO);
+    }
+
+    /// Time spent in the preprocessor, if measured
+    pub const fn preprocessingTime(&self) -> Option<Duration> {
+        self.preprocessingTime
+    }
+
     /// Returns the resulting preffix
     fn calcPrefix(
         e1: EncodingPrefix,
@@ -78,8 +92,9 @@ impl Lexer {
             (tok1.tokPos.start, tok1.tokPos.end)
         };
         let file = tok1.file;
+        let expansion = tok1.expansion;
         let (tok1, tok2) = (tok1.tokPos.tok, tok2.tokPos.tok);
-        match (tok1, tok2) {
+        let merged = match (tok1, tok2) {
             (Token::StringLiteral(enc1, text1), Token::StringLiteral(enc2, text2)) => {
                 let prefix = Self::calcPrefix(enc1, enc2, file, start, end)?;
                 Ok(FileTokPos::new(
@@ -129,7 +144,11 @@ impl Lexer {
                 ))
             }
             _ => unreachable!(),
-        }
+        };
+        merged.map(|mut tok| {
+            tok.expansion = expansion;
+            tok
+        })
     }
 
     /// Merges all the string literals buffered. In case of error, will still generate the last valid string literal.
@@ -146,10 +165,11 @@ impl Lexer {
         {
             let tok1 = self.lastTokens.pop_front().unwrap();
             let tok2 = self.lastTokens.pop_front().unwrap();
-            if let Err(err) =
-                Self::doMergeStringLiterals(tok1, tok2).map(|x| self.lastTokens.push_front(x))
+            let expansion = tok1.expansion.clone();
+            if let Err(err) = Self::doMergeStringLiterals(tok1, tok2.clone())
+                .map(|x| self.lastTokens.push_front(x))
             {
-                self.errors.push(err);
+                self.errors.push(err.withExpansion(expansion));
                 self.lastTokens.push_front(tok2);
             }
         }
@@ -163,11 +183,25 @@ impl Lexer {
                 Token::StringLiteral(_, _) | Token::UdStringLiteral(_, _, _)
             )
         {
-            match self.preprocessor.next() {
+            let preTok = if let Some(time) = self.preprocessingTime.as_mut() {
+                let start = Instant::now();
+                let preTok = self.preprocessor.next();
+                *time += start.elapsed();
+                preTok
+            } else {
+                self.preprocessor.next()
+            };
+            match preTok {
                 None => break,
                 Some(Err(err)) => self.errors.push(err),
                 Some(Ok(preTok)) => {
-                    match Token::from_preToken(preTok).map(|x| self.lastTokens.extend(x)) {
+                    let expansion = preTok.expansion.clone();
+                    match Token::from_preToken(preTok).map(|x| {
+                        self.lastTokens.extend(x.into_iter().map(|mut tok| {
+                            tok.expansion.clone_from(&expansion);
+                            tok
+                        }));
+                    }) {
                         Err(None) => {
                             self.greaterInLastToken = false;
                         }
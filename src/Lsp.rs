@@ -0,0 +1,14 @@
+//! Language server of the compiler.
+//!
+//! Editors talk to it with the language server protocol over stdio, see
+//! [`serve`]. The documents open in the editor are compiled as they are, not
+//! as they are saved, together with the rest of the project, so the editor
+//! gets the compiler's own view of modules and macros: the diagnostics of
+//! each file, its namespaces and enums, where a namespace name leads, and the
+//! folding of its `#if` blocks.
+
+pub mod Analysis;
+pub mod Protocol;
+pub mod Server;
+
+pub use Server::serve;
@@ -0,0 +1,391 @@
+//! What the language server knows of the project after compiling it: the
+//! diagnostics, the declarations of each file, and the names resolved by the
+//! semantic analysis.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use json::{object, JsonValue};
+
+use crate::Ast::Common::{AstDecl, AstTu};
+use crate::Compiler::{Compiler, TranslationUnit};
+use crate::Preprocessor::ConditionalReport::ConditionalReport;
+use crate::Sema::Scope::ScopeRef;
+use crate::Utils::FileMap::FileMap;
+use crate::Utils::Structs::{CompileFile, CompileMsg, CompileMsgKind, SourceRange};
+
+use super::Protocol::{pathToUri, positionToOffset, range};
+
+/// `SymbolKind` of the protocol for namespaces
+const SYMBOL_NAMESPACE: u8 = 3;
+/// `SymbolKind` of the protocol for enums
+const SYMBOL_ENUM: u8 = 10;
+
+/// A name in the source, and the declaration it resolved to
+#[derive(Clone)]
+struct Reference {
+    /// Where the name is
+    range: SourceRange,
+    /// Declaration it names
+    target: AstDecl,
+}
+
+/// Result of compiling the project once
+pub struct Analysis {
+    /// Files of the compilation
+    fileMap: Arc<Mutex<FileMap>>,
+    /// AST of each translation unit, sorted by path
+    trees: Vec<(String, AstTu)>,
+    /// Diagnostics found
+    diagnostics: Vec<CompileMsg>,
+    /// Names resolved in all the translation units
+    references: Vec<Reference>,
+    /// Lines to fold of the `#if` groups, by file
+    folding: HashMap<u64, Vec<(usize, usize)>>,
+}
+
+/// Name of a declaration, if it has one
+fn declName(decl: AstDecl) -> Option<String> {
+    match decl {
+        AstDecl::AstDeclNamespace(namespace) => Some(namespace.getName().to_string()),
+        AstDecl::AstDeclCustomRustyCppEnum(astEnum) => Some(astEnum.getName().to_string()),
+        _ => None,
+    }
+}
+
+/// Name of a declaration, qualified with the namespaces that contain it
+fn qualifiedName(decl: AstDecl) -> String {
+    let mut names = declName(decl).into_iter().collect::<Vec<_>>();
+    let mut scope = decl.getScope();
+    loop {
+        let Some(parent) = scope.borrow().parent.clone() else {
+            break;
+        };
+        if let Some(name) = parent.borrow().causingDecl.and_then(declName) {
+            names.push(name);
+        }
+        scope = parent;
+    }
+    names.reverse();
+    names.join("::")
+}
+
+/// Declaration that caused `scope`, if any
+fn causingDecl(scope: &ScopeRef) -> Option<AstDecl> {
+    scope.borrow().causingDecl
+}
+
+/// Adds the names resolved in `decls` and their contents to `references`
+fn collectReferences(decls: &[AstDecl], references: &mut Vec<Reference>) {
+    for decl in decls {
+        match decl {
+            AstDecl::AstDeclNamespace(namespace) => {
+                // Extensions of a namespace lead to its first declaration
+                references.push(Reference {
+                    range: namespace.getSourceRange(),
+                    target: causingDecl(&namespace.getScope()).unwrap_or(*decl),
+                });
+                collectReferences(namespace.getContents(), references);
+            }
+            AstDecl::AstDeclCustomRustyCppEnum(astEnum) => references.push(Reference {
+                range: astEnum.getSourceRange(),
+                target: *decl,
+            }),
+            AstDecl::AstDeclUsingNamespace(using) => {
+                for nested in using.getNestedNameSpecifier() {
+                    let target = nested.scope.borrow().as_ref().and_then(causingDecl);
+                    if let Some(target) = target {
+                        references.push(Reference {
+                            range: nested.sourceRange.clone(),
+                            target,
+                        });
+                    }
+                }
+                if let Some(target) = causingDecl(&using.getScope()) {
+                    references.push(Reference {
+                        range: using.getNameRange(),
+                        target,
+                    });
+                }
+            }
+            AstDecl::AstDeclEmpty(_) | AstDecl::AstDeclAsm(_) => {}
+        }
+    }
+}
+
+impl Analysis {
+    /// Compiles the project of `compiler`
+    pub fn new(compiler: &mut Compiler) -> Self {
+        compiler.recordConditionalReports();
+        let (trees, diagnostics) = compiler.parsedTrees();
+        let mut trees = trees.into_iter().collect::<Vec<_>>();
+        trees.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut references = vec![];
+        for (_, tree) in &trees {
+            collectReferences(tree.getStatic().getGlobalDecl(), &mut references);
+        }
+        Self {
+            fileMap: compiler.fileMap().clone(),
+            trees,
+            diagnostics,
+            references,
+            folding: foldingRanges(&compiler.takeConditionalReports(), compiler.fileMap()),
+        }
+    }
+
+    /// Opened file of the compilation
+    fn file(&self, file: u64) -> Arc<CompileFile> {
+        self.fileMap.lock().unwrap().getOpenedFile(file)
+    }
+
+    /// File of the compilation at `path`, if it was used
+    fn fileAt(&self, path: &str) -> Option<u64> {
+        self.fileMap.lock().unwrap().getPath(path).ok()
+    }
+
+    /// Range of a [`SourceRange`], in its first file
+    fn range(&self, sourceRange: &SourceRange) -> JsonValue {
+        let file = self.file(sourceRange.startfile);
+        let end = if sourceRange.endfile == sourceRange.startfile {
+            sourceRange.end
+        } else {
+            sourceRange.start
+        };
+        range(file.content(), sourceRange.start, end)
+    }
+
+    /// Location of a [`SourceRange`], in its first file
+    fn location(&self, sourceRange: &SourceRange) -> JsonValue {
+        object! {
+            uri: pathToUri(self.file(sourceRange.startfile).path()),
+            range: self.range(sourceRange),
+        }
+    }
+
+    /// Diagnostic of the protocol for a message located in a file
+    fn diagnostic(&self, msg: &CompileMsg) -> JsonValue {
+        let (file, at, atEnd) = msg.loc();
+        let at = at.unwrap_or(0);
+        let severity = match msg.severity() {
+            CompileMsgKind::Error | CompileMsgKind::FatalError => 1,
+            CompileMsgKind::Warning => 2,
+            CompileMsgKind::Notice => 3,
+        };
+        let mut diagnostic = object! {
+            range: range(self.file(file).content(), at, atEnd.unwrap_or(at)),
+            severity: severity,
+            source: "rustycpp",
+            message: msg.msg(),
+        };
+        if let Some(expansion) = msg.expansion() {
+            let mut related = JsonValue::new_array();
+            for loc in expansion.chain() {
+                let _ = related.push(object! {
+                    location: self.location(&loc.expandedAt),
+                    message: format!("in expansion of macro {}", loc.macroName),
+                });
+                if let Some(definedAt) = &loc.definedAt {
+                    let _ = related.push(object! {
+                        location: self.location(&definedAt),
+                        message: format!("macro {} defined here", loc.macroName),
+                    });
+                }
+            }
+            diagnostic["relatedInformation"] = related;
+        }
+        diagnostic
+    }
+
+    /// Diagnostics of each file, by URI, and the messages not located in any
+    /// file
+    pub fn diagnostics(&self) -> (BTreeMap<String, Vec<JsonValue>>, Vec<String>) {
+        let mut byUri = BTreeMap::<String, Vec<JsonValue>>::new();
+        let mut unlocated = vec![];
+        for msg in &self.diagnostics {
+            let (file, _, _) = msg.loc();
+            if file == 0 {
+                unlocated.push(msg.msg().to_string());
+                continue;
+            }
+            let uri = pathToUri(self.file(file).path());
+            byUri.entry(uri).or_default().push(self.diagnostic(msg));
+        }
+        (byUri, unlocated)
+    }
+
+    /// Byte offset of a position in the file at `path`
+    fn offsetAt(&self, path: &str, line: usize, character: usize) -> Option<(u64, usize)> {
+        let file = self.fileAt(path)?;
+        let offset = positionToOffset(self.file(file).content(), line, character);
+        Some((file, offset))
+    }
+
+    /// Innermost name at a position of the file at `path`
+    fn referenceAt(&self, path: &str, line: usize, character: usize) -> Option<Reference> {
+        let (file, offset) = self.offsetAt(path, line, character)?;
+        self.references
+            .iter()
+            .filter(|reference| {
+                reference.range.startfile == file
+                    && reference.range.start <= offset
+                    && offset <= reference.range.end
+            })
+            .min_by_key(|reference| reference.range.end - reference.range.start)
+            .cloned()
+    }
+
+    /// Location of the declaration named at a position, or null
+    pub fn definition(&self, path: &str, line: usize, character: usize) -> JsonValue {
+        self.referenceAt(path, line, character)
+            .map_or(JsonValue::Null, |reference| {
+                self.location(&reference.target.getSourceRange())
+            })
+    }
+
+    /// Description of the declaration named at a position, or null
+    pub fn hover(&self, path: &str, line: usize, character: usize) -> JsonValue {
+        let Some(reference) = self.referenceAt(path, line, character) else {
+            return JsonValue::Null;
+        };
+        let target = reference.target;
+        let kind = match target {
+            AstDecl::AstDeclNamespace(namespace) if namespace.isInline() => "inline namespace",
+            AstDecl::AstDeclNamespace(_) => "namespace",
+            AstDecl::AstDeclCustomRustyCppEnum(_) => "enum",
+            _ => return JsonValue::Null,
+        };
+        let sourceRange = target.getSourceRange();
+        let declaredAt = self
+            .file(sourceRange.startfile)
+            .getLocStr(Some(sourceRange.start));
+        object! {
+            contents: object! {
+                kind: "markdown",
+                value: format!(
+                    "```cpp\n{kind} {}\n```\nDeclared at {declaredAt}",
+                    qualifiedName(target)
+                ),
+            },
+            range: self.range(&reference.range),
+        }
+    }
+
+    /// Symbols of `decls` declared in `file`, nested as in the source.
+    /// `seen` avoids repeating the declarations of headers included by
+    /// several translation units.
+    fn symbols(
+        &self,
+        decls: &[AstDecl],
+        file: u64,
+        seen: &mut HashSet<SourceRange>,
+    ) -> Vec<JsonValue> {
+        let mut symbols = vec![];
+        for decl in decls {
+            let (kind, children) = match decl {
+                AstDecl::AstDeclNamespace(namespace) => (
+                    SYMBOL_NAMESPACE,
+                    self.symbols(namespace.getContents(), file, seen),
+                ),
+                AstDecl::AstDeclCustomRustyCppEnum(_) => (SYMBOL_ENUM, vec![]),
+                _ => continue,
+            };
+            let sourceRange = decl.getSourceRange();
+            if sourceRange.startfile != file {
+                // Declared in an included file, but it may contain
+                // declarations of this one
+                symbols.extend(children);
+                continue;
+            }
+            if !seen.insert(sourceRange.clone()) {
+                continue;
+            }
+            let range = self.range(&sourceRange);
+            let mut symbol = object! {
+                name: declName(*decl).unwrap_or_default(),
+                kind: kind,
+                range: range.clone(),
+                selectionRange: range,
+                children: children,
+            };
+            if let AstDecl::AstDeclNamespace(namespace) = decl {
+                if namespace.isInline() {
+                    symbol["detail"] = "inline".into();
+                }
+            }
+            symbols.push(symbol);
+        }
+        symbols
+    }
+
+    /// Namespaces and enums declared in the file at `path`
+    pub fn documentSymbols(&self, path: &str) -> JsonValue {
+        let Some(file) = self.fileAt(path) else {
+            return JsonValue::new_array();
+        };
+        let mut seen = HashSet::new();
+        let symbols = self
+            .trees
+            .iter()
+            .flat_map(|(_, tree)| self.symbols(tree.getStatic().getGlobalDecl(), file, &mut seen))
+            .collect::<Vec<_>>();
+        JsonValue::Array(symbols)
+    }
+
+    /// Folding ranges of the `#if` groups of the file at `path`
+    pub fn foldingRanges(&self, path: &str) -> JsonValue {
+        let ranges = self
+            .fileAt(path)
+            .and_then(|file| self.folding.get(&file))
+            .into_iter()
+            .flatten()
+            .map(|(startLine, endLine)| {
+                object! {
+                    startLine: *startLine,
+                    endLine: *endLine,
+                    kind: "region",
+                }
+            })
+            .collect();
+        JsonValue::Array(ranges)
+    }
+}
+
+/// Lines (from 0) to fold for each branch of the `#if` groups, by file. A
+/// branch folds from its directive to the line before the next directive of
+/// its group.
+fn foldingRanges(
+    reports: &[(TranslationUnit, ConditionalReport)],
+    fileMap: &Arc<Mutex<FileMap>>,
+) -> HashMap<u64, Vec<(usize, usize)>> {
+    let mut fileMap = fileMap.lock().unwrap();
+    let mut ranges = HashMap::<u64, Vec<(usize, usize)>>::new();
+    for (_, report) in reports {
+        for group in report.groups() {
+            let Some(ref endif) = group.endif else {
+                continue;
+            };
+            let file = fileMap.getOpenedFile(endif.startfile);
+            let line = |offset| file.content()[..offset].matches('\n').count();
+            let ends = group
+                .branches
+                .iter()
+                .skip(1)
+                .map(|branch| branch.directive.start)
+                .chain([endif.start]);
+            for (branch, end) in group.branches.iter().zip(ends) {
+                let (start, end) = (line(branch.directive.start), line(end));
+                if end > start + 1 {
+                    ranges
+                        .entry(endif.startfile)
+                        .or_default()
+                        .push((start, end - 1));
+                }
+            }
+        }
+    }
+    drop(fileMap);
+    for lines in ranges.values_mut() {
+        lines.sort_unstable();
+        lines.dedup();
+    }
+    ranges
+}
@@ -0,0 +1,163 @@
+//! Wire format of the language server protocol: JSON-RPC messages framed by a
+//! `Content-Length` header, `file://` URIs, and positions counted in lines and
+//! UTF-16 code units.
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+
+use json::{object, JsonValue};
+
+/// Reads the next message. None at the end of the input.
+pub fn readMessage(input: &mut impl BufRead) -> Result<Option<JsonValue>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|err| format!("Invalid Content-Length {value}: {err}"))?,
+                );
+            }
+        }
+    }
+    let length = length.ok_or("Message without Content-Length")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|err| err.to_string())?;
+    let body = String::from_utf8(body).map_err(|err| err.to_string())?;
+    json::parse(&body)
+        .map(Some)
+        .map_err(|err| format!("Invalid message {body}: {err}"))
+}
+
+/// Writes a message
+pub fn writeMessage(output: &mut impl Write, message: &JsonValue) -> std::io::Result<()> {
+    let body = message.dump();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+/// Response to the request `id`
+pub fn response(id: &JsonValue, result: JsonValue) -> JsonValue {
+    object! {
+        jsonrpc: "2.0",
+        id: id.clone(),
+        result: result,
+    }
+}
+
+/// Error response to the request `id`
+pub fn errorResponse(id: &JsonValue, code: i32, message: &str) -> JsonValue {
+    object! {
+        jsonrpc: "2.0",
+        id: id.clone(),
+        error: object! {
+            code: code,
+            message: message,
+        },
+    }
+}
+
+/// Notification from the server
+pub fn notification(method: &str, params: JsonValue) -> JsonValue {
+    object! {
+        jsonrpc: "2.0",
+        method: method,
+        params: params,
+    }
+}
+
+/// Path of a `file://` URI. None for other schemes.
+pub fn uriToPath(uri: &str) -> Option<String> {
+    let path = uri.strip_prefix("file://")?;
+    // Skip the authority, usually empty
+    let path = &path[path.find('/')?..];
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' && tail.len() >= 2 {
+            if let Ok(decoded) = u8::from_str_radix(std::str::from_utf8(&tail[..2]).ok()?, 16) {
+                bytes.push(decoded);
+                rest = &tail[2..];
+                continue;
+            }
+        }
+        bytes.push(byte);
+        rest = tail;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// `file://` URI of an absolute path
+pub fn pathToUri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            let _ = write!(uri, "%{byte:02X}");
+        }
+    }
+    uri
+}
+
+/// Line and character (in UTF-16 code units) of the byte `offset`, both from
+/// 0
+pub fn offsetToPosition(content: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &content[..offset];
+    let lineStart = before.rfind('\n').map_or(0, |nl| nl + 1);
+    (
+        before.matches('\n').count(),
+        content[lineStart..offset].encode_utf16().count(),
+    )
+}
+
+/// Byte offset of a line and character (in UTF-16 code units), both from 0.
+/// Past the end of a line, its end.
+pub fn positionToOffset(content: &str, line: usize, character: usize) -> usize {
+    let lineStart = if line == 0 {
+        0
+    } else {
+        match content.match_indices('\n').nth(line - 1) {
+            Some((nl, _)) => nl + 1,
+            None => return content.len(),
+        }
+    };
+    let mut units = 0;
+    for (pos, char) in content[lineStart..].char_indices() {
+        if units >= character || char == '\n' {
+            return lineStart + pos;
+        }
+        units += char.len_utf16();
+    }
+    content.len()
+}
+
+/// Position of the byte `offset`
+pub fn position(content: &str, offset: usize) -> JsonValue {
+    let (line, character) = offsetToPosition(content, offset);
+    object! {
+        line: line,
+        character: character,
+    }
+}
+
+/// Range between the byte offsets `start` and `end`
+pub fn range(content: &str, start: usize, end: usize) -> JsonValue {
+    object! {
+        start: position(content, start),
+        end: position(content, end),
+    }
+}
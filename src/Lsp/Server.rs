@@ -0,0 +1,344 @@
+//! State of the language server and the handling of each message.
+use std::collections::{BTreeSet, HashSet};
+use std::io::{BufRead, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use json::{object, JsonValue};
+
+use crate::Compiler::{Compiler, CompilerCaches};
+use crate::Utils::FileSystem::{FileSystem, InMemoryFileSystem, OverlayFileSystem, RealFileSystem};
+use crate::Utils::Parameters::Parameters;
+
+use super::Analysis::Analysis;
+use super::Protocol::{
+    errorResponse, notification, pathToUri, readMessage, response, uriToPath, writeMessage,
+};
+
+/// Error code of the protocol for unknown methods
+const METHOD_NOT_FOUND: i32 = -32601;
+/// Error code of the protocol for invalid parameters
+const INVALID_PARAMS: i32 = -32602;
+
+/// Extensions of the files compiled on their own when opened. Headers are
+/// only compiled through the translation units that include them.
+const SOURCE_EXTENSIONS: [&str; 4] = ["cpp", "cppm", "ixx", "mpp"];
+
+/// Servers started by this process, to give each one its own BMI directory
+static SERVERS_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// A language server. Every time a document is opened or changes, the project
+/// is compiled again, with the documents as they are in the editor. The files
+/// that did not change are not read nor lexed again.
+struct Server {
+    /// Project of the file list
+    parameters: Parameters,
+    /// Contents of the open documents
+    documents: Arc<InMemoryFileSystem>,
+    /// Paths of the open documents
+    openPaths: BTreeSet<String>,
+    /// Open documents over the disk
+    fileSystem: Arc<dyn FileSystem>,
+    /// Files, tokens and BMIs of the previous compilations. None until the
+    /// first one, or if it crashed.
+    caches: Option<CompilerCaches>,
+    /// Result of the last compilation
+    analysis: Option<Analysis>,
+    /// URIs with diagnostics published
+    published: HashSet<String>,
+    /// Shutdown was requested
+    shutdown: bool,
+    /// Where the BMIs of the open documents are written. They may not be
+    /// saved, so they can't go to the `bmiDir` of the project. Removed when
+    /// the server ends.
+    bmiDir: PathBuf,
+}
+
+impl Server {
+    /// Server of the project of `parameters`
+    fn new(parameters: Parameters) -> Self {
+        let documents = Arc::new(InMemoryFileSystem::new());
+        let fileSystem = Arc::new(OverlayFileSystem::new(vec![
+            documents.clone(),
+            Arc::new(RealFileSystem),
+        ]));
+        Self {
+            parameters,
+            documents,
+            openPaths: BTreeSet::new(),
+            fileSystem,
+            caches: None,
+            analysis: None,
+            published: HashSet::new(),
+            shutdown: false,
+            bmiDir: std::env::temp_dir().join("rustycpp-lsp").join(format!(
+                "{}-{}",
+                std::process::id(),
+                SERVERS_STARTED.fetch_add(1, Ordering::Relaxed)
+            )),
+        }
+    }
+
+    /// Parameters of the project, with the open source files that are not in
+    /// it as translation units. Nothing is written to the build outputs of
+    /// the project: the documents may not be saved.
+    fn compileParameters(&self) -> Parameters {
+        let mut parameters = self.parameters.clone();
+        parameters.bmiDir = self.bmiDir.to_string_lossy().to_string();
+        parameters.timingCacheFile = None;
+        parameters.rebuildStateFile = String::new();
+        let canonical = |path: &str| self.fileSystem.canonicalize(Path::new(path)).ok();
+        let known = parameters
+            .translationUnits
+            .iter()
+            .chain(&parameters.moduleHeaderUnits)
+            .filter_map(|path| canonical(path))
+            .collect::<HashSet<_>>();
+        for path in &self.openPaths {
+            let isSource = Path::new(path).extension().is_some_and(|ext| {
+                SOURCE_EXTENSIONS
+                    .iter()
+                    .any(|source| ext.eq_ignore_ascii_case(source))
+            });
+            if isSource && canonical(path).is_some_and(|path| !known.contains(&path)) {
+                parameters.translationUnits.push(path.clone());
+            }
+        }
+        parameters
+    }
+
+    /// Compiles the project again, and publishes its diagnostics
+    fn analyze(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        let parameters = Arc::new(self.compileParameters());
+        let caches = match self.caches.take() {
+            Some(caches) if caches.refresh().is_ok() => caches,
+            _ => CompilerCaches::new(parameters.clone(), self.fileSystem.clone()),
+        };
+        self.analysis = catch_unwind(AssertUnwindSafe(|| {
+            Analysis::new(&mut Compiler::new_with_caches(parameters, &caches))
+        }))
+        .ok();
+        if self.analysis.is_some() {
+            self.caches = Some(caches);
+        }
+        let Some(analysis) = &self.analysis else {
+            return Self::logMessage(output, 1, "The compiler crashed analyzing the project");
+        };
+
+        let (mut diagnostics, unlocated) = analysis.diagnostics();
+        for msg in unlocated {
+            Self::logMessage(output, 1, &msg)?;
+        }
+        // Clear the diagnostics of the files that no longer have any
+        let uris = self
+            .published
+            .iter()
+            .cloned()
+            .chain(self.openPaths.iter().map(|path| pathToUri(path)))
+            .collect::<Vec<_>>();
+        for uri in uris {
+            diagnostics.entry(uri).or_default();
+        }
+        self.published.clear();
+        for (uri, diagnostics) in diagnostics {
+            if !diagnostics.is_empty() {
+                self.published.insert(uri.clone());
+            }
+            writeMessage(
+                output,
+                &notification(
+                    "textDocument/publishDiagnostics",
+                    object! {
+                        uri: uri,
+                        diagnostics: diagnostics,
+                    },
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Shows a message in the log of the editor
+    fn logMessage(output: &mut impl Write, kind: u8, message: &str) -> std::io::Result<()> {
+        writeMessage(
+            output,
+            &notification(
+                "window/logMessage",
+                object! {
+                    type: kind,
+                    message: message,
+                },
+            ),
+        )
+    }
+
+    /// Capabilities of the server
+    fn initialize(&mut self, params: &JsonValue) -> Result<JsonValue, String> {
+        if let Some(files) = params["initializationOptions"]["files"].as_str() {
+            self.parameters = Parameters::new_file(files)?;
+            self.caches = None;
+        }
+        Ok(object! {
+            capabilities: object! {
+                textDocumentSync: object! {
+                    openClose: true,
+                    change: 1,
+                },
+                documentSymbolProvider: true,
+                definitionProvider: true,
+                hoverProvider: true,
+                foldingRangeProvider: true,
+            },
+            serverInfo: object! {
+                name: "rustycpp",
+                version: env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    /// Answer to a request about a position in a document
+    fn positionRequest(&self, method: &str, params: &JsonValue) -> Result<JsonValue, String> {
+        let path = Self::documentPath(params)?;
+        let (Some(line), Some(character)) = (
+            params["position"]["line"].as_usize(),
+            params["position"]["character"].as_usize(),
+        ) else {
+            return Err("Missing position".to_string());
+        };
+        let Some(analysis) = &self.analysis else {
+            return Ok(JsonValue::Null);
+        };
+        Ok(match method {
+            "textDocument/definition" => analysis.definition(&path, line, character),
+            _ => analysis.hover(&path, line, character),
+        })
+    }
+
+    /// Path of the document of a request
+    fn documentPath(params: &JsonValue) -> Result<String, String> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or("Missing document URI")?;
+        uriToPath(uri).ok_or_else(|| format!("Not a file URI: {uri}"))
+    }
+
+    /// Handles a notification that changes the documents. Returns if the
+    /// project must be compiled again.
+    fn documentNotification(&mut self, method: &str, params: &JsonValue) -> Result<bool, String> {
+        let path = Self::documentPath(params)?;
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"]
+                    .as_str()
+                    .ok_or("Missing document text")?;
+                self.documents.addFile(&path, text);
+                self.openPaths.insert(path);
+            }
+            "textDocument/didChange" => {
+                // Only full syncs are requested, so the last change is the
+                // whole document
+                let changes = &params["contentChanges"];
+                let text = changes[changes.len().saturating_sub(1)]["text"]
+                    .as_str()
+                    .ok_or("Missing document text")?;
+                self.documents.addFile(&path, text);
+            }
+            "textDocument/didClose" => {
+                self.documents.removeFile(&path);
+                self.openPaths.remove(&path);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Handles a message. Returns false once the server must exit.
+    fn handle(&mut self, message: &JsonValue, output: &mut impl Write) -> std::io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = &message["id"];
+        let result = match method {
+            "exit" => return Ok(false),
+            "initialize" => self.initialize(params),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(JsonValue::Null)
+            }
+            "textDocument/didOpen" | "textDocument/didChange" | "textDocument/didClose" => {
+                match self.documentNotification(method, params) {
+                    Ok(true) => self.analyze(output)?,
+                    Ok(false) => {}
+                    Err(err) => Self::logMessage(output, 1, &err)?,
+                }
+                return Ok(true);
+            }
+            "textDocument/documentSymbol" => Self::documentPath(params).map(|path| {
+                self.analysis
+                    .as_ref()
+                    .map_or_else(JsonValue::new_array, |analysis| {
+                        analysis.documentSymbols(&path)
+                    })
+            }),
+            "textDocument/definition" | "textDocument/hover" => {
+                self.positionRequest(method, params)
+            }
+            "textDocument/foldingRange" => Self::documentPath(params).map(|path| {
+                self.analysis
+                    .as_ref()
+                    .map_or_else(JsonValue::new_array, |analysis| {
+                        analysis.foldingRanges(&path)
+                    })
+            }),
+            _ if id.is_null() => return Ok(true),
+            _ => {
+                writeMessage(
+                    output,
+                    &errorResponse(id, METHOD_NOT_FOUND, &format!("Unknown method {method}")),
+                )?;
+                return Ok(true);
+            }
+        };
+        if !id.is_null() {
+            let response = match result {
+                Ok(result) => response(id, result),
+                Err(err) => errorResponse(id, INVALID_PARAMS, &err),
+            };
+            writeMessage(output, &response)?;
+        }
+        Ok(true)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.bmiDir);
+    }
+}
+
+/// Runs a language server of the project of `parameters`, until the editor
+/// asks it to exit.
+///
+/// The messages of the editor are read from `input`, and answered to
+/// `output`. The editor can choose another project by giving the path of its
+/// file list as the `files` initialization option.
+///
+/// Returns if the editor asked it to shut down before exiting, as the exit
+/// code of the server depends on it.
+pub fn serve(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    parameters: Parameters,
+) -> Result<bool, String> {
+    let mut server = Server::new(parameters);
+    while let Some(message) = readMessage(&mut input)? {
+        if !server
+            .handle(&message, &mut output)
+            .map_err(|err| err.to_string())?
+        {
+            break;
+        }
+    }
+    Ok(server.shutdown)
+}
@@ -1,9 +1,12 @@
 //! Finds the module related operations, translates them to custom tokens, and
 //! generates the module dependency tree.
+pub mod CriticalPath;
 mod DependencyAnnotate;
 mod DependencyDfs;
 mod DependencyInterpreter;
 pub mod DependencyIterator;
 pub mod DependencyParser;
 pub mod Generate;
+pub mod ModuleMap;
+pub mod RebuildPlan;
 pub mod Structs;
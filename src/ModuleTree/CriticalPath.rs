@@ -0,0 +1,318 @@
+//! Critical path of the module tree.
+//!
+//! A translation unit can't be parsed before the modules it imports, so the
+//! most expensive chain of imports bounds how fast the project compiles, no
+//! matter the number of threads. Handing out first the translation units that
+//! start the most expensive remaining chains keeps the threads busy until the
+//! end, instead of leaving a long chain for last.
+//!
+//! The cost of a translation unit is the time it took in the previous
+//! compilation, if it's in the timing cache. Otherwise it's estimated from its
+//! number of tokens once lexed, or from its size before that.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use json::{object, JsonValue};
+
+use crate::Compiler::TranslationUnit;
+use crate::Utils::FileMap::FileMap;
+
+use super::Structs::ModuleTree;
+
+/// Version of the format of the timing cache. Bump it on any change to the
+/// format.
+pub const TIMING_CACHE_VERSION: u32 = 1;
+
+/// Estimated size of a token, to guess the tokens of a file not lexed yet
+const BYTES_PER_TOKEN: u64 = 4;
+
+/// Cost of compiling a translation unit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnitTiming {
+    /// Number of tokens
+    pub tokens: u64,
+    /// Time lexing and parsing it, in microseconds
+    pub micros: u64,
+}
+
+/// Costs of the translation units in previous compilations
+#[derive(Debug, Default)]
+pub struct TimingCache {
+    /// Cost of each translation unit, by path
+    pub units: HashMap<String, UnitTiming>,
+}
+
+impl TimingCache {
+    /// Reads a timing cache. Fails if it does not exist, or it was written
+    /// with another format version.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let json = json::parse(&content).map_err(|err| err.to_string())?;
+        if json["version"].as_u32() != Some(TIMING_CACHE_VERSION) {
+            return Err(format!(
+                "The timing cache {} has format version {}, but version {TIMING_CACHE_VERSION} is required",
+                path.display(),
+                json["version"]
+            ));
+        }
+        let units = json["units"]
+            .entries()
+            .filter_map(|(unitPath, unit)| {
+                let timing = UnitTiming {
+                    tokens: unit["tokens"].as_u64()?,
+                    micros: unit["micros"].as_u64()?,
+                };
+                Some((unitPath.to_string(), timing))
+            })
+            .collect();
+        Ok(Self { units })
+    }
+
+    /// Writes the timing cache, creating its directory if needed.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let mut units = JsonValue::new_object();
+        for (unitPath, unit) in &self.units {
+            units[unitPath.as_str()] = object! {
+                tokens: unit.tokens,
+                micros: unit.micros,
+            };
+        }
+        let json = object! {
+            version: TIMING_CACHE_VERSION,
+            units: units,
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        std::fs::write(path, json.pretty(2)).map_err(|err| err.to_string())
+    }
+}
+
+/// Cost of the most expensive chain of translation units that starts at
+/// `tu`, following the translation units that depend on it. Stores in
+/// `paths` the cost of the chain and the next translation unit in it, for
+/// `tu` and everything visited.
+fn longestPath(
+    tu: TranslationUnit,
+    dependedBy: &HashMap<TranslationUnit, Vec<TranslationUnit>>,
+    cost: &impl Fn(TranslationUnit) -> u64,
+    paths: &mut HashMap<TranslationUnit, (u64, Option<TranslationUnit>)>,
+) -> u64 {
+    if let Some((pathCost, _)) = paths.get(&tu) {
+        return *pathCost;
+    }
+    let next = dependedBy
+        .get(&tu)
+        .into_iter()
+        .flatten()
+        .map(|child| (longestPath(*child, dependedBy, cost, paths), *child))
+        .max();
+    let pathCost = cost(tu) + next.map_or(0, |(nextCost, _)| nextCost);
+    paths.insert(tu, (pathCost, next.map(|(_, child)| child)));
+    pathCost
+}
+
+/// Estimates and measures the cost of the translation units of a module tree
+/// while they compile.
+#[derive(Debug)]
+pub struct CostModel {
+    /// The translation units that depend on each one
+    dependedBy: HashMap<TranslationUnit, Vec<TranslationUnit>>,
+    /// The translation units that don't depend on any other
+    roots: Vec<TranslationUnit>,
+    /// Cost in the previous compilations
+    history: HashMap<TranslationUnit, UnitTiming>,
+    /// Total microseconds and tokens of the history. Their ratio estimates
+    /// the cost of a token.
+    historyTotal: UnitTiming,
+    /// Number of tokens of each translation unit. Estimated from its size
+    /// until it's lexed.
+    tokens: Mutex<HashMap<TranslationUnit, u64>>,
+    /// Cost measured in this compilation
+    measured: Mutex<HashMap<TranslationUnit, UnitTiming>>,
+    /// Result of `remainingCost` for every translation unit. Computed again
+    /// when an estimate changes, not on each query: the scheduler asks for it
+    /// while holding its lock.
+    remaining: Mutex<HashMap<TranslationUnit, u64>>,
+}
+
+impl CostModel {
+    /// Model of the translation units of `tree`, with the costs of the
+    /// previous compilations in `cache`
+    pub fn new(tree: &ModuleTree, cache: &TimingCache, fileMap: &mut FileMap) -> Self {
+        let mut dependedBy = HashMap::new();
+        let mut tokens = HashMap::new();
+        let mut history = HashMap::new();
+        for node in tree.roots.values().chain(tree.childModules.values()) {
+            let tu = node.module.1;
+            dependedBy.insert(tu, node.dependedBy.iter().map(|child| child.1).collect());
+            let file = fileMap.getOpenedFile(tu);
+            tokens.insert(tu, file.content().len() as u64 / BYTES_PER_TOKEN);
+            if let Some(timing) = cache.units.get(file.path()) {
+                history.insert(tu, *timing);
+            }
+        }
+        let mut roots = tree
+            .roots
+            .values()
+            .map(|node| node.module.1)
+            .collect::<Vec<_>>();
+        roots.sort_unstable();
+        let historyTotal = cache
+            .units
+            .values()
+            .fold(UnitTiming::default(), |total, unit| UnitTiming {
+                tokens: total.tokens + unit.tokens,
+                micros: total.micros + unit.micros,
+            });
+        let model = Self {
+            dependedBy,
+            roots,
+            history,
+            historyTotal,
+            tokens: Mutex::new(tokens),
+            measured: Mutex::new(HashMap::new()),
+            remaining: Mutex::new(HashMap::new()),
+        };
+        model.updateRemainingCosts();
+        model
+    }
+
+    /// Computes again the remaining cost of every translation unit, sharing
+    /// the chains found between them
+    fn updateRemainingCosts(&self) {
+        let mut paths = HashMap::new();
+        for tu in self.dependedBy.keys() {
+            longestPath(*tu, &self.dependedBy, &|tu| self.estimate(tu), &mut paths);
+        }
+        *self.remaining.lock().unwrap() = paths
+            .into_iter()
+            .map(|(tu, (pathCost, _))| (tu, pathCost))
+            .collect();
+    }
+
+    /// Estimated cost of a translation unit, in microseconds
+    pub fn estimate(&self, tu: TranslationUnit) -> u64 {
+        if let Some(timing) = self.history.get(&tu) {
+            return timing.micros;
+        }
+        let tokens = self.tokens.lock().unwrap().get(&tu).copied().unwrap_or(0);
+        // Without history, a microsecond per token
+        tokens
+            .saturating_mul(self.historyTotal.micros)
+            .checked_div(self.historyTotal.tokens)
+            .unwrap_or(tokens)
+    }
+
+    /// Records that a translation unit was lexed. It's lexed before it's
+    /// parsed. Its estimate changes with the tokens found, unless it's in the
+    /// history.
+    pub fn lexed(&self, tu: TranslationUnit, tokens: usize, took: Duration) {
+        let tokens = tokens as u64;
+        let previous = self.tokens.lock().unwrap().insert(tu, tokens);
+        if previous != Some(tokens) && !self.history.contains_key(&tu) {
+            self.updateRemainingCosts();
+        }
+        self.measured.lock().unwrap().insert(
+            tu,
+            UnitTiming {
+                tokens,
+                micros: u64::try_from(took.as_micros()).unwrap_or(u64::MAX),
+            },
+        );
+    }
+
+    /// Records that a translation unit was parsed
+    pub fn parsed(&self, tu: TranslationUnit, took: Duration) {
+        let micros = u64::try_from(took.as_micros()).unwrap_or(u64::MAX);
+        self.measured.lock().unwrap().entry(tu).or_default().micros += micros;
+    }
+
+    /// Cost measured in this compilation
+    pub fn measured(&self) -> HashMap<TranslationUnit, UnitTiming> {
+        self.measured.lock().unwrap().clone()
+    }
+
+    /// Estimated cost of the most expensive chain of translation units that
+    /// starts at `tu`. The higher, the sooner it should be compiled.
+    pub fn remainingCost(&self, tu: TranslationUnit) -> u64 {
+        self.remaining
+            .lock()
+            .unwrap()
+            .get(&tu)
+            .copied()
+            .unwrap_or_else(|| self.estimate(tu))
+    }
+
+    /// The most expensive chain of translation units with the given costs,
+    /// in compilation order, with its cost
+    pub fn criticalPath(
+        &self,
+        cost: impl Fn(TranslationUnit) -> u64,
+    ) -> (Vec<TranslationUnit>, u64) {
+        let mut paths = HashMap::new();
+        let Some((pathCost, root)) = self
+            .roots
+            .iter()
+            .map(|root| {
+                (
+                    longestPath(*root, &self.dependedBy, &cost, &mut paths),
+                    *root,
+                )
+            })
+            .max()
+        else {
+            return (vec![], 0);
+        };
+        let mut path = vec![root];
+        while let Some((_, Some(next))) = paths.get(path.last().unwrap()) {
+            path.push(*next);
+        }
+        (path, pathCost)
+    }
+}
+
+/// A translation unit in a critical path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CriticalPathStep {
+    /// Path of the translation unit
+    pub path: String,
+    /// Cost estimated before compiling, in microseconds
+    pub estimatedMicros: u64,
+    /// Cost measured, in microseconds. 0 if it was not compiled.
+    pub micros: u64,
+}
+
+/// The critical path predicted before compiling, and the one found with the
+/// costs measured
+#[derive(Debug, Clone, Default)]
+pub struct CriticalPathReport {
+    /// Critical path with the costs estimated before compiling
+    pub predicted: Vec<CriticalPathStep>,
+    /// Critical path with the costs measured
+    pub actual: Vec<CriticalPathStep>,
+}
+
+impl Display for CriticalPathReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (title, steps) in [("Predicted", &self.predicted), ("Actual", &self.actual)] {
+            let estimated = steps.iter().map(|step| step.estimatedMicros).sum::<u64>();
+            let micros = steps.iter().map(|step| step.micros).sum::<u64>();
+            writeln!(
+                f,
+                "{title} critical path: estimated {estimated} us, took {micros} us"
+            )?;
+            for step in steps {
+                writeln!(
+                    f,
+                    "  {} (estimated {} us, took {} us)",
+                    step.path, step.estimatedMicros, step.micros
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
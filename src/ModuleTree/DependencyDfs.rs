@@ -1,12 +1,139 @@
 //! Create the dependency tree from the translation units
 
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::vec;
 
+use crate::Utils::FileMap::FileMap;
 use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl};
 
 use super::Structs::{ModuleDeclaration, ModuleTree, Node};
 
+/// Name of a module as written in the imports. Files without a module name
+/// use their path.
+fn moduleName(module: ModuleDeclaration, fileMap: &mut FileMap) -> String {
+    match module {
+        ModuleDeclaration::ExportPrimary(name) | ModuleDeclaration::Primary(name) => {
+            name.to_string()
+        }
+        ModuleDeclaration::ExportPartition(name, part)
+        | ModuleDeclaration::Partition(name, part) => {
+            format!("{name}:{part}")
+        }
+        ModuleDeclaration::ModuleHeaderUnit(tu) | ModuleDeclaration::Global(tu) => {
+            fileMap.getOpenedFile(tu).path().clone()
+        }
+    }
+}
+
+/// The modules a node depends on, sorted by translation unit so the cycles
+/// found don't depend on the hashing
+fn sortedDependencies(node: &Node) -> Vec<ModuleDeclaration> {
+    let mut dependencies = node.dependsOn.iter().map(|dep| **dep).collect::<Vec<_>>();
+    // Reversed, as they are popped
+    dependencies.sort_by_key(|dep| std::cmp::Reverse(dep.1));
+    dependencies.into_iter().map(|dep| dep.0).collect()
+}
+
+/// Finds the import cycles of the tree. Each cycle is a list of modules where
+/// each one imports the next, and the last one imports the first.
+fn findCycles(tree: &ModuleTree) -> Vec<Vec<ModuleDeclaration>> {
+    let mut starts = tree.childModules.values().collect::<Vec<_>>();
+    starts.sort_by_key(|node| node.module.1);
+
+    let mut cycles = vec![];
+    let mut finished = HashSet::new();
+    for start in starts {
+        if finished.contains(&start.module.0) {
+            continue;
+        }
+        let mut path = vec![start.module.0];
+        let mut leftToVisit = vec![sortedDependencies(start)];
+        while let Some(left) = leftToVisit.last_mut() {
+            let Some(next) = left.pop() else {
+                finished.insert(path.pop().unwrap());
+                leftToVisit.pop();
+                continue;
+            };
+            if let Some(pos) = path.iter().position(|module| *module == next) {
+                cycles.push(path[pos..].to_vec());
+            } else if !finished.contains(&next) {
+                // Roots don't depend on anything, so they can't be in a cycle
+                if let Some(node) = tree.childModules.get(&next) {
+                    path.push(next);
+                    leftToVisit.push(sortedDependencies(node));
+                }
+            }
+        }
+    }
+    cycles
+}
+
+/// Report an import cycle, with the location of each of its imports. Cycles
+/// between the partitions of a single module are reported as such.
+fn reportCycle(
+    tree: &ModuleTree,
+    mut cycle: Vec<ModuleDeclaration>,
+    fileMap: &Arc<Mutex<FileMap>>,
+) -> CompileMsg {
+    let node = |module: &ModuleDeclaration| tree.childModules.get(module).unwrap();
+    // Start at the first file, so the report is the same wherever the cycle
+    // was found
+    let first = (0..cycle.len())
+        .min_by_key(|i| node(&cycle[*i]).module.1)
+        .unwrap();
+    cycle.rotate_left(first);
+
+    let mut fileMap = fileMap.lock().unwrap();
+    let mut imports = vec![];
+    for (i, importer) in cycle.iter().enumerate() {
+        let imported = cycle[(i + 1) % cycle.len()];
+        let location = node(importer).importedAt.get(&imported).map_or_else(
+            || "implicitly".to_string(),
+            |at| {
+                let loc = fileMap
+                    .getOpenedFile(at.startfile)
+                    .getLocStr(Some(at.start));
+                format!("at {loc}")
+            },
+        );
+        imports.push(format!(
+            "{} imports {} ({location})",
+            moduleName(*importer, &mut fileMap),
+            moduleName(imported, &mut fileMap)
+        ));
+    }
+    drop(fileMap);
+
+    let partitionsOf = |module: &ModuleDeclaration| match module {
+        ModuleDeclaration::ExportPartition(name, _) | ModuleDeclaration::Partition(name, _) => {
+            Some(*name)
+        }
+        _ => None,
+    };
+    let msg = match partitionsOf(&cycle[0]) {
+        Some(name)
+            if cycle
+                .iter()
+                .all(|module| partitionsOf(module) == Some(name)) =>
+        {
+            format!(
+                "Cycle between the partitions of module {name}: {}",
+                imports.join(" -> ")
+            )
+        }
+        _ => format!("Module dependency cycle: {}", imports.join(" -> ")),
+    };
+    let importer = node(&cycle[0]);
+    importer
+        .importedAt
+        .get(&cycle[1 % cycle.len()])
+        .map_or_else(
+            || CompileError::onFile(msg.clone(), importer.module.1),
+            |at| CompileError::fromSourceRange(msg.clone(), at),
+        )
+}
+
 /// Check for loops in the dependency graph.
 fn dfsLoops(tree: &mut ModuleTree) -> Result<(), Vec<CompileMsg>> {
     if tree.roots.is_empty() && !tree.childModules.is_empty() {
@@ -98,6 +225,7 @@ fn dfsLoops(tree: &mut ModuleTree) -> Result<(), Vec<CompileMsg>> {
 /// Generates the module tree of the modules, and checks for loops.
 pub fn generateModuleTree(
     nodes: HashMap<ModuleDeclaration, Node>,
+    fileMap: &Arc<Mutex<FileMap>>,
 ) -> Result<ModuleTree, Vec<CompileMsg>> {
     let mut tree = ModuleTree {
         roots: HashMap::new(),
@@ -112,6 +240,14 @@ pub fn generateModuleTree(
         }
     }
 
+    let cycles = findCycles(&tree);
+    if !cycles.is_empty() {
+        return Err(cycles
+            .into_iter()
+            .map(|cycle| reportCycle(&tree, cycle, fileMap))
+            .collect());
+    }
+
     dfsLoops(&mut tree)?;
     Ok(tree)
 }
@@ -1,4 +1,5 @@
-//! Interprets dependency instructions to create the nodes of the tree
+//! Interprets dependency instructions to create the nodes of the tree, and
+//! validates the structure of the module units ([module.unit])
 use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::slice;
 use std::sync::atomic::AtomicUsize;
@@ -6,33 +7,60 @@ use std::sync::Arc;
 
 use crate::Compiler::TranslationUnit;
 use crate::Utils::StringRef::ToStringRef;
-use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl};
+use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl, CompileNote, SourceRange};
 
 use super::Structs::{ModuleDeclaration, ModuleOperator, Node};
 
+/// A module imported by a TU
+struct Import {
+    /// The module imported
+    module: ModuleDeclaration,
+    /// Location of the import. Implicit imports have no location.
+    at: Option<SourceRange>,
+    /// Is it an `export import`?
+    exported: bool,
+}
+
 /// At the start, or after encountering any module; operator, the module fragment import operations are parsed. It returns the next module operator, if any, and the module depenedencies
 fn parseGlobalPartOfModuleFile(
     iter: &mut slice::Iter<ModuleOperator>,
-) -> Result<(Option<ModuleOperator>, Vec<ModuleDeclaration>), String> {
+) -> Result<(Option<ModuleOperator>, Vec<Import>), CompileMsg> {
     let mut imports = vec![];
     loop {
         let op = iter.next();
         match op {
             None => return Ok((None, imports)),
             Some(op) => match op {
-                ModuleOperator::Import(module) => {
+                ModuleOperator::Import(module, at) => {
                     if module.starts_with(':') {
-                        return Err(format!(
-                            "Can't import partitions in global module. Tried to import: {module}"
+                        return Err(CompileError::fromSourceRange(
+                            format!(
+                                "Can't import partitions in global module. Tried to import: {module}"
+                            ),
+                            at,
                         ));
                     }
-                    imports.push(ModuleDeclaration::ExportPrimary(module.to_StringRef()));
+                    imports.push(Import {
+                        module: ModuleDeclaration::ExportPrimary(module.to_StringRef()),
+                        at: Some(at.clone()),
+                        exported: false,
+                    });
+                }
+                ModuleOperator::ExportImport(_, at) => {
+                    return Err(CompileError::fromSourceRange(
+                        "Can't export imports in the global module",
+                        at,
+                    ));
                 }
                 ModuleOperator::ImportHeader(path) => {
-                    imports.push(ModuleDeclaration::ModuleHeaderUnit(*path));
+                    imports.push(Import {
+                        module: ModuleDeclaration::ModuleHeaderUnit(*path),
+                        at: None,
+                        exported: false,
+                    });
                 }
 
-                ModuleOperator::ExportModule(_) | ModuleOperator::Module(_) => {
+                ModuleOperator::ExportModule(..) | ModuleOperator::Module(..) => {
                     return Ok((Some(op.clone()), imports));
                 }
             },
@@ -40,16 +68,14 @@ fn parseGlobalPartOfModuleFile(
     }
 }
 
-/// At the first module <name>;, the imports are parsed. It returns the next module operator, if any, and the module depenedencies
+/// At the first module <name>;, the imports are parsed. It returns the next module operator, if any, and the module depenedencies.
+/// Only the purview of a module interface unit can export imports.
 fn parseModulePartOfModuleFile(
     iter: &mut slice::Iter<ModuleOperator>,
-    mut name: String,
-) -> Result<(Option<ModuleOperator>, Vec<ModuleDeclaration>), String>
-where
-{
-    if let Some((n, _)) = name.split_once(':') {
-        name = n.to_string();
-    }
+    name: &str,
+    isInterface: bool,
+) -> Result<(Option<ModuleOperator>, Vec<Import>), CompileMsg> {
+    let name = name.split_once(':').map_or(name, |(n, _)| n);
 
     let mut imports = vec![];
     loop {
@@ -57,21 +83,38 @@ where
         match op {
             None => return Ok((None, imports)),
             Some(op) => match op {
-                ModuleOperator::Import(module) => {
-                    if let Some(module) = module.strip_prefix(':') {
-                        imports.push(ModuleDeclaration::ExportPartition(
-                            name.to_StringRef(),
-                            module.to_StringRef(),
+                ModuleOperator::Import(module, at) | ModuleOperator::ExportImport(module, at) => {
+                    let exported = matches!(op, ModuleOperator::ExportImport(..));
+                    if exported && !isInterface {
+                        return Err(CompileError::fromSourceRange(
+                            "Imports can only be exported from the purview of a module interface unit",
+                            at,
                         ));
-                    } else {
-                        imports.push(ModuleDeclaration::ExportPrimary(module.to_StringRef()));
                     }
+                    let module = module.strip_prefix(':').map_or_else(
+                        || ModuleDeclaration::ExportPrimary(module.to_StringRef()),
+                        |partition| {
+                            ModuleDeclaration::ExportPartition(
+                                name.to_StringRef(),
+                                partition.to_StringRef(),
+                            )
+                        },
+                    );
+                    imports.push(Import {
+                        module,
+                        at: Some(at.clone()),
+                        exported,
+                    });
                 }
                 ModuleOperator::ImportHeader(path) => {
-                    imports.push(ModuleDeclaration::ModuleHeaderUnit(*path));
+                    imports.push(Import {
+                        module: ModuleDeclaration::ModuleHeaderUnit(*path),
+                        at: None,
+                        exported: false,
+                    });
                 }
 
-                ModuleOperator::ExportModule(_) | ModuleOperator::Module(_) => {
+                ModuleOperator::ExportModule(..) | ModuleOperator::Module(..) => {
                     return Ok((Some(op.clone()), imports));
                 }
             },
@@ -79,114 +122,134 @@ where
     }
 }
 
+/// Error at `at`, with a note at the previous declaration
+fn redeclarationError(msg: String, at: &SourceRange, previousAt: &SourceRange) -> Vec<CompileMsg> {
+    vec![
+        CompileError::fromSourceRange(msg, at),
+        CompileNote::fromSourceRange("Previous declaration is here", previousAt),
+    ]
+}
+
 /// From the Module operations, generates a `Node` with the module field filled, and extra nodes for header imports. Also returns the resolved dependency names
 #[allow(clippy::too_many_lines)]
 pub fn generateNode(
     tu: TranslationUnit,
     ops: &[ModuleOperator],
     isModuleHeader: bool,
-    genNewArcTable: &mut HashMap<ModuleDeclaration, Arc<(ModuleDeclaration, TranslationUnit)>>,
 ) -> Result<(Node, Vec<ModuleDeclaration>), Vec<CompileMsg>> {
     let mut err = vec![];
 
-    let mut moduleImports: Vec<ModuleDeclaration> = vec![];
-    let mut moduleName: Option<String> = None;
-    let mut modulePrivateFound = false;
+    let mut moduleImports: Vec<Import> = vec![];
+    let mut moduleName: Option<(String, SourceRange)> = None;
+    let mut modulePrivateAt: Option<SourceRange> = None;
     let mut moduleIsExport = false;
-    let mut explicitGlobalModuleFound = false;
+    let mut explicitGlobalModuleAt: Option<SourceRange> = None;
 
     let mut iter = ops.iter();
-    let mut res = parseGlobalPartOfModuleFile(&mut iter)
-        .map_err(|err| vec![CompileError::onFile(err, tu)])?;
-    while res.0.is_some() {
-        moduleImports.extend(res.1.clone());
-        match res.0.as_ref().unwrap() {
-            ModuleOperator::ExportModule(name) => {
+    let mut res = parseGlobalPartOfModuleFile(&mut iter).map_err(|err| vec![err])?;
+    while let Some(op) = res.0.take() {
+        moduleImports.append(&mut res.1);
+        match op {
+            ModuleOperator::ExportModule(name, at) => {
                 moduleIsExport = true;
 
                 if name.is_empty() {
-                    err.push(CompileError::onFile(
-                        "global part can't be exported".to_string(),
-                        tu,
+                    err.push(CompileError::fromSourceRange(
+                        "global part can't be exported",
+                        &at,
                     ));
                     return Err(err);
                 }
 
                 if name == ":private" {
-                    err.push(CompileError::onFile(
+                    err.push(CompileError::fromSourceRange(
                         format!(
                             ":private part can't be exported on module {}",
-                            moduleName.unwrap_or_else(|| "<unknown module name>".to_string())
+                            moduleName.map_or_else(
+                                || "<unknown module name>".to_string(),
+                                |(name, _)| name
+                            )
                         ),
-                        tu,
+                        &at,
                     ));
                     return Err(err);
                 }
 
-                if moduleName.is_some() {
-                    err.push(CompileError::onFile(
-                        format!("Module name already defined as {}", moduleName.unwrap()),
-                        tu,
+                if let Some((previous, previousAt)) = moduleName {
+                    err.extend(redeclarationError(
+                        format!("Module name already defined as {previous}"),
+                        &at,
+                        &previousAt,
                     ));
                     return Err(err);
                 }
-                moduleName = Some(name.to_string());
-                res = parseModulePartOfModuleFile(&mut iter, name.to_string())
-                    .map_err(|err| vec![CompileError::onFile(err, tu)])?;
+                res =
+                    parseModulePartOfModuleFile(&mut iter, &name, true).map_err(|err| vec![err])?;
+                moduleName = Some((name, at));
             }
-            ModuleOperator::Module(name) => {
+            ModuleOperator::Module(name, at) => {
                 if name.is_empty() {
-                    if explicitGlobalModuleFound {
-                        err.push(CompileError::onFile("global part already defined", tu));
+                    if let Some(previousAt) = explicitGlobalModuleAt {
+                        err.extend(redeclarationError(
+                            "global part already defined".to_string(),
+                            &at,
+                            &previousAt,
+                        ));
                         return Err(err);
                     }
-                    explicitGlobalModuleFound = true;
-                    res = parseGlobalPartOfModuleFile(&mut iter)
-                        .map_err(|err| vec![CompileError::onFile(err, tu)])?;
+                    explicitGlobalModuleAt = Some(at);
+                    res = parseGlobalPartOfModuleFile(&mut iter).map_err(|err| vec![err])?;
                     continue;
                 }
 
-                if name != ":private" && moduleName.is_some() {
-                    err.push(CompileError::onFile(
-                        format!("Module name already defined as {}", moduleName.unwrap()),
-                        tu,
-                    ));
-                    return Err(err);
-                } else if name == ":private" {
-                    if moduleName.is_none() {
-                        err.push(CompileError::onFile(
+                if name == ":private" {
+                    let Some((module, _)) = &moduleName else {
+                        err.push(CompileError::fromSourceRange(
                             "Private part of a module must be in a named module. Currently on global",
-                            tu,
+                            &at,
+                        ));
+                        return Err(err);
+                    };
+                    if let Some(previousAt) = modulePrivateAt {
+                        err.extend(redeclarationError(
+                            format!("Private part of a module already defined in module {module}"),
+                            &at,
+                            &previousAt,
                         ));
                         return Err(err);
                     }
-                    if modulePrivateFound {
-                        err.push(CompileError::onFile(
+                    if !moduleIsExport || module.contains(':') {
+                        err.push(CompileError::fromSourceRange(
                             format!(
-                                "Private part of a module already defined in module {}",
-                                moduleName.unwrap()
+                                "The private part of module {module} can only be in its primary module interface unit"
                             ),
-                            tu,
+                            &at,
                         ));
                         return Err(err);
                     }
-                    modulePrivateFound = true;
-                    res = parseModulePartOfModuleFile(
-                        &mut iter,
-                        moduleName.as_ref().unwrap().to_string(),
-                    )
-                    .map_err(|err| vec![CompileError::onFile(err, tu)])?;
+                    modulePrivateAt = Some(at);
+                    res = parseModulePartOfModuleFile(&mut iter, module, false)
+                        .map_err(|err| vec![err])?;
                     continue;
                 }
-                moduleName = Some(name.to_string());
-                res = parseModulePartOfModuleFile(&mut iter, name.to_string())
-                    .map_err(|err| vec![CompileError::onFile(err, tu)])?;
+                if let Some((previous, previousAt)) = moduleName {
+                    err.extend(redeclarationError(
+                        format!("Module name already defined as {previous}"),
+                        &at,
+                        &previousAt,
+                    ));
+                    return Err(err);
+                }
+                res = parseModulePartOfModuleFile(&mut iter, &name, false)
+                    .map_err(|err| vec![err])?;
+                moduleName = Some((name, at));
             }
             _ => unreachable!(),
         }
     }
-    moduleImports.extend(res.1);
+    moduleImports.append(&mut res.1);
 
+    let declaredAt = moduleName.as_ref().map(|(_, at)| at.clone());
     let moduleDecl = moduleName.map_or_else(
         || {
             if isModuleHeader {
@@ -195,11 +258,11 @@ pub fn generateNode(
                 ModuleDeclaration::Global(tu)
             }
         },
-        |moduleName| {
+        |(moduleName, at)| {
             if isModuleHeader {
-                err.push(CompileError::onFile(
+                err.push(CompileError::fromSourceRange(
                     "Module headers can't have a module name declared in them...".to_string(),
-                    tu,
+                    &at,
                 ));
                 ModuleDeclaration::ModuleHeaderUnit(tu)
             } else if moduleIsExport {
@@ -216,60 +279,96 @@ pub fn generateNode(
                 let (module, partition) = moduleName.split_once(':').unwrap();
                 ModuleDeclaration::Partition(module.to_StringRef(), partition.to_StringRef())
             } else {
-                moduleImports.push(ModuleDeclaration::ExportPrimary(moduleName.to_StringRef()));
+                moduleImports.push(Import {
+                    module: ModuleDeclaration::ExportPrimary(moduleName.to_StringRef()),
+                    at: None,
+                    exported: false,
+                });
                 ModuleDeclaration::Primary(moduleName.to_StringRef())
             }
         },
     );
-    match genNewArcTable.entry(moduleDecl) {
-        Entry::Vacant(res) => {
-            let res = res.insert(Arc::new((moduleDecl, tu)));
-            if err.is_empty() {
-                Ok((
-                    Node {
-                        module: res.clone(),
-                        dependedBy: vec![],
-                        dependsOn: HashSet::new(),
-                        depth: 0,
-                        stepsCompleted: Arc::new(AtomicUsize::new(0)),
-                    },
-                    moduleImports,
-                ))
-            } else {
-                Err(err)
-            }
+    if !err.is_empty() {
+        return Err(err);
+    }
+
+    let mut dependencies = vec![];
+    let mut importedAt = HashMap::new();
+    let mut exportedImports = HashSet::new();
+    for import in moduleImports {
+        if !dependencies.contains(&import.module) {
+            dependencies.push(import.module);
         }
-        Entry::Occupied(error) => {
-            err.push(CompileError::onFile(
-                format!(
-                    "Module {} already defined. Conflicting files: {} and {}",
-                    moduleDecl,
-                    error.get().1,
-                    tu
-                ),
-                tu,
-            ));
-            Err(err)
+        if let Some(at) = import.at {
+            importedAt.entry(import.module).or_insert(at);
+        }
+        if import.exported {
+            exportedImports.insert(import.module);
         }
     }
+    Ok((
+        Node {
+            module: Arc::new((moduleDecl, tu)),
+            dependedBy: vec![],
+            dependsOn: HashSet::new(),
+            importedAt,
+            exportedImports,
+            declaredAt,
+            depth: 0,
+            stepsCompleted: Arc::new(AtomicUsize::new(0)),
+        },
+        dependencies,
+    ))
 }
 
 type GeneratedEmptyNodes = HashMap<ModuleDeclaration, (Node, Vec<ModuleDeclaration>)>;
 type GenNewArcTable = HashMap<ModuleDeclaration, Arc<(ModuleDeclaration, u64)>>;
 
+/// Error for a module unit declared in two files
+fn duplicateModuleUnit(node: &Node, previous: &Node) -> Vec<CompileMsg> {
+    let msg = match node.module.0 {
+        ModuleDeclaration::ExportPrimary(module) => {
+            format!("Duplicate primary module interface unit of module {module}")
+        }
+        ModuleDeclaration::ExportPartition(module, partition)
+        | ModuleDeclaration::Partition(module, partition) => {
+            format!("Duplicate module partition {module}:{partition}")
+        }
+        ModuleDeclaration::Primary(module) => {
+            format!("Multiple module implementation units of module {module} are not supported yet")
+        }
+        ModuleDeclaration::ModuleHeaderUnit(_) | ModuleDeclaration::Global(_) => unreachable!(),
+    };
+    redeclarationError(
+        msg,
+        node.declaredAt.as_ref().unwrap(),
+        previous.declaredAt.as_ref().unwrap(),
+    )
+}
+
 /// Generates all the necessary `Node` for each TU (might result in some extras from headers) and fills the dependsOn fields.
 pub fn generateEmptyNodes(
     translationUnitContent: &mut dyn Iterator<Item = (TranslationUnit, Vec<ModuleOperator>, bool)>,
 ) -> Result<(GeneratedEmptyNodes, GenNewArcTable), Vec<CompileMsg>> {
     let mut err = vec![];
 
-    let mut generatedEmptyNodes = HashMap::new();
+    // In order, so the duplicates found don't depend on the hashing
+    let mut translationUnitContent = translationUnitContent.collect::<Vec<_>>();
+    translationUnitContent.sort_by_key(|(tu, _, _)| *tu);
+
+    let mut generatedEmptyNodes: GeneratedEmptyNodes = HashMap::new();
     let mut genNewArcTable = HashMap::new();
     for (tu, op, isModuleFile) in translationUnitContent {
-        match generateNode(tu, &op, isModuleFile, &mut genNewArcTable) {
-            Ok((node, depends)) => {
-                generatedEmptyNodes.insert(node.module.0, (node, depends));
-            }
+        match generateNode(tu, &op, isModuleFile) {
+            Ok((node, depends)) => match generatedEmptyNodes.entry(node.module.0) {
+                Entry::Vacant(entry) => {
+                    genNewArcTable.insert(node.module.0, node.module.clone());
+                    entry.insert((node, depends));
+                }
+                Entry::Occupied(previous) => {
+                    err.append(&mut duplicateModuleUnit(&node, &previous.get().0));
+                }
+            },
             Err(mut err2) => err.append(&mut err2),
         }
     }
@@ -278,6 +377,113 @@ pub fn generateEmptyNodes(
     }
     Ok((generatedEmptyNodes, genNewArcTable))
 }
+
+/// Partitions are always imported as interface partitions. Point the imports
+/// of implementation partitions to them instead.
+fn resolveImplementationPartitions(generatedEmptyNodes: &mut GeneratedEmptyNodes) {
+    let declared = generatedEmptyNodes.keys().copied().collect::<HashSet<_>>();
+    for (node, depends) in generatedEmptyNodes.values_mut() {
+        for dep in depends {
+            let ModuleDeclaration::ExportPartition(module, partition) = *dep else {
+                continue;
+            };
+            let implementation = ModuleDeclaration::Partition(module, partition);
+            if declared.contains(dep) || !declared.contains(&implementation) {
+                continue;
+            }
+            if let Some(at) = node.importedAt.remove(dep) {
+                node.importedAt.insert(implementation, at);
+            }
+            if node.exportedImports.remove(dep) {
+                node.exportedImports.insert(implementation);
+            }
+            *dep = implementation;
+        }
+    }
+}
+
+/// Checks the rules of [module.unit] that involve several module units:
+/// partitions need a primary module interface unit, which must export all
+/// the interface partitions (directly or through other partitions), the
+/// partition names are unique, and implementation partitions can't be
+/// exported.
+fn validateModuleUnits(generatedEmptyNodes: &GeneratedEmptyNodes) -> Vec<CompileMsg> {
+    let mut err = vec![];
+    let mut nodes = generatedEmptyNodes
+        .values()
+        .map(|(node, _)| node)
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|node| node.module.1);
+
+    for node in &nodes {
+        for exported in &node.exportedImports {
+            if let ModuleDeclaration::Partition(module, partition) = exported {
+                err.push(CompileError::fromSourceRange(
+                    format!(
+                        "Module implementation partition {module}:{partition} can't be exported"
+                    ),
+                    &node.importedAt[exported],
+                ));
+            }
+        }
+
+        let (ModuleDeclaration::ExportPartition(module, partition)
+        | ModuleDeclaration::Partition(module, partition)) = node.module.0
+        else {
+            continue;
+        };
+        let declaredAt = node.declaredAt.as_ref().unwrap();
+        let Some((primary, _)) = generatedEmptyNodes.get(&ModuleDeclaration::ExportPrimary(module))
+        else {
+            err.push(CompileError::fromSourceRange(
+                format!("Module {module} has no primary module interface unit, but it has the partition {module}:{partition}"),
+                &declaredAt,
+            ));
+            continue;
+        };
+
+        if matches!(node.module.0, ModuleDeclaration::Partition(..)) {
+            if let Some((interface, _)) =
+                generatedEmptyNodes.get(&ModuleDeclaration::ExportPartition(module, partition))
+            {
+                err.extend(redeclarationError(
+                    format!("Duplicate module partition {module}:{partition}"),
+                    &declaredAt,
+                    interface.declaredAt.as_ref().unwrap(),
+                ));
+            }
+            continue;
+        }
+
+        // Interface partitions must be reachable from the primary interface
+        // through exported imports of interface partitions
+        let mut exported = HashSet::new();
+        let mut pending = vec![primary];
+        while let Some(current) = pending.pop() {
+            for dep in &current.exportedImports {
+                if let ModuleDeclaration::ExportPartition(depModule, _) = dep {
+                    if *depModule == module && exported.insert(*dep) {
+                        if let Some((dep, _)) = generatedEmptyNodes.get(dep) {
+                            pending.push(dep);
+                        }
+                    }
+                }
+            }
+        }
+        if !exported.contains(&node.module.0) {
+            err.push(CompileError::fromSourceRange(
+                format!("Module interface partition {module}:{partition} is not exported by the primary module interface unit of {module}"),
+                &declaredAt,
+            ));
+            err.push(CompileNote::fromSourceRange(
+                "Primary module interface unit is here",
+                primary.declaredAt.as_ref().unwrap(),
+            ));
+        }
+    }
+    err
+}
+
 /// Generates all the necessary `Node` for each TU (might result in some extras from headers) and fills the dependsOn fields.
 pub fn generateNodes(
     translationUnitContent: &mut dyn Iterator<Item = (TranslationUnit, Vec<ModuleOperator>, bool)>,
@@ -286,6 +492,11 @@ pub fn generateNodes(
     let mut res = HashMap::new();
 
     let (mut generatedEmptyNodes, genNewArcTable) = generateEmptyNodes(translationUnitContent)?;
+    resolveImplementationPartitions(&mut generatedEmptyNodes);
+    let validationErrors = validateModuleUnits(&generatedEmptyNodes);
+    if !validationErrors.is_empty() {
+        return Err(validationErrors);
+    }
 
     #[allow(clippy::needless_collect)]
     for module in generatedEmptyNodes.keys().copied().collect::<Vec<_>>() {
@@ -10,13 +10,16 @@ use crate::Compiler::TranslationUnit;
 
 use super::Structs::{ModuleDeclaration, ModuleTree, Node};
 
+/// Priority of a root. The ones with the highest priority are sent first.
+type Priority = Box<dyn Fn(&Node) -> u64 + Send>;
+
 /// Mutex-protected internal state, as `DependencyIterator` will be accessible
 /// from multiple threads.
 struct Data {
     /// Roots that may not have completed their min stage required.
     rootsNotReady: Vec<Node>,
     /// Roots that have completed their min stage required.
-    rootsReady: PriorityQueue<Node, usize>,
+    rootsReady: PriorityQueue<Node, u64>,
     /// Roots that have been sent to the consumer, but the consumer has not
     /// notified it is done with it.
     rootsSentButNotDone: HashMap<TranslationUnit, Node>,
@@ -24,11 +27,12 @@ struct Data {
     childModules: HashMap<ModuleDeclaration, Node>,
     /// Minimum stage completed required before sending a TU.
     minStageCompleted: usize,
-    /// The total number of TU. Used for the heuristic of what to send.
-    totalNumModules: usize,
+    /// Priority of the roots that are ready
+    priority: Priority,
 }
 
-/// Iterator over the dependency tree, with priorization based on depth and stage completed.
+/// Iterator over the dependency tree, with priorization based on depth (or a
+/// custom priority) and stage completed.
 pub struct DependencyIterator {
     /// Condvar for waiting for new roots once all roots have been sent.
     waitForNewRoots: Condvar,
@@ -42,8 +46,25 @@ lazy_static! {
 }
 
 impl DependencyIterator {
-    /// Create a new `DependencyIterator` from a `ModuleTree`.
+    /// Create a new `DependencyIterator` from a `ModuleTree`. The TUs that
+    /// are depended by the most, and then the deepest ones, are sent first.
     pub fn new(dependencyTree: &ModuleTree, minStageCompleted: usize) -> Self {
+        let totalNumModules =
+            (dependencyTree.roots.len() + dependencyTree.childModules.len()) as u64;
+        Self::new_prioritized(dependencyTree, minStageCompleted, move |root| {
+            root.dependedBy.len() as u64 * totalNumModules + root.depth as u64
+        })
+    }
+
+    /// Create a new `DependencyIterator` from a `ModuleTree`, that sends first
+    /// the TUs with the highest `priority`. The priority of the TUs that are
+    /// ready is computed again every time a TU advances, so it can change as
+    /// the compilation progresses.
+    pub fn new_prioritized(
+        dependencyTree: &ModuleTree,
+        minStageCompleted: usize,
+        priority: impl Fn(&Node) -> u64 + Send + 'static,
+    ) -> Self {
         Self {
             waitForNewRoots: Condvar::new(),
             d: Mutex::new(Data {
@@ -52,19 +73,22 @@ impl DependencyIterator {
                 rootsSentButNotDone: HashMap::new(),
                 childModules: dependencyTree.childModules.clone(),
                 minStageCompleted,
-                totalNumModules: dependencyTree.roots.len() + dependencyTree.childModules.len(),
+                priority: Box::new(priority),
             }),
         }
     }
 
     /// Check if any new TU have been updated to be ready to be sent. Checks for the stage completed.
     fn updateReadies(d: &mut Data) {
+        for (root, priority) in &mut d.rootsReady {
+            *priority = (d.priority)(root);
+        }
         let mut stillNotReady = Vec::new();
         for root in d.rootsNotReady.drain(..) {
             let stepsCompleted = root.stepsCompleted.load(Ordering::Relaxed);
             if stepsCompleted >= d.minStageCompleted {
-                let idx = root.dependedBy.len() * d.totalNumModules + root.depth;
-                d.rootsReady.push(root, idx);
+                let priority = (d.priority)(&root);
+                d.rootsReady.push(root, priority);
             } else {
                 stillNotReady.push(root);
             }
@@ -100,6 +124,14 @@ impl DependencyIterator {
         ALL_DEPENDENCY_ITERATORS_WAIT.notify_all();
     }
 
+    /// Wakes up the consumers after a TU advanced its stage completed outside
+    /// of `markDone` (for example, when it finished lexing).
+    pub fn notifyStageCompleted(&self) {
+        Self::updateReadies(&mut self.d.lock().unwrap());
+        self.waitForNewRoots.notify_one();
+        ALL_DEPENDENCY_ITERATORS_WAIT.notify_all();
+    }
+
     /// [DEBUGGING PURPOSES] Checks if calling next without marking anything as done would lock the iterator
     pub fn wouldLockIfNext(&self) -> bool {
         let d = self.d.lock().unwrap();
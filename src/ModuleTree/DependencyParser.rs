@@ -2,7 +2,7 @@
 use lazy_regex::regex_is_match;
 
 use crate::fileTokPosMatchArm;
-use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl, TokPos};
+use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl, SourceRange, TokPos};
 use crate::{Compiler::TranslationUnit, Lex::Token::Token, Utils::Structs::FileTokPos};
 
 use super::Structs::ModuleOperator;
@@ -16,7 +16,8 @@ fn parseModuleOp(
     let mut at = usize::MAX;
     let mut atEnd = usize::MIN;
     let mut name = String::new();
-    for tok in tokens.iter().skip(pos) {
+    let mut lastTok = pos - 1;
+    for (i, tok) in tokens.iter().enumerate().skip(pos) {
         match tok.tokPos.tok {
             Token::Private => {
                 name.push_str("private");
@@ -36,6 +37,7 @@ fn parseModuleOp(
         }
         at = at.min(tok.tokPos.start);
         atEnd = atEnd.max(tok.tokPos.end);
+        lastTok = i;
     }
 
     if !regex_is_match!(
@@ -49,9 +51,12 @@ fn parseModuleOp(
             translationUnit,
             at,
             Some(atEnd),
-        ));
+        )
+        .withExpansion(tokens.get(pos).and_then(|tok| tok.expansion.clone())));
     }
-    Ok(Some(ModuleOperator::Module(name)))
+    // From the module keyword to the end of the name
+    let range = SourceRange::newDoubleTok(&tokens[pos - 1], &tokens[lastTok]);
+    Ok(Some(ModuleOperator::Module(name, range)))
 }
 
 /// When encountering an import operator, validates it can be used and parses it.
@@ -64,7 +69,8 @@ fn parseImportOp(
     let mut atEnd = usize::MIN;
 
     let mut name = String::new();
-    for tok in tokens.iter().skip(pos) {
+    let mut lastTok = pos - 1;
+    for (i, tok) in tokens.iter().enumerate().skip(pos) {
         match tok.tokPos.tok {
             Token::ImportableHeaderName(header) => {
                 return Ok(Some(ModuleOperator::ImportHeader(header)));
@@ -84,6 +90,7 @@ fn parseImportOp(
         }
         at = at.min(tok.tokPos.start);
         atEnd = atEnd.max(tok.tokPos.end);
+        lastTok = i;
     }
     if !regex_is_match!(
         r"(:?(:?[\w\d_]+\.)*[\w\d_]+|:(:?[\w\d_]+\.)*[\w\d_]+)",
@@ -94,9 +101,12 @@ fn parseImportOp(
             translationUnit,
             at,
             Some(atEnd),
-        ));
+        )
+        .withExpansion(tokens.get(pos).and_then(|tok| tok.expansion.clone())));
     }
-    Ok(Some(ModuleOperator::Import(name)))
+    // From the import keyword to the end of the name
+    let range = SourceRange::newDoubleTok(&tokens[pos - 1], &tokens[lastTok]);
+    Ok(Some(ModuleOperator::Import(name, range)))
 }
 
 /// When encountering an export operator, validates it can be used and parses it.
@@ -107,11 +117,19 @@ fn parseExportOp(
 ) -> Result<Option<ModuleOperator>, CompileMsg> {
     if let Some(fileTokPosMatchArm!(tok)) = tokens.get(pos) {
         return match tok {
-            Token::Import => parseImportOp(translationUnit, tokens, pos + 1),
+            Token::Import => parseImportOp(translationUnit, tokens, pos + 1).map(|x| {
+                x.map(|op| {
+                    if let ModuleOperator::Import(module, at) = op {
+                        ModuleOperator::ExportImport(module, at)
+                    } else {
+                        op
+                    }
+                })
+            }),
             Token::Module => parseModuleOp(translationUnit, tokens, pos + 1).map(|x| {
                 x.map(|op| {
-                    if let ModuleOperator::Module(module) = op {
-                        ModuleOperator::ExportModule(module)
+                    if let ModuleOperator::Module(module, at) = op {
+                        ModuleOperator::ExportModule(module, at)
                     } else {
                         op
                     }
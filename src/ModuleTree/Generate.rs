@@ -12,12 +12,19 @@ pub fn generateDependencyTree(
     compilerState: &CompilerState,
 ) -> Result<ModuleTree, Vec<CompileMsg>> {
     let mut it = compilerState.compileUnits.iter().map(|(tu, state)| {
-        let operations = state.moduleOperations.lock().unwrap().take().unwrap();
+        // The units that crashed, or whose module directives are wrong, have
+        // no module operations
+        let operations = state
+            .moduleOperations
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or_default();
         let isModuleHeaderFile = compilerState.moduleHeaderUnitsFiles.contains(tu);
         (*tu, operations, isModuleHeaderFile)
     });
 
     generateNodes(&mut it)
         .map(|x| annotateTuWithKind(x, &compilerState.compileUnits))
-        .and_then(generateModuleTree)
+        .and_then(|nodes| generateModuleTree(nodes, &compilerState.compileFiles))
 }
@@ -0,0 +1,103 @@
+//! Finds the interfaces of the imported modules that are not listed in the
+//! translation units, through the `moduleMap` and the `moduleSearchDirs` of
+//! the parameters. Only the entry points of a project need to be listed: the
+//! rest of the modules are pulled in as they are imported.
+use std::path::Path;
+
+use crate::Compiler::TranslationUnit;
+use crate::Utils::FileMap::FileMap;
+use crate::Utils::Parameters::Parameters;
+use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl, SourceRange};
+
+use super::Structs::ModuleOperator;
+
+/// Extensions tried, in order, when searching an interface in the
+/// `moduleSearchDirs`
+const INTERFACE_EXTENSIONS: [&str; 4] = ["cppm", "ixx", "mpp", "cpp"];
+
+/// A module a translation unit needs
+#[derive(Debug, Clone)]
+pub struct ModuleRequest {
+    /// Name of the module, as `name` or `name:partition`
+    pub name: String,
+    /// Translation unit that needs it
+    pub tu: TranslationUnit,
+    /// Location of the import, or of the module declaration for the implicit
+    /// import of the primary interface by an implementation unit
+    pub at: SourceRange,
+}
+
+impl ModuleRequest {
+    /// Error reported when no translation unit provides the module
+    pub fn notFound(&self) -> CompileMsg {
+        CompileError::fromSourceRange(format!("Module {} not found", self.name), &self.at)
+    }
+}
+
+/// The module or partition a translation unit declares, if any, and the
+/// modules it imports
+pub fn moduleRequirements(
+    tu: TranslationUnit,
+    ops: &[ModuleOperator],
+) -> (Option<String>, Vec<ModuleRequest>) {
+    let mut declared = None;
+    let mut moduleName: Option<String> = None;
+    let mut requests = vec![];
+    for op in ops {
+        match op {
+            ModuleOperator::ExportModule(name, _) | ModuleOperator::Module(name, _)
+                if name.is_empty() || name == ":private" => {}
+            ModuleOperator::ExportModule(name, _) => {
+                declared = Some(name.clone());
+                moduleName = Some(name.split(':').next().unwrap().to_string());
+            }
+            ModuleOperator::Module(name, at) => {
+                if name.contains(':') {
+                    declared = Some(name.clone());
+                } else {
+                    requests.push(ModuleRequest {
+                        name: name.clone(),
+                        tu,
+                        at: at.clone(),
+                    });
+                }
+                moduleName = Some(name.split(':').next().unwrap().to_string());
+            }
+            ModuleOperator::Import(name, at) | ModuleOperator::ExportImport(name, at) => {
+                let name = match (name.strip_prefix(':'), &moduleName) {
+                    (Some(partition), Some(module)) => format!("{module}:{partition}"),
+                    // Reported when generating the tree
+                    (Some(_), None) => continue,
+                    (None, _) => name.clone(),
+                };
+                requests.push(ModuleRequest {
+                    name,
+                    tu,
+                    at: at.clone(),
+                });
+            }
+            ModuleOperator::ImportHeader(_) => {}
+        }
+    }
+    (declared, requests)
+}
+
+/// Finds the interface of a module: first in the `moduleMap`, then in the
+/// `moduleSearchDirs`, as `<dir>/<name>.<ext>`. The `:` of partitions is
+/// replaced by a `-` in the file names (`mod:part` is `mod-part.cppm`).
+pub fn findModuleInterface(
+    parameters: &Parameters,
+    fileMap: &mut FileMap,
+    name: &str,
+) -> Option<TranslationUnit> {
+    if let Some(path) = parameters.moduleMap.get(name) {
+        return fileMap.getPath(path).ok();
+    }
+    let stem = name.replace(':', "-");
+    fileMap.getFirstPath(parameters.moduleSearchDirs.iter().flat_map(|dir| {
+        INTERFACE_EXTENSIONS
+            .iter()
+            .map(|ext| Path::new(dir).join(format!("{stem}.{ext}")))
+            .collect::<Vec<_>>()
+    }))
+}
@@ -0,0 +1,261 @@
+//! Incremental rebuild planning.
+//!
+//! Given the files that changed, finds the translation units that must be
+//! recompiled: the ones that enter a changed file (themselves or through an
+//! include), and transitively the ones that import them, following the
+//! `dependedBy` edges of the [`ModuleTree`].
+//!
+//! The result of the previous dependency scan is kept in a state file: the
+//! module operations of each translation unit, and the files it entered with
+//! the hash of their contents. The translation units whose files all have the
+//! same hash are not scanned again, their module operations are restored from
+//! the state. Files whose hash changed are detected even if they are not in
+//! the list of changed files.
+//!
+//! The translation units of a plan are kept as pending in the state, and are
+//! part of the following plans until the rebuild is confirmed. So a rebuild
+//! that fails, or never happens, is planned again.
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use json::{object, JsonValue};
+
+use crate::Compiler::TranslationUnit;
+use crate::Preprocessor::Callbacks::PreprocessorCallbacks;
+use crate::Sema::Bmi::{loadSourceRange, serializeSourceRange, sourceHash};
+use crate::Utils::FileMap::FileMap;
+
+use super::DependencyIterator::DependencyIterator;
+use super::Structs::{ModuleOperator, ModuleTree};
+
+/// Version of the format of the state file. Bump it on any change to the
+/// format.
+pub const REBUILD_STATE_VERSION: u32 = 2;
+
+/// Records the files entered by each translation unit while scanning it,
+/// including the translation unit itself.
+#[derive(Debug, Default)]
+pub struct FilesEntered {
+    /// Files entered, by translation unit
+    files: Mutex<HashMap<TranslationUnit, HashSet<u64>>>,
+}
+
+impl FilesEntered {
+    /// Files entered by a translation unit
+    pub fn take(&self, tu: TranslationUnit) -> HashSet<u64> {
+        self.files.lock().unwrap().remove(&tu).unwrap_or_default()
+    }
+}
+
+impl PreprocessorCallbacks for FilesEntered {
+    fn fileEntered(&self, tu: u64, file: u64) {
+        self.files
+            .lock()
+            .unwrap()
+            .entry(tu)
+            .or_default()
+            .insert(file);
+    }
+}
+
+/// What a dependency scan found about a translation unit
+#[derive(Debug, Clone)]
+pub struct UnitState {
+    /// Files entered, by path, with the hash of their contents
+    files: HashMap<String, String>,
+    /// Serialized module operations
+    operations: JsonValue,
+}
+
+impl UnitState {
+    /// State of a translation unit that was just scanned
+    pub fn new(files: &HashSet<u64>, operations: &[ModuleOperator], fileMap: &mut FileMap) -> Self {
+        let files = files
+            .iter()
+            .map(|file| {
+                let file = fileMap.getOpenedFile(*file);
+                (file.path().clone(), sourceHash(file.content()))
+            })
+            .collect();
+        let operations = operations
+            .iter()
+            .map(|op| match op {
+                ModuleOperator::Import(module, at) => object! {
+                    kind: "import",
+                    module: module.as_str(),
+                    location: serializeSourceRange(at, fileMap),
+                },
+                ModuleOperator::ExportImport(module, at) => object! {
+                    kind: "exportImport",
+                    module: module.as_str(),
+                    location: serializeSourceRange(at, fileMap),
+                },
+                ModuleOperator::ImportHeader(header) => object! {
+                    kind: "importHeader",
+                    header: fileMap.getOpenedFile(*header).path().as_str(),
+                },
+                ModuleOperator::ExportModule(module, at) => object! {
+                    kind: "exportModule",
+                    module: module.as_str(),
+                    location: serializeSourceRange(at, fileMap),
+                },
+                ModuleOperator::Module(module, at) => object! {
+                    kind: "module",
+                    module: module.as_str(),
+                    location: serializeSourceRange(at, fileMap),
+                },
+            })
+            .collect();
+        Self {
+            files,
+            operations: JsonValue::Array(operations),
+        }
+    }
+
+    /// Do all the files entered still have the same contents?
+    pub fn isUpToDate(&self, fileMap: &mut FileMap) -> bool {
+        self.files.iter().all(|(path, hash)| {
+            fileMap
+                .getPath(path)
+                .is_ok_and(|file| &sourceHash(fileMap.getOpenedFile(file).content()) == hash)
+        })
+    }
+
+    /// Does the translation unit enter any of these files?
+    pub fn entersAny(&self, files: &HashSet<String>) -> bool {
+        self.files.keys().any(|path| files.contains(path))
+    }
+
+    /// The module operations found by the scan. None if an imported header
+    /// unit no longer exists.
+    pub fn operations(&self, fileMap: &mut FileMap) -> Option<Vec<ModuleOperator>> {
+        self.operations
+            .members()
+            .map(|op| {
+                let module = || op["module"].as_str().map(ToString::to_string);
+                let at = loadSourceRange(&op["location"], fileMap);
+                Some(match op["kind"].as_str()? {
+                    "import" => ModuleOperator::Import(module()?, at),
+                    "exportImport" => ModuleOperator::ExportImport(module()?, at),
+                    "importHeader" => {
+                        ModuleOperator::ImportHeader(fileMap.getPath(op["header"].as_str()?).ok()?)
+                    }
+                    "exportModule" => ModuleOperator::ExportModule(module()?, at),
+                    "module" => ModuleOperator::Module(module()?, at),
+                    _ => return None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Result of the previous dependency scan
+#[derive(Debug, Default)]
+pub struct RebuildState {
+    /// State of each translation unit, by path
+    pub units: HashMap<String, UnitState>,
+    /// Paths of the translation units planned for a rebuild that was not
+    /// confirmed yet
+    pub pending: HashSet<String>,
+}
+
+impl RebuildState {
+    /// Reads a state file. Fails if it does not exist, or it was written with
+    /// another format version.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let json = json::parse(&content).map_err(|err| err.to_string())?;
+        if json["version"].as_u32() != Some(REBUILD_STATE_VERSION) {
+            return Err(format!(
+                "The state file {} has format version {}, but version {REBUILD_STATE_VERSION} is required",
+                path.display(),
+                json["version"]
+            ));
+        }
+        let mut units = HashMap::new();
+        for (unitPath, unit) in json["units"].entries() {
+            let files = unit["files"]
+                .entries()
+                .filter_map(|(file, hash)| Some((file.to_string(), hash.as_str()?.to_string())))
+                .collect();
+            units.insert(
+                unitPath.to_string(),
+                UnitState {
+                    files,
+                    operations: unit["operations"].clone(),
+                },
+            );
+        }
+        let pending = json["pending"]
+            .members()
+            .filter_map(|unitPath| Some(unitPath.as_str()?.to_string()))
+            .collect();
+        Ok(Self { units, pending })
+    }
+
+    /// Writes the state file, creating its directory if needed.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let mut units = JsonValue::new_object();
+        for (unitPath, unit) in &self.units {
+            let mut files = JsonValue::new_object();
+            for (file, hash) in &unit.files {
+                files[file.as_str()] = hash.as_str().into();
+            }
+            units[unitPath.as_str()] = object! {
+                files: files,
+                operations: unit.operations.clone(),
+            };
+        }
+        let mut pending = self.pending.iter().map(String::as_str).collect::<Vec<_>>();
+        pending.sort_unstable();
+        let json = object! {
+            version: REBUILD_STATE_VERSION,
+            units: units,
+            pending: pending,
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        std::fs::write(path, json.pretty(2)).map_err(|err| err.to_string())
+    }
+}
+
+/// Translation units to recompile
+#[derive(Debug, Clone, Default)]
+pub struct RebuildPlan {
+    /// Paths of the translation units to recompile, in a valid build order
+    pub rebuild: Vec<String>,
+    /// Paths of the translation units that had to be scanned again, sorted
+    pub rescanned: Vec<String>,
+}
+
+/// The `dirty` translation units, and the ones that depend on them, in a
+/// valid build order.
+pub fn planRebuild(tree: &ModuleTree, dirty: &HashSet<TranslationUnit>) -> Vec<TranslationUnit> {
+    let nodes = tree
+        .roots
+        .values()
+        .chain(tree.childModules.values())
+        .map(|node| (node.module.1, node))
+        .collect::<HashMap<_, _>>();
+    let mut toRebuild = HashSet::new();
+    let mut pending = dirty.iter().copied().collect::<Vec<_>>();
+    while let Some(tu) = pending.pop() {
+        if toRebuild.insert(tu) {
+            if let Some(node) = nodes.get(&tu) {
+                pending.extend(node.dependedBy.iter().map(|child| child.1));
+            }
+        }
+    }
+
+    let dependencyIterator = DependencyIterator::new(tree, 0);
+    let mut order = vec![];
+    while let Some(tu) = dependencyIterator.next() {
+        if toRebuild.contains(&tu) {
+            order.push(tu);
+        }
+        dependencyIterator.markDone(tu, 0);
+    }
+    order
+}
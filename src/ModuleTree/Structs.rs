@@ -6,6 +6,7 @@ use std::sync::Arc;
 
 use crate::Compiler::TranslationUnit;
 use crate::Utils::StringRef::StringRef;
+use crate::Utils::Structs::SourceRange;
 
 /// Kind of module the TU is of. This also includes ones where the TU does not
 /// use modules, like a generated one (import <header>) or a classical .cpp file
@@ -41,14 +42,16 @@ impl Display for ModuleDeclaration {
 /// Rellevant module operators. These ony include the rellevant ones for dependency scanning!
 #[derive(Debug, Clone)]
 pub enum ModuleOperator {
-    /// an import <module> directive.
-    Import(String),
+    /// an import <module> directive, with the location of the import.
+    Import(String, SourceRange),
+    /// an export import <module> directive, with the location of the import.
+    ExportImport(String, SourceRange),
     /// an import <header> directive.
     ImportHeader(u64),
-    /// an export module <module> directive.
-    ExportModule(String),
-    /// a module <module> directive.
-    Module(String),
+    /// an export module <module> directive, with its location.
+    ExportModule(String, SourceRange),
+    /// a module <module> directive, with its location.
+    Module(String, SourceRange),
 }
 
 /// A node holds all the relevant dependency information of a TU.
@@ -60,6 +63,14 @@ pub struct Node {
     pub dependedBy: Vec<Arc<(ModuleDeclaration, TranslationUnit)>>,
     /// The TU that this node depends on
     pub dependsOn: HashSet<Arc<(ModuleDeclaration, TranslationUnit)>>,
+    /// Where the TU imports each of the modules it depends on. Implicit
+    /// imports (like the one of the primary interface from its implementation
+    /// units) are missing.
+    pub importedAt: HashMap<ModuleDeclaration, SourceRange>,
+    /// The modules the TU imports with an `export import`
+    pub exportedImports: HashSet<ModuleDeclaration>,
+    /// Where the module of the TU is declared, if it is a named module
+    pub declaredAt: Option<SourceRange>,
     /// How deep is the node in the tree. The way this is calculated is the
     /// inverse from the roots:
     ///
@@ -84,6 +95,9 @@ impl Node {
             module: Arc::new((ModuleDeclaration::Global(0), 0)),
             dependedBy: vec![],
             dependsOn: HashSet::new(),
+            importedAt: HashMap::new(),
+            exportedImports: HashSet::new(),
+            declaredAt: None,
             depth: 0,
             stepsCompleted: Arc::default(),
         }
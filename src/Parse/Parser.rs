@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use strum::IntoEnumIterator;
 
 use crate::{
@@ -7,7 +9,7 @@ use crate::{
     },
     Compiler::TranslationUnit,
     Lex::Token::Token,
-    Sema::AstContext::AstContext,
+    Sema::{AstContext::AstContext, Bmi::BmiImport},
     Utils::{
         CompilerState::CompilerState,
         Structs::{CompileMsg, FileTokPos},
@@ -42,6 +44,12 @@ pub struct Parser {
     compilerState: CompilerState,
 
     moduleImportState: ModuleImportState,
+    /// Are we inside an export-declaration?
+    exporting: bool,
+    /// Modules whose BMI has already been loaded
+    importedModules: HashSet<String>,
+    /// Modules imported by the purview of this unit, for its BMI
+    moduleImports: Vec<BmiImport>,
 
     errors: Vec<CompileMsg>,
     astContext: AstContext,
@@ -60,6 +68,9 @@ impl Parser {
             filePath,
             compilerState,
             moduleImportState: ModuleImportState::StartFile,
+            exporting: false,
+            importedModules: HashSet::new(),
+            moduleImports: vec![],
             errors: vec![],
             astContext: AstContext::new(),
         }
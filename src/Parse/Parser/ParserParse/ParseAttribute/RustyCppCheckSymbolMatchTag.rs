@@ -50,15 +50,15 @@ impl Parser {
             if matchedQualified.matched() {
                 AstAttributeCXXRustyCppCheckSymbolMatchTag::new_qualified(
                     self.alloc(),
-                    *number,
-                    *name,
+                    number.clone(),
+                    name.clone(),
                     qualifiedNameSpecifier,
                 )
             } else {
                 AstAttributeCXXRustyCppCheckSymbolMatchTag::new_unqualified(
                     self.alloc(),
-                    *number,
-                    *name,
+                    number.clone(),
+                    name.clone(),
                 )
             }
             .into(),
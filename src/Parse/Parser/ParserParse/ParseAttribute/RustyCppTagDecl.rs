@@ -26,6 +26,6 @@ impl Parser {
                 return None;
             };
 
-        Some(AstAttributeCXXRustyCppTagDecl::new(self.alloc(), *number).into())
+        Some(AstAttributeCXXRustyCppTagDecl::new(self.alloc(), number.clone()).into())
     }
 }
@@ -7,6 +7,8 @@ use crate::{
 
 mod ParseAsmDeclaration;
 mod ParseCustomRustycppDeclaration;
+mod ParseExportDeclaration;
+mod ParseModuleImportDeclaration;
 mod ParseNamespaceDeclaration;
 mod ParseUsingNamespaceDeclaration;
 
@@ -219,7 +221,7 @@ impl Parser {
             Token::Decltype => todo!(),
             Token::Double => todo!(),
             Token::Explicit => todo!(),
-            Token::Export => todo!(),
+            Token::Export => self.parseExportDeclaration(lexpos, attr),
             Token::Extern => todo!(),
             Token::Float => todo!(),
             Token::Friend => todo!(),
@@ -262,20 +264,7 @@ impl Parser {
             Token::LParen => todo!(),
             Token::DoubleColon => todo!(),
             Token::Less => todo!(),
-            Token::Import => {
-                // TODO: Stub until we can accually import stuff
-                self.lexer().next(lexpos);
-                loop {
-                    if self.lexer().getIfEq(lexpos, Token::Semicolon).is_some() {
-                        return self.actOnEmptyDecl(attr, SourceRange::newSingleTok(tok));
-                    } else if !self.lexer().consumeToken(lexpos) {
-                        let pos = self.lexer().getWithOffsetSaturating(lexpos, 0);
-                        self.errors
-                            .push(CompileError::fromPreTo("Expected ';'", pos));
-                        return vec![];
-                    }
-                }
-            }
+            Token::Import => self.parseModuleImportDeclaration(lexpos, attr, false),
             Token::ImportableHeaderName(_) => unreachable!(),
             Token::__rustycpp__ => self.parseCustom__rustycpp__Decl(lexpos, attr),
         }
@@ -0,0 +1,86 @@
+use crate::{
+    fileTokPosMatchArm,
+    Ast::Common::{AstAttribute, AstDecl},
+    Lex::Token::Token,
+    Parse::BufferedLexer::StateBufferedLexer,
+    Utils::Structs::{CompileError, CompileMsgImpl, FileTokPos, SourceRange, TokPos},
+};
+
+use super::super::super::Parser;
+
+impl Parser {
+    /**
+     * export-declaration:
+     *   export name-declaration
+     *   export { declaration-seq [opt] }
+     *   export-keyword module-import-declaration
+     */
+    pub fn parseExportDeclaration(
+        &mut self,
+        lexpos: &mut StateBufferedLexer,
+        attr: &[AstAttribute],
+    ) -> Vec<AstDecl> {
+        self.actWrongAttributeLocation(attr);
+
+        let Some(exportTok) = self.lexer().getConsumeTokenIfEq(lexpos, Token::Export) else {
+            // We already expected an export keyword. Reaching this is a bug.
+            let posErr = self.lexer().getWithOffsetSaturating(lexpos, 0);
+            self.errors.push(CompileError::fromPreTo(
+                "Expected 'export' keyword. This is a bug. Report is please.",
+                posErr,
+            ));
+            return vec![];
+        };
+
+        let wasExporting = self.actOnStartExportDeclaration(SourceRange::newSingleTok(exportTok));
+        let decls = match self.lexer().get(lexpos) {
+            None => {
+                self.errors.push(CompileError::fromPreTo(
+                    "Expected a declaration after 'export'.",
+                    exportTok,
+                ));
+                vec![]
+            }
+            Some(fileTokPosMatchArm!(Token::Import)) => {
+                self.parseModuleImportDeclaration(lexpos, &[], true)
+            }
+            Some(fileTokPosMatchArm!(Token::LBrace)) => {
+                self.lexer().consumeToken(lexpos);
+                self.parseExportBlock(lexpos)
+            }
+            Some(_) => {
+                let attrs = self.parseAttributes(lexpos);
+                self.parseDeclaration(lexpos, attrs.as_slice())
+            }
+        };
+        self.actOnEndExportDeclaration(wasExporting);
+        decls
+    }
+
+    /**
+     * The '{' was already parsed.
+     *   declaration-seq [opt] }
+     */
+    fn parseExportBlock(&mut self, lexpos: &mut StateBufferedLexer) -> Vec<AstDecl> {
+        let mut decls = vec![];
+        loop {
+            if let Some(fileTokPosMatchArm!(tok)) = self.lexer().get(lexpos) {
+                if matches!(tok, Token::RBrace) {
+                    self.lexer().consumeToken(lexpos);
+                    break;
+                }
+                let attrs = self.parseAttributes(lexpos);
+                let newDecls = self.parseDeclaration(lexpos, attrs.as_slice());
+                decls.extend(newDecls);
+            } else {
+                let posErr = self.lexer().getWithOffsetSaturating(lexpos, -1);
+                self.errors.push(CompileError::fromPreTo(
+                    "Expected '}' to end the export block. Maybe insert one here?",
+                    posErr,
+                ));
+                break;
+            }
+        }
+        decls
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    fileTokPosMatchArm,
+    Ast::Common::{AstAttribute, AstDecl},
+    Lex::Token::Token,
+    Parse::BufferedLexer::StateBufferedLexer,
+    Utils::Structs::{CompileError, CompileMsgImpl, FileTokPos, SourceRange, TokPos},
+};
+
+use super::super::super::Parser;
+
+impl Parser {
+    /**
+     * module-import-declaration:
+     *   import-keyword module-name attribute-specifier-seq [opt] ;
+     *   import-keyword module-partition attribute-specifier-seq [opt] ;
+     *   import-keyword header-name attribute-specifier-seq [opt] ;
+     *
+     * The export-keyword [opt] before it was already parsed, if any.
+     */
+    pub fn parseModuleImportDeclaration(
+        &mut self,
+        lexpos: &mut StateBufferedLexer,
+        attr: &[AstAttribute],
+        isExport: bool,
+    ) -> Vec<AstDecl> {
+        let Some(importTok) = self.lexer().getConsumeTokenIfEq(lexpos, Token::Import) else {
+            // We already expected an import keyword. Reaching this is a bug.
+            let posErr = self.lexer().getWithOffsetSaturating(lexpos, 0);
+            self.errors.push(CompileError::fromPreTo(
+                "Expected 'import' keyword. This is a bug. Report is please.",
+                posErr,
+            ));
+            return vec![];
+        };
+
+        if let Some(fileTokPosMatchArm!(Token::ImportableHeaderName(_))) = self.lexer().get(lexpos)
+        {
+            // Header units are handled by the preprocessor. Their declarations
+            // are not imported yet.
+            loop {
+                if self.lexer().getIfEq(lexpos, Token::Semicolon).is_some() {
+                    self.lexer().consumeToken(lexpos);
+                    return self.actOnEmptyDecl(attr, SourceRange::newSingleTok(importTok));
+                } else if !self.lexer().consumeToken(lexpos) {
+                    let pos = self.lexer().getWithOffsetSaturating(lexpos, 0);
+                    self.errors
+                        .push(CompileError::fromPreTo("Expected ';'", pos));
+                    return vec![];
+                }
+            }
+        }
+
+        let moduleName = self.optParseModuleName(lexpos);
+        let modulePartition = self.optParseModulePartition(lexpos);
+        let lastNameTok = self.lexer().getWithOffsetSaturating(lexpos, -1);
+        if moduleName.is_empty() && modulePartition.is_none() {
+            let posErr = self.lexer().getWithOffsetSaturating(lexpos, 0);
+            self.errors.push(CompileError::fromPreTo(
+                "Expected a module name after 'import'.",
+                posErr,
+            ));
+            return vec![];
+        }
+        self.ignoreAttributes(lexpos);
+
+        if !self.lexer().consumeTokenIfEq(lexpos, Token::Semicolon) {
+            let posErr = self.lexer().getWithOffsetSaturating(lexpos, -1);
+            self.errors.push(CompileError::fromPreTo(
+                "Expected ';' at the end of the module import.",
+                posErr,
+            ));
+            return vec![];
+        }
+
+        self.actOnModuleImport(
+            isExport,
+            moduleName,
+            modulePartition,
+            SourceRange::newDoubleTok(importTok, lastNameTok),
+        );
+        self.actOnEmptyDecl(attr, SourceRange::newSingleTok(importTok))
+    }
+}
@@ -63,9 +63,10 @@ impl Parser {
 
         let scope = match nestedNamespaceMatched {
             ParseMatched::Matched => {
-                let Some(scope) = nestedNamespace.last().and_then(|nestedName| {
-                    nestedName.scope.borrow().clone()
-                }) else {
+                let Some(scope) = nestedNamespace
+                    .last()
+                    .and_then(|nestedName| nestedName.scope.borrow().clone())
+                else {
                     self.errors.push(CompileError::fromPreTo(
                         "We were unable to resolve this name. Something's wrong with the nested name specifier",
                         nameTok,
@@ -79,6 +80,7 @@ impl Parser {
         self.actOnUsingNamespaceDefinition(
             name,
             SourceRange::newDoubleTok(usingTok, semiTok),
+            SourceRange::newSingleTok(nameTok),
             attr,
             nestedNamespace,
             scope.as_ref(),
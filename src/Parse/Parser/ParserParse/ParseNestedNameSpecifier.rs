@@ -3,7 +3,7 @@ use crate::{
     Ast::NestedNameSpecifier::{self, AstNestedNameSpecifier},
     Lex::Token::Token,
     Parse::{BufferedLexer::StateBufferedLexer, Parser::ParserParse::ParseMatched},
-    Sema::Scope::ScopeKind,
+    Sema::Scope::{Child, ScopeKind},
     Utils::Structs::{CompileError, CompileMsgImpl, FileTokPos, SourceRange, TokPos},
 };
 
@@ -56,19 +56,28 @@ impl Parser {
                 }
 
                 // Figure out what this name refers to
-                let res = self.unqualifiedNameLookupWithCond(*ident, |child| {
+                let cond: fn(&Child) -> bool = |child| {
                     match child {
                         // namespaces, classes, structs, unions, enums, etc have scope
-                        crate::Sema::Scope::Child::Decl(_) => false,
-                        crate::Sema::Scope::Child::Scope(scope) => scope
+                        Child::Decl(_) => false,
+                        Child::Scope(scope) => scope
                             .borrow()
                             .flags
                             .intersects(ScopeKind::ENUM | ScopeKind::CLASS | ScopeKind::NAMESPACE),
                     }
-                });
+                };
+                let res = self.unqualifiedNameLookupWithCond(*ident, cond);
                 self.lexer().moveForward(lexpos, 2);
                 if res.is_empty() {
-                    if reportNormalErrors {
+                    // Not being visible is always an error, even if we don't
+                    // report the normal ones.
+                    let notVisible = self.diagnoseNotVisible(
+                        *ident,
+                        None,
+                        cond,
+                        &SourceRange::newSingleTok(startingTok.unwrap()),
+                    );
+                    if reportNormalErrors && !notVisible {
                         self.errors.push(CompileError::fromPreTo(
                             "This identifier could not be resolved to a type or namespace.",
                             startingTok.unwrap(),
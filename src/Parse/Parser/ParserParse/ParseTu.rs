@@ -31,6 +31,8 @@ impl Parser {
             }
         }
 
+        self.actOnEndOfTu();
+
         /*WARNING. INVALIDATING PARSER!!! DON'T USE THE PARSER AFTER THIS!*/
         let tuAstContext = std::mem::replace(&mut self.astContext, AstContext::new());
         return AstTu::new(
@@ -204,7 +206,7 @@ impl Parser {
     /// module-name:
     ///   [identifier .? ]*
     /// Notice that there can be an extra dot at the end. This must be checked at call site.
-    fn optParseModuleName(&mut self, lexpos: &mut StateBufferedLexer) -> String {
+    pub fn optParseModuleName(&mut self, lexpos: &mut StateBufferedLexer) -> String {
         let mut moduleName = String::new();
         loop {
             macro_rules! pushName {
@@ -237,7 +239,7 @@ impl Parser {
     ///   : module-name
     /// Notice that there can be an extra dot at the end. This must be checked at call site.
     /// Notice that this can return an empty string. This must be checked at call site.
-    fn optParseModulePartition(&mut self, lexpos: &mut StateBufferedLexer) -> Option<String> {
+    pub fn optParseModulePartition(&mut self, lexpos: &mut StateBufferedLexer) -> Option<String> {
         if let Some(fileTokPosMatchArm!(Token::Colon)) = self.lexer().get(lexpos) {
             self.lexer().consumeToken(lexpos);
             return Some(self.optParseModuleName(lexpos));
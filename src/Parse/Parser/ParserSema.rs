@@ -1,5 +1,6 @@
 mod SemaAttribute;
 mod SemaDeclaration;
 mod SemaLookups;
+mod SemaModule;
 mod SemaNestedNameSpecifier;
 mod SemaTu;
@@ -7,7 +7,7 @@ use crate::Ast::{
 };
 use crate::{
     Ast::NestedNameSpecifier::AstNestedNameSpecifier,
-    Sema::Scope::{Child, RefCellScope, Scope, ScopeKind, ScopeRef},
+    Sema::Scope::{Child, Reachability, RefCellScope, Scope, ScopeKind, ScopeRef},
     Utils::{
         StringRef::StringRef,
         Structs::{CompileError, CompileMsgImpl, SourceRange},
@@ -75,7 +75,7 @@ impl Parser {
         let createNamespace = |parser: &mut Self, scope: ScopeRef| -> AstDeclNamespace {
             return AstDeclNamespace::new(
                 parser.alloc(),
-                locationName,
+                locationName.clone(),
                 scope,
                 parser.alloc().alloc_slice_copy(attr),
                 name,
@@ -95,6 +95,10 @@ impl Parser {
             }
             let astNamespaceDecl = createNamespace(self, originalDecl.clone());
             causingDecl.addExtension(astNamespaceDecl);
+            originalDecl.raiseVisibility(self.declarationVisibility());
+            // Even if it was imported without being visible, this declaration
+            // makes it visible.
+            originalDecl.borrow_mut().reachability = Reachability::Visible;
             self.astContext.currentScope = originalDecl;
             return vec![astNamespaceDecl.into()];
         }
@@ -111,6 +115,7 @@ impl Parser {
                 .currentScope
                 .addChild(name, Child::Scope(enumScope.clone()));
         }
+        enumScope.raiseVisibility(self.declarationVisibility());
         self.astContext.currentScope = enumScope;
         vec![astNamespaceDecl.into()]
     }
@@ -157,6 +162,7 @@ impl Parser {
         self.astContext
             .currentScope
             .addChild(name, Child::Scope(enumScope.clone()));
+        enumScope.raiseVisibility(self.declarationVisibility());
         self.astContext.currentScope = enumScope;
 
         // Imediately pop, for now.
@@ -175,6 +181,7 @@ impl Parser {
         &mut self,
         name: StringRef,
         location: SourceRange,
+        nameLocation: SourceRange,
         attr: &[AstAttribute],
         nestedNameSpecifier: &'static [AstNestedNameSpecifier],
         scope: Option<&ScopeRef>,
@@ -187,6 +194,9 @@ impl Parser {
         let result = if let Some(scope) = scope {
             let result = Self::qualifiedNameLookupWithCond(name, scope, onlyNamespacesFunc);
             let Some(Child::Scope(result)) = result.first() else {
+                    if self.diagnoseNotVisible(name, Some(scope), onlyNamespacesFunc, &location) {
+                        return vec![];
+                    }
                     self.errors.push(CompileError::fromSourceRange(
                     "We were unable to resolve this name. Something may be wrong with the nested name specifier",
                     &location,
@@ -196,6 +206,9 @@ impl Parser {
             result.clone()
         } else {
             let Some(Child::Scope(result)) = self.unqualifiedNameLookupWithCond(name, onlyNamespacesFunc).first().cloned() else {
+                    if self.diagnoseNotVisible(name, None, onlyNamespacesFunc, &location) {
+                        return vec![];
+                    }
                     self.errors.push(CompileError::fromSourceRange(
                         "We were unable to resolve this name",
                         &location,
@@ -214,6 +227,7 @@ impl Parser {
             result,
             attr,
             name,
+            nameLocation,
             nestedNameSpecifier,
         )
         .into()]
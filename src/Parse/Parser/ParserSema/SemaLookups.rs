@@ -1,6 +1,9 @@
 use crate::{
-    Sema::Scope::{Child, Scope, ScopeKind, ScopeRef},
-    Utils::StringRef::StringRef,
+    Sema::Scope::{Child, Reachability, Scope, ScopeKind, ScopeRef},
+    Utils::{
+        StringRef::StringRef,
+        Structs::{CompileError, CompileMsgImpl, CompileNote, SourceRange},
+    },
 };
 
 use super::super::Parser;
@@ -19,11 +22,24 @@ impl Parser {
      * While I'm aware of the rules in 6.4.10 regarding name higing,
      * I think I'd prefer for now to return all the names that match, and apply
      * name hiding later.
+     *
+     * Declarations imported from a module that are not visible in this
+     * translation unit (not exported, or from its global module fragment)
+     * are not found.
      */
     pub fn unqualifiedNameLookupWithCond(
         &self,
         name: StringRef,
         cond: fn(&Child) -> bool,
+    ) -> Vec<Child> {
+        self.unqualifiedNameLookupImpl(name, cond, false)
+    }
+
+    fn unqualifiedNameLookupImpl(
+        &self,
+        name: StringRef,
+        cond: fn(&Child) -> bool,
+        includeHidden: bool,
     ) -> Vec<Child> {
         /*
         Rules 1 to 3 are not accually rules, but comments on how to apply the rules.
@@ -42,7 +58,8 @@ impl Parser {
                 unsafe { self.astContext.currentScope.try_borrow_unguarded() }.unwrap();
             return loop {
                 let mut result =
-                    Self::getChildsAndAliased(name, currVisitingScope, cond).peekable();
+                    Self::getChildsAndAliased(name, currVisitingScope, cond, includeHidden)
+                        .peekable();
                 if result.peek().is_some() {
                     break result.cloned().collect::<Vec<_>>();
                 }
@@ -75,26 +92,29 @@ impl Parser {
         scope: &Scope,
         name: StringRef,
         cond: fn(&Child) -> bool,
+        includeHidden: bool,
     ) -> impl Iterator<Item = &Child> {
         scope
             .childs
             .get(&name)
             .into_iter()
             .flatten()
-            .filter(move |x| cond(x))
+            .filter(move |x| cond(x) && (includeHidden || x.isVisible()))
     }
     fn getChildsAndAliased<'scope>(
         name: StringRef,
         scope: &'scope Scope,
         cond: fn(&Child) -> bool,
+        includeHidden: bool,
     ) -> Box<dyn Iterator<Item = &'scope Child> + 'scope> {
         return Box::new(
-            Self::getChilds(scope, name, cond)
+            Self::getChilds(scope, name, cond, includeHidden)
                 .chain(scope.inlinedNamespaces.iter().flat_map(move |x| {
                     Self::getChildsAndAliased(
                         name,
                         unsafe { x.try_borrow_unguarded() }.unwrap(),
                         cond,
+                        includeHidden,
                     )
                 }))
                 .chain(scope.usingNamespaces.iter().flat_map(move |x| {
@@ -102,6 +122,7 @@ impl Parser {
                         name,
                         unsafe { x.try_borrow_unguarded() }.unwrap(),
                         cond,
+                        includeHidden,
                     )
                 })),
         );
@@ -114,18 +135,18 @@ impl Parser {
         name: StringRef,
         scope: &'scope Scope,
         cond: fn(&Child) -> bool,
+        includeHidden: bool,
     ) -> Box<dyn Iterator<Item = &'scope Child> + 'scope> {
-        Box::new(
-            Self::getChilds(scope, name, cond).chain(scope.inlinedNamespaces.iter().flat_map(
-                move |x| {
-                    Self::getChildsAndOnlyInlined(
-                        name,
-                        unsafe { x.try_borrow_unguarded() }.unwrap(),
-                        cond,
-                    )
-                },
-            )),
-        )
+        Box::new(Self::getChilds(scope, name, cond, includeHidden).chain(
+            scope.inlinedNamespaces.iter().flat_map(move |x| {
+                Self::getChildsAndOnlyInlined(
+                    name,
+                    unsafe { x.try_borrow_unguarded() }.unwrap(),
+                    cond,
+                    includeHidden,
+                )
+            }),
+        ))
     }
 
     #[allow(clippy::needless_lifetimes)]
@@ -149,27 +170,41 @@ impl Parser {
         name: StringRef,
         scope: &'scope Scope,
         cond: fn(&Child) -> bool,
+        includeHidden: bool,
     ) -> Box<dyn Iterator<Item = &'scope Child> + 'scope> {
         // Rule 2: Check namespace scope and all the inlined namespaces
-        let mut result = Self::getChildsAndOnlyInlined(name, scope, cond).peekable();
+        let mut result = Self::getChildsAndOnlyInlined(name, scope, cond, includeHidden).peekable();
         if result.peek().is_some() {
             return Box::new(result);
         }
         // Rule 3: If nothing found, check the using namespaces in the same way, and make a union of them.
         Box::new(Self::getAllUsingNamespaceInlined(scope).flat_map(move |x| {
-            Self::qualifiedNameLookupOnNamespace(name, unsafe { &*x.as_ptr() }, cond)
+            Self::qualifiedNameLookupOnNamespace(name, unsafe { &*x.as_ptr() }, cond, includeHidden)
         }))
     }
 
+    /**
+     * Like the unqualified lookup, the declarations that are not visible in
+     * this translation unit are not found.
+     */
     pub fn qualifiedNameLookupWithCond(
         name: StringRef,
         scope: &ScopeRef,
         cond: fn(&Child) -> bool,
+    ) -> Vec<Child> {
+        Self::qualifiedNameLookupImpl(name, scope, cond, false)
+    }
+
+    fn qualifiedNameLookupImpl(
+        name: StringRef,
+        scope: &ScopeRef,
+        cond: fn(&Child) -> bool,
+        includeHidden: bool,
     ) -> Vec<Child> {
         // Namespace qualified?
         let scope = scope.borrow();
         if scope.flags.contains(ScopeKind::NAMESPACE) {
-            return Self::qualifiedNameLookupOnNamespace(name, &scope, cond)
+            return Self::qualifiedNameLookupOnNamespace(name, &scope, cond, includeHidden)
                 .cloned()
                 .collect::<Vec<_>>();
         }
@@ -188,18 +223,74 @@ impl Parser {
      * set of that namespace, the namespace-definition extends the previously-declared namespace. Otherwise, the
      * identifier is introduced as a namespace-name into the declarative region in which the named-namespace-definition
      * appears
+     *
+     * Namespaces that are not visible (imported from a module that does not
+     * export them) are also extended: it is still the same namespace.
      */
     pub fn namespaceExtendableLookup(&self, name: StringRef) -> Option<ScopeRef> {
         let currentScope = self.astContext.currentScope.borrow();
-        let candidate = Self::getChildsAndOnlyInlined(name, &currentScope, |scope: &Child| {
-            let Child::Scope(scope) = scope else {return false;};
-            scope.borrow().flags == ScopeKind::NAMESPACE | ScopeKind::CAN_DECL
-        })
+        let candidate = Self::getChildsAndOnlyInlined(
+            name,
+            &currentScope,
+            |scope: &Child| {
+                let Child::Scope(scope) = scope else {
+                    return false;
+                };
+                scope.borrow().flags == ScopeKind::NAMESPACE | ScopeKind::CAN_DECL
+            },
+            true,
+        )
         .map(|scope| {
-            let Child::Scope(scope) = scope else {unreachable!();};
+            let Child::Scope(scope) = scope else {
+                unreachable!();
+            };
             scope.clone()
         })
         .next();
         candidate
     }
+
+    /**
+     * After a lookup found nothing, reports if it is because the declaration
+     * was imported from a module that does not make it visible here. The
+     * lookup is qualified if `scope` is given. Returns if it was reported.
+     */
+    pub fn diagnoseNotVisible(
+        &mut self,
+        name: StringRef,
+        scope: Option<&ScopeRef>,
+        cond: fn(&Child) -> bool,
+        location: &SourceRange,
+    ) -> bool {
+        let candidates = scope.map_or_else(
+            || self.unqualifiedNameLookupImpl(name, cond, true),
+            |scope| Self::qualifiedNameLookupImpl(name, scope, cond, true),
+        );
+        let Some(hidden) = candidates.iter().find_map(|candidate| {
+            let scope = candidate.getScope()?;
+            let scope = scope.borrow();
+            (scope.reachability != Reachability::Visible)
+                .then(|| (scope.reachability, scope.causingDecl))
+        }) else {
+            return false;
+        };
+        let message = match hidden.0 {
+            Reachability::NotExported(module) => {
+                format!("Declaration of {name} is not exported by module {module}")
+            }
+            Reachability::GlobalModuleFragment(module) => format!(
+                "Declaration of {name} is in the global module fragment of module {module}, which is not visible to its importers"
+            ),
+            Reachability::Visible => unreachable!(),
+        };
+        self.errors
+            .push(CompileError::fromSourceRange(message, location));
+        if let Some(decl) = hidden.1 {
+            self.errors.push(CompileNote::fromSourceRange(
+                "Declared here",
+                &decl.getSourceRange(),
+            ));
+        }
+        true
+    }
 }
@@ -0,0 +1,228 @@
+use crate::{
+    ModuleTree::Structs::ModuleDeclaration,
+    Parse::Parser::ModuleImportState,
+    Sema::{
+        Bmi::{bmiPath, sourceHash, Bmi, BmiImport},
+        Scope::ModuleVisibility,
+    },
+    Utils::{
+        StringRef::{StringRef, ToStringRef},
+        Structs::{CompileError, CompileMsgImpl, SourceRange},
+    },
+};
+
+use super::super::Parser;
+
+impl Parser {
+    /// Module declaration of this unit, as found by the dependency scanning
+    fn moduleDeclaration(&self) -> ModuleDeclaration {
+        self.compilerState
+            .compileUnits
+            .get(&self.filePath)
+            .unwrap()
+            .moduleKind
+            .lock()
+            .unwrap()
+            .module
+            .0
+    }
+
+    /// Name of the module this unit belongs to, if any
+    fn currentModuleName(&self) -> Option<StringRef> {
+        match self.moduleDeclaration() {
+            ModuleDeclaration::ExportPrimary(module)
+            | ModuleDeclaration::Primary(module)
+            | ModuleDeclaration::ExportPartition(module, _)
+            | ModuleDeclaration::Partition(module, _) => Some(module),
+            ModuleDeclaration::ModuleHeaderUnit(_) | ModuleDeclaration::Global(_) => None,
+        }
+    }
+
+    /// Are we in the purview of a module interface unit?
+    fn inInterfacePurview(&self) -> bool {
+        matches!(
+            self.moduleImportState,
+            ModuleImportState::ImportSection | ModuleImportState::CodeSection
+        ) && matches!(
+            self.moduleDeclaration(),
+            ModuleDeclaration::ExportPrimary(_) | ModuleDeclaration::ExportPartition(_, _)
+        )
+    }
+
+    /// Visibility, for the importers of this module, of a declaration found
+    /// at this point
+    pub const fn declarationVisibility(&self) -> ModuleVisibility {
+        match self.moduleImportState {
+            ModuleImportState::ImportSection | ModuleImportState::CodeSection => {
+                if self.exporting {
+                    ModuleVisibility::Exported
+                } else {
+                    ModuleVisibility::Module
+                }
+            }
+            ModuleImportState::GlobalSection => ModuleVisibility::GlobalModuleFragment,
+            _ => ModuleVisibility::NotInModule,
+        }
+    }
+
+    /**
+     * Start of an export-declaration. Returns if we were already inside one,
+     * to restore it at the end.
+     */
+    pub fn actOnStartExportDeclaration(&mut self, location: SourceRange) -> bool {
+        if self.exporting {
+            self.errors.push(CompileError::fromSourceRange(
+                "Export declarations can't be nested in other export declarations.",
+                &location,
+            ));
+        } else if !self.inInterfacePurview() {
+            self.errors.push(CompileError::fromSourceRange(
+                "Export declarations are only allowed in the purview of a module interface unit.",
+                &location,
+            ));
+        }
+        std::mem::replace(&mut self.exporting, true)
+    }
+
+    /**
+     * End of an export-declaration.
+     */
+    pub const fn actOnEndExportDeclaration(&mut self, wasExporting: bool) {
+        self.exporting = wasExporting;
+    }
+
+    /**
+     * module-import-declaration:
+     *   export-keyword [opt] import-keyword module-name attribute-specifier-seq [opt] ;
+     *   export-keyword [opt] import-keyword module-partition attribute-specifier-seq [opt] ;
+     */
+    pub fn actOnModuleImport(
+        &mut self,
+        isExport: bool,
+        moduleName: String,
+        modulePartition: Option<String>,
+        location: SourceRange,
+    ) {
+        let module = match modulePartition {
+            Some(partition) => {
+                // Importing a partition outside of a module is reported when
+                // generating the module tree.
+                let Some(current) = self.currentModuleName() else {
+                    return;
+                };
+                format!("{current}:{partition}")
+            }
+            None => moduleName,
+        };
+        if matches!(
+            self.moduleImportState,
+            ModuleImportState::ImportSection | ModuleImportState::CodeSection
+        ) {
+            self.moduleImports.push(BmiImport {
+                module: module.clone(),
+                exported: isExport && self.inInterfacePurview(),
+            });
+        }
+        self.importModule(&module, &location);
+    }
+
+    /**
+     * Loads the BMI of a module in the root scope, and transitively the BMIs
+     * of the modules it imports that become visible: the ones it exports, or
+     * all of them if it belongs to the same module as this unit. Errors are
+     * reported at the import that caused the load.
+     */
+    pub fn importModule(&mut self, module: &str, location: &SourceRange) {
+        if !self.importedModules.insert(module.to_string()) {
+            return;
+        }
+        let path = bmiPath(&self.compilerState.parameters.bmiDir, module);
+        let bmi = match self.compilerState.bmiCache.read(&path) {
+            Ok(bmi) => bmi,
+            Err(err) => {
+                self.errors.push(CompileError::fromSourceRange(
+                    format!(
+                        "Can't load the BMI of module {module} ({}): {err}",
+                        path.display()
+                    ),
+                    location,
+                ));
+                return;
+            }
+        };
+
+        let primaryModule = module.split(':').next().unwrap_or(module);
+        let sameModule = self
+            .currentModuleName()
+            .is_some_and(|current| primaryModule == current.as_ref());
+        let alloc = self.alloc();
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        let currentHash = compileFiles
+            .getPath(&bmi.source)
+            .ok()
+            .map(|source| sourceHash(compileFiles.getOpenedFile(source).content()));
+        if currentHash.as_ref() != Some(&bmi.sourceHash) {
+            drop(compileFiles);
+            self.errors.push(CompileError::fromSourceRange(
+                format!(
+                    "The BMI of module {module} ({}) is out of date: {} changed after it was written. Rebuild the module.",
+                    path.display(),
+                    bmi.source
+                ),
+                location,
+            ));
+            return;
+        }
+        bmi.load(
+            &self.astContext.rootScope,
+            &mut self.astContext.typeDict,
+            alloc,
+            &mut compileFiles,
+            primaryModule.to_StringRef(),
+            sameModule,
+        );
+        drop(compileFiles);
+
+        for import in &bmi.imports {
+            if sameModule || import.exported {
+                self.importModule(&import.module, location);
+            }
+        }
+    }
+
+    /**
+     * End of the translation unit. Module interface units and partitions
+     * write their BMI.
+     */
+    pub fn actOnEndOfTu(&mut self) {
+        let module = match self.moduleDeclaration() {
+            ModuleDeclaration::ExportPrimary(module) => module.to_string(),
+            ModuleDeclaration::ExportPartition(module, partition)
+            | ModuleDeclaration::Partition(module, partition) => format!("{module}:{partition}"),
+            _ => return,
+        };
+        let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+        let file = compileFiles.getOpenedFile(self.filePath);
+        let bmi = Bmi::new(
+            module.clone(),
+            file.path().clone(),
+            sourceHash(file.content()),
+            std::mem::take(&mut self.moduleImports),
+            &self.astContext.rootScope,
+            &self.astContext.typeDict,
+            &mut compileFiles,
+        );
+        drop(compileFiles);
+
+        let path = bmiPath(&self.compilerState.parameters.bmiDir, &module);
+        if let Err(err) = self.compilerState.bmiCache.write(&path, bmi) {
+            self.errors.push(CompileError::onFile(
+                format!(
+                    "Can't write the BMI of module {module} to {}: {err}",
+                    path.display()
+                ),
+                self.filePath,
+            ));
+        }
+    }
+}
@@ -18,32 +18,36 @@ impl Parser {
             // Resolve all namespaces
             loop {
                 let currentUnresolved = &remainingNameSpecifier[0];
-                let currentResolvedScopeScope = currentResolvedScope.scope.borrow();
-                if currentResolvedScopeScope.is_none() {
+                let Some(currentResolvedScopeScope) = currentResolvedScope.scope.borrow().clone()
+                else {
                     return; // Previous resolution failed...
-                }
-                let currentResolvedScopeScope = currentResolvedScopeScope.as_ref().unwrap();
-                let candidates = Self::qualifiedNameLookup(
-                    currentUnresolved.getName(),
-                    currentResolvedScopeScope,
-                )
-                .into_iter()
+                };
                 /* We want only types, enums and namespaces*/
-                .filter(|x| match x {
+                let cond: fn(&Child) -> bool = |x| match x {
                     Child::Decl(_) => false,
                     Child::Scope(scope) => scope
                         .borrow()
                         .flags
                         .intersects(ScopeKind::NAMESPACE | ScopeKind::ENUM | ScopeKind::CLASS),
-                })
-                .collect::<Vec<_>>();
+                };
+                let candidates = Self::qualifiedNameLookupWithCond(
+                    currentUnresolved.getName(),
+                    &currentResolvedScopeScope,
+                    cond,
+                );
                 if candidates.len() > 1 {
                     self.errors.push(CompileError::fromSourceRange(
                         "Ambiguous name, compiler bug, please report.",
                         &currentUnresolved.sourceRange,
                     ));
                 } else if candidates.is_empty() {
-                    if reportNormalErrors {
+                    let notVisible = self.diagnoseNotVisible(
+                        currentUnresolved.getName(),
+                        Some(&currentResolvedScopeScope),
+                        cond,
+                        &currentUnresolved.sourceRange,
+                    );
+                    if reportNormalErrors && !notVisible {
                         self.errors.push(CompileError::fromSourceRange(
                             "The name could not be resolved to a type, enum or namespace.",
                             &currentUnresolved.sourceRange,
@@ -75,7 +75,7 @@ impl Parser {
                     .module
                     .0;
                 #[rustfmt::skip]
-                let mismatch = match (initialModuleDecl, isExport, moduleName, modulePartition) {
+                let mismatch = match (initialModuleDecl, isExport, &moduleName, &modulePartition) {
                     (crate::ModuleTree::Structs::ModuleDeclaration::ExportPrimary(iniModuleName), true, moduleName, None) if iniModuleName.as_ref() == moduleName.as_str() => {false},
                     (crate::ModuleTree::Structs::ModuleDeclaration::Primary(iniModuleName), false, moduleName, None) if iniModuleName.as_ref() == moduleName.as_str() => {false},
                     (crate::ModuleTree::Structs::ModuleDeclaration::ExportPartition(iniModuleName, iniModulePartition), true, moduleName, Some(modulePartition)) if iniModuleName.as_ref() == moduleName.as_str() && iniModulePartition.as_ref() == modulePartition.as_str() => {false},
@@ -91,6 +91,11 @@ impl Parser {
                     ));
                     return;
                 }
+                if !isExport && modulePartition.is_none() {
+                    // Module implementation units implicitly import their
+                    // primary module interface unit.
+                    self.importModule(&moduleName, &location);
+                }
             }
         } else {
             self.errors.push(CompileError::fromSourceRange(
@@ -4,10 +4,19 @@
 //! tokenization done by the [prelexer], followed by the preprocessor, mostly
 //! driven by the [driver], and it's submodules.
 
+pub mod Amalgamate;
+pub mod Callbacks;
+pub mod ConditionalReport;
 pub mod Driver;
+pub mod IncludeTree;
+pub mod MacroIndex;
+pub mod MacroTrace;
+pub mod Minimize;
 pub mod Multilexer;
 pub mod Prelexer;
 pub mod Pretoken;
 pub mod Structs;
+pub mod TokenCache;
+pub mod Unifdef;
 
 pub use Driver::*;
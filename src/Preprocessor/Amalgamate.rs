@@ -0,0 +1,241 @@
+//! Header amalgamation: bundles a file and the project headers it includes
+//! into a single self-contained file.
+//!
+//! Only `#include "..."` directives of headers found through the include
+//! directories are inlined. System headers (and anything that can't be
+//! resolved) are left as `#include` lines. So are the computed includes
+//! (`#include MACRO`), which are reported as errors: the header they include
+//! depends on the macros defined at that point, which the amalgamation does
+//! not track. Neither are the includes inside conditional blocks
+//! (`#if`/`#ifdef`/...), other than the include guard of their file, which
+//! are reported as warnings: whether they are included depends on the macros
+//! too, and inlining them would wrongly skip the guarded headers included
+//! again later. Headers with include guards or `#pragma once` are inlined
+//! only the first time they are included, in dependency order. Headers
+//! without them are inlined every time, as they may be meant to be included
+//! several times.
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    Preprocessor::{Preprocessor, Pretoken::PreToken, Structs::SourceLine},
+    Utils::{
+        FileMap::FileMap,
+        Structs::{CompileError, CompileMsg, CompileMsgImpl, CompileWarning, FileTokPos, TokPos},
+    },
+};
+
+/// Bundles a file and its project headers
+pub struct Amalgamator<'a> {
+    /// Files of the compilation
+    fileMap: &'a Arc<Mutex<FileMap>>,
+    /// Guarded headers already inlined
+    inlinedOnce: HashSet<u64>,
+    /// Files being inlined. Used to break include cycles
+    stack: Vec<u64>,
+    /// Resulting source
+    output: String,
+    /// Problems found while bundling
+    messages: Vec<CompileMsg>,
+}
+
+impl<'a> Amalgamator<'a> {
+    /// Bundles the file, returning the resulting source and the problems found
+    pub fn run(fileMap: &'a Arc<Mutex<FileMap>>, file: u64) -> (String, Vec<CompileMsg>) {
+        let mut amalgamator = Self {
+            fileMap,
+            inlinedOnce: HashSet::new(),
+            stack: vec![],
+            output: String::new(),
+            messages: vec![],
+        };
+        amalgamator.inlineFile(file, None);
+        (amalgamator.output, amalgamator.messages)
+    }
+
+    /// Is it a `#pragma once` directive?
+    fn isPragmaOnce(line: &SourceLine) -> bool {
+        line.directive().is_some_and(|(name, rest)| {
+            name.tok.to_str() == "pragma" && rest.first().is_some_and(|t| t.tok.to_str() == "once")
+        })
+    }
+
+    /// Is the file protected against multiple inclusion? Either by a
+    /// `#pragma once`, or by an include guard surrounding the whole file
+    fn isGuarded(lines: &[SourceLine]) -> bool {
+        lines.iter().any(Self::isPragmaOnce) || Self::hasIncludeGuard(lines)
+    }
+
+    /// Is the whole file surrounded by an include guard?
+    fn hasIncludeGuard(lines: &[SourceLine]) -> bool {
+        let significant = lines
+            .iter()
+            .filter(|line| {
+                line.toks
+                    .iter()
+                    .any(|tok| !matches!(tok.tok, PreToken::Whitespace(_)))
+            })
+            .collect::<Vec<_>>();
+        let [first, second, .., last] = significant.as_slice() else {
+            return false;
+        };
+        let guardName = match first.directive() {
+            Some((name, rest)) if name.tok.to_str() == "ifndef" => rest,
+            Some((name, rest)) if name.tok.to_str() == "if" => rest
+                .into_iter()
+                .filter(|tok| !matches!(tok.tok, PreToken::OperatorPunctuator("(" | ")")))
+                .skip_while(|tok| matches!(tok.tok.to_str(), "!" | "defined"))
+                .collect(),
+            _ => return false,
+        };
+        let [TokPos {
+            tok: PreToken::Ident(guard),
+            ..
+        }] = guardName.as_slice()
+        else {
+            return false;
+        };
+        let defined = second.directive().is_some_and(|(name, rest)| {
+            name.tok.to_str() == "define"
+                && matches!(rest.first(), Some(TokPos { tok: PreToken::Ident(id), .. }) if id == guard)
+        });
+        if !defined {
+            return false;
+        }
+        // The #endif of the guard must be the last line of the file
+        let mut depth = 0_usize;
+        for line in &significant {
+            match line.directive().map(|(name, _)| name.tok.to_str()) {
+                Some("if" | "ifdef" | "ifndef") => depth += 1,
+                Some("endif") => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return std::ptr::eq(*line, *last);
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// If the line includes a project header with `#include "..."`, the
+    /// header and the path as written. `conditional` tells if the line is
+    /// inside a conditional block, in which case it's not inlined.
+    fn projectInclude(
+        &mut self,
+        file: u64,
+        line: &SourceLine,
+        conditional: bool,
+    ) -> Option<(u64, String)> {
+        let (name, rest) = line.directive()?;
+        if name.tok.to_str() != "include" {
+            return None;
+        }
+        let quoted = match &rest.first()?.tok {
+            PreToken::StringLiteral(_) => true,
+            PreToken::HeaderName(header) => header.starts_with('"'),
+            PreToken::Ident(_) => {
+                self.messages.push(CompileError::fromAt(
+                    "A computed #include can't be amalgamated. It is left as is",
+                    file,
+                    name.start,
+                    Some(name.end),
+                ));
+                return None;
+            }
+            _ => false,
+        };
+        if !quoted {
+            return None;
+        }
+        let path = Preprocessor::checkForInclude(
+            &rest
+                .into_iter()
+                .map(|tok| FileTokPos::new(file, tok))
+                .collect::<VecDeque<_>>(),
+        )?;
+        let mut fileMap = self.fileMap.lock().unwrap();
+        let header = fileMap.getLocalPath(&path);
+        let accessible = header.is_some() || fileMap.hasFileAccess(&path);
+        drop(fileMap);
+        if header.is_some() && conditional {
+            self.messages.push(CompileWarning::fromAt(
+                format!(
+                    "The header {path} is included inside a conditional block, so it can't be \
+                     amalgamated. The #include is left as is"
+                ),
+                file,
+                name.start,
+                Some(name.end),
+            ));
+            return None;
+        }
+        if let Some(header) = header {
+            return Some((header, path));
+        }
+        if !accessible {
+            self.messages.push(CompileWarning::fromAt(
+                format!("Can't find the header {path}. The #include is left as is"),
+                file,
+                name.start,
+                Some(name.end),
+            ));
+        }
+        None
+    }
+
+    /// Inline a file in the output. `includedAs` is the path used to include
+    /// it, None for the main file
+    fn inlineFile(&mut self, file: u64, includedAs: Option<&str>) {
+        let compileFile = self.fileMap.lock().unwrap().getOpenedFile(file);
+        let lines = SourceLine::split(compileFile.content());
+        if let Some(includedAs) = includedAs {
+            if Self::isGuarded(&lines) && !self.inlinedOnce.insert(file) {
+                return;
+            }
+            if self.stack.contains(&file) {
+                self.messages.push(CompileWarning::onFile(
+                    format!("The header {includedAs} includes itself. Skipping the cycle"),
+                    file,
+                ));
+                return;
+            }
+            self.output.push_str("// begin \"");
+            self.output.push_str(includedAs);
+            self.output.push_str("\"\n");
+        } else {
+            self.inlinedOnce.insert(file);
+        }
+        self.stack.push(file);
+
+        // The include guard of the file does not make its lines conditional
+        let unconditionalDepth = usize::from(Self::hasIncludeGuard(&lines));
+        let mut depth = 0_usize;
+        for line in &lines {
+            let conditional = depth > unconditionalDepth;
+            match line.directive().map(|(name, _)| name.tok.to_str()) {
+                Some("if" | "ifdef" | "ifndef") => depth += 1,
+                Some("endif") => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            if let Some((header, path)) = self.projectInclude(file, line, conditional) {
+                self.inlineFile(header, Some(&path));
+            } else if includedAs.is_none() || !Self::isPragmaOnce(line) {
+                self.output.push_str(line.text);
+            }
+        }
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.output.push('\n');
+        }
+
+        self.stack.pop();
+        if let Some(includedAs) = includedAs {
+            self.output.push_str("// end \"");
+            self.output.push_str(includedAs);
+            self.output.push_str("\"\n");
+        }
+    }
+}
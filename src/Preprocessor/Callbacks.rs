@@ -0,0 +1,79 @@
+//! Callbacks to observe the preprocessing, for tools built on top of the
+//! compiler (include analysis, macro usage, ...).
+//!
+//! Register them with
+//! [`Compiler::addPreprocessorCallbacks`](crate::Compiler::Compiler::addPreprocessorCallbacks).
+//! Translation units are preprocessed in parallel, so the callbacks can be
+//! called from several threads at the same time. All the methods do nothing
+//! by default, so implementors only override the events they want.
+use std::fmt::Debug;
+
+use crate::{
+    Grammars::DefineAst::DefineAst, Preprocessor::Pretoken::PreToken, Utils::Structs::FileTokPos,
+};
+
+/// Events of the preprocessor. `tu` is the translation unit being
+/// preprocessed. Directives are located by the token with their name
+#[allow(unused_variables)]
+pub trait PreprocessorCallbacks: Debug + Send + Sync {
+    /// The preprocessor starts reading `file`. It's also called for the main
+    /// file of the translation unit
+    fn fileEntered(&self, tu: u64, file: u64) {}
+
+    /// The preprocessor finished reading `file`
+    fn fileExited(&self, tu: u64, file: u64) {}
+
+    /// An `#include` found the header `path`, which is `file`. `file` might be
+    /// a module header unit, in that case it's imported instead of entered
+    fn includeResolved(&self, tu: u64, directive: &FileTokPos<PreToken>, path: &str, file: u64) {}
+
+    /// An `#include` could not be resolved. `path` is None if the directive
+    /// did not have a valid header name
+    fn includeFailed(&self, tu: u64, directive: &FileTokPos<PreToken>, path: Option<&str>) {}
+
+    /// A macro was defined (or redefined) by a `#define`
+    fn macroDefined(&self, tu: u64, directive: &FileTokPos<PreToken>, definition: &DefineAst) {}
+
+    /// A macro was removed by an `#undef`
+    fn macroUndefined(&self, tu: u64, directive: &FileTokPos<PreToken>, name: &str) {}
+
+    /// The preprocessor generated a token, after the macro expansions
+    fn tokenGenerated(&self, tu: u64, tok: &FileTokPos<PreToken>) {}
+
+    /// A macro is going to be expanded, invoked by `at`. Also called for the
+    /// expansions inside of `#if` conditions and module directives. The
+    /// translation unit is not known in this event
+    fn macroExpanded(&self, definition: &DefineAst, at: &FileTokPos<PreToken>) {}
+
+    /// A macro name was tested by a condition: in an `#ifdef`/`#ifndef`, in a
+    /// `defined`, or used in an `#if`/`#elif`. `defined` tells if it was
+    /// defined at that point
+    fn macroTested(&self, tu: u64, directive: &FileTokPos<PreToken>, name: &str, defined: bool) {}
+
+    /// The condition of an `#if`, `#ifdef`, `#ifndef` or `#elif` was
+    /// evaluated. Conditions in skipped regions are not evaluated
+    fn conditionalEvaluated(&self, tu: u64, directive: &FileTokPos<PreToken>, value: bool) {}
+
+    /// A `#pragma`, with the tokens that follow it
+    fn pragma(&self, tu: u64, directive: &FileTokPos<PreToken>, toks: &[FileTokPos<PreToken>]) {}
+
+    /// A module declaration, with its macro-expanded tokens
+    fn moduleDirective(
+        &self,
+        tu: u64,
+        directive: &FileTokPos<PreToken>,
+        toks: &[FileTokPos<PreToken>],
+    ) {
+    }
+
+    /// An import directive, with its macro-expanded tokens. Imported header
+    /// units are already resolved into a
+    /// [`PreToken::ImportableHeaderName`]
+    fn importDirective(
+        &self,
+        tu: u64,
+        directive: &FileTokPos<PreToken>,
+        toks: &[FileTokPos<PreToken>],
+    ) {
+    }
+}
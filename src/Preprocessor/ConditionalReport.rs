@@ -0,0 +1,258 @@
+//! Report of the conditional structure (`#if`/`#elif`/`#else`/`#endif`) seen
+//! by the preprocessor, with the regions that were skipped.
+//!
+//! Editors use it to grey out inactive code exactly as the compiler saw it.
+//! It is only recorded when requested, see
+//! [`Preprocessor::withConditionalReport`](super::Preprocessor::withConditionalReport),
+//! or for a whole compilation
+//! [`Compiler::recordConditionalReports`](crate::Compiler::Compiler::recordConditionalReports).
+use std::sync::{Arc, Mutex};
+
+use json::{object, JsonValue};
+
+use crate::{
+    Preprocessor::Pretoken::PreToken,
+    Utils::{
+        FileMap::FileMap,
+        Structs::{CompileFile, FileTokPos, SourceRange},
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A branch of a conditional group: the `#if` itself, or one of its
+/// `#elif`/`#else`
+pub struct ConditionalBranch {
+    /// Directive that opens the branch (if, ifdef, ifndef, elif, else)
+    pub kind: String,
+    /// Text of the condition, as written in the source
+    pub condition: String,
+    /// Result of the condition. None if it was not evaluated
+    pub evaluated: Option<bool>,
+    /// The tokens of this branch were compiled
+    pub taken: bool,
+    /// The directive line(s)
+    pub directive: SourceRange,
+    /// The contents of the branch, up to the next directive of the group
+    pub body: SourceRange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A whole `#if` ... `#endif` group
+pub struct ConditionalGroup {
+    /// The group was reached in an active region. If not, none of its
+    /// conditions were evaluated
+    pub active: bool,
+    /// Branches of the group, in order
+    pub branches: Vec<ConditionalBranch>,
+    /// The `#endif` line. None if the file ended before it
+    pub endif: Option<SourceRange>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Conditional groups processed by a preprocessor
+pub struct ConditionalReport {
+    /// All the groups, in the order they were opened
+    groups: Vec<ConditionalGroup>,
+    /// Groups that have not reached their `#endif` yet
+    open: Vec<usize>,
+}
+
+impl ConditionalReport {
+    /// Offset of the end of the line where `pos` is, skipping escaped newlines
+    fn lineEnd(content: &str, pos: usize) -> usize {
+        let mut end = pos;
+        loop {
+            match content[end..].find('\n') {
+                Some(nl) if content[..end + nl].ends_with('\\') => end += nl + 1,
+                Some(nl) => return end + nl,
+                None => return content.len(),
+            }
+        }
+    }
+
+    /// Offset of the start of the line where `pos` is
+    fn lineStart(content: &str, pos: usize) -> usize {
+        content[..pos].rfind('\n').map_or(0, |nl| nl + 1)
+    }
+
+    /// Creates a new branch from its directive
+    fn newBranch(
+        file: &CompileFile,
+        hash: &FileTokPos<PreToken>,
+        operation: &FileTokPos<PreToken>,
+        evaluated: Option<bool>,
+        taken: bool,
+    ) -> ConditionalBranch {
+        let content = file.content();
+        let end = Self::lineEnd(content, operation.tokPos.end);
+        let bodyStart = (end + 1).min(content.len());
+        ConditionalBranch {
+            kind: operation.tokPos.tok.to_str().to_string(),
+            condition: content[operation.tokPos.end..end]
+                .replace("\\\n", " ")
+                .trim()
+                .to_string(),
+            evaluated,
+            taken,
+            directive: SourceRange {
+                startfile: hash.file,
+                endfile: hash.file,
+                start: Self::lineStart(content, hash.tokPos.start),
+                end,
+                expansion: None,
+            },
+            body: SourceRange {
+                startfile: hash.file,
+                endfile: hash.file,
+                start: bodyStart,
+                end: bodyStart,
+                expansion: None,
+            },
+        }
+    }
+
+    /// Ends the body of the last branch of the innermost open group, at the
+    /// line of `hash`
+    fn endLastBranch(&mut self, file: &CompileFile, hash: &FileTokPos<PreToken>) -> Option<usize> {
+        let group = *self.open.last()?;
+        if let Some(branch) = self.groups[group].branches.last_mut() {
+            branch.body.end =
+                Self::lineStart(file.content(), hash.tokPos.start).max(branch.body.start);
+        }
+        Some(group)
+    }
+
+    /// A new group is opened by an `#if`, `#ifdef` or `#ifndef`
+    pub fn openGroup(
+        &mut self,
+        file: &CompileFile,
+        hash: &FileTokPos<PreToken>,
+        operation: &FileTokPos<PreToken>,
+        evaluated: Option<bool>,
+        taken: bool,
+    ) {
+        self.open.push(self.groups.len());
+        self.groups.push(ConditionalGroup {
+            active: evaluated.is_some(),
+            branches: vec![Self::newBranch(file, hash, operation, evaluated, taken)],
+            endif: None,
+        });
+    }
+
+    /// An `#elif` or `#else` of the innermost open group
+    pub fn addBranch(
+        &mut self,
+        file: &CompileFile,
+        hash: &FileTokPos<PreToken>,
+        operation: &FileTokPos<PreToken>,
+        evaluated: Option<bool>,
+        taken: bool,
+    ) {
+        if let Some(group) = self.endLastBranch(file, hash) {
+            self.groups[group]
+                .branches
+                .push(Self::newBranch(file, hash, operation, evaluated, taken));
+        }
+    }
+
+    /// The `#endif` of the innermost open group
+    pub fn closeGroup(
+        &mut self,
+        file: &CompileFile,
+        hash: &FileTokPos<PreToken>,
+        operation: &FileTokPos<PreToken>,
+    ) {
+        if let Some(group) = self.endLastBranch(file, hash) {
+            let start = Self::lineStart(file.content(), hash.tokPos.start);
+            self.groups[group].endif = Some(SourceRange {
+                startfile: hash.file,
+                endfile: hash.file,
+                start,
+                end: Self::lineEnd(file.content(), operation.tokPos.end),
+                expansion: None,
+            });
+            self.open.pop();
+        }
+    }
+
+    /// The file ended. Unterminated groups extend until the end of their file
+    pub fn finish(&mut self, fileMap: &Arc<Mutex<FileMap>>) {
+        let mut fileMap = fileMap.lock().unwrap();
+        for group in self.open.drain(..) {
+            if let Some(branch) = self.groups[group].branches.last_mut() {
+                branch.body.end = fileMap.getOpenedFile(branch.body.startfile).content().len();
+            }
+        }
+    }
+
+    /// The recorded groups
+    pub fn groups(&self) -> &[ConditionalGroup] {
+        &self.groups
+    }
+
+    /// Regions whose tokens were skipped. Regions nested inside other skipped
+    /// regions are not reported
+    pub fn skippedRanges(&self) -> Vec<SourceRange> {
+        self.groups
+            .iter()
+            .filter(|group| group.active)
+            .flat_map(|group| group.branches.iter())
+            .filter(|branch| !branch.taken)
+            .map(|branch| branch.body.clone())
+            .collect()
+    }
+
+    /// JSON representation of a position
+    fn posToJson(file: &CompileFile, pos: usize) -> JsonValue {
+        let (line, column) = file.getRowColumn(pos);
+        object! {
+            offset: pos,
+            line: line,
+            column: column,
+        }
+    }
+
+    /// JSON representation of a range
+    fn rangeToJson(fileMap: &mut FileMap, range: &SourceRange) -> JsonValue {
+        let file = fileMap.getOpenedFile(range.startfile);
+        object! {
+            file: file.path().as_str(),
+            start: Self::posToJson(&file, range.start),
+            end: Self::posToJson(&file, range.end),
+        }
+    }
+
+    /// JSON representation of the report
+    pub fn toJson(&self, fileMap: &Arc<Mutex<FileMap>>) -> JsonValue {
+        let mut fileMap = fileMap.lock().unwrap();
+        let mut groups = JsonValue::new_array();
+        for group in &self.groups {
+            let mut branches = JsonValue::new_array();
+            for branch in &group.branches {
+                let _ = branches.push(object! {
+                    kind: branch.kind.as_str(),
+                    condition: branch.condition.as_str(),
+                    evaluated: branch.evaluated,
+                    taken: branch.taken,
+                    directive: Self::rangeToJson(&mut fileMap, &branch.directive),
+                    body: Self::rangeToJson(&mut fileMap, &branch.body),
+                });
+            }
+            let _ = groups.push(object! {
+                active: group.active,
+                branches: branches,
+                endif: group.endif.as_ref().map(|endif| Self::rangeToJson(&mut fileMap, endif)),
+            });
+        }
+        let skipped = self
+            .skippedRanges()
+            .iter()
+            .map(|range| Self::rangeToJson(&mut fileMap, range))
+            .collect::<Vec<_>>();
+        drop(fileMap);
+        object! {
+            groups: groups,
+            skipped: skipped,
+        }
+    }
+}
@@ -6,8 +6,10 @@ use crate::{
     fileTokPosMatchArm,
     Compiler::TranslationUnit,
     Utils::{
-        CompilerState::CompilerState, ModuleHeaderAtomicLexingList::ModuleHeaderAtomicLexingList,
-        StateCompileUnit::StageCompileUnit, Structs::TokPos,
+        CompilerState::CompilerState,
+        ModuleHeaderAtomicLexingList::ModuleHeaderAtomicLexingList,
+        StateCompileUnit::StageCompileUnit,
+        Structs::{CompileFile, SourceRange, TokPos},
     },
 };
 use std::{
@@ -25,7 +27,9 @@ use crate::{
 use multiset::HashMultiSet;
 
 use super::{
-    Multilexer::MultiLexer,
+    Callbacks::PreprocessorCallbacks,
+    ConditionalReport::ConditionalReport,
+    Multilexer::{FileChange, MultiLexer},
     Pretoken::{PreToken, PreprocessingOperator},
 };
 
@@ -75,6 +79,9 @@ pub struct Preprocessor {
     definitions: HashMap<String, DefineAst>,
     /// Macros that are disabled in the preprocessor at this point in the evaluation.
     disabledMacros: HashMultiSet<String>,
+    /// Macros defined in the main file that have not been used yet, if
+    /// warning about them was requested.
+    unusedMacros: HashMap<String, SourceRange>,
     /// The preprocessor is at the start of a line. No tokens have been found
     /// yet in this one, except for whitespace.
     atStartLine: bool,
@@ -82,6 +89,8 @@ pub struct Preprocessor {
     alreadyEmittedEnd: bool,
     /// If we are parsing a module header, this is the ditributor of tasks so we can parse another file.
     moduleHeaderAtomicLexingList: Option<Arc<ModuleHeaderAtomicLexingList>>,
+    /// If requested, the conditional structure found so far.
+    conditionalReport: Option<ConditionalReport>,
 }
 
 impl Preprocessor {
@@ -89,16 +98,22 @@ impl Preprocessor {
     pub fn new(data: (CompilerState, TranslationUnit)) -> Self {
         Self {
             tu: data.1,
-            compilerState: data.0.clone(),
-            multilexer: MultiLexer::new((data.0.compileFiles, data.1)),
+            compilerState: Self::unitState(&data.0, data.1),
+            multilexer: MultiLexer::new((data.0.compileFiles, data.0.tokenCache, data.1)),
             generated: VecDeque::new(),
             errors: VecDeque::new(),
             scope: vec![],
             definitions: HashMap::new(),
             disabledMacros: HashMultiSet::new(),
+            unusedMacros: HashMap::new(),
             atStartLine: true,
             alreadyEmittedEnd: false,
             moduleHeaderAtomicLexingList: None,
+            conditionalReport: data
+                .0
+                .conditionalReports
+                .as_ref()
+                .map(|_| ConditionalReport::default()),
         }
         .initCustomMacros()
     }
@@ -109,20 +124,97 @@ impl Preprocessor {
     ) -> Self {
         Self {
             tu: data.1,
-            compilerState: data.0.clone(),
-            multilexer: MultiLexer::new((data.0.compileFiles, data.1)),
+            compilerState: Self::unitState(&data.0, data.1),
+            multilexer: MultiLexer::new((data.0.compileFiles, data.0.tokenCache, data.1)),
             generated: VecDeque::new(),
             errors: VecDeque::new(),
             scope: vec![],
             definitions: HashMap::new(),
             disabledMacros: HashMultiSet::new(),
+            unusedMacros: HashMap::new(),
             atStartLine: true,
             alreadyEmittedEnd: false,
             moduleHeaderAtomicLexingList: Some(moduleHeaderAtomicLexingList),
+            conditionalReport: data
+                .0
+                .conditionalReports
+                .as_ref()
+                .map(|_| ConditionalReport::default()),
         }
         .initCustomMacros()
     }
 
+    /// The compiler state of the preprocessor of `tu`. Its macro tracer, if
+    /// any, records the steps of `tu` apart from the other translation units.
+    fn unitState(compilerState: &CompilerState, tu: TranslationUnit) -> CompilerState {
+        let mut compilerState = compilerState.clone();
+        if let Some(tracer) = &compilerState.macroTracer {
+            compilerState.macroTracer = Some(Arc::new(tracer.forUnit(tu)));
+        }
+        compilerState
+    }
+
+    /// Record the conditional structure of the preprocessed files. See
+    /// [`ConditionalReport`]
+    pub fn withConditionalReport(mut self) -> Self {
+        self.conditionalReport = Some(ConditionalReport::default());
+        self
+    }
+
+    /// Take the conditional report, if it was requested. Should be called once
+    /// all the tokens have been consumed.
+    pub fn takeConditionalReport(&mut self) -> Option<ConditionalReport> {
+        let mut report = self.conditionalReport.take()?;
+        report.finish(&self.compilerState.compileFiles);
+        Some(report)
+    }
+
+    /// Store the conditional report of the translation unit in the compiler
+    /// state, if it records them
+    fn recordConditionalReport(&mut self) {
+        let Some(reports) = self.compilerState.conditionalReports.clone() else {
+            return;
+        };
+        if let Some(report) = self.takeConditionalReport() {
+            reports.lock().unwrap().insert(self.tu, report);
+        }
+    }
+
+    /// Record something in the conditional report, if it was requested
+    fn reportConditional(
+        &mut self,
+        operation: &FileTokPos<PreToken>,
+        record: impl FnOnce(&mut ConditionalReport, &CompileFile),
+    ) {
+        if let Some(report) = self.conditionalReport.as_mut() {
+            let file = self
+                .compilerState
+                .compileFiles
+                .lock()
+                .unwrap()
+                .getOpenedFile(operation.file);
+            record(report, &file);
+        }
+    }
+
+    /// Tell the registered callbacks about an event
+    fn notify(&self, event: impl Fn(&dyn PreprocessorCallbacks)) {
+        for callbacks in &self.compilerState.preprocessorCallbacks {
+            event(callbacks.as_ref());
+        }
+    }
+
+    /// Tell the registered callbacks about the files entered and exited
+    fn notifyFileChanges(&mut self) {
+        let tu = self.tu;
+        for change in self.multilexer.takeFileChanges() {
+            match change {
+                FileChange::Entered(file) => self.notify(|cb| cb.fileEntered(tu, file)),
+                FileChange::Exited(file) => self.notify(|cb| cb.fileExited(tu, file)),
+            }
+        }
+    }
+
     /// Creates a new preprocessor from the given parameters, filemap and path
     fn undefineMacro(&mut self, preToken: &FileTokPos<PreToken>) {
         let vecPrepro = Iterator::take_while(&mut self.multilexer, |pre| {
@@ -138,11 +230,22 @@ impl Preprocessor {
             }
             Some(e) => match e.tokPos.tok {
                 PreToken::Ident(id) => {
+                    if Self::isReservedMacroName(&id) {
+                        self.errors.push_back(CompileWarning::fromPreTo(
+                            format!(
+                                "Undefining the reserved macro name {id} is undefined behavior"
+                            ),
+                            preToken,
+                        ));
+                    }
+                    self.warnUnusedMacro(&id);
                     if self.definitions.remove(&id).is_none() {
                         self.errors.push_back(CompileError::fromPreTo(
                             format!("Macro {id} is not defined when reached"),
                             preToken,
                         ));
+                    } else {
+                        self.notify(|cb| cb.macroUndefined(self.tu, preToken, &id));
                     }
                 }
                 _ => {
@@ -240,6 +343,8 @@ impl Preprocessor {
                 return VecDeque::new();
             }
             let mut expandedTokens = expandedTokens.unwrap();
+            let toks = expandedTokens.make_contiguous();
+            self.notify(|cb| cb.moduleDirective(self.tu, &module, toks));
             expandedTokens.push_front(FileTokPos::new_meta_c(PreToken::Module, &module));
             return expandedTokens;
         }
@@ -251,33 +356,56 @@ impl Preprocessor {
         VecDeque::new()
     }
 
+    /// Marks this header as blocked by the import of another header
+    fn blockOnImportHeader(&self, tu: TranslationUnit, import: &FileTokPos<PreToken>) {
+        let compileUnit = self.compilerState.compileUnits.get(&self.tu).unwrap();
+        *compileUnit.blockedByImportHeaderAt.lock().unwrap() =
+            Some(SourceRange::newSingleTok(import));
+        compileUnit
+            .blockedByImportHeader
+            .store(tu, Ordering::Relaxed);
+    }
+
+    /// Marks this header as no longer blocked by an import
+    fn unblockImportHeader(&self) {
+        let compileUnit = self.compilerState.compileUnits.get(&self.tu).unwrap();
+        compileUnit
+            .blockedByImportHeader
+            .store(0, Ordering::Relaxed);
+        *compileUnit.blockedByImportHeaderAt.lock().unwrap() = None;
+    }
+
     /// We know there is a dependency loop, so we need to find it.
-    /// Returns the paths of the headers in the loop.
+    /// Returns each import of the loop, as `a imports b (at location)`, and
+    /// if the loop was closed.
     fn getDependencyLoop(&self) -> (Vec<String>, bool) {
         let mut loopVec = Vec::new();
         let startLoop = self.tu;
         let mut current = self.tu;
         let loopFound = loop {
-            let filePath = self
-                .compilerState
-                .compileFiles
-                .lock()
-                .unwrap()
-                .getOpenedFile(current)
-                .path()
-                .clone();
-            loopVec.push(filePath);
-            let nextTu = self
-                .compilerState
-                .compileUnits
-                .get(&current)
-                .unwrap()
-                .blockedByImportHeader
-                .load(Ordering::Relaxed);
+            let compileUnit = self.compilerState.compileUnits.get(&current).unwrap();
+            let nextTu = compileUnit.blockedByImportHeader.load(Ordering::Relaxed);
+            let at = compileUnit.blockedByImportHeaderAt.lock().unwrap().clone();
             if nextTu == 0 {
                 break false;
-            } else if nextTu == startLoop {
-                loopVec.push(loopVec.first().unwrap().clone());
+            }
+            let mut compileFiles = self.compilerState.compileFiles.lock().unwrap();
+            let mut import = format!(
+                "{} imports {}",
+                compileFiles.getOpenedFile(current).path(),
+                compileFiles.getOpenedFile(nextTu).path()
+            );
+            if let Some(at) = at {
+                let loc = compileFiles
+                    .getOpenedFile(at.startfile)
+                    .getLocStr(Some(at.start));
+                import.push_str(" (at ");
+                import.push_str(&loc);
+                import.push(')');
+            }
+            drop(compileFiles);
+            loopVec.push(import);
+            if nextTu == startLoop {
                 break true;
             }
             current = nextTu;
@@ -314,8 +442,7 @@ impl Preprocessor {
 
         let normalTu = self.moduleHeaderAtomicLexingList.is_none(); // Normal TU don't have a list of headers to lex
         if normalTu {
-            while importableHeader.finishedStage.load(Ordering::Relaxed) != StageCompileUnit::Lexer
-            {
+            while importableHeader.finishedStage.load(Ordering::Relaxed) < StageCompileUnit::Lexer {
                 // Wait for the header to finish. Hot loop, it shouldn't take that long.
                 std::thread::yield_now();
             }
@@ -328,7 +455,7 @@ impl Preprocessor {
             );
         }
         // We are in a header file, we need to check if the header is being preprocessed by another thread.
-        if importableHeader.finishedStage.load(Ordering::Relaxed) == StageCompileUnit::Lexer {
+        if importableHeader.finishedStage.load(Ordering::Relaxed) >= StageCompileUnit::Lexer {
             // It is done! return directly
             return Some(
                 importableHeader
@@ -341,19 +468,9 @@ impl Preprocessor {
         // It is not done, we need to see if there is another task available.
         if let Some(task) = self.moduleHeaderAtomicLexingList.as_ref().unwrap().pop() {
             // There is another task, we'll start that task, marking this header as blocked by import of this other header.
-            self.compilerState
-                .compileUnits
-                .get(&self.tu)
-                .unwrap()
-                .blockedByImportHeader
-                .store(tu, Ordering::Relaxed);
+            self.blockOnImportHeader(tu, import);
             task();
-            self.compilerState
-                .compileUnits
-                .get(&self.tu)
-                .unwrap()
-                .blockedByImportHeader
-                .store(0, Ordering::Relaxed);
+            self.unblockImportHeader();
         } else {
             if self
                 .moduleHeaderAtomicLexingList
@@ -361,12 +478,7 @@ impl Preprocessor {
                 .unwrap()
                 .markThreadLocked()
             {
-                self.compilerState
-                    .compileUnits
-                    .get(&self.tu)
-                    .unwrap()
-                    .blockedByImportHeader
-                    .store(tu, Ordering::Relaxed);
+                self.blockOnImportHeader(tu, import);
                 // We are the last thread, we need to bail out; To do so, we'll have to report an error explaining that there is a loop somewhere in the import graph.
                 self.errors.push_back(CompileError::fromPreTo(
                     format!(
@@ -375,24 +487,13 @@ impl Preprocessor {
                     ),
                     import,
                 ));
-                self.compilerState
-                    .compileUnits
-                    .get(&self.tu)
-                    .unwrap()
-                    .blockedByImportHeader
-                    .store(0, Ordering::Relaxed);
+                self.unblockImportHeader();
                 return None;
             }
-            self.compilerState
-                .compileUnits
-                .get(&self.tu)
-                .unwrap()
-                .blockedByImportHeader
-                .store(tu, Ordering::Relaxed);
+            self.blockOnImportHeader(tu, import);
             // There is no other task, we'll block for the header to finish and then return the definitions.
             let mut start = Instant::now();
-            while importableHeader.finishedStage.load(Ordering::Relaxed) != StageCompileUnit::Lexer
-            {
+            while importableHeader.finishedStage.load(Ordering::Relaxed) < StageCompileUnit::Lexer {
                 // Wait for the header to finish. Hot loop, it shouldn't take that long.
                 std::thread::yield_now();
                 if start.elapsed().as_millis() > 5 {
@@ -412,19 +513,14 @@ impl Preprocessor {
                     start = Instant::now();
                 }
             }
-            self.compilerState
-                .compileUnits
-                .get(&self.tu)
-                .unwrap()
-                .blockedByImportHeader
-                .store(0, Ordering::Relaxed);
+            self.unblockImportHeader();
 
             self.moduleHeaderAtomicLexingList
                 .as_ref()
                 .unwrap()
                 .markThreadUnlocked();
 
-            if importableHeader.finishedStage.load(Ordering::Relaxed) != StageCompileUnit::Lexer {
+            if importableHeader.finishedStage.load(Ordering::Relaxed) < StageCompileUnit::Lexer {
                 return None;
             }
         }
@@ -482,6 +578,8 @@ impl Preprocessor {
                     &pathTok,
                 ));
             }
+            let toks = expandedTokens.make_contiguous();
+            self.notify(|cb| cb.importDirective(self.tu, &import, toks));
             expandedTokens.push_front(FileTokPos::new_meta_c(PreToken::Import, &import));
             return expandedTokens;
         }
@@ -545,7 +643,7 @@ impl Preprocessor {
 
     /// Encountered a preprocessor directive. Evaluate it accordingly, alering
     /// the state of the preprocessor.
-    fn preprocessorDirective(&mut self, _PreToken: &FileTokPos<PreToken>) {
+    fn preprocessorDirective(&mut self, hash: &FileTokPos<PreToken>) {
         let operation;
         let enabledBlock = matches!(self.scope.last(), Some(ScopeStatus::Success) | None);
         loop {
@@ -567,44 +665,36 @@ impl Preprocessor {
         }
         if enabledBlock {
             match operation.tokPos.tok.to_str() {
-                "include" => {
-                    self.multilexer.expectHeader();
-                    match self.consumeMacroInclude(&operation) {
-                        Ok(path) => {
-                            let tuModuleHeader = {
-                                let mut compileFiles =
-                                    self.compilerState.compileFiles.lock().unwrap();
-                                compileFiles
-                                    .getPath(&path)
-                                    .ok()
-                                    .and_then(|path| {
-                                        self.compilerState.moduleHeaderUnitsFiles.get(&path)
-                                    })
-                                    .copied()
-                            };
-                            if let Some(tu) = tuModuleHeader {
-                                let otherDefinitions =
-                                    self.importHeaderDirectiveGetDefinitions(tu, &path, &operation);
-                                if let Some(otherDefinitions) = otherDefinitions {
-                                    self.definitions.extend(otherDefinitions);
-                                }
-                                self.generated.push_back(FileTokPos::new_meta_c(
-                                    PreToken::Import,
-                                    &operation,
-                                ));
-                                self.generated.push_back(FileTokPos::new_meta_c(
-                                    PreToken::ImportableHeaderName(tu),
-                                    &operation,
-                                ));
-                            } else if let Err(err) = self.includeFile(&operation, &path) {
-                                self.errors.push_back(err);
+                "include" => match self.consumeMacroInclude(&operation) {
+                    Ok(path) => {
+                        if let Some(tu) = self.translatedInclude(&path) {
+                            self.notify(|cb| {
+                                cb.includeResolved(self.tu, &operation, &path, tu);
+                            });
+                            let otherDefinitions =
+                                self.importHeaderDirectiveGetDefinitions(tu, &path, &operation);
+                            if let Some(otherDefinitions) = otherDefinitions {
+                                self.definitions.extend(otherDefinitions);
                             }
-                        }
-                        Err(err) => {
+                            self.generated
+                                .push_back(FileTokPos::new_meta_c(PreToken::Import, &operation));
+                            self.generated.push_back(FileTokPos::new_meta_c(
+                                PreToken::ImportableHeaderName(tu),
+                                &operation,
+                            ));
+                            self.generated.push_back(FileTokPos::new_meta_c(
+                                PreToken::OperatorPunctuator(";"),
+                                &operation,
+                            ));
+                        } else if let Err(err) = self.includeFile(&operation, &path) {
                             self.errors.push_back(err);
                         }
                     }
-                }
+                    Err(err) => {
+                        self.notify(|cb| cb.includeFailed(self.tu, &operation, None));
+                        self.errors.push_back(err);
+                    }
+                },
                 "define" => {
                     self.defineMacro(&operation);
                 }
@@ -612,7 +702,7 @@ impl Preprocessor {
                     self.undefineMacro(&operation);
                 }
                 "if" => {
-                    let sequenceToEval = self.consumeMacroExpr();
+                    let sequenceToEval = self.consumeMacroExpr(&operation);
                     match sequenceToEval {
                         Err(err) => {
                             self.errors.push_back(err);
@@ -621,6 +711,12 @@ impl Preprocessor {
                             match Self::evalIfScope(&sequenceToEval, &operation) {
                                 Ok((b, err)) => {
                                     self.errors.extend(err);
+                                    self.notify(|cb| {
+                                        cb.conditionalEvaluated(self.tu, &operation, b);
+                                    });
+                                    self.reportConditional(&operation, |report, file| {
+                                        report.openGroup(file, hash, &operation, Some(b), b);
+                                    });
                                     if b {
                                         self.scope.push(ScopeStatus::Success);
                                     } else {
@@ -634,26 +730,30 @@ impl Preprocessor {
                         }
                     }
                 }
-                "ifdef" => {
+                "ifdef" | "ifndef" => {
                     let t = self.consumeMacroDef(&operation);
-                    self.scope.push(if self.evalIfDef(t) {
+                    if let Some(name) = &t {
+                        let defined = self.definitions.contains_key(name);
+                        self.unusedMacros.remove(name);
+                        self.notify(|cb| cb.macroTested(self.tu, &operation, name, defined));
+                    }
+                    let b = self.evalIfDef(t) == (operation.tokPos.tok.to_str() == "ifdef");
+                    self.notify(|cb| cb.conditionalEvaluated(self.tu, &operation, b));
+                    self.reportConditional(&operation, |report, file| {
+                        report.openGroup(file, hash, &operation, Some(b), b);
+                    });
+                    self.scope.push(if b {
                         ScopeStatus::Success
                     } else {
                         ScopeStatus::Failure
                     });
                 }
-                "ifndef" => {
-                    let t = self.consumeMacroDef(&operation);
-                    let t2 = if self.evalIfDef(t) {
-                        ScopeStatus::Failure
-                    } else {
-                        ScopeStatus::Success
-                    };
-                    self.scope.push(t2);
-                }
                 "elif" | "else" => {
                     if let Some(scope) = self.scope.last_mut() {
                         *scope = ScopeStatus::AlreadySucceeded;
+                        self.reportConditional(&operation, |report, file| {
+                            report.addBranch(file, hash, &operation, None, false);
+                        });
                         self.reachNl(); // TODO: Check empty in else
                     } else {
                         self.errors.push_back(CompileError::fromPreTo(
@@ -664,7 +764,10 @@ impl Preprocessor {
                 }
                 "pragma" => {
                     self.errors.push_back(CompileError::fromPreTo("LMAO, you really expected me to implement this now XD. No worries, we'll get there :D", &operation));
-                    self.reachNl();
+                    let mut toks = self.reachNl();
+                    toks.retain(|tok| !fileTokPosMatches!(tok, PreToken::Newline));
+                    let toks = toks.make_contiguous();
+                    self.notify(|cb| cb.pragma(self.tu, &operation, toks));
                 }
                 "endif" => {
                     if self.scope.is_empty() {
@@ -673,6 +776,9 @@ impl Preprocessor {
                             &operation,
                         ));
                     } else {
+                        self.reportConditional(&operation, |report, file| {
+                            report.closeGroup(file, hash, &operation);
+                        });
                         self.scope.pop();
                     }
                     self.reachNl(); // TODO: Check empty
@@ -708,10 +814,13 @@ impl Preprocessor {
         } else if &ScopeStatus::Failure == self.scope.last().unwrap() {
             match operation.tokPos.tok.to_str() {
                 "if" | "ifdef" | "ifndef" => {
+                    self.reportConditional(&operation, |report, file| {
+                        report.openGroup(file, hash, &operation, None, false);
+                    });
                     self.scope.push(ScopeStatus::AlreadySucceeded);
                 }
                 "elif" => {
-                    let sequenceToEval = self.consumeMacroExpr();
+                    let sequenceToEval = self.consumeMacroExpr(&operation);
                     match sequenceToEval {
                         Err(err) => {
                             self.errors.push_back(err);
@@ -722,9 +831,33 @@ impl Preprocessor {
                                     let scope = self.scope.last_mut().unwrap();
                                     *scope = ScopeStatus::Success;
                                     self.errors.extend(err);
+                                    self.notify(|cb| {
+                                        cb.conditionalEvaluated(self.tu, &operation, true);
+                                    });
+                                    self.reportConditional(&operation, |report, file| {
+                                        report.addBranch(file, hash, &operation, Some(true), true);
+                                    });
+                                }
+                                Ok((false, err)) => {
+                                    self.errors.extend(err);
+                                    self.notify(|cb| {
+                                        cb.conditionalEvaluated(self.tu, &operation, false);
+                                    });
+                                    self.reportConditional(&operation, |report, file| {
+                                        report.addBranch(
+                                            file,
+                                            hash,
+                                            &operation,
+                                            Some(false),
+                                            false,
+                                        );
+                                    });
                                 }
-                                Ok((false, err)) | Err(err) => {
+                                Err(err) => {
                                     self.errors.extend(err);
+                                    self.reportConditional(&operation, |report, file| {
+                                        report.addBranch(file, hash, &operation, None, false);
+                                    });
                                 }
                             };
                         }
@@ -733,9 +866,15 @@ impl Preprocessor {
                 "else" => {
                     let scope = self.scope.last_mut().unwrap();
                     *scope = ScopeStatus::Success;
+                    self.reportConditional(&operation, |report, file| {
+                        report.addBranch(file, hash, &operation, None, true);
+                    });
                     self.reachNl(); // TODO: Check it is empty
                 }
                 "endif" => {
+                    self.reportConditional(&operation, |report, file| {
+                        report.closeGroup(file, hash, &operation);
+                    });
                     self.reachNl(); // TODO: Check it is empty
                     self.scope.pop();
                 }
@@ -746,10 +885,22 @@ impl Preprocessor {
         } else if &ScopeStatus::AlreadySucceeded == self.scope.last().unwrap() {
             match operation.tokPos.tok.to_str() {
                 "if" | "ifdef" | "ifndef" => {
+                    self.reportConditional(&operation, |report, file| {
+                        report.openGroup(file, hash, &operation, None, false);
+                    });
                     self.reachNl();
                     self.scope.push(ScopeStatus::AlreadySucceeded);
                 }
+                "elif" | "else" => {
+                    self.reportConditional(&operation, |report, file| {
+                        report.addBranch(file, hash, &operation, None, false);
+                    });
+                    self.reachNl();
+                }
                 "endif" => {
+                    self.reportConditional(&operation, |report, file| {
+                        report.closeGroup(file, hash, &operation);
+                    });
                     self.reachNl(); // TODO: Check empty
                     self.scope.pop();
                 }
@@ -819,6 +970,7 @@ impl Preprocessor {
                             break;
                         }
                         PreToken::DisableMacro(macroName) => {
+                            self.unusedMacros.remove(&macroName);
                             self.disabledMacros.insert(macroName);
                             break;
                         }
@@ -886,6 +1038,8 @@ impl Preprocessor {
                     match newToken.tokPos.tok {
                         PreToken::Newline => {
                             self.atStartLine = true;
+                            // Nothing until the next directive is relevant
+                            self.multilexer.skipToNextDirective();
                             break;
                         }
                         _ => {
@@ -906,10 +1060,13 @@ impl Iterator for Preprocessor {
             }
             match self.generated.pop_front() {
                 Some(tok) => {
+                    let tu = self.tu;
+                    self.notify(|callbacks| callbacks.tokenGenerated(tu, &tok));
                     return Some(Ok(tok));
                 }
                 None => match self.multilexer.next() {
                     None => {
+                        self.notifyFileChanges();
                         if !self.alreadyEmittedEnd {
                             self.compilerState
                                 .compileUnits
@@ -920,10 +1077,14 @@ impl Iterator for Preprocessor {
                                 .unwrap()
                                 .extend(self.definitions.clone());
                             self.alreadyEmittedEnd = true;
+                            self.warnUnusedMacros();
+                            self.recordConditionalReport();
+                            continue;
                         }
                         return None;
                     }
                     Some(token) => {
+                        self.notifyFileChanges();
                         self.consume(token);
                     }
                 },
@@ -4,7 +4,7 @@
 #![allow(non_camel_case_types, clippy::string_to_string)]
 
 use std::collections::{HashMap, VecDeque};
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard};
 
 use crate::Grammars::DefineAst::{DefineAst, IsVariadic};
 use crate::Preprocessor::Pretoken::PreToken;
@@ -36,6 +36,7 @@ macro_rules! declCMVar {
                     variadic: IsVariadic::False,
                     replacement: vec![],
                     expandFunc: &Self::expand,
+                    definedAt: None,
                 }
             }
             fn expand(
@@ -78,6 +79,7 @@ impl CustomMacro for __cplusplus {
             variadic: IsVariadic::False,
             replacement: vec![],
             expandFunc: &Self::expand,
+            definedAt: None,
         }
     }
 
@@ -111,6 +113,7 @@ impl CustomMacro for __has_include {
             variadic: IsVariadic::True(String::new()),
             replacement: vec![],
             expandFunc: &Self::expand,
+            definedAt: None,
         }
     }
 
@@ -170,6 +173,7 @@ impl CustomMacro for __has_cpp_attribute {
             variadic: IsVariadic::True(String::new()),
             replacement: vec![],
             expandFunc: &__has_include::expand,
+            definedAt: None,
         }
     }
 
@@ -229,16 +233,35 @@ impl Preprocessor {
         )
     }
 
-    /// Register all the custom macros to current Preprocessor
-    pub fn initCustomMacros(mut self) -> Self {
+    /// Is `name` one that can't be the subject of a `#define` or `#undef`?
+    /// ([cpp.predefined]/4 and [cpp.replace.general]/6)
+    pub fn isReservedMacroName(name: &str) -> bool {
+        matches!(
+            name,
+            "defined"
+                | "__VA_ARGS__"
+                | "__VA_OPT__"
+                | "__STDC__"
+                | "__STDC_VERSION__"
+                | "__STDC_ISO_10646__"
+                | "__STDC_MB_MIGHT_NEQ_WC__"
+                | "__STDCPP_THREADS__"
+        ) || Self::customMacros().contains_key(name)
+    }
+
+    /// The custom macros, generated once
+    fn customMacros() -> MutexGuard<'static, HashMap<String, DefineAst>> {
         lazy_static! {
             static ref CUSTOM_MACROS: Mutex<HashMap<String, DefineAst>> =
                 Mutex::new(Preprocessor::generateCustomMacro());
         }
+        CUSTOM_MACROS.lock().unwrap()
+    }
+
+    /// Register all the custom macros to current Preprocessor
+    pub fn initCustomMacros(mut self) -> Self {
         self.definitions.extend(
-            CUSTOM_MACROS
-                .lock()
-                .unwrap()
+            Self::customMacros()
                 .iter()
                 .map(|(k, v)| (k.clone(), v.clone())),
         );
@@ -11,7 +11,10 @@ use crate::{
     Preprocessor::Pretoken::{PreToken, PreprocessingOperator},
     Utils::{
         Funcs::all_unique_elements,
-        Structs::{CompileError, CompileMsg, CompileMsgImpl, CompileWarning, FileTokPos, TokPos},
+        Structs::{
+            CompileError, CompileMsg, CompileMsgImpl, CompileNote, CompileWarning, FileTokPos,
+            SourceRange, TokPos,
+        },
     },
 };
 
@@ -35,6 +38,7 @@ impl Preprocessor {
             .into_iter()
             .map(|tok| FileTokPos {
                 file: tok.file,
+                expansion: tok.expansion,
                 tokPos: TokPos::<PreTokenDefinePreParse> {
                     start: tok.tokPos.start,
                     tok: match tok.tokPos.tok {
@@ -126,12 +130,14 @@ impl Preprocessor {
             variadic: IsVariadic::False,
             replacement: vec![],
             expandFunc: &Self::expand,
+            definedAt: None,
         };
         let mut ntok = tokens
             .into_iter()
             .skip_while(|tok| tok.tokPos.tok.isWhitespace());
         res.id = if let Some(tokId) = ntok.next() {
             if let PreToken::Ident(idStr) = &tokId.tokPos.tok {
+                res.definedAt = Some(SourceRange::newSingleTok(&tokId));
                 idStr.to_string()
             } else {
                 return Err(CompileError::fromPreTo(
@@ -263,30 +269,123 @@ impl Preprocessor {
         Ok(res)
     }
 
+    /// Are both replacement lists identical? Whitespace is compared by its
+    /// presence, not its contents ([cpp.replace.general]/1)
+    fn sameReplacement(a: &[PreTokenDefine], b: &[PreTokenDefine]) -> bool {
+        /// Collapse whitespace sequences (like comments between spaces)
+        fn normalize(list: &[PreTokenDefine]) -> Vec<&PreTokenDefine> {
+            let mut res: Vec<&PreTokenDefine> = vec![];
+            for tok in list {
+                let isWhitespace = |tok: &PreTokenDefine| {
+                    matches!(
+                        tok,
+                        PreTokenDefine::Normal(fileTokPosMatchArm!(PreToken::Whitespace(_)))
+                    )
+                };
+                if !(isWhitespace(tok) && res.last().is_some_and(|last| isWhitespace(last))) {
+                    res.push(tok);
+                }
+            }
+            res
+        }
+        let (a, b) = (normalize(a), normalize(b));
+        a.len() == b.len()
+            && a.iter().zip(b.iter()).all(|pair| match pair {
+                (PreTokenDefine::Normal(a), PreTokenDefine::Normal(b)) => {
+                    match (&a.tokPos.tok, &b.tokPos.tok) {
+                        (PreToken::Whitespace(_), PreToken::Whitespace(_)) => true,
+                        (a, b) => a == b,
+                    }
+                }
+                (PreTokenDefine::Arg(a), PreTokenDefine::Arg(b)) => a.tokPos.tok == b.tokPos.tok,
+                (PreTokenDefine::VariadicArg(_), PreTokenDefine::VariadicArg(_)) => true,
+                (PreTokenDefine::Hash(_, a), PreTokenDefine::Hash(_, b))
+                | (PreTokenDefine::VariadicOpt(_, a), PreTokenDefine::VariadicOpt(_, b)) => {
+                    Self::sameReplacement(a, b)
+                }
+                (PreTokenDefine::HashHash(_, al, ar), PreTokenDefine::HashHash(_, bl, br)) => {
+                    Self::sameReplacement(al, bl) && Self::sameReplacement(ar, br)
+                }
+                _ => false,
+            })
+    }
+
+    /// Is `def` a valid redefinition of `other`? ([cpp.replace.general]/2)
+    fn sameDefinition(def: &DefineAst, other: &DefineAst) -> bool {
+        let sameVariadic = match (&def.variadic, &other.variadic) {
+            (IsVariadic::True(a), IsVariadic::True(b)) => a == b,
+            (IsVariadic::False, IsVariadic::False) => true,
+            _ => false,
+        };
+        def.param == other.param
+            && sameVariadic
+            && Self::sameReplacement(&def.replacement, &other.replacement)
+    }
+
+    /// Warn if the macro `name` of the main file was never used. Used when it
+    /// stops existing, either by an `#undef`, a redefinition or the end of
+    /// the translation unit.
+    pub fn warnUnusedMacro(&mut self, name: &str) {
+        if let Some(definedAt) = self.unusedMacros.remove(name) {
+            self.errors.push_back(CompileWarning::fromSourceRange(
+                format!("Macro {name} is never used"),
+                &definedAt,
+            ));
+        }
+    }
+
+    /// Warn about all the macros of the main file that were never used
+    pub fn warnUnusedMacros(&mut self) {
+        let mut unused = self.unusedMacros.drain().collect::<Vec<_>>();
+        unused.sort_by_key(|(_, definedAt)| definedAt.start);
+        for (name, definedAt) in unused {
+            self.errors.push_back(CompileWarning::fromSourceRange(
+                format!("Macro {name} is never used"),
+                &definedAt,
+            ));
+        }
+    }
+
     /// Parse a macro definition and add it to the list of definitions.
     fn defineMacroImpl(
         &mut self,
         vecPrepro: Vec<FileTokPos<PreToken>>,
         preToken: &FileTokPos<PreToken>,
     ) -> Result<(), CompileMsg> {
-        let def = {
-            match Self::getAstMacro(preToken, vecPrepro) {
-                Err(err) => {
-                    return Err(err);
-                }
-                Ok(def) => def,
-            }
-        };
+        let def = Self::getAstMacro(preToken, vecPrepro)?;
 
-        match self.definitions.get_mut(&def.id) {
-            Some(other) => {
-                *other = def;
-                return Err(CompileWarning::fromPreTo("Redefining macro", preToken));
-            }
-            None => {
-                self.definitions.insert(def.id.clone(), def);
+        if Self::isReservedMacroName(&def.id) {
+            self.errors.push_back(CompileWarning::fromPreTo(
+                format!(
+                    "Defining the reserved macro name {} is undefined behavior",
+                    def.id
+                ),
+                preToken,
+            ));
+        }
+        self.notify(|cb| cb.macroDefined(self.tu, preToken, &def));
+        self.warnUnusedMacro(&def.id);
+        if self.compilerState.parameters.warnUnusedMacros {
+            if let Some(definedAt) = def.definedAt.as_ref().filter(|at| at.startfile == self.tu) {
+                self.unusedMacros.insert(def.id.clone(), definedAt.clone());
             }
         }
+        let differentDefinition = self
+            .definitions
+            .get(&def.id)
+            .filter(|other| !Self::sameDefinition(&def, other))
+            .and_then(|other| other.definedAt.clone());
+        if let Some(previous) = differentDefinition {
+            self.errors.push_back(CompileWarning::fromPreTo(
+                format!("Macro {} redefined with a different definition", def.id),
+                preToken,
+            ));
+            self.errors.push_back(CompileNote::fromSourceRange(
+                "Previous definition is here",
+                &previous,
+            ));
+        }
+        self.definitions.insert(def.id.clone(), def);
         Ok(())
     }
 
@@ -4,6 +4,7 @@
 
 use std::collections::{HashMap, VecDeque};
 
+use crate::Compiler::TranslationUnit;
 use crate::Grammars::DefineAst::DefineAst;
 use crate::Preprocessor::Prelexer::PreLexer;
 use crate::Preprocessor::Pretoken::PreToken;
@@ -25,8 +26,10 @@ impl Preprocessor {
         file: &str,
     ) -> Result<(), CompileMsg> {
         if self.multilexer.hasFileAccess(file) {
-            self.multilexer.pushFile(file);
+            let header = self.multilexer.pushFile(file);
+            self.notify(|cb| cb.includeResolved(self.tu, preToken, file, header));
         } else {
+            self.notify(|cb| cb.includeFailed(self.tu, preToken, Some(file)));
             return Err(CompileError::fromPreTo(
                 format!("Can't include the file in path: {file}"),
                 preToken,
@@ -35,6 +38,25 @@ impl Preprocessor {
         Ok(())
     }
 
+    /// The module header unit included by `path`, if the `#include` has to be
+    /// translated into an import of it ([cpp.include]/7)
+    pub fn translatedInclude(&self, path: &str) -> Option<TranslationUnit> {
+        if !self.compilerState.parameters.translateIncludes {
+            return None;
+        }
+        let path = self
+            .compilerState
+            .compileFiles
+            .lock()
+            .unwrap()
+            .getPath(path)
+            .ok()?;
+        self.compilerState
+            .moduleHeaderUnitsFiles
+            .get(&path)
+            .copied()
+    }
+
     /// Evaluates the #include directive. Finds a candidate and returns the file path
     pub fn consumeMacroInclude(
         &mut self,
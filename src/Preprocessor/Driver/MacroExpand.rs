@@ -7,11 +7,17 @@ use crate::{
     fileTokPosMatchArm, fileTokPosMatches,
     Grammars::DefineAst::{DefineAst, IsVariadic, PreTokenDefine},
     Preprocessor::{
-        Multilexer::MultiLexer, Prelexer::PreLexer, Pretoken::PreToken, Structs::ExpandData,
+        MacroTrace::{MacroTraceStep, MacroTracer},
+        Multilexer::MultiLexer,
+        Prelexer::PreLexer,
+        Pretoken::PreToken,
+        Structs::ExpandData,
     },
     Utils::{
         CompilerState::CompilerState,
-        Structs::{CompileError, CompileMsg, CompileMsgImpl, FileTokPos, TokPos},
+        MacroExpansionRef::{MacroExpansionLoc, MacroExpansionRef},
+        StringRef::ToStringRef,
+        Structs::{CompileError, CompileMsg, CompileMsgImpl, FileTokPos, SourceRange, TokPos},
     },
 };
 
@@ -129,6 +135,26 @@ impl Preprocessor {
             if preproTokie.is_empty() {
                 preproTokie.push_back(FileTokPos::new_meta_c(PreToken::ValidNop, a));
             }
+            if let Some(tracer) = MacroTracer::get(
+                expandData.compilerState,
+                expandData.newToken,
+                expandData.astId,
+            ) {
+                let from =
+                    MacroTracer::tokensToText(expandData.namedArgs.get(&a.tokPos.tok).unwrap());
+                let to = MacroTracer::tokensToText(&preproTokie);
+                if from != to {
+                    tracer.record(
+                        expandData.newToken,
+                        MacroTraceStep::ArgumentPreExpansion {
+                            macroName: expandData.astId.clone(),
+                            param: a.tokPos.tok.clone(),
+                            from,
+                            to,
+                        },
+                    );
+                }
+            }
             result.append(&mut preproTokie);
         } else {
             result.extend(expandData.namedArgs.get(&a.tokPos.tok).unwrap().clone());
@@ -174,6 +200,30 @@ impl Preprocessor {
             if tempResult.is_empty() {
                 tempResult.push_back(FileTokPos::new_meta_c(PreToken::ValidNop, vaTok));
             }
+            if let Some(tracer) = MacroTracer::get(
+                expandData.compilerState,
+                expandData.newToken,
+                expandData.astId,
+            ) {
+                let from = expandData
+                    .variadic
+                    .iter()
+                    .map(MacroTracer::tokensToText)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let to = MacroTracer::tokensToText(&tempResult);
+                if from != to {
+                    tracer.record(
+                        expandData.newToken,
+                        MacroTraceStep::ArgumentPreExpansion {
+                            macroName: expandData.astId.clone(),
+                            param: "__VA_ARGS__".to_string(),
+                            from,
+                            to,
+                        },
+                    );
+                }
+            }
             result.append(&mut tempResult);
         } else {
             for posVariadic in 0..expandData.variadic.len() {
@@ -256,6 +306,20 @@ impl Preprocessor {
         text.insert(0, '"');
         text.push('"');
 
+        if let Some(tracer) = MacroTracer::get(
+            expandData.compilerState,
+            expandData.newToken,
+            expandData.astId,
+        ) {
+            tracer.record(
+                expandData.newToken,
+                MacroTraceStep::Stringification {
+                    macroName: expandData.astId.clone(),
+                    result: text.clone(),
+                },
+            );
+        }
+
         result.push_back(FileTokPos::new_meta_c(
             PreToken::RawStringLiteral(text),
             pos,
@@ -345,14 +409,26 @@ impl Preprocessor {
             // And we merge them. Note that the resulting token may not be valid
             if !expL.is_empty() || !expR.is_empty() {
                 let mut expectedStr = String::new();
-                for ele in expL {
-                    expectedStr += ele.tokPos.tok.to_str();
-                }
-                for ele in expR {
+                for ele in expL.iter().chain(&expR) {
                     expectedStr += ele.tokPos.tok.to_str();
                 }
                 let mut receivedTok = PreLexer::new(expectedStr.clone()).collect::<Vec<_>>();
                 receivedTok.pop();
+                if let Some(tracer) = MacroTracer::get(
+                    expandData.compilerState,
+                    expandData.newToken,
+                    expandData.astId,
+                ) {
+                    tracer.record(
+                        expandData.newToken,
+                        MacroTraceStep::Concatenation {
+                            macroName: expandData.astId.clone(),
+                            left: MacroTracer::tokensToText(&expL),
+                            right: MacroTracer::tokensToText(&expR),
+                            result: expectedStr.clone(),
+                        },
+                    );
+                }
                 for x in receivedTok {
                     result.push_back(FileTokPos::new_meta_c(x.tok, pos));
                 }
@@ -378,7 +454,7 @@ impl Preprocessor {
             namedArgs: expandData.namedArgs,
             variadic: expandData.variadic,
             astId: expandData.astId,
-            replacement: &vec![PreTokenDefine::VariadicArg(*pos)],
+            replacement: &vec![PreTokenDefine::VariadicArg(pos.clone())],
             compilerState: expandData.compilerState,
             expandArg: true,
             newToken: expandData.newToken,
@@ -595,6 +671,67 @@ impl Preprocessor {
         ))
     }
 
+    /// Marks the tokens resulting of an expansion as generated by it. Tokens
+    /// that already came from a different expansion than the invocation (like
+    /// pre-expanded arguments) keep it as the parent, so the full chain can be
+    /// reported.
+    fn markExpansion(
+        mut toks: VecDeque<FileTokPos<PreToken>>,
+        macroAst: &DefineAst,
+        newToken: &FileTokPos<PreToken>,
+    ) -> VecDeque<FileTokPos<PreToken>> {
+        let macroName = macroAst.id.to_StringRef();
+        let mut expansions: Vec<(Option<MacroExpansionRef>, MacroExpansionRef)> = vec![];
+        for tok in &mut toks {
+            let parent = tok.expansion.take().or_else(|| newToken.expansion.clone());
+            let expansion = if let Some((_, exp)) = expansions.iter().find(|(p, _)| *p == parent) {
+                exp.clone()
+            } else {
+                let exp = MacroExpansionRef::new(MacroExpansionLoc {
+                    macroName,
+                    definedAt: macroAst.definedAt.clone(),
+                    expandedAt: SourceRange::newSingleTok(newToken),
+                    parent: parent.clone(),
+                });
+                expansions.push((parent, exp.clone()));
+                exp
+            };
+            tok.expansion = Some(expansion);
+        }
+        toks
+    }
+
+    /// Records that a function-like macro was not expanded, because it was not
+    /// followed by a '('
+    fn traceNotInvoked(tracer: Option<&MacroTracer>, newToken: &FileTokPos<PreToken>) {
+        if let Some(tracer) = tracer {
+            tracer.record(
+                newToken,
+                MacroTraceStep::NotInvoked {
+                    macroName: newToken.tokPos.tok.to_str().to_string(),
+                },
+            );
+        }
+    }
+
+    /// Records the result of substituting the replacement list of a macro
+    fn traceSubstitution(
+        tracer: Option<&MacroTracer>,
+        newToken: &FileTokPos<PreToken>,
+        macroAst: &DefineAst,
+        success: &VecDeque<FileTokPos<PreToken>>,
+    ) {
+        if let Some(tracer) = tracer {
+            tracer.record(
+                newToken,
+                MacroTraceStep::Substitution {
+                    macroName: macroAst.id.clone(),
+                    result: MacroTracer::tokensToText(success),
+                },
+            );
+        }
+    }
+
     /// Internal function to expand a macro invocation. See `Preprocessor::macroExpand` for more information.
     pub fn macroExpandInternal(
         compilerState: &CompilerState,
@@ -603,6 +740,18 @@ impl Preprocessor {
         lexer: &mut MultiLexer,
         newToken: FileTokPos<PreToken>,
     ) -> Result<Vec<FileTokPos<PreToken>>, CompileMsg> {
+        let tracer = MacroTracer::get(compilerState, &newToken, newToken.tokPos.tok.to_str());
+        if let Some(tracer) = tracer.filter(|_| {
+            disabledMacros.contains(newToken.tokPos.tok.to_str())
+                && definitions.contains_key(newToken.tokPos.tok.to_str())
+        }) {
+            tracer.record(
+                &newToken,
+                MacroTraceStep::PaintedBlue {
+                    macroName: newToken.tokPos.tok.to_str().to_string(),
+                },
+            );
+        }
         if !disabledMacros.contains(newToken.tokPos.tok.to_str()) {
             if let Some(macroAst) = definitions.get(newToken.tokPos.tok.to_str()) {
                 if let Some(params) = &macroAst.param {
@@ -630,21 +779,39 @@ impl Preprocessor {
                                 _ => {
                                     residual.push(t);
                                     lexer.pushTokensVec(residual);
+                                    Self::traceNotInvoked(tracer, &newToken);
                                     return Ok(vec![newToken]);
                                 }
                             }
                         } else {
                             lexer.pushTokensVec(residual);
+                            Self::traceNotInvoked(tracer, &newToken);
                             return Ok(vec![newToken]);
                         }
                     };
                     if !Self::hasMatchingClosingParen(lexer) {
                         residual.push(tokParen);
                         lexer.pushTokensVec(residual);
+                        Self::traceNotInvoked(tracer, &newToken);
                         return Ok(vec![newToken]);
                     }
 
                     let paramsRes = Self::parseParams(lexer, min, max, &tokParen)?;
+                    for callbacks in &compilerState.preprocessorCallbacks {
+                        callbacks.macroExpanded(macroAst, &newToken);
+                    }
+                    if let Some(tracer) = tracer {
+                        tracer.record(
+                            &newToken,
+                            MacroTraceStep::Invocation {
+                                macroName: macroAst.id.clone(),
+                                args: Some(
+                                    paramsRes.iter().map(MacroTracer::tokensToText).collect(),
+                                ),
+                                rescan: newToken.expansion.is_some(),
+                            },
+                        );
+                    }
                     let ParamMapResult {
                         namedParameters: namedArgs,
                         varadicParameters: variadic,
@@ -661,6 +828,8 @@ impl Preprocessor {
                         expandArg: true,
                         newToken: &newToken,
                     })?;
+                    Self::traceSubstitution(tracer, &newToken, macroAst, &success);
+                    let success = Self::markExpansion(success, macroAst, &newToken);
 
                     log::trace!(
                         "Macro expansion success: {:?}",
@@ -684,6 +853,19 @@ impl Preprocessor {
                             .collect(),
                     );
                 } else {
+                    for callbacks in &compilerState.preprocessorCallbacks {
+                        callbacks.macroExpanded(macroAst, &newToken);
+                    }
+                    if let Some(tracer) = tracer {
+                        tracer.record(
+                            &newToken,
+                            MacroTraceStep::Invocation {
+                                macroName: macroAst.id.clone(),
+                                args: None,
+                                rescan: newToken.expansion.is_some(),
+                            },
+                        );
+                    }
                     let success = (macroAst.expandFunc)(&ExpandData {
                         definitions,
                         disabledMacros,
@@ -696,6 +878,8 @@ impl Preprocessor {
                         expandArg: true,
                         newToken: &newToken,
                     })?;
+                    Self::traceSubstitution(tracer, &newToken, macroAst, &success);
+                    let success = Self::markExpansion(success, macroAst, &newToken);
                     log::trace!(
                         "Macro expansion success: {:?}",
                         success
@@ -5,7 +5,9 @@ use crate::Grammars::MacroIntConstantExpressionAst::PreTokenIf;
 use crate::Grammars::MacroIntConstantExpressionParser;
 use crate::Preprocessor::Multilexer::MultiLexer;
 use crate::Preprocessor::Pretoken::PreToken;
-use crate::Utils::Structs::{CompileError, CompileMsg, CompileMsgImpl, FileTokPos, TokPos};
+use crate::Utils::Structs::{
+    CompileError, CompileMsg, CompileMsgImpl, CompileWarning, FileTokPos, TokPos,
+};
 use crate::{fileTokPosMatchArm, fileTokPosMatches};
 
 use super::Preprocessor;
@@ -130,8 +132,12 @@ impl Preprocessor {
 
     /// Consumes all the tokens until the next newline, and returns the exanded
     /// version of them. Takes special care of the `defined` operator. It also
-    /// makes some minor transformations to handle char literals.
-    pub fn consumeMacroExpr(&mut self) -> Result<VecDeque<FileTokPos<PreToken>>, CompileMsg> {
+    /// makes some minor transformations to handle char literals. `directive`
+    /// is the `if`/`elif` being evaluated.
+    pub fn consumeMacroExpr(
+        &mut self,
+        directive: &FileTokPos<PreToken>,
+    ) -> Result<VecDeque<FileTokPos<PreToken>>, CompileMsg> {
         let mut paramDisabledMacros = self.disabledMacros.clone();
         paramDisabledMacros.remove(&"__has_include".to_owned());
         paramDisabledMacros.remove(&"__has_cpp_attribute".to_owned());
@@ -147,23 +153,28 @@ impl Preprocessor {
                     preproTokie.push_back(tok.clone());
                 }
                 fileTokPosMatchArm!(PreToken::DisableMacro(nameMacro)) => {
+                    self.unusedMacros.remove(nameMacro);
                     paramDisabledMacros.insert(nameMacro.clone());
                     preproTokie.push_back(tok.clone());
                 }
                 fileTokPosMatchArm!(PreToken::Ident(name)) => {
                     if name == "defined" {
+                        if tok.expansion.is_some() {
+                            self.errors.push_back(CompileWarning::fromPreTo(
+                                "defined generated by a macro expansion is undefined behavior",
+                                &tok,
+                            ));
+                        }
                         let nameDefined = Self::getDefinedName(&mut self.multilexer, &tok);
                         match nameDefined {
                             Ok(nameDefined) => {
+                                let defined = self.definitions.contains_key(&nameDefined);
+                                self.unusedMacros.remove(&nameDefined);
+                                self.notify(|cb| {
+                                    cb.macroTested(self.tu, directive, &nameDefined, defined);
+                                });
                                 preproTokie.push_back(FileTokPos::new_meta_c(
-                                    PreToken::PPNumber(
-                                        if self.definitions.contains_key(&nameDefined) {
-                                            "1"
-                                        } else {
-                                            "0"
-                                        }
-                                        .to_owned(),
-                                    ),
+                                    PreToken::PPNumber(if defined { "1" } else { "0" }.to_owned()),
                                     &tok,
                                 ));
                             }
@@ -173,6 +184,8 @@ impl Preprocessor {
                             }
                         }
                     } else {
+                        let defined = self.definitions.contains_key(name);
+                        self.notify(|cb| cb.macroTested(self.tu, directive, name, defined));
                         let toks = Self::macroExpandInternal(
                             &self.compilerState,
                             &self.definitions,
@@ -0,0 +1,265 @@
+//! Include hierarchy of the translation units, and how much each header
+//! costs to preprocess. Used by the `-H` mode.
+//!
+//! The tree is built from the [`PreprocessorCallbacks`] events, as the files
+//! are entered and exited, and the tokens generated by the preprocessor are
+//! counted to know the tokens of each header.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Preprocessor::{Callbacks::PreprocessorCallbacks, Pretoken::PreToken},
+    Utils::{FileMap::FileMap, Structs::FileTokPos},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A file entered by the preprocessor
+pub struct IncludeNode {
+    /// The file
+    pub file: u64,
+    /// Nesting depth. The translation unit is 0
+    pub depth: usize,
+    /// The file was already entered before in this translation unit
+    pub repeated: bool,
+    /// All the contents of the file were skipped by a conditional, like an
+    /// include guard
+    pub guardSkipped: bool,
+    /// Tokens generated by the file and the ones it includes. Whitespace is
+    /// not counted
+    pub tokens: usize,
+    /// Time spent preprocessing the file and the ones it includes
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Total cost of a header in all the translation units
+pub struct IncludeCost {
+    /// The header
+    pub file: u64,
+    /// Times it was entered
+    pub includes: usize,
+    /// Tokens generated by it and the ones it includes
+    pub tokens: usize,
+    /// Time spent preprocessing it and the ones it includes
+    pub time: Duration,
+}
+
+#[derive(Debug)]
+/// A node that has not been exited yet
+struct OpenNode {
+    /// Index of the node
+    node: usize,
+    /// When the file was entered
+    start: Instant,
+    /// Result of the first condition evaluated in the file, if any
+    firstCondition: Option<bool>,
+    /// Some file was included by this one
+    hasChildren: bool,
+}
+
+#[derive(Debug, Default)]
+/// The include hierarchy of a translation unit
+struct TuIncludes {
+    /// Entered files, in the order they were entered
+    nodes: Vec<IncludeNode>,
+    /// Files being preprocessed, innermost last
+    open: Vec<OpenNode>,
+    /// Files already entered
+    seen: HashSet<u64>,
+}
+
+#[derive(Debug, Default)]
+/// Records the include hierarchy of the translation units
+pub struct IncludeTree {
+    /// Hierarchy of each translation unit
+    tus: Mutex<HashMap<u64, TuIncludes>>,
+}
+
+impl IncludeTree {
+    /// Count a token generated by the preprocessor of `tu`. It's attributed to
+    /// the innermost open file it comes from. If none, to the innermost open
+    /// file.
+    fn countToken(&self, tu: u64, tok: &FileTokPos<PreToken>) {
+        if matches!(
+            tok.tokPos.tok,
+            PreToken::Whitespace(_)
+                | PreToken::Newline
+                | PreToken::ValidNop
+                | PreToken::EnableMacro(_)
+                | PreToken::DisableMacro(_)
+        ) {
+            return;
+        }
+        let mut tus = self.tus.lock().unwrap();
+        let Some(includes) = tus.get_mut(&tu) else {
+            return;
+        };
+        let open = includes
+            .open
+            .iter()
+            .rev()
+            .find(|open| includes.nodes[open.node].file == tok.file)
+            .or_else(|| includes.open.last());
+        if let Some(open) = open {
+            includes.nodes[open.node].tokens += 1;
+        }
+        drop(tus);
+    }
+
+    /// The include hierarchy of `tu`, in the order the files were entered
+    pub fn nodes(&self, tu: u64) -> Vec<IncludeNode> {
+        self.tus
+            .lock()
+            .unwrap()
+            .get(&tu)
+            .map(|includes| includes.nodes.clone())
+            .unwrap_or_default()
+    }
+
+    /// Cost of each header in all the translation units. The most expensive
+    /// ones first
+    pub fn costs(&self) -> Vec<IncludeCost> {
+        let mut costs = HashMap::<u64, IncludeCost>::new();
+        for includes in self.tus.lock().unwrap().values() {
+            for node in includes.nodes.iter().filter(|node| node.depth > 0) {
+                let cost = costs.entry(node.file).or_insert(IncludeCost {
+                    file: node.file,
+                    includes: 0,
+                    tokens: 0,
+                    time: Duration::ZERO,
+                });
+                cost.includes += 1;
+                cost.tokens += node.tokens;
+                cost.time += node.time;
+            }
+        }
+        let mut costs = costs.into_values().collect::<Vec<_>>();
+        costs.sort_by(|a, b| {
+            b.time
+                .cmp(&a.time)
+                .then(b.tokens.cmp(&a.tokens))
+                .then(a.file.cmp(&b.file))
+        });
+        costs
+    }
+
+    /// Time in milliseconds, for humans
+    fn millis(time: Duration) -> String {
+        format!("{:.3}ms", time.as_secs_f64() * 1000.0)
+    }
+
+    /// Readable hierarchy of `tu`, with a dot per nesting level like `-H` of
+    /// other compilers
+    pub fn treeToText(&self, tu: u64, fileMap: &Arc<Mutex<FileMap>>) -> String {
+        let mut fileMap = fileMap.lock().unwrap();
+        let text = self
+            .nodes(tu)
+            .into_iter()
+            .map(|node| {
+                let path = fileMap.getOpenedFile(node.file).path().clone();
+                let mut marks = vec![];
+                if node.repeated {
+                    marks.push("repeated".to_string());
+                }
+                if node.guardSkipped {
+                    marks.push("guard-skipped".to_string());
+                } else {
+                    marks.push(format!("tokens: {}", node.tokens));
+                    marks.push(Self::millis(node.time));
+                }
+                let prefix = if node.depth == 0 {
+                    "// file:".to_string()
+                } else {
+                    ".".repeat(node.depth)
+                };
+                format!("{prefix} {path} ({})\n", marks.join(", "))
+            })
+            .collect::<Vec<_>>()
+            .concat();
+        drop(fileMap);
+        text
+    }
+
+    /// Readable table of the cost of the headers
+    pub fn costsToText(&self, fileMap: &Arc<Mutex<FileMap>>) -> String {
+        let mut fileMap = fileMap.lock().unwrap();
+        let header = format!("{:>12} {:>10} {:>9} header\n", "time", "tokens", "includes");
+        let text = std::iter::once(header)
+            .chain(self.costs().into_iter().map(|cost| {
+                format!(
+                    "{:>12} {:>10} {:>9} {}\n",
+                    Self::millis(cost.time),
+                    cost.tokens,
+                    cost.includes,
+                    fileMap.getOpenedFile(cost.file).path()
+                )
+            }))
+            .collect();
+        drop(fileMap);
+        text
+    }
+}
+
+impl PreprocessorCallbacks for IncludeTree {
+    fn fileEntered(&self, tu: u64, file: u64) {
+        let mut tus = self.tus.lock().unwrap();
+        let includes = tus.entry(tu).or_default();
+        if let Some(parent) = includes.open.last_mut() {
+            parent.hasChildren = true;
+        }
+        includes.nodes.push(IncludeNode {
+            file,
+            depth: includes.open.len(),
+            repeated: !includes.seen.insert(file),
+            guardSkipped: false,
+            tokens: 0,
+            time: Duration::ZERO,
+        });
+        includes.open.push(OpenNode {
+            node: includes.nodes.len() - 1,
+            start: Instant::now(),
+            firstCondition: None,
+            hasChildren: false,
+        });
+        drop(tus);
+    }
+
+    fn fileExited(&self, tu: u64, _file: u64) {
+        let mut tus = self.tus.lock().unwrap();
+        let Some(includes) = tus.get_mut(&tu) else {
+            return;
+        };
+        let Some(open) = includes.open.pop() else {
+            return;
+        };
+        let node = &mut includes.nodes[open.node];
+        node.time = open.start.elapsed();
+        node.guardSkipped =
+            open.firstCondition == Some(false) && !open.hasChildren && node.tokens == 0;
+        let tokens = node.tokens;
+        if let Some(parent) = includes.open.last() {
+            includes.nodes[parent.node].tokens += tokens;
+        }
+        drop(tus);
+    }
+
+    fn tokenGenerated(&self, tu: u64, tok: &FileTokPos<PreToken>) {
+        self.countToken(tu, tok);
+    }
+
+    fn conditionalEvaluated(&self, tu: u64, directive: &FileTokPos<PreToken>, value: bool) {
+        let mut tus = self.tus.lock().unwrap();
+        let Some(includes) = tus.get_mut(&tu) else {
+            return;
+        };
+        if let Some(open) = includes.open.last_mut() {
+            if open.firstCondition.is_none() && includes.nodes[open.node].file == directive.file {
+                open.firstCondition = Some(value);
+            }
+        }
+        drop(tus);
+    }
+}
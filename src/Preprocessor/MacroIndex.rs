@@ -0,0 +1,231 @@
+//! Cross-reference index of the macros of a project: where each macro is
+//! defined, redefined and undefined, where it is expanded and which
+//! conditions test it. Used by the `--macro-index` mode.
+//!
+//! The index is built from the [`PreprocessorCallbacks`] events, so it can be
+//! aggregated over all the translation units of the compilation. A header
+//! preprocessed by several translation units only adds its sites once.
+//! Builtin macros are not indexed.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use json::{object, JsonValue};
+
+use crate::{
+    Grammars::DefineAst::DefineAst,
+    Preprocessor::{Callbacks::PreprocessorCallbacks, Pretoken::PreToken},
+    Utils::{FileMap::FileMap, Structs::FileTokPos},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// How a macro is referenced
+pub enum MacroSiteKind {
+    /// `#define` of a macro that was not defined
+    Definition,
+    /// `#define` of a macro that was already defined
+    Redefinition,
+    /// `#undef`
+    Undefinition,
+    /// The macro was expanded
+    Expansion,
+    /// A conditional tested the macro
+    Test,
+}
+
+impl MacroSiteKind {
+    /// Name of the kind in the JSON output
+    const fn jsonKey(self) -> &'static str {
+        match self {
+            Self::Definition => "definitions",
+            Self::Redefinition => "redefinitions",
+            Self::Undefinition => "undefinitions",
+            Self::Expansion => "expansions",
+            Self::Test => "tests",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A reference to a macro in the source
+pub struct MacroSite {
+    /// How it is referenced
+    pub kind: MacroSiteKind,
+    /// File of the reference
+    pub file: u64,
+    /// Offset of the reference in the file
+    pub at: usize,
+}
+
+#[derive(Debug, Default)]
+/// Everything the index knows
+struct MacroIndexData {
+    /// Sites of each macro
+    macros: BTreeMap<String, BTreeSet<MacroSite>>,
+    /// Macros found defined by some condition, even if their definition was
+    /// not seen (like builtin macros)
+    seenDefined: HashSet<String>,
+    /// Macros currently defined in each translation unit, to tell
+    /// definitions from redefinitions
+    defined: HashMap<u64, HashSet<String>>,
+}
+
+#[derive(Debug, Default)]
+/// Cross-reference index of the macros
+pub struct MacroIndex {
+    /// The index, shared by the preprocessors of all the translation units
+    data: Mutex<MacroIndexData>,
+}
+
+impl MacroIndex {
+    /// Record a site
+    fn addSite(&self, name: &str, kind: MacroSiteKind, file: u64, at: usize) {
+        self.data
+            .lock()
+            .unwrap()
+            .macros
+            .entry(name.to_string())
+            .or_default()
+            .insert(MacroSite { kind, file, at });
+    }
+
+    /// Sites of each macro, sorted by macro name
+    pub fn sites(&self) -> BTreeMap<String, BTreeSet<MacroSite>> {
+        self.data.lock().unwrap().macros.clone()
+    }
+
+    /// Macros that are defined, but never expanded nor tested
+    pub fn unused(&self) -> Vec<String> {
+        self.data
+            .lock()
+            .unwrap()
+            .macros
+            .iter()
+            .filter(|(_, sites)| {
+                sites.iter().any(|site| {
+                    matches!(
+                        site.kind,
+                        MacroSiteKind::Definition | MacroSiteKind::Redefinition
+                    )
+                }) && !sites
+                    .iter()
+                    .any(|site| matches!(site.kind, MacroSiteKind::Expansion | MacroSiteKind::Test))
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Macros that are tested by some condition, but never defined anywhere
+    pub fn testedNeverDefined(&self) -> Vec<String> {
+        let data = self.data.lock().unwrap();
+        data.macros
+            .iter()
+            .filter(|(name, sites)| {
+                !data.seenDefined.contains(*name)
+                    && sites.iter().all(|site| site.kind == MacroSiteKind::Test)
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// JSON representation of the index
+    pub fn toJson(&self, fileMap: &Arc<Mutex<FileMap>>) -> JsonValue {
+        let mut fileMap = fileMap.lock().unwrap();
+        let mut macros = JsonValue::new_array();
+        for (name, sites) in self.sites() {
+            let mut json = object! { name: name.as_str() };
+            for kind in [
+                MacroSiteKind::Definition,
+                MacroSiteKind::Redefinition,
+                MacroSiteKind::Undefinition,
+                MacroSiteKind::Expansion,
+                MacroSiteKind::Test,
+            ] {
+                json[kind.jsonKey()] = JsonValue::Array(
+                    sites
+                        .iter()
+                        .filter(|site| site.kind == kind)
+                        .map(|site| {
+                            fileMap
+                                .getOpenedFile(site.file)
+                                .getLocStr(Some(site.at))
+                                .into()
+                        })
+                        .collect(),
+                );
+            }
+            let _ = macros.push(json);
+        }
+        drop(fileMap);
+        object! {
+            macros: macros,
+            unused: self.unused(),
+            testedNeverDefined: self.testedNeverDefined(),
+        }
+    }
+}
+
+impl PreprocessorCallbacks for MacroIndex {
+    fn macroDefined(&self, tu: u64, directive: &FileTokPos<PreToken>, definition: &DefineAst) {
+        let redefinition = !self
+            .data
+            .lock()
+            .unwrap()
+            .defined
+            .entry(tu)
+            .or_default()
+            .insert(definition.id.clone());
+        let kind = if redefinition {
+            MacroSiteKind::Redefinition
+        } else {
+            MacroSiteKind::Definition
+        };
+        let (file, at) = definition
+            .definedAt
+            .as_ref()
+            .map_or((directive.file, directive.tokPos.start), |at| {
+                (at.startfile, at.start)
+            });
+        self.addSite(&definition.id, kind, file, at);
+    }
+
+    fn macroUndefined(&self, tu: u64, directive: &FileTokPos<PreToken>, name: &str) {
+        if let Some(defined) = self.data.lock().unwrap().defined.get_mut(&tu) {
+            defined.remove(name);
+        }
+        self.addSite(
+            name,
+            MacroSiteKind::Undefinition,
+            directive.file,
+            directive.tokPos.start,
+        );
+    }
+
+    fn macroExpanded(&self, definition: &DefineAst, at: &FileTokPos<PreToken>) {
+        if definition.definedAt.is_some() {
+            self.addSite(
+                &definition.id,
+                MacroSiteKind::Expansion,
+                at.file,
+                at.tokPos.start,
+            );
+        }
+    }
+
+    fn macroTested(&self, _tu: u64, directive: &FileTokPos<PreToken>, name: &str, defined: bool) {
+        if defined {
+            self.data
+                .lock()
+                .unwrap()
+                .seenDefined
+                .insert(name.to_string());
+        }
+        self.addSite(
+            name,
+            MacroSiteKind::Test,
+            directive.file,
+            directive.tokPos.start,
+        );
+    }
+}
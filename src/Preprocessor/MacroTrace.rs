@@ -0,0 +1,371 @@
+//! Records the steps of macro expansions, so they can be explained to the
+//! user. Used by the `--explainMacro` mode.
+//!
+//! The tracer is stored in the [`CompilerState`], so every expansion function
+//! can reach it. Only the expansions that originate in the requested location
+//! (or the requested macro) are recorded, including all the nested expansions
+//! that happen while rescanning.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use json::{object, JsonValue};
+
+use crate::{
+    fileTokPosMatchArm,
+    Compiler::TranslationUnit,
+    Preprocessor::Pretoken::PreToken,
+    Utils::{
+        CompilerState::CompilerState,
+        FileMap::FileMap,
+        Structs::{FileTokPos, TokPos},
+    },
+};
+
+/// Which expansions should be recorded
+#[derive(Debug, Clone)]
+pub enum MacroTraceFilter {
+    /// Expansions invoked in this file, between these offsets (a line)
+    Line {
+        /// File of the line
+        file: u64,
+        /// Offset of the start of the line
+        start: usize,
+        /// Offset of the end of the line
+        end: usize,
+    },
+    /// Expansions that start with an invocation of this macro
+    Macro(String),
+}
+
+/// A single step of a macro expansion
+#[derive(Debug, Clone)]
+pub enum MacroTraceStep {
+    /// A macro is going to be expanded
+    Invocation {
+        /// Name of the macro
+        macroName: String,
+        /// Arguments, if function-like
+        args: Option<Vec<String>>,
+        /// The macro name was generated by another expansion
+        rescan: bool,
+    },
+    /// A function-like macro name was found, but not followed by a '('
+    NotInvoked {
+        /// Name of the macro
+        macroName: String,
+    },
+    /// A macro name was found, but its expansion is disabled (painted blue)
+    PaintedBlue {
+        /// Name of the macro
+        macroName: String,
+    },
+    /// An argument was fully macro-expanded before substitution
+    ArgumentPreExpansion {
+        /// Name of the macro being expanded
+        macroName: String,
+        /// Name of the parameter
+        param: String,
+        /// Tokens of the argument before expansion
+        from: String,
+        /// Tokens of the argument after expansion
+        to: String,
+    },
+    /// Result of a # operator
+    Stringification {
+        /// Name of the macro being expanded
+        macroName: String,
+        /// Resulting string literal
+        result: String,
+    },
+    /// Result of a ## operator
+    Concatenation {
+        /// Name of the macro being expanded
+        macroName: String,
+        /// Left side of the operator
+        left: String,
+        /// Right side of the operator
+        right: String,
+        /// Resulting tokens
+        result: String,
+    },
+    /// Result of the substitution of the replacement list, which will be rescanned
+    Substitution {
+        /// Name of the macro being expanded
+        macroName: String,
+        /// Resulting tokens
+        result: String,
+    },
+}
+
+/// A recorded step, with where it happened
+#[derive(Debug, Clone)]
+pub struct MacroTraceEntry {
+    /// File of the token that caused the step
+    pub file: u64,
+    /// Offset of the token that caused the step
+    pub at: usize,
+    /// How many expansions deep the step is
+    pub depth: usize,
+    /// The step itself
+    pub step: MacroTraceStep,
+}
+
+/// Records the expansion steps that pass the filter. The translation units
+/// are preprocessed in parallel, so each one records its steps apart, with
+/// its own tracer (see [`MacroTracer::forUnit`]).
+#[derive(Debug)]
+pub struct MacroTracer {
+    /// What to record
+    filter: MacroTraceFilter,
+    /// Translation unit of the steps recorded
+    tu: TranslationUnit,
+    /// Recorded steps, by translation unit. Shared with the tracers of the
+    /// other translation units.
+    entries: Arc<Mutex<HashMap<TranslationUnit, Vec<MacroTraceEntry>>>>,
+}
+
+impl MacroTracer {
+    /// New tracer with the given filter
+    pub fn new(filter: MacroTraceFilter) -> Self {
+        Self {
+            filter,
+            tu: 0,
+            entries: Arc::default(),
+        }
+    }
+
+    /// Tracer of the translation unit `tu`, with the same filter, that records
+    /// its steps with the ones of this tracer
+    pub fn forUnit(&self, tu: TranslationUnit) -> Self {
+        Self {
+            filter: self.filter.clone(),
+            tu,
+            entries: self.entries.clone(),
+        }
+    }
+
+    /// Creates a tracer from the user request, either `path:line` or a macro name
+    pub fn fromRequest(request: &str, fileMap: &Arc<Mutex<FileMap>>) -> Result<Self, String> {
+        if let Some((path, line)) = request.rsplit_once(':') {
+            if let Ok(line) = line.parse::<usize>() {
+                let mut fileMap = fileMap.lock().unwrap();
+                let file = fileMap.getPath(path)?;
+                let compileFile = fileMap.getOpenedFile(file);
+                drop(fileMap);
+                let (start, end) = compileFile
+                    .getLineOffsets(line)
+                    .ok_or_else(|| format!("The file {path} has no line {line}"))?;
+                return Ok(Self::new(MacroTraceFilter::Line { file, start, end }));
+            }
+        }
+        Ok(Self::new(MacroTraceFilter::Macro(request.to_string())))
+    }
+
+    /// Tracer of the compilation, if it wants to know about the expansion
+    /// invoked by `tok`. `macroName` is the macro being expanded.
+    pub fn get<'a, T: Clone + std::fmt::Debug>(
+        compilerState: &'a CompilerState,
+        tok: &FileTokPos<T>,
+        macroName: &str,
+    ) -> Option<&'a Self> {
+        compilerState
+            .macroTracer
+            .as_deref()
+            .filter(|tracer| tracer.interested(tok, macroName))
+    }
+
+    /// Does the expansion invoked by `tok` pass the filter?
+    fn interested<T: Clone + std::fmt::Debug>(&self, tok: &FileTokPos<T>, macroName: &str) -> bool {
+        let outermost = tok.expansion.as_ref().and_then(|exp| exp.chain().last());
+        match &self.filter {
+            MacroTraceFilter::Line { file, start, end } => {
+                let (tokFile, tokStart) = outermost.map_or((tok.file, tok.tokPos.start), |loc| {
+                    (loc.expandedAt.startfile, loc.expandedAt.start)
+                });
+                tokFile == *file && *start <= tokStart && tokStart <= *end
+            }
+            MacroTraceFilter::Macro(name) => {
+                outermost.map_or(macroName, |loc| loc.macroName.as_ref()) == name
+            }
+        }
+    }
+
+    /// Record a step
+    pub fn record<T: Clone + std::fmt::Debug>(&self, tok: &FileTokPos<T>, step: MacroTraceStep) {
+        let entry = MacroTraceEntry {
+            file: tok.file,
+            at: tok.tokPos.start,
+            depth: tok.expansion.as_ref().map_or(0, |exp| exp.chain().count()),
+            step,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .entry(self.tu)
+            .or_default()
+            .push(entry);
+    }
+
+    /// Take the steps recorded in the translation unit `tu`
+    pub fn take(&self, tu: TranslationUnit) -> Vec<MacroTraceEntry> {
+        self.entries.lock().unwrap().remove(&tu).unwrap_or_default()
+    }
+
+    /// Readable text of a token sequence. Meta tokens are skipped, and
+    /// whitespace is collapsed.
+    pub fn tokensToText<'a>(toks: impl IntoIterator<Item = &'a FileTokPos<PreToken>>) -> String {
+        let mut text = String::new();
+        for tok in toks {
+            match tok {
+                fileTokPosMatchArm!(
+                    PreToken::ValidNop | PreToken::EnableMacro(_) | PreToken::DisableMacro(_)
+                ) => {}
+                fileTokPosMatchArm!(PreToken::Whitespace(_) | PreToken::Newline) => {
+                    if !text.is_empty() && !text.ends_with(' ') {
+                        text.push(' ');
+                    }
+                }
+                _ => text.push_str(tok.tokPos.tok.to_str()),
+            }
+        }
+        text.trim_end().to_string()
+    }
+
+    /// Readable description of a step
+    pub fn stepToText(step: &MacroTraceStep) -> String {
+        match step {
+            MacroTraceStep::Invocation {
+                macroName,
+                args,
+                rescan,
+            } => {
+                let kind = if *rescan { "rescan: expand" } else { "expand" };
+                args.as_ref().map_or_else(
+                    || format!("{kind} {macroName}"),
+                    |args| format!("{kind} {macroName}({})", args.join(", ")),
+                )
+            }
+            MacroTraceStep::NotInvoked { macroName } => {
+                format!("{macroName} is function-like, but not invoked")
+            }
+            MacroTraceStep::PaintedBlue { macroName } => {
+                format!("{macroName} is not expanded: painted blue (already being expanded)")
+            }
+            MacroTraceStep::ArgumentPreExpansion {
+                macroName,
+                param,
+                from,
+                to,
+            } => format!("{macroName}: argument {param}: {from} -> {to}"),
+            MacroTraceStep::Stringification { macroName, result } => {
+                format!("{macroName}: # -> {result}")
+            }
+            MacroTraceStep::Concatenation {
+                macroName,
+                left,
+                right,
+                result,
+            } => format!("{macroName}: {left} ## {right} -> {result}"),
+            MacroTraceStep::Substitution { macroName, result } => {
+                format!("{macroName}: substituted into: {result}")
+            }
+        }
+    }
+
+    /// Readable trace of the recorded steps
+    pub fn entriesToText(entries: &[MacroTraceEntry], fileMap: &Arc<Mutex<FileMap>>) -> String {
+        let mut fileMap = fileMap.lock().unwrap();
+        let mut text = String::new();
+        for entry in entries {
+            text.push_str(&"  ".repeat(entry.depth));
+            text.push_str(&Self::stepToText(&entry.step));
+            if entry.file != 0 {
+                text.push_str(" [at ");
+                text.push_str(&fileMap.getOpenedFile(entry.file).getLocStr(Some(entry.at)));
+                text.push(']');
+            }
+            text.push('\n');
+        }
+        drop(fileMap);
+        text
+    }
+
+    /// JSON representation of a step
+    fn stepToJson(step: &MacroTraceStep) -> JsonValue {
+        match step {
+            MacroTraceStep::Invocation {
+                macroName,
+                args,
+                rescan,
+            } => object! {
+                step: "invocation",
+                macro: macroName.as_str(),
+                args: args.clone(),
+                rescan: *rescan,
+            },
+            MacroTraceStep::NotInvoked { macroName } => object! {
+                step: "notInvoked",
+                macro: macroName.as_str(),
+            },
+            MacroTraceStep::PaintedBlue { macroName } => object! {
+                step: "paintedBlue",
+                macro: macroName.as_str(),
+            },
+            MacroTraceStep::ArgumentPreExpansion {
+                macroName,
+                param,
+                from,
+                to,
+            } => object! {
+                step: "argumentPreExpansion",
+                macro: macroName.as_str(),
+                param: param.as_str(),
+                from: from.as_str(),
+                to: to.as_str(),
+            },
+            MacroTraceStep::Stringification { macroName, result } => object! {
+                step: "stringification",
+                macro: macroName.as_str(),
+                result: result.as_str(),
+            },
+            MacroTraceStep::Concatenation {
+                macroName,
+                left,
+                right,
+                result,
+            } => object! {
+                step: "concatenation",
+                macro: macroName.as_str(),
+                left: left.as_str(),
+                right: right.as_str(),
+                result: result.as_str(),
+            },
+            MacroTraceStep::Substitution { macroName, result } => object! {
+                step: "substitution",
+                macro: macroName.as_str(),
+                result: result.as_str(),
+            },
+        }
+    }
+
+    /// JSON representation of the recorded steps
+    pub fn entriesToJson(entries: &[MacroTraceEntry], fileMap: &Arc<Mutex<FileMap>>) -> JsonValue {
+        let mut fileMap = fileMap.lock().unwrap();
+        JsonValue::Array(
+            entries
+                .iter()
+                .map(|entry| {
+                    let mut json = Self::stepToJson(&entry.step);
+                    json["depth"] = entry.depth.into();
+                    if entry.file != 0 {
+                        json["at"] = fileMap
+                            .getOpenedFile(entry.file)
+                            .getLocStr(Some(entry.at))
+                            .into();
+                    }
+                    json
+                })
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,204 @@
+//! Minimization of source files for the dependency scanning.
+//!
+//! Discovering the module dependencies of a file only needs its preprocessor
+//! directives and its `module`/`import`/`export` lines. Everything else is
+//! replaced by spaces before lexing it, so the preprocessor has almost nothing
+//! to expand. Newlines and the length of the file are kept, so the locations of
+//! the remaining tokens (and their diagnostics) are the same as in the
+//! original file.
+
+/// Can the character be part of an identifier?
+const fn isIdentChar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+/// Scanner of the logical lines of a file
+struct Minimizer<'a> {
+    /// Contents of the file
+    content: &'a [u8],
+    /// Current position
+    pos: usize,
+}
+
+impl Minimizer<'_> {
+    /// Byte at `pos`, if any
+    fn at(&self, pos: usize) -> Option<u8> {
+        self.content.get(pos).copied()
+    }
+
+    /// Does the content at the current position start with `text`?
+    fn startsWith(&self, text: &[u8]) -> bool {
+        self.content[self.pos..].starts_with(text)
+    }
+
+    /// Skip spaces, tabs, line splices and comments contained in the current
+    /// physical line. Returns false if a comment spans several lines, as
+    /// whatever follows it is not at the start of a line.
+    fn skipLineStartWhitespace(&mut self) -> bool {
+        loop {
+            match self.at(self.pos) {
+                Some(b' ' | b'\t' | b'\x0b' | b'\x0c' | b'\r') => self.pos += 1,
+                Some(b'\\') if self.at(self.pos + 1) == Some(b'\n') => self.pos += 2,
+                Some(b'/') if self.at(self.pos + 1) == Some(b'*') => {
+                    let start = self.pos;
+                    self.skipBlockComment();
+                    if self.content[start..self.pos].contains(&b'\n') {
+                        return false;
+                    }
+                }
+                _ => return true,
+            }
+        }
+    }
+
+    /// Is the identifier `word` at the current position?
+    fn isWordAt(&self, pos: usize, word: &[u8]) -> bool {
+        self.content[pos..].starts_with(word) && !self.at(pos + word.len()).is_some_and(isIdentChar)
+    }
+
+    /// Does the line at the current position have to be kept? It has to if
+    /// it's a directive, or a module directive
+    fn isKeptLine(&self) -> bool {
+        if self.startsWith(b"#") || self.startsWith(b"%:") {
+            return true;
+        }
+        if self.isWordAt(self.pos, b"module") || self.isWordAt(self.pos, b"import") {
+            return true;
+        }
+        if self.isWordAt(self.pos, b"export") {
+            let mut pos = self.pos + b"export".len();
+            while matches!(self.at(pos), Some(b' ' | b'\t')) {
+                pos += 1;
+            }
+            // A lone `export` is kept too: without it, a `module` in the next
+            // line would start a module directive, which it does not.
+            return self.isWordAt(pos, b"module")
+                || self.isWordAt(pos, b"import")
+                || matches!(self.at(pos), None | Some(b'\r' | b'\n'));
+        }
+        false
+    }
+
+    /// Skip a block comment, starting at its `/*`
+    fn skipBlockComment(&mut self) {
+        self.pos += 2;
+        while self.pos < self.content.len() && !self.startsWith(b"*/") {
+            self.pos += 1;
+        }
+        self.pos = (self.pos + 2).min(self.content.len());
+    }
+
+    /// Skip a string or character literal, starting at its quote. They can't
+    /// span several lines, so they also end at a newline that is not spliced.
+    fn skipQuoted(&mut self, quote: u8) {
+        self.pos += 1;
+        while let Some(c) = self.at(self.pos) {
+            match c {
+                b'\\' => self.pos += 2,
+                b'\n' => return,
+                c if c == quote => {
+                    self.pos += 1;
+                    return;
+                }
+                _ => self.pos += 1,
+            }
+        }
+        self.pos = self.pos.min(self.content.len());
+    }
+
+    /// Skip a raw string literal, starting at its quote
+    fn skipRawString(&mut self) {
+        let delimStart = self.pos + 1;
+        let Some(paren) = self.content[delimStart..]
+            .iter()
+            .take(17)
+            .position(|&c| c == b'(')
+        else {
+            // Not a valid raw string. Treat it as a normal one
+            self.skipQuoted(b'"');
+            return;
+        };
+        let mut end = b")".to_vec();
+        end.extend_from_slice(&self.content[delimStart..delimStart + paren]);
+        end.push(b'"');
+        self.pos = delimStart + paren + 1;
+        while self.pos < self.content.len() && !self.startsWith(&end) {
+            self.pos += 1;
+        }
+        self.pos = (self.pos + end.len()).min(self.content.len());
+    }
+
+    /// Does the `"` at the current position start a raw string?
+    fn isRawString(&self) -> bool {
+        let start = self.content[..self.pos]
+            .iter()
+            .rposition(|&c| !isIdentChar(c))
+            .map_or(0, |pos| pos + 1);
+        matches!(
+            &self.content[start..self.pos],
+            b"R" | b"u8R" | b"uR" | b"UR" | b"LR"
+        )
+    }
+
+    /// Skip until the end of the logical line, leaving the position at its
+    /// newline (or the end of the file)
+    fn skipLine(&mut self) {
+        while let Some(c) = self.at(self.pos) {
+            match c {
+                b'\n' => return,
+                b'\\' => {
+                    self.pos += if self.at(self.pos + 1) == Some(b'\n') {
+                        2
+                    } else {
+                        1
+                    }
+                }
+                b'/' if self.at(self.pos + 1) == Some(b'*') => self.skipBlockComment(),
+                b'/' if self.at(self.pos + 1) == Some(b'/') => {
+                    while let Some(c) = self.at(self.pos) {
+                        match c {
+                            b'\n' => return,
+                            b'\\' if self.at(self.pos + 1) == Some(b'\n') => self.pos += 2,
+                            _ => self.pos += 1,
+                        }
+                    }
+                }
+                b'"' if self.isRawString() => self.skipRawString(),
+                b'"' => self.skipQuoted(b'"'),
+                // A digit separator, not a character literal
+                b'\'' if self.pos > 0 && self.content[self.pos - 1].is_ascii_alphanumeric() => {
+                    self.pos += 1;
+                }
+                b'\'' => self.skipQuoted(b'\''),
+                _ => self.pos += 1,
+            }
+        }
+    }
+}
+
+/// Minimized version of `content`: only the directives and the module
+/// directives are kept, everything else is replaced by spaces. Newlines are
+/// kept, so the result has the same lines and offsets as `content`.
+pub fn minimize(content: &str) -> String {
+    let mut minimizer = Minimizer {
+        content: content.as_bytes(),
+        pos: 0,
+    };
+    let mut res = content.as_bytes().to_vec();
+    while minimizer.pos < res.len() {
+        let lineStart = minimizer.pos;
+        let kept = minimizer.skipLineStartWhitespace() && minimizer.isKeptLine();
+        minimizer.skipLine();
+        let lineEnd = minimizer.pos.min(res.len());
+        if !kept {
+            for c in &mut res[lineStart..lineEnd] {
+                if *c != b'\n' {
+                    *c = b' ';
+                }
+            }
+        }
+        minimizer.pos = lineEnd + 1;
+    }
+    // Only whole characters were replaced, so the result is still valid UTF-8
+    String::from_utf8(res).unwrap()
+}
@@ -6,17 +6,27 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use crate::Preprocessor::Prelexer::PreLexer;
 use crate::Utils::FileMap::FileMap;
 use crate::Utils::Structs::FileTokPos;
 
 use super::Pretoken::PreToken;
+use super::TokenCache::{PreLexedFile, TokenCache};
 
 #[derive(Debug)]
 #[doc(hidden)]
 struct FileLexer {
     pub compFile: u64,
-    pub lexer: PreLexer,
+    pub lexed: Arc<PreLexedFile>,
+    pub pos: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A file started or finished being read
+pub enum FileChange {
+    /// Its first token is going to be read
+    Entered(u64),
+    /// All its tokens were read
+    Exited(u64),
 }
 
 #[derive(Debug)]
@@ -25,10 +35,15 @@ struct FileLexer {
 pub struct MultiLexer {
     /// The current files opened by the hole compiler
     fileMapping: Arc<Mutex<FileMap>>,
+    /// Pre-lexed files of the compilation. None if this multilexer never
+    /// opens files
+    tokenCache: Option<Arc<TokenCache>>,
     /// Files in the order they were opened
     files: Vec<FileLexer>,
     /// Pushed tokens to return back. This is specially useful when reevaluating an expanded macro
     pushedTokens: VecDeque<FileTokPos<PreToken>>,
+    /// Files entered and exited, not yet reported
+    fileChanges: Vec<FileChange>,
 }
 
 impl MultiLexer {
@@ -36,24 +51,44 @@ impl MultiLexer {
     pub fn new_def(files: Arc<Mutex<FileMap>>) -> Self {
         Self {
             fileMapping: files,
+            tokenCache: None,
             files: vec![],
             pushedTokens: VecDeque::new(),
+            fileChanges: vec![],
         }
     }
 
     /// Creates a new multilexer with the starting file
-    pub fn new((files, file): (Arc<Mutex<FileMap>>, u64)) -> Self {
-        let currFile = files.lock().unwrap().getOpenedFile(file);
-        let lexer = PreLexer::new(currFile.content().clone());
-
-        Self {
+    pub fn new((files, tokenCache, file): (Arc<Mutex<FileMap>>, Arc<TokenCache>, u64)) -> Self {
+        let mut me = Self {
             fileMapping: files,
-            files: vec![FileLexer {
-                compFile: file,
-                lexer,
-            }],
+            tokenCache: Some(tokenCache),
+            files: vec![],
             pushedTokens: VecDeque::new(),
-        }
+            fileChanges: vec![],
+        };
+        me.pushFileId(file);
+        me
+    }
+
+    /// Push a new file, by its id
+    fn pushFileId(&mut self, compFile: u64) {
+        let currFile = self.fileMapping.lock().unwrap().getOpenedFile(compFile);
+        let lexed = self.tokenCache.as_ref().map_or_else(
+            || Arc::new(PreLexedFile::new(currFile.content())),
+            |cache| cache.get(compFile, &currFile),
+        );
+        self.files.push(FileLexer {
+            compFile,
+            lexed,
+            pos: 0,
+        });
+        self.fileChanges.push(FileChange::Entered(compFile));
+    }
+
+    /// Take the files entered and exited since the last call, in order
+    pub fn takeFileChanges(&mut self) -> Vec<FileChange> {
+        std::mem::take(&mut self.fileChanges)
     }
 
     /// Push tokens to be returned back
@@ -77,19 +112,21 @@ impl MultiLexer {
 
     /// Push a new file. Please be careful when you're doing this, as the pushed
     /// tokens will still be returned first!
-    pub fn pushFile(&mut self, path: &str) {
-        let mut fileMapping = self.fileMapping.lock().unwrap();
-        let compFile = fileMapping.getAddFile(path);
-        let lexer = PreLexer::new(fileMapping.getOpenedFile(compFile).content().to_string());
-        drop(fileMapping);
-        self.files.push(FileLexer { compFile, lexer });
+    pub fn pushFile(&mut self, path: &str) -> u64 {
+        let compFile = self.fileMapping.lock().unwrap().getAddFile(path);
+        self.pushFileId(compFile);
+        compFile
     }
 
-    /// Push a new file. Please be careful when you're doing this, as the pushed
-    /// tokens will still be returned first!
-    pub fn expectHeader(&mut self) {
-        if let Some(lex) = self.files.last_mut() {
-            lex.lexer.expectHeader();
+    /// Skips the tokens of the current file up to the next line that starts
+    /// a directive. Must only be called at the start of a line. Does nothing
+    /// if there are pushed tokens.
+    pub fn skipToNextDirective(&mut self) {
+        if !self.pushedTokens.is_empty() {
+            return;
+        }
+        if let Some(lexer) = self.files.last_mut() {
+            lexer.pos = lexer.lexed.nextDirective(lexer.pos);
         }
     }
 
@@ -114,17 +151,17 @@ impl Iterator for MultiLexer {
         }
         loop {
             if let Some(lexer) = self.files.last_mut() {
-                match lexer.lexer.next() {
-                    None => {}
-                    Some(tok) => {
-                        return Some(FileTokPos::new(lexer.compFile, tok));
-                    }
+                if let Some(tok) = lexer.lexed.tokens().get(lexer.pos) {
+                    lexer.pos += 1;
+                    return Some(FileTokPos::new(lexer.compFile, tok.clone()));
                 }
             } else {
                 return None;
             }
             // If we reach here, the single-lexer is empty. We pop it and hope the next one provides more content.
-            self.files.pop();
+            if let Some(lexer) = self.files.pop() {
+                self.fileChanges.push(FileChange::Exited(lexer.compFile));
+            }
         }
     }
 }
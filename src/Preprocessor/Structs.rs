@@ -6,10 +6,11 @@ use multiset::HashMultiSet;
 
 use crate::Grammars::DefineAst::{DefineAst, PreTokenDefine};
 use crate::Utils::CompilerState::CompilerState;
-use crate::Utils::Structs::FileTokPos;
+use crate::Utils::Structs::{FileTokPos, TokPos};
 
 use super::Multilexer::MultiLexer;
-use super::Pretoken::PreToken;
+use super::Prelexer::PreLexer;
+use super::Pretoken::{PreToken, PreprocessingOperator};
 
 #[derive(Debug, Clone)]
 /// When a macro is expanded, this struct is passed to the expand functions so
@@ -42,3 +43,71 @@ pub struct ExpandData<'a> {
     /// The result is "A", not "a".
     pub expandArg: bool,
 }
+
+#[derive(Debug, Clone)]
+/// A line of a source file, as seen by the prelexer. Escaped newlines and
+/// multi-line comments are part of the same line. Used by the modes that
+/// output source instead of tokens.
+pub struct SourceLine<'a> {
+    /// The text of the line, with its newline
+    pub text: &'a str,
+    /// Tokens of the line, with offsets relative to the file
+    pub toks: Vec<TokPos<PreToken>>,
+    /// Offset of the line in the file
+    pub start: usize,
+}
+
+impl<'a> SourceLine<'a> {
+    /// Split the file into lines, using the prelexer so comments, splices and
+    /// literals are handled like in the preprocessor
+    pub fn split(content: &'a str) -> Vec<Self> {
+        let mut lines = vec![];
+        let mut start = 0;
+        let mut toks = vec![];
+        for tok in PreLexer::new(content.to_string()) {
+            if matches!(tok.tok, PreToken::Newline) {
+                let end = tok.end.min(content.len());
+                lines.push(Self {
+                    text: &content[start..end],
+                    toks: std::mem::take(&mut toks),
+                    start,
+                });
+                start = end;
+            } else {
+                toks.push(tok);
+            }
+        }
+        if start < content.len() {
+            lines.push(Self {
+                text: &content[start..],
+                toks,
+                start,
+            });
+        }
+        lines
+    }
+
+    /// If the line is a directive, its name and the rest of its tokens,
+    /// without whitespace
+    pub fn directive(&self) -> Option<(&TokPos<PreToken>, Vec<TokPos<PreToken>>)> {
+        let mut toks = self
+            .toks
+            .iter()
+            .filter(|tok| !matches!(tok.tok, PreToken::Whitespace(_)));
+        if !matches!(
+            toks.next()?.tok,
+            PreToken::PreprocessingOperator(PreprocessingOperator::Hash)
+        ) {
+            return None;
+        }
+        let name = toks.next()?;
+        Some((name, toks.cloned().collect()))
+    }
+
+    /// The line, with the token `tok` replaced by `text`
+    pub fn replaceTok(&self, tok: &TokPos<PreToken>, text: &str) -> String {
+        let mut res = self.text.to_string();
+        res.replace_range(tok.start - self.start..tok.end - self.start, text);
+        res
+    }
+}
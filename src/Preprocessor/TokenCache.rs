@@ -0,0 +1,168 @@
+//! Cache of pre-lexed files, shared by all the translation units.
+//!
+//! Headers are included by many translation units, and lexing them is a big
+//! part of the preprocessing time. Each file is lexed once into its
+//! [`PreToken`]s, and every [`MultiLexer`](super::Multilexer::MultiLexer) that
+//! includes it reads from the same tokens. An entry is lexed again if the
+//! contents of the file change, which is detected by their hash.
+//!
+//! The dependency scanning uses its own cache, of the minimized files.
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    Preprocessor::{
+        Minimize::minimize,
+        Prelexer::PreLexer,
+        Pretoken::{PreToken, PreprocessingOperator},
+    },
+    Utils::Structs::{CompileFile, TokPos},
+};
+
+#[derive(Debug)]
+/// The tokens of a file, and where its directives are
+pub struct PreLexedFile {
+    /// Hash of the contents that were lexed
+    hash: u64,
+    /// Tokens of the file, in order
+    tokens: Vec<TokPos<PreToken>>,
+    /// Skeleton of directives: index of the first token of each line that
+    /// starts a directive (`#`, or the `import`/`module`/`export` of module
+    /// directives). Ordered.
+    directives: Vec<usize>,
+}
+
+impl PreLexedFile {
+    /// Lex the contents of a file. A header name is expected after
+    /// `#include`, as the preprocessor does when it finds the directive.
+    fn lex(hash: u64, content: &str) -> Self {
+        let mut lexer = PreLexer::new(content.to_string());
+        let mut tokens = vec![];
+        let mut directives = vec![];
+        // Significant tokens found so far in the current line
+        let mut lineToks = 0;
+        let mut hashLine = false;
+        let mut expectingHeader = false;
+        while let Some(tok) = lexer.next() {
+            match &tok.tok {
+                PreToken::Newline => lineToks = 0,
+                PreToken::Whitespace(_) => {}
+                significant => {
+                    if expectingHeader {
+                        expectingHeader = false;
+                        lexer.doNotExpectHeader();
+                    }
+                    if lineToks == 0 {
+                        hashLine = matches!(
+                            significant,
+                            PreToken::PreprocessingOperator(PreprocessingOperator::Hash)
+                        );
+                        if hashLine || Self::isModuleDirective(significant) {
+                            directives.push(tokens.len());
+                        }
+                    } else if lineToks == 1
+                        && hashLine
+                        && matches!(significant, PreToken::Ident(id) if id == "include")
+                    {
+                        expectingHeader = true;
+                        lexer.expectHeader();
+                    }
+                    lineToks += 1;
+                }
+            }
+            tokens.push(tok);
+        }
+        Self {
+            hash,
+            tokens,
+            directives,
+        }
+    }
+
+    /// Lex the contents of a file, without caching them
+    pub fn new(content: &str) -> Self {
+        Self::lex(TokenCache::hash(content), content)
+    }
+
+    /// Can the token start a module directive?
+    fn isModuleDirective(tok: &PreToken) -> bool {
+        match tok {
+            PreToken::Ident(id) => id == "import" || id == "module",
+            PreToken::Keyword(keyword) => *keyword == "export",
+            _ => false,
+        }
+    }
+
+    /// Tokens of the file
+    pub fn tokens(&self) -> &[TokPos<PreToken>] {
+        &self.tokens
+    }
+
+    /// Index of the first token of the next directive, starting at `pos`. If
+    /// there are no more directives, the number of tokens.
+    pub fn nextDirective(&self, pos: usize) -> usize {
+        let next = self
+            .directives
+            .partition_point(|&directive| directive < pos);
+        self.directives
+            .get(next)
+            .copied()
+            .unwrap_or(self.tokens.len())
+    }
+}
+
+#[derive(Debug, Default)]
+/// Pre-lexed files, shared by the whole compilation
+pub struct TokenCache {
+    /// Lexed files, by their id in the [`FileMap`](crate::Utils::FileMap::FileMap)
+    files: Mutex<HashMap<u64, Arc<PreLexedFile>>>,
+    /// The files are [minimized](super::Minimize::minimize) before lexing
+    /// them. Used to scan the module dependencies
+    minimize: bool,
+}
+
+impl TokenCache {
+    /// A cache of the minimized files, for the dependency scanning
+    pub fn new_minimizing() -> Self {
+        Self {
+            files: Mutex::default(),
+            minimize: true,
+        }
+    }
+
+    /// Are the files minimized before lexing them?
+    pub const fn minimizes(&self) -> bool {
+        self.minimize
+    }
+
+    /// Hash of the contents of a file
+    fn hash(content: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Tokens of the file. Lexes it if it's not in the cache, or if its
+    /// contents changed since it was lexed.
+    pub fn get(&self, file: u64, compileFile: &CompileFile) -> Arc<PreLexedFile> {
+        let hash = Self::hash(compileFile.content());
+        if let Some(lexed) = self.files.lock().unwrap().get(&file) {
+            if lexed.hash == hash {
+                return lexed.clone();
+            }
+        }
+        // The lock is not held while lexing, so other files can be lexed in
+        // parallel. If two threads lex the same file, both results are equal.
+        let lexed = if self.minimize {
+            PreLexedFile::lex(hash, &minimize(compileFile.content()))
+        } else {
+            PreLexedFile::lex(hash, compileFile.content())
+        };
+        let lexed = Arc::new(lexed);
+        self.files.lock().unwrap().insert(file, lexed.clone());
+        lexed
+    }
+}
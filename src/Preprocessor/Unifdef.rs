@@ -0,0 +1,315 @@
+//! Partial preprocessing, in the spirit of the `unifdef` tool.
+//!
+//! Only a user supplied set of macros is considered defined or undefined. The
+//! `#if` groups whose conditions depend solely on them are resolved, and
+//! everything else (other directives, includes, macro uses, comments) is left
+//! untouched in the output. The output is the original source, not tokens.
+//!
+//! Conditions are evaluated with the same evaluator as the preprocessor, see
+//! [`Preprocessor::evalIfScope`].
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    Preprocessor::{Prelexer::PreLexer, Preprocessor, Pretoken::PreToken, Structs::SourceLine},
+    Utils::Structs::{FileTokPos, TokPos},
+};
+
+#[derive(Debug, Clone, Default)]
+/// The macros known to the partial preprocessing
+pub struct UnifdefConfig {
+    /// Macros known to be defined, with their value
+    defined: HashMap<String, String>,
+    /// Macros known to be undefined
+    undefined: HashSet<String>,
+}
+
+impl UnifdefConfig {
+    /// New config, from a list of `NAME` or `NAME=VALUE` defined macros and a
+    /// list of undefined macro names
+    pub fn new(defines: &[String], undefines: &[String]) -> Self {
+        let mut config = Self::default();
+        for define in defines {
+            let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+            config.defined.insert(name.to_string(), value.to_string());
+        }
+        for undefine in undefines {
+            config.defined.remove(undefine);
+            config.undefined.insert(undefine.clone());
+        }
+        config
+    }
+
+    /// Is the macro known? If so, is it defined?
+    fn isDefined(&self, name: &str) -> Option<bool> {
+        if self.defined.contains_key(name) {
+            Some(true)
+        } else if self.undefined.contains(name) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Status of the innermost `#if` group being processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+struct UnifdefGroup {
+    /// The directives of the group are kept in the output, because some
+    /// condition could not be resolved
+    emitted: bool,
+    /// The current branch is kept in the output
+    keep: bool,
+    /// A branch known to be true was already found. The rest is dropped
+    taken: bool,
+    /// The whole group is inside a dropped region
+    dropped: bool,
+}
+
+/// Partially preprocesses a file
+pub struct Unifdef<'a> {
+    /// Known macros
+    config: &'a UnifdefConfig,
+    /// Open groups
+    groups: Vec<UnifdefGroup>,
+    /// Resulting source
+    output: String,
+}
+
+impl<'a> Unifdef<'a> {
+    /// Partially preprocess `content` with the given config
+    pub fn run(config: &'a UnifdefConfig, content: &str) -> String {
+        let mut unifdef = Self {
+            config,
+            groups: vec![],
+            output: String::with_capacity(content.len()),
+        };
+        for line in SourceLine::split(content) {
+            unifdef.line(&line);
+        }
+        unifdef.output
+    }
+
+    /// Is the current line dropped from the output?
+    fn dropping(&self) -> bool {
+        self.groups
+            .last()
+            .is_some_and(|group| group.dropped || !group.keep)
+    }
+
+    /// The line, replaced by an `#else`
+    fn toElse(line: &SourceLine, name: &TokPos<PreToken>) -> String {
+        let mut text = line.text[..name.start - line.start].to_string();
+        text.push_str("else");
+        if line.text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Process a line of the source
+    fn line(&mut self, line: &SourceLine) {
+        let Some((name, condition)) = line.directive() else {
+            if !self.dropping() {
+                self.output.push_str(line.text);
+            }
+            return;
+        };
+        match name.tok.to_str() {
+            kind @ ("if" | "ifdef" | "ifndef") => {
+                if self.dropping() {
+                    self.groups.push(UnifdefGroup {
+                        emitted: false,
+                        keep: false,
+                        taken: false,
+                        dropped: true,
+                    });
+                    return;
+                }
+                let value = self.eval(kind, &condition);
+                self.groups.push(UnifdefGroup {
+                    emitted: value.is_none(),
+                    keep: value.unwrap_or(true),
+                    taken: value == Some(true),
+                    dropped: false,
+                });
+                if value.is_none() {
+                    self.output.push_str(line.text);
+                }
+            }
+            kind @ ("elif" | "else") => {
+                let Some(group) = self.groups.last().copied() else {
+                    self.output.push_str(line.text);
+                    return;
+                };
+                if group.dropped {
+                    return;
+                }
+                if group.taken {
+                    self.groups.last_mut().unwrap().keep = false;
+                    return;
+                }
+                let value = if kind == "else" {
+                    Some(true)
+                } else {
+                    self.eval("if", &condition)
+                };
+                let group = self.groups.last_mut().unwrap();
+                match value {
+                    Some(true) => {
+                        group.keep = true;
+                        group.taken = true;
+                        if group.emitted {
+                            let text = if kind == "else" {
+                                line.text.to_string()
+                            } else {
+                                Self::toElse(line, name)
+                            };
+                            self.output.push_str(&text);
+                        }
+                    }
+                    Some(false) => {
+                        group.keep = false;
+                    }
+                    None => {
+                        group.keep = true;
+                        if group.emitted {
+                            self.output.push_str(line.text);
+                        } else {
+                            group.emitted = true;
+                            self.output.push_str(&line.replaceTok(name, "if"));
+                        }
+                    }
+                }
+            }
+            "endif" => match self.groups.pop() {
+                Some(group) if group.dropped || !group.emitted => {}
+                _ => self.output.push_str(line.text),
+            },
+            _ => {
+                if !self.dropping() {
+                    self.output.push_str(line.text);
+                }
+            }
+        }
+    }
+
+    /// Evaluate a condition. None if it depends on unknown macros, or it can't
+    /// be evaluated
+    fn eval(&self, kind: &str, condition: &[TokPos<PreToken>]) -> Option<bool> {
+        match kind {
+            "ifdef" | "ifndef" => {
+                let [TokPos {
+                    tok: PreToken::Ident(name),
+                    ..
+                }] = condition
+                else {
+                    return None;
+                };
+                self.config
+                    .isDefined(name)
+                    .map(|defined| defined == (kind == "ifdef"))
+            }
+            _ => {
+                let sequence = self.resolveCondition(condition)?;
+                let token = FileTokPos::new_meta(PreToken::Newline);
+                Preprocessor::evalIfScope(&sequence, &token)
+                    .ok()
+                    .filter(|(_, errors)| errors.is_empty())
+                    .map(|(value, _)| value)
+            }
+        }
+    }
+
+    /// Replace the known macros in a condition by their values. None if some
+    /// identifier is not known, or if no known macro is used at all (so
+    /// conditions like `#if 0` are left alone)
+    fn resolveCondition(
+        &self,
+        condition: &[TokPos<PreToken>],
+    ) -> Option<VecDeque<FileTokPos<PreToken>>> {
+        let mut usesKnownMacro = false;
+        let mut sequence = VecDeque::new();
+        let mut toks = condition.iter();
+        while let Some(tok) = toks.next() {
+            match &tok.tok {
+                PreToken::Ident(name) if name == "defined" => {
+                    let defined = match toks.next()? {
+                        TokPos {
+                            tok: PreToken::Ident(name),
+                            ..
+                        } => self.config.isDefined(name)?,
+                        TokPos {
+                            tok: PreToken::OperatorPunctuator("("),
+                            ..
+                        } => {
+                            let TokPos {
+                                tok: PreToken::Ident(name),
+                                ..
+                            } = toks.next()?
+                            else {
+                                return None;
+                            };
+                            let defined = self.config.isDefined(name)?;
+                            toks.next().filter(|tok| {
+                                matches!(tok.tok, PreToken::OperatorPunctuator(")"))
+                            })?;
+                            defined
+                        }
+                        _ => return None,
+                    };
+                    usesKnownMacro = true;
+                    sequence.push_back(FileTokPos::new_meta(PreToken::PPNumber(
+                        if defined { "1" } else { "0" }.to_string(),
+                    )));
+                }
+                PreToken::Ident(name) => {
+                    usesKnownMacro = true;
+                    self.pushMacroValue(name, &mut sequence, &mut Vec::new())?;
+                }
+                PreToken::Keyword("true") => {
+                    sequence.push_back(FileTokPos::new_meta(PreToken::PPNumber("1".to_string())));
+                }
+                PreToken::Keyword("false") => {
+                    sequence.push_back(FileTokPos::new_meta(PreToken::PPNumber("0".to_string())));
+                }
+                PreToken::PPNumber(_) | PreToken::OperatorPunctuator(_) => {
+                    sequence.push_back(FileTokPos::new_meta(tok.tok.clone()));
+                }
+                _ => return None,
+            }
+        }
+        usesKnownMacro.then_some(sequence)
+    }
+
+    /// Push the value of a known macro to the sequence, replacing the known
+    /// macros it uses too. Undefined macros are `0`. None if the value uses an
+    /// identifier that is not a known macro, or a macro that is already being
+    /// expanded
+    fn pushMacroValue(
+        &self,
+        name: &str,
+        sequence: &mut VecDeque<FileTokPos<PreToken>>,
+        expanding: &mut Vec<String>,
+    ) -> Option<()> {
+        if let Some(value) = self.config.defined.get(name) {
+            if expanding.iter().any(|expanded| expanded == name) {
+                return None;
+            }
+            expanding.push(name.to_string());
+            for valueTok in PreLexer::new(value.clone()) {
+                match valueTok.tok {
+                    PreToken::Whitespace(_) | PreToken::Newline => {}
+                    PreToken::Ident(inner) => self.pushMacroValue(&inner, sequence, expanding)?,
+                    tok => sequence.push_back(FileTokPos::new_meta(tok)),
+                }
+            }
+            expanding.pop();
+        } else if self.config.undefined.contains(name) {
+            sequence.push_back(FileTokPos::new_meta(PreToken::PPNumber("0".to_string())));
+        } else {
+            return None;
+        }
+        Some(())
+    }
+}
@@ -1,3 +1,4 @@
 pub mod AstContext;
+pub mod Bmi;
 pub mod Scope;
 pub mod TypeDict;
@@ -0,0 +1,550 @@
+//! Binary module interfaces (BMIs).
+//!
+//! After parsing a module interface unit or a module partition, the
+//! declarations of its purview and of its global module fragment are written
+//! to `<bmiDir>/<module>.bmi`, with their visibility for the importers. The
+//! units that import the module load them in their root scope. Despite the
+//! name, the format is JSON. It records a format version and a hash of the
+//! source of the interface, so a BMI written by another version of the
+//! compiler or from an older source is reported instead of being used.
+//!
+//! For now, only the namespaces and the `__rustycpp__` enums (with their
+//! attributes), and the derived types of the [`TypeDict`] are stored.
+//!
+//! The BMIs read or written are kept in a [`BmiCache`], so each one is parsed
+//! once, even if many units import it, or if the compiler is kept running
+//! between compilations.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use json::{object, JsonValue};
+
+use crate::{
+    Ast::{
+        Attribute::Kind,
+        Common::{
+            AstAttribute, AstAttributeCXX, AstAttributeCXXRustyCppTagDecl,
+            AstAttributeCXXRustyCppUnused, AstDecl, AstDeclCustomRustyCppEnum, AstDeclNamespace,
+            AstType,
+        },
+        Type::{Builtin::BuiltinTypeKind, QualType, QualTypeFlags},
+    },
+    Lex::Token::{IntegerSuffix, IntegerSuffixSignedness, Token},
+    Sema::{
+        Scope::{Child, ModuleVisibility, Reachability, RefCellScope, Scope, ScopeKind, ScopeRef},
+        TypeDict::TypeDict,
+    },
+    Utils::{
+        FileMap::FileMap,
+        StringRef::{StringRef, ToStringRef},
+        Structs::{FileTokPos, SourceRange, TokPos},
+    },
+};
+
+/// Version of the format of the BMIs. Bump it on any change to the format.
+pub const BMI_FORMAT_VERSION: u32 = 3;
+
+/// Path of the BMI of a module (`name` or `name:partition`). The `:` of
+/// partitions is replaced by a `-`, like in the `moduleSearchDirs`.
+pub fn bmiPath(bmiDir: &str, module: &str) -> PathBuf {
+    Path::new(bmiDir).join(format!("{}.bmi", module.replace(':', "-")))
+}
+
+/// Hash of the source of an interface (64 bits FNV-1a), to detect stale BMIs.
+/// Stable between runs and versions of rust, unlike the std hashers.
+pub fn sourceHash(content: &str) -> String {
+    let hash = content
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{hash:016x}")
+}
+
+/// A module imported by the unit of a BMI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BmiImport {
+    /// Name of the module, as `name` or `name:partition`
+    pub module: String,
+    /// Is it an `export import`?
+    pub exported: bool,
+}
+
+/// Contents of a BMI
+pub struct Bmi {
+    /// Name of the module, as `name` or `name:partition`
+    pub module: String,
+    /// Path of the source of the interface
+    pub source: String,
+    /// [`sourceHash`] of the source when the BMI was written
+    pub sourceHash: String,
+    /// Modules imported by the interface
+    pub imports: Vec<BmiImport>,
+    /// Declarations of the root scope
+    declarations: JsonValue,
+    /// Derived types
+    types: JsonValue,
+}
+
+impl Bmi {
+    /// Serializes the declarations of the module purview of `rootScope`
+    pub fn new(
+        module: String,
+        source: String,
+        sourceHash: String,
+        imports: Vec<BmiImport>,
+        rootScope: &ScopeRef,
+        typeDict: &TypeDict,
+        fileMap: &mut FileMap,
+    ) -> Self {
+        let mut types = typeDict
+            .pointerTypes()
+            .map(|ty| object! {pointer: serializeQualType(ty.getPointee())})
+            .chain(
+                typeDict
+                    .lvalueReferenceTypes()
+                    .map(|ty| object! {lvalueReference: serializeQualType(ty.getReferenced())}),
+            )
+            .collect::<Vec<_>>();
+        // The dictionary is a hash map. Sort them, so the BMI is reproducible
+        types.sort_by_cached_key(JsonValue::dump);
+        Self {
+            module,
+            source,
+            sourceHash,
+            imports,
+            declarations: serializeScope(rootScope, fileMap),
+            types: JsonValue::Array(types),
+        }
+    }
+
+    /// Reads a BMI. Fails if it can't be read, or if it's from another
+    /// version of the format.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let json = json::parse(&contents).map_err(|err| err.to_string())?;
+        let version = json["version"].as_u32();
+        if version != Some(BMI_FORMAT_VERSION) {
+            return Err(format!(
+                "it has the format version {}, but this compiler uses the version {BMI_FORMAT_VERSION}",
+                version.map_or_else(|| "(unknown)".to_string(), |v| v.to_string())
+            ));
+        }
+        let string = |key: &str| {
+            json[key]
+                .as_str()
+                .map(ToString::to_string)
+                .ok_or_else(|| format!("it has no {key}"))
+        };
+        let imports = json["imports"]
+            .members()
+            .map(|import| {
+                Some(BmiImport {
+                    module: import["module"].as_str()?.to_string(),
+                    exported: import["exported"].as_bool()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| "it has invalid imports".to_string())?;
+        Ok(Self {
+            module: string("module")?,
+            source: string("source")?,
+            sourceHash: string("sourceHash")?,
+            imports,
+            declarations: json["declarations"].clone(),
+            types: json["types"].clone(),
+        })
+    }
+
+    /// Writes the BMI to `path`, creating its directory if needed
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let json = object! {
+            version: BMI_FORMAT_VERSION,
+            module: self.module.clone(),
+            source: self.source.clone(),
+            sourceHash: self.sourceHash.clone(),
+            imports: self.imports.iter().map(|import| object! {
+                module: import.module.clone(),
+                exported: import.exported,
+            }).collect::<Vec<_>>(),
+            declarations: self.declarations.clone(),
+            types: self.types.clone(),
+        };
+        std::fs::write(path, json.pretty(2)).map_err(|err| err.to_string())
+    }
+
+    /// Loads the declarations and types of the BMI in the root scope and type
+    /// dictionary of an importer. All the declarations are loaded, but the
+    /// non-exported ones are only visible to the units of the same module
+    /// (`sameModule`), and the ones of the global module fragment to none.
+    /// `module` is the primary module name, for the diagnostics.
+    pub fn load(
+        &self,
+        rootScope: &ScopeRef,
+        typeDict: &mut TypeDict,
+        alloc: &'static bumpalo::Bump,
+        fileMap: &mut FileMap,
+        module: StringRef,
+        sameModule: bool,
+    ) {
+        for ty in self.types.members() {
+            if let Some(pointee) = loadQualType(&ty["pointer"], typeDict) {
+                typeDict.getPtrType(pointee);
+            } else if let Some(referenced) = loadQualType(&ty["lvalueReference"], typeDict) {
+                typeDict.getLValueReference(referenced);
+            }
+        }
+        BmiLoader {
+            alloc,
+            fileMap,
+            module,
+            sameModule,
+        }
+        .loadScope(&self.declarations, rootScope);
+    }
+}
+
+/// BMIs already parsed, by path
+#[derive(Default)]
+pub struct BmiCache {
+    /// The BMIs, by path
+    bmis: Mutex<HashMap<PathBuf, Arc<Bmi>>>,
+}
+
+impl std::fmt::Debug for BmiCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BmiCache")
+            .field(
+                "bmis",
+                &self.bmis.lock().unwrap().keys().collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl BmiCache {
+    /// The BMI at `path`. Read from disk if it's not in the cache.
+    pub fn read(&self, path: &Path) -> Result<Arc<Bmi>, String> {
+        if let Some(bmi) = self.bmis.lock().unwrap().get(path) {
+            return Ok(bmi.clone());
+        }
+        let bmi = Arc::new(Bmi::read(path)?);
+        self.bmis
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), bmi.clone());
+        Ok(bmi)
+    }
+
+    /// Writes the BMI to `path`, and keeps it in the cache
+    pub fn write(&self, path: &Path, bmi: Bmi) -> Result<(), String> {
+        let written = bmi.write(path);
+        // Even if it could not be written, it's the current interface
+        self.bmis
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Arc::new(bmi));
+        written
+    }
+}
+
+/// Serializes a location, with the paths of its files
+pub fn serializeSourceRange(range: &SourceRange, fileMap: &mut FileMap) -> JsonValue {
+    object! {
+        startFile: fileMap.getOpenedFile(range.startfile).path().clone(),
+        endFile: fileMap.getOpenedFile(range.endfile).path().clone(),
+        start: range.start,
+        end: range.end,
+    }
+}
+
+/// Loads a location serialized by [`serializeSourceRange`]. Files that no
+/// longer exist map to the unknown file.
+pub fn loadSourceRange(json: &JsonValue, fileMap: &mut FileMap) -> SourceRange {
+    let mut file = |key: &str| {
+        json[key]
+            .as_str()
+            .and_then(|path| fileMap.getPath(path).ok())
+            .unwrap_or(0)
+    };
+    SourceRange {
+        startfile: file("startFile"),
+        endfile: file("endFile"),
+        start: json["start"].as_usize().unwrap_or(0),
+        end: json["end"].as_usize().unwrap_or(0),
+        expansion: None,
+    }
+}
+
+/// Serializes the attributes that mean something for a declaration
+fn serializeAttributes(attrs: &[AstAttribute], fileMap: &mut FileMap) -> JsonValue {
+    let attrs = attrs
+        .iter()
+        .map(|attr| match attr.getKind() {
+            Kind::AlignAs => object! {
+                kind: "alignas",
+                location: serializeSourceRange(&attr.getSourceRange(), fileMap),
+            },
+            Kind::Cxx(members) => object! {
+                kind: "cxx",
+                location: serializeSourceRange(&attr.getSourceRange(), fileMap),
+                members: members.iter().filter_map(|member| match member {
+                    AstAttributeCXX::AstAttributeCXXRustyCppUnused(_) => {
+                        Some(object! {name: "unused"})
+                    }
+                    AstAttributeCXX::AstAttributeCXXRustyCppTagDecl(tag) => {
+                        let number = tag.getNumber();
+                        let Token::IntegerLiteral(value, _) = number.tokPos.tok else {
+                            unreachable!();
+                        };
+                        Some(object! {
+                            name: "tagDecl",
+                            number: value.to_string(),
+                            location: serializeSourceRange(&SourceRange::newSingleTok(&number), fileMap),
+                        })
+                    }
+                    // Only meaningful in attribute declarations
+                    AstAttributeCXX::AstAttributeCXXRustyCppCheckSymbolMatchTag(_) => None,
+                }).collect::<Vec<_>>(),
+            },
+        })
+        .collect::<Vec<_>>();
+    JsonValue::Array(attrs)
+}
+
+/// Serializes the declarations of a scope that are in the module purview, in
+/// order of name so the BMI is reproducible
+fn serializeScope(scope: &ScopeRef, fileMap: &mut FileMap) -> JsonValue {
+    let scope = scope.borrow();
+    let mut names = scope.childs.keys().collect::<Vec<_>>();
+    names.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    let mut declarations = vec![];
+    for name in names {
+        for child in &scope.childs[name] {
+            let Child::Scope(childScope) = child else {
+                continue;
+            };
+            let visibility = childScope.borrow().visibility;
+            if visibility == ModuleVisibility::NotInModule {
+                continue;
+            }
+            let visibility = match visibility {
+                ModuleVisibility::Exported => "exported",
+                ModuleVisibility::Module => "module",
+                _ => "global",
+            };
+            let decl = childScope.borrow().causingDecl.unwrap();
+            let location = serializeSourceRange(&decl.getSourceRange(), fileMap);
+            let attributes = serializeAttributes(decl.getAttributes(), fileMap);
+            match decl {
+                AstDecl::AstDeclNamespace(namespace) => declarations.push(object! {
+                    kind: "namespace",
+                    name: name.as_ref(),
+                    inline: namespace.isInline(),
+                    visibility: visibility,
+                    location: location,
+                    attributes: attributes,
+                    declarations: serializeScope(childScope, fileMap),
+                }),
+                AstDecl::AstDeclCustomRustyCppEnum(_) => declarations.push(object! {
+                    kind: "enum",
+                    name: name.as_ref(),
+                    visibility: visibility,
+                    location: location,
+                    attributes: attributes,
+                }),
+                _ => {}
+            }
+        }
+    }
+    JsonValue::Array(declarations)
+}
+
+/// Serializes a type of the [`TypeDict`]
+fn serializeQualType(ty: &QualType) -> JsonValue {
+    let unqualType = match ty.getUnqualType() {
+        AstType::AstTypeBuiltin(builtin) => object! {builtin: <&str>::from(builtin.getKind())},
+        AstType::AstTypePointer(pointer) => {
+            object! {pointer: serializeQualType(pointer.getPointee())}
+        }
+        AstType::AstTypeReference(reference) => {
+            object! {lvalueReference: serializeQualType(reference.getReferenced())}
+        }
+    };
+    object! {
+        flags: ty.getFlags().bits(),
+        type: unqualType,
+    }
+}
+
+/// Recreates a serialized type in the [`TypeDict`] of the importer
+fn loadQualType(json: &JsonValue, typeDict: &mut TypeDict) -> Option<QualType> {
+    let flags = QualTypeFlags::from_bits(json["flags"].as_u8()?)?;
+    let ty = &json["type"];
+    let unqualType: AstType = if let Some(builtin) = ty["builtin"].as_str() {
+        typeDict
+            .getBuiltinType(BuiltinTypeKind::from_str(builtin).ok()?)
+            .into()
+    } else if ty["pointer"].is_object() {
+        let pointee = loadQualType(&ty["pointer"], typeDict)?;
+        typeDict.getPtrType(pointee).into()
+    } else {
+        let referenced = loadQualType(&ty["lvalueReference"], typeDict)?;
+        typeDict.getLValueReference(referenced).into()
+    };
+    Some(QualType::new(unqualType, flags))
+}
+
+/// Recreates the declarations of a BMI in the arena of an importer
+struct BmiLoader<'a> {
+    /// Arena of the importer
+    alloc: &'static bumpalo::Bump,
+    /// To map the paths of the locations to files
+    fileMap: &'a mut FileMap,
+    /// Primary name of the module of the BMI
+    module: StringRef,
+    /// Does the importer belong to the same module?
+    sameModule: bool,
+}
+
+impl BmiLoader<'_> {
+    /// Can the importer name a serialized declaration?
+    fn reachability(&self, json: &JsonValue) -> Reachability {
+        match json["visibility"].as_str() {
+            Some("exported") => Reachability::Visible,
+            Some("module") if self.sameModule => Reachability::Visible,
+            Some("module") => Reachability::NotExported(self.module),
+            _ => Reachability::GlobalModuleFragment(self.module),
+        }
+    }
+
+    /// A serialized location
+    fn sourceRange(&mut self, json: &JsonValue) -> SourceRange {
+        loadSourceRange(json, self.fileMap)
+    }
+
+    /// Serialized attributes
+    fn attributes(&mut self, json: &JsonValue) -> &'static [AstAttribute] {
+        let mut attrs = vec![];
+        for attr in json.members() {
+            let location = self.sourceRange(&attr["location"]);
+            let kind = if attr["kind"] == "alignas" {
+                Kind::AlignAs
+            } else {
+                let mut members: Vec<AstAttributeCXX> = vec![];
+                for member in attr["members"].members() {
+                    if member["name"] == "unused" {
+                        members.push(AstAttributeCXXRustyCppUnused::new(self.alloc).into());
+                    } else if let Some(number) = member["number"]
+                        .as_str()
+                        .and_then(|number| number.parse::<i128>().ok())
+                    {
+                        let location = self.sourceRange(&member["location"]);
+                        let tok = FileTokPos::new(
+                            location.startfile,
+                            TokPos {
+                                start: location.start,
+                                end: location.end,
+                                tok: Token::IntegerLiteral(
+                                    number,
+                                    IntegerSuffix(None, IntegerSuffixSignedness::Signed),
+                                ),
+                            },
+                        );
+                        members.push(AstAttributeCXXRustyCppTagDecl::new(self.alloc, tok).into());
+                    }
+                }
+                Kind::Cxx(self.alloc.alloc_slice_copy(&members))
+            };
+            attrs.push(AstAttribute::new(self.alloc, kind, location));
+        }
+        self.alloc.alloc_slice_copy(&attrs)
+    }
+
+    /// Adds the serialized declarations to a scope. Namespaces that already
+    /// exist in it (from another import) are extended.
+    fn loadScope(&mut self, json: &JsonValue, scope: &ScopeRef) {
+        for decl in json.members() {
+            let Some(name) = decl["name"].as_str() else {
+                continue;
+            };
+            let name = name.to_StringRef();
+            let location = self.sourceRange(&decl["location"]);
+            let attrs = self.attributes(&decl["attributes"]);
+            let reachability = self.reachability(decl);
+            if decl["kind"] == "namespace" {
+                let isInline = decl["inline"].as_bool().unwrap_or(false);
+                let existing = scope.borrow().childs.get(&name).and_then(|childs| {
+                    childs.iter().find_map(|child| match child {
+                        Child::Scope(child)
+                            if child.borrow().flags
+                                == ScopeKind::NAMESPACE | ScopeKind::CAN_DECL =>
+                        {
+                            Some(child.clone())
+                        }
+                        _ => None,
+                    })
+                });
+                let namespaceScope = if let Some(existing) = existing {
+                    let extension = AstDeclNamespace::new(
+                        self.alloc,
+                        location,
+                        existing.clone(),
+                        attrs,
+                        name,
+                        isInline,
+                        scope.clone(),
+                    );
+                    let AstDecl::AstDeclNamespace(original) =
+                        existing.borrow().causingDecl.unwrap()
+                    else {
+                        unreachable!();
+                    };
+                    original.addExtension(extension);
+                    // Visible if any of its declarations is.
+                    if reachability == Reachability::Visible {
+                        existing.borrow_mut().reachability = reachability;
+                    }
+                    existing
+                } else {
+                    let namespaceScope = Scope::new(ScopeKind::NAMESPACE | ScopeKind::CAN_DECL);
+                    let namespace = AstDeclNamespace::new(
+                        self.alloc,
+                        location,
+                        namespaceScope.clone(),
+                        attrs,
+                        name,
+                        isInline,
+                        scope.clone(),
+                    );
+                    namespaceScope.setCausingDecl(namespace.into());
+                    namespaceScope.borrow_mut().reachability = reachability;
+                    if isInline {
+                        scope.addInlinedChild(name, namespaceScope.clone());
+                    } else {
+                        scope.addChild(name, Child::Scope(namespaceScope.clone()));
+                    }
+                    namespaceScope
+                };
+                self.loadScope(&decl["declarations"], &namespaceScope);
+            } else if decl["kind"] == "enum" {
+                let enumScope = Scope::new(ScopeKind::ENUM | ScopeKind::CAN_DECL);
+                let astEnum = AstDeclCustomRustyCppEnum::new(
+                    self.alloc,
+                    location,
+                    enumScope.clone(),
+                    attrs,
+                    name,
+                );
+                enumScope.setCausingDecl(astEnum.into());
+                enumScope.borrow_mut().reachability = reachability;
+                scope.addChild(name, Child::Scope(enumScope));
+            }
+        }
+    }
+}
@@ -80,6 +80,42 @@ pub struct ScopeKind : u32 {
 }
 }
 
+/**
+ * How visible a declaration is for the importers of the module that declares
+ * it. The scopes of namespaces take the highest visibility of the
+ * declarations they contain.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ModuleVisibility {
+    /// Not in the purview of a module: a non-module translation unit, the
+    /// private module fragment, or an imported declaration.
+    NotInModule,
+    /// In the global module fragment of a module unit. Reachable, but never
+    /// visible, for the importers.
+    GlobalModuleFragment,
+    /// In the purview of a module, but not exported. Only visible to the
+    /// other units of the same module.
+    Module,
+    /// Exported from the module.
+    Exported,
+}
+
+/**
+ * Can this translation unit name a declaration? The ones imported from a
+ * module that doesn't export them are still found by lookup, but only to
+ * report that they are not visible.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reachability {
+    /// Declared in this translation unit, exported by an imported module, or
+    /// from another unit of the same module (module linkage).
+    Visible,
+    /// Declared in the purview of this module, which does not export it.
+    NotExported(StringRef),
+    /// Declared in the global module fragment of this module.
+    GlobalModuleFragment(StringRef),
+}
+
 #[derive(Clone)]
 pub enum Child {
     /**
@@ -93,6 +129,13 @@ pub enum Child {
 }
 
 impl Child {
+    pub fn isVisible(&self) -> bool {
+        match self {
+            Self::Decl(_) => true,
+            Self::Scope(scope) => scope.borrow().reachability == Reachability::Visible,
+        }
+    }
+
     pub fn getScope(&self) -> Option<ScopeRef> {
         match self {
             Self::Decl(_) => None,
@@ -149,6 +192,16 @@ pub struct Scope {
      * These are using namespace scopes
      */
     pub usingNamespaces: Vec<ScopeRef>,
+
+    /**
+     * Visibility of the causing declaration for the importers of this module
+     */
+    pub visibility: ModuleVisibility,
+
+    /**
+     * Can this translation unit name the causing declaration?
+     */
+    pub reachability: Reachability,
 }
 
 impl Scope {
@@ -161,6 +214,8 @@ impl Scope {
             causingDecl: None,
             inlinedNamespaces: Vec::new(),
             usingNamespaces: Vec::new(),
+            visibility: ModuleVisibility::NotInModule,
+            reachability: Reachability::Visible,
         }))
     }
 
@@ -173,6 +228,8 @@ impl Scope {
             causingDecl: None,
             inlinedNamespaces: Vec::new(),
             usingNamespaces: Vec::new(),
+            visibility: ModuleVisibility::NotInModule,
+            reachability: Reachability::Visible,
         }))
     }
 
@@ -188,6 +245,7 @@ pub trait RefCellScope {
     fn addChild(&self, name: StringRef, child: Child);
     fn addInlinedChild(&self, name: StringRef, child: ScopeRef);
     fn addUsingNamespace(&self, child: ScopeRef);
+    fn raiseVisibility(&self, visibility: ModuleVisibility);
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -238,4 +296,22 @@ impl RefCellScope for ScopeRef {
         let mut this = self.borrow_mut();
         this.usingNamespaces.push(child);
     }
+
+    fn raiseVisibility(&self, visibility: ModuleVisibility) {
+        let mut scope = self.clone();
+        loop {
+            let parent = {
+                let mut this = scope.borrow_mut();
+                if this.visibility >= visibility {
+                    break;
+                }
+                this.visibility = visibility;
+                this.parent.clone()
+            };
+            let Some(parent) = parent else {
+                break;
+            };
+            scope = parent;
+        }
+    }
 }
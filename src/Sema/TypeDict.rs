@@ -58,4 +58,12 @@ impl TypeDict {
             .entry(nodeId)
             .or_insert_with(|| AstTypeReference::new(alloc, t))
     }
+
+    pub fn pointerTypes(&self) -> impl Iterator<Item = AstTypePointer> + '_ {
+        self.pointer.values().copied()
+    }
+
+    pub fn lvalueReferenceTypes(&self) -> impl Iterator<Item = AstTypeReference> + '_ {
+        self.lvalueReference.values().copied()
+    }
 }
@@ -1,10 +1,18 @@
 //! Test suite of the compiler
 #![allow(missing_docs, clippy::missing_docs_in_private_items)]
 #[cfg(test)]
+pub mod TestAmalgamate;
+#[cfg(test)]
+pub mod TestDaemon;
+#[cfg(test)]
+pub mod TestFileSystem;
+#[cfg(test)]
 pub mod TestIncluder;
 #[cfg(test)]
 pub mod TestLexer;
 #[cfg(test)]
+pub mod TestLsp;
+#[cfg(test)]
 pub mod TestPreprocessorDefine;
 #[cfg(test)]
 pub mod TestPreprocessorIf;
@@ -12,3 +20,7 @@ pub mod TestPreprocessorIf;
 pub mod TestProject;
 #[cfg(test)]
 pub mod TestSingleFile;
+#[cfg(test)]
+pub mod TestTokenCache;
+#[cfg(test)]
+pub mod TestUnifdef;
@@ -0,0 +1,92 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::Preprocessor::Amalgamate::Amalgamator;
+use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::{InMemoryFileSystem, OverlayFileSystem, RealFileSystem};
+use crate::Utils::Parameters::Parameters;
+use crate::Utils::Structs::{CompileMsg, CompileMsgKind};
+
+use test_log::test;
+
+fn amalgamate(content: &'static str) -> (String, Vec<CompileMsg>) {
+    let mut params = Parameters::new();
+    params.includeDirs.push(
+        Path::new(file!())
+            .parent()
+            .unwrap()
+            .join("include")
+            .to_str()
+            .unwrap()
+            .to_string(),
+    );
+    params.translationUnits.push("/test".to_string());
+    let fileSystem = InMemoryFileSystem::new();
+    fileSystem.addFile("/test", content);
+    let fileSystem = OverlayFileSystem::new(vec![Arc::new(fileSystem), Arc::new(RealFileSystem)]);
+    let mut fileMap = FileMap::new_with_fs(Arc::new(params), Arc::new(fileSystem));
+    let tu = fileMap.getAddFile("/test");
+    Amalgamator::run(&Arc::new(Mutex::new(fileMap)), tu)
+}
+
+#[test]
+fn inlinesHeadersOnceInOrder() {
+    let (res, warnings) = amalgamate("#include \"amalgamate/lib.h\"\nint main();\n");
+    assert!(warnings.is_empty());
+    assert_eq!(
+        res,
+        "// begin \"amalgamate/lib.h\"\n\
+         // The library\n\
+         #ifndef LIB_H\n\
+         #define LIB_H\n\
+         // begin \"amalgamate/detail.h\"\n\
+         // begin \"amalgamate/xmacro.h\"\n\
+         X(a)\n\
+         // end \"amalgamate/xmacro.h\"\n\
+         int detail();\n\
+         // end \"amalgamate/detail.h\"\n\
+         #include <vector>\n\
+         int lib();\n\
+         #endif\n\
+         // end \"amalgamate/lib.h\"\n\
+         int main();\n"
+    );
+}
+
+#[test]
+fn unguardedHeadersAreInlinedEachTime() {
+    let (res, _) =
+        amalgamate("#include \"amalgamate/xmacro.h\"\n#include \"amalgamate/xmacro.h\"\n");
+    assert_eq!(res.matches("X(a)").count(), 2);
+}
+
+#[test]
+fn missingHeadersAreKept() {
+    let (res, warnings) = amalgamate("#include \"amalgamate/missing.h\"\n#include <stdio.h>\n");
+    assert_eq!(
+        res,
+        "#include \"amalgamate/missing.h\"\n#include <stdio.h>\n"
+    );
+    assert_eq!(warnings.len(), 1);
+}
+
+#[test]
+fn computedIncludesAreReported() {
+    let (res, messages) = amalgamate("#define HEADER \"amalgamate/xmacro.h\"\n#include HEADER\n");
+    assert_eq!(
+        res,
+        "#define HEADER \"amalgamate/xmacro.h\"\n#include HEADER\n"
+    );
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].severity(), CompileMsgKind::Error);
+}
+
+#[test]
+fn conditionalIncludesAreKept() {
+    let (res, messages) =
+        amalgamate("#if 0\n#include \"amalgamate/lib.h\"\n#endif\n#include \"amalgamate/lib.h\"\n");
+    assert!(res.starts_with("#if 0\n#include \"amalgamate/lib.h\"\n#endif\n"));
+    assert_eq!(res.matches("int lib();").count(), 1);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].severity(), CompileMsgKind::Warning);
+}
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use ::function_name::named;
+use test_log::test;
+
+use crate::Daemon::{
+    requestCompile, requestStop, serveCompiles,
+    Server::{CompileServer, CompileSummary},
+};
+
+/// Empty directory for a test
+fn testDir(funcName: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-daemon")
+        .join(funcName);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+/// Writes a project of three translation units: `b.cpp` imports the module
+/// of `a.cpp`, which includes `a.h`, and `c.cpp` has an error. Returns the
+/// path of its file list.
+fn writeProject(dir: &Path) -> String {
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write("a.h", "#define A 1\n");
+    write(
+        "a.cpp",
+        "module;\n#include \"a.h\"\nexport module a;\nexport namespace a1 {}\n",
+    );
+    write(
+        "b.cpp",
+        "export module b;\nimport a;\nnamespace b1 { using namespace a1; }\n",
+    );
+    write("c.cpp", "namespace c {}\nusing namespace nope;\n");
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let list = json::object! {
+        translationUnits: ["a.cpp", "b.cpp", "c.cpp"].map(path).to_vec(),
+        includeDirs: [dir.to_str().unwrap()],
+        bmiDir: path("bmi"),
+    };
+    write("list.json", &list.dump());
+    path("list.json")
+}
+
+#[test]
+#[named]
+fn recompilesWhatChanged() {
+    let dir = testDir(function_name!());
+    let list = writeProject(&dir);
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let paths = |files: &[&str]| files.iter().map(|file| path(file)).collect::<Vec<_>>();
+    let mut server = CompileServer::default();
+    let mut compile = || {
+        let mut output = vec![];
+        let summary = server.compile(&list, &dir, &mut output).unwrap();
+        (summary, String::from_utf8(output).unwrap())
+    };
+
+    let (first, output) = compile();
+    assert_eq!(
+        first,
+        CompileSummary {
+            compiled: paths(&["a.cpp", "b.cpp", "c.cpp"]),
+            cached: vec![],
+            errors: 1,
+        }
+    );
+    assert!(output.contains("unable to resolve"), "{output}");
+
+    // Nothing changed: the diagnostics are replayed
+    let (unchanged, output) = compile();
+    assert!(unchanged.compiled.is_empty());
+    assert_eq!(unchanged.cached, paths(&["a.cpp", "b.cpp", "c.cpp"]));
+    assert_eq!(unchanged.errors, 1);
+    let lines = output
+        .lines()
+        .map(|line| json::parse(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines[0]["kind"], "diagnostic");
+    assert_eq!(lines[0]["unit"], path("c.cpp"));
+    assert_eq!(lines[0]["cached"], true);
+    assert_eq!(lines.last().unwrap()["kind"], "done");
+
+    // A header changed: its includer and the importer of its module
+    std::fs::write(dir.join("a.h"), "#define A 2\n").unwrap();
+    let (header, _) = compile();
+    assert_eq!(header.compiled, paths(&["a.cpp", "b.cpp"]));
+    assert_eq!(header.cached, paths(&["c.cpp"]));
+    assert_eq!(header.errors, 1);
+
+    // Fixing the error only compiles its translation unit
+    std::fs::write(dir.join("c.cpp"), "namespace c {}\nusing namespace c;\n").unwrap();
+    let (fixed, _) = compile();
+    assert_eq!(fixed.compiled, paths(&["c.cpp"]));
+    assert_eq!(fixed.errors, 0);
+
+    // A new file list starts over
+    std::fs::write(
+        &list,
+        json::object! {translationUnits: [path("c.cpp")]}.dump(),
+    )
+    .unwrap();
+    let (newList, _) = compile();
+    assert_eq!(newList.compiled, paths(&["c.cpp"]));
+    assert!(newList.cached.is_empty());
+}
+
+#[test]
+#[named]
+fn resolvesAgainstClientDirectory() {
+    let dir = testDir(function_name!());
+    for (client, content) in [("a", "namespace c {}\n"), ("b", "using namespace nope;\n")] {
+        std::fs::create_dir(dir.join(client)).unwrap();
+        std::fs::write(dir.join(client).join("c.cpp"), content).unwrap();
+    }
+    let list = json::object! {translationUnits: ["c.cpp"], includeDirs: ["."]};
+    std::fs::write(dir.join("list.json"), list.dump()).unwrap();
+    let cwd = std::env::current_dir().unwrap();
+    let mut server = CompileServer::default();
+    let mut compile = |client: &str| {
+        server
+            .compile("../list.json", &dir.join(client), &mut vec![])
+            .unwrap()
+    };
+
+    // The same file list is a different project for each directory
+    let a = compile("a");
+    assert_eq!(a.errors, 0);
+    let b = compile("b");
+    assert_eq!(b.compiled, [dir.join("b").join("c.cpp").to_str().unwrap()]);
+    assert_eq!(b.errors, 1);
+    assert_eq!(compile("a").cached.len(), 1);
+    assert_eq!(std::env::current_dir().unwrap(), cwd);
+}
+
+#[test]
+#[named]
+fn serveOverSocket() {
+    let dir = testDir(function_name!());
+    let list = writeProject(&dir);
+    let socket = dir.join("daemon.sock");
+    let server = {
+        let socket = socket.clone();
+        thread::spawn(move || serveCompiles(&socket))
+    };
+    while !socket.exists() {
+        thread::yield_now();
+    }
+    assert!(serveCompiles(&socket).is_err());
+
+    let mut output = vec![];
+    assert!(!requestCompile(&socket, &list, &mut output).unwrap());
+    let mut again = vec![];
+    assert!(!requestCompile(&socket, &list, &mut again).unwrap());
+    let output = String::from_utf8(output).unwrap();
+    let again = String::from_utf8(again).unwrap();
+    assert!(output.contains("unable to resolve"), "{output}");
+    assert!(output.ends_with("3 translation units compiled, 0 up to date\n"));
+    assert!(again.ends_with("0 translation units compiled, 3 up to date\n"));
+
+    let missing = dir.join("missing.json");
+    let err = requestCompile(&socket, missing.to_str().unwrap(), &mut vec![]).unwrap_err();
+    assert!(err.contains("Can't find the file list"), "{err}");
+
+    requestStop(&socket).unwrap();
+    server.join().unwrap().unwrap();
+    assert!(!socket.exists());
+}
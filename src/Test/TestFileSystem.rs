@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ::function_name::named;
+use test_log::test;
+
+use crate::{
+    Ast::Common::CommonAst,
+    Compiler::Compiler,
+    Utils::{
+        FileSystem::{FileSystem, InMemoryFileSystem, OverlayFileSystem, RealFileSystem},
+        Parameters::Parameters,
+    },
+};
+
+#[test]
+fn inMemoryNormalizesPaths() {
+    let fileSystem = InMemoryFileSystem::new();
+    fileSystem.addFile("/project/./src/../a.cpp", "namespace a {}");
+    assert!(fileSystem.exists(Path::new("/project/a.cpp")));
+    assert!(fileSystem.exists(Path::new("project/a.cpp")));
+    assert!(!fileSystem.exists(Path::new("/project/src/a.cpp")));
+    assert_eq!(
+        fileSystem.canonicalize(Path::new("/project/src/../a.cpp")),
+        Ok(PathBuf::from("/project/a.cpp"))
+    );
+    assert_eq!(
+        fileSystem.read(Path::new("/project/a.cpp")).as_deref(),
+        Ok("namespace a {}")
+    );
+
+    fileSystem.addFile("/project/a.cpp", "namespace b {}");
+    assert_eq!(
+        fileSystem.read(Path::new("/project/a.cpp")).as_deref(),
+        Ok("namespace b {}")
+    );
+    assert!(fileSystem.removeFile("/project/a.cpp"));
+    assert!(!fileSystem.removeFile("/project/a.cpp"));
+    assert!(fileSystem.read(Path::new("/project/a.cpp")).is_err());
+    assert!(fileSystem
+        .canonicalize(Path::new("/project/a.cpp"))
+        .is_err());
+}
+
+#[test]
+fn overlayPrefersUpperLayers() {
+    let upper = InMemoryFileSystem::new();
+    upper.addFile("/a.cpp", "upper");
+    let lower = InMemoryFileSystem::new();
+    lower.addFile("/a.cpp", "lower");
+    lower.addFile("/b.cpp", "only lower");
+    let overlay = OverlayFileSystem::new(vec![Arc::new(upper), Arc::new(lower)]);
+    assert_eq!(overlay.read(Path::new("/a.cpp")).as_deref(), Ok("upper"));
+    assert_eq!(
+        overlay.read(Path::new("/b.cpp")).as_deref(),
+        Ok("only lower")
+    );
+    assert!(overlay.exists(Path::new("/b.cpp")));
+    assert!(!overlay.exists(Path::new("/c.cpp")));
+    assert!(overlay.read(Path::new("/c.cpp")).is_err());
+}
+
+#[test]
+#[named]
+fn unsavedBuffersOverDisk() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-file-system")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write("a.cpp", "namespace saved {}\n");
+    write("disk.h", "#define DISK disk\n");
+
+    // The buffer of a.cpp is not saved, and includes a header only in memory
+    let buffers = InMemoryFileSystem::new();
+    buffers.addFile(
+        dir.join("a.cpp"),
+        "#include \"disk.h\"\n#include \"memory.h\"\nnamespace DISK {}\nnamespace MEMORY {}\n",
+    );
+    buffers.addFile(dir.join("memory.h"), "#define MEMORY memory\n");
+    let fileSystem = OverlayFileSystem::new(vec![Arc::new(buffers), Arc::new(RealFileSystem)]);
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let mut parameters = Parameters::new();
+    parameters.translationUnits = vec![path("a.cpp")];
+    parameters.includeDirs = vec![dir.to_str().unwrap().to_string()];
+    parameters.bmiDir = path("bmi");
+    let mut compiler = Compiler::new_with_fs(parameters, Arc::new(fileSystem));
+    let (asts, errors) = compiler.parsedTrees();
+    for err in &errors {
+        err.print(compiler.fileMap());
+    }
+    assert!(errors.is_empty());
+    let ast = asts[&path("a.cpp")].getDebugNode().to_string_colorless();
+    assert!(ast.contains("name: disk"));
+    assert!(ast.contains("name: memory"));
+    assert!(!ast.contains("name: saved"));
+}
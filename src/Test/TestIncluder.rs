@@ -2,10 +2,15 @@ use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use crate::Grammars::DefineAst::DefineAst;
+use crate::Preprocessor::Callbacks::PreprocessorCallbacks;
+use crate::Preprocessor::IncludeTree::IncludeTree;
+use crate::Preprocessor::MacroIndex::{MacroIndex, MacroSiteKind};
 use crate::Preprocessor::Preprocessor;
 use crate::Preprocessor::Pretoken::PreToken;
 use crate::Utils::CompilerState::CompilerState;
 use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::{InMemoryFileSystem, OverlayFileSystem, RealFileSystem};
 use crate::Utils::Parameters::Parameters;
 use crate::Utils::StateCompileUnit::StateCompileUnit;
 use crate::Utils::Structs::{CompileMsg, FileTokPos};
@@ -24,19 +29,23 @@ fn generateFileMap(files: &[(&'static str, &'static str)]) -> (CompilerState, u6
             .to_string(),
     );
 
-    for (filePath, _) in files {
-        params.translationUnits.push((*filePath).to_string());
+    let fileSystem = InMemoryFileSystem::new();
+    for (filePath, fileContents) in files {
+        let path = Path::new("/").join(filePath).to_str().unwrap().to_string();
+        fileSystem.addFile(&path, fileContents.to_string());
+        params.translationUnits.push(path);
     }
 
     let parameters = Arc::new(params);
-    let fileMap = Arc::new(Mutex::new(FileMap::new(parameters.clone())));
+    let fileSystem = OverlayFileSystem::new(vec![Arc::new(fileSystem), Arc::new(RealFileSystem)]);
+    let fileMap = Arc::new(Mutex::new(FileMap::new_with_fs(
+        parameters.clone(),
+        Arc::new(fileSystem),
+    )));
     let mut compileUnits = HashMap::new();
-    for (i, (filePath, fileContents)) in files.iter().enumerate() {
-        fileMap
-            .lock()
-            .unwrap()
-            .addTestFile((*filePath).to_string(), fileContents);
-        compileUnits.insert(i as u64 + 1, StateCompileUnit::new());
+    for path in &parameters.translationUnits {
+        let tu = fileMap.lock().unwrap().getAddFile(path);
+        compileUnits.insert(tu, StateCompileUnit::new());
     }
 
     (
@@ -47,6 +56,12 @@ fn generateFileMap(files: &[(&'static str, &'static str)]) -> (CompilerState, u6
             translationUnitsFiles: Arc::new((1..2).collect::<HashSet<_>>()),
             moduleHeaderUnitsFiles: Arc::new(HashSet::new()),
             foundErrors: Arc::default(),
+            tokenCache: Arc::default(),
+            bmiCache: Arc::default(),
+            preprocessorCallbacks: vec![],
+            macroTracer: None,
+            timeTrace: None,
+            conditionalReports: None,
         },
         1,
     )
@@ -145,3 +160,192 @@ fn testHeaderOpeningTwice() {
     assert_eq!(toks[0].to_str(), "SUCCESS");
     assert_eq!(toks[1].to_str(), "SUCCESS");
 }
+
+#[derive(Debug, Default)]
+struct RecordCallbacks {
+    events: Mutex<Vec<String>>,
+}
+
+impl PreprocessorCallbacks for RecordCallbacks {
+    fn fileEntered(&self, _tu: u64, file: u64) {
+        self.events.lock().unwrap().push(format!("enter {file}"));
+    }
+
+    fn fileExited(&self, _tu: u64, file: u64) {
+        self.events.lock().unwrap().push(format!("exit {file}"));
+    }
+
+    fn includeResolved(&self, _tu: u64, _: &FileTokPos<PreToken>, path: &str, _file: u64) {
+        self.events.lock().unwrap().push(format!("include {path}"));
+    }
+
+    fn includeFailed(&self, _tu: u64, _: &FileTokPos<PreToken>, path: Option<&str>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("include failed {path:?}"));
+    }
+
+    fn macroDefined(&self, _tu: u64, _: &FileTokPos<PreToken>, definition: &DefineAst) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("define {}", definition.id));
+    }
+
+    fn macroUndefined(&self, _tu: u64, _: &FileTokPos<PreToken>, name: &str) {
+        self.events.lock().unwrap().push(format!("undef {name}"));
+    }
+
+    fn macroExpanded(&self, definition: &DefineAst, _: &FileTokPos<PreToken>) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("expand {}", definition.id));
+    }
+
+    fn conditionalEvaluated(&self, _tu: u64, directive: &FileTokPos<PreToken>, value: bool) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{} {value}", directive.tokPos.tok.to_str()));
+    }
+
+    fn pragma(&self, _tu: u64, _: &FileTokPos<PreToken>, toks: &[FileTokPos<PreToken>]) {
+        self.events.lock().unwrap().push(format!(
+            "pragma {}",
+            toks.iter()
+                .map(|tok| tok.tokPos.tok.to_str())
+                .collect::<String>()
+                .trim()
+        ));
+    }
+}
+
+#[test]
+fn testCallbacks() {
+    let (mut compilerState, tu) = generateFileMap(&[(
+        "test",
+        r#"#define A(x) x
+#include "header.h"
+#if A(0)
+#elif defined(A)
+#pragma once
+#endif
+#ifdef B
+#endif
+#undef A
+#include "missing.h"
+"#,
+    )]);
+    let callbacks = Arc::new(RecordCallbacks::default());
+    compilerState.preprocessorCallbacks.push(callbacks.clone());
+    for _ in Preprocessor::new((compilerState, tu)) {}
+    assert_eq!(
+        *callbacks.events.lock().unwrap(),
+        [
+            "enter 1",
+            "define A",
+            "include header.h",
+            "enter 2",
+            "exit 2",
+            "expand A",
+            "if false",
+            "elif true",
+            "pragma once",
+            "ifdef false",
+            "undef A",
+            "include failed Some(\"missing.h\")",
+            "exit 1",
+        ]
+    );
+}
+
+#[test]
+fn testIncludeTree() {
+    let (mut compilerState, tu) = generateFileMap(&[(
+        "test",
+        r#"#include "includeTree/a.h"
+#include "includeTree/b.h"
+#include "includeTree/a.h"
+int main();
+"#,
+    )]);
+    let includeTree = Arc::new(IncludeTree::default());
+    compilerState
+        .preprocessorCallbacks
+        .push(includeTree.clone());
+    for _ in Preprocessor::new((compilerState, tu)) {}
+    let nodes = includeTree
+        .nodes(tu)
+        .into_iter()
+        .map(|node| (node.depth, node.repeated, node.guardSkipped, node.tokens))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        nodes,
+        [
+            (0, false, false, 13),
+            (1, false, false, 8),
+            (2, false, false, 5),
+            (1, true, true, 0),
+            (1, true, true, 0),
+        ]
+    );
+    let costs = includeTree
+        .costs()
+        .into_iter()
+        .map(|cost| (cost.includes, cost.tokens))
+        .collect::<Vec<_>>();
+    assert!(costs.contains(&(2, 8)));
+    assert!(costs.contains(&(2, 5)));
+}
+
+#[test]
+fn testMacroIndex() {
+    let (mut compilerState, tu) = generateFileMap(&[(
+        "test",
+        r#"#define A 1
+#define A 1
+#define UNUSED
+#ifdef MISSING
+#endif
+#if defined(A) && A
+#endif
+int a = A;
+#undef A
+"#,
+    )]);
+    let macroIndex = Arc::new(MacroIndex::default());
+    compilerState.preprocessorCallbacks.push(macroIndex.clone());
+    for _ in Preprocessor::new((compilerState, tu)) {}
+    let sites = macroIndex
+        .sites()
+        .into_iter()
+        .map(|(name, sites)| {
+            (
+                name,
+                sites.into_iter().map(|site| site.kind).collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sites,
+        [
+            (
+                "A".to_string(),
+                vec![
+                    MacroSiteKind::Definition,
+                    MacroSiteKind::Redefinition,
+                    MacroSiteKind::Undefinition,
+                    MacroSiteKind::Expansion,
+                    MacroSiteKind::Expansion,
+                    MacroSiteKind::Test,
+                ]
+            ),
+            ("MISSING".to_string(), vec![MacroSiteKind::Test]),
+            ("UNUSED".to_string(), vec![MacroSiteKind::Definition]),
+        ]
+    );
+    assert_eq!(macroIndex.unused(), ["UNUSED"]);
+    assert_eq!(macroIndex.testedNeverDefined(), ["MISSING"]);
+}
@@ -10,6 +10,7 @@ use crate::Preprocessor::Preprocessor;
 use crate::Preprocessor::Pretoken::PreToken;
 use crate::Utils::CompilerState::CompilerState;
 use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::{InMemoryFileSystem, OverlayFileSystem, RealFileSystem};
 use crate::Utils::Parameters::Parameters;
 use crate::Utils::StateCompileUnit::StateCompileUnit;
 use crate::Utils::StringRef::ToStringRef;
@@ -30,19 +31,23 @@ fn generateFileMap(files: &[(&'static str, &'static str)]) -> (CompilerState, u6
             .to_string(),
     );
 
-    for (filePath, _) in files {
-        params.translationUnits.push((*filePath).to_string());
+    let fileSystem = InMemoryFileSystem::new();
+    for (filePath, fileContents) in files {
+        let path = Path::new("/").join(filePath).to_str().unwrap().to_string();
+        fileSystem.addFile(&path, fileContents.to_string());
+        params.translationUnits.push(path);
     }
 
     let parameters = Arc::new(params);
-    let fileMap = Arc::new(Mutex::new(FileMap::new(parameters.clone())));
+    let fileSystem = OverlayFileSystem::new(vec![Arc::new(fileSystem), Arc::new(RealFileSystem)]);
+    let fileMap = Arc::new(Mutex::new(FileMap::new_with_fs(
+        parameters.clone(),
+        Arc::new(fileSystem),
+    )));
     let mut compileUnits = HashMap::new();
-    for (i, (filePath, fileContents)) in files.iter().enumerate() {
-        fileMap
-            .lock()
-            .unwrap()
-            .addTestFile((*filePath).to_string(), fileContents);
-        compileUnits.insert(i as u64 + 1, StateCompileUnit::new());
+    for path in &parameters.translationUnits {
+        let tu = fileMap.lock().unwrap().getAddFile(path);
+        compileUnits.insert(tu, StateCompileUnit::new());
     }
 
     (
@@ -53,6 +58,12 @@ fn generateFileMap(files: &[(&'static str, &'static str)]) -> (CompilerState, u6
             translationUnitsFiles: Arc::new((1..2).collect::<HashSet<_>>()),
             moduleHeaderUnitsFiles: Arc::new(HashSet::new()),
             foundErrors: Arc::default(),
+            tokenCache: Arc::default(),
+            bmiCache: Arc::default(),
+            preprocessorCallbacks: vec![],
+            macroTracer: None,
+            timeTrace: None,
+            conditionalReports: None,
         },
         1,
     )
@@ -0,0 +1,306 @@
+use std::io::Cursor;
+
+use json::{object, JsonValue};
+use test_log::test;
+
+use crate::{
+    Lsp::Protocol::{
+        offsetToPosition, pathToUri, positionToOffset, readMessage, uriToPath, writeMessage,
+    },
+    Lsp::Server::serve,
+    Utils::Parameters::Parameters,
+};
+
+/// A scripted editor: the messages it sends, in order
+#[derive(Default)]
+struct Client {
+    /// Messages sent, framed
+    input: Vec<u8>,
+    /// Next request id
+    nextId: usize,
+}
+
+impl Client {
+    fn request(&mut self, method: &str, params: JsonValue) -> usize {
+        self.nextId += 1;
+        writeMessage(
+            &mut self.input,
+            &object! {jsonrpc: "2.0", id: self.nextId, method: method, params: params},
+        )
+        .unwrap();
+        self.nextId
+    }
+
+    fn notify(&mut self, method: &str, params: JsonValue) {
+        writeMessage(
+            &mut self.input,
+            &object! {jsonrpc: "2.0", method: method, params: params},
+        )
+        .unwrap();
+    }
+
+    fn open(&mut self, path: &str, text: &str) {
+        self.notify(
+            "textDocument/didOpen",
+            object! {textDocument: object! {uri: pathToUri(path), languageId: "cpp", version: 1, text: text}},
+        );
+    }
+
+    fn change(&mut self, path: &str, text: &str) {
+        self.notify(
+            "textDocument/didChange",
+            object! {
+                textDocument: object! {uri: pathToUri(path), version: 2},
+                contentChanges: [object! {text: text}],
+            },
+        );
+    }
+
+    fn at(&mut self, method: &str, path: &str, line: usize, character: usize) -> usize {
+        self.request(
+            method,
+            object! {
+                textDocument: object! {uri: pathToUri(path)},
+                position: object! {line: line, character: character},
+            },
+        )
+    }
+
+    fn document(&mut self, method: &str, path: &str) -> usize {
+        self.request(
+            method,
+            object! {textDocument: object! {uri: pathToUri(path)}},
+        )
+    }
+
+    /// Runs a server through the whole script, ending it properly. Returns
+    /// the messages of the server.
+    fn run(self) -> Server {
+        self.runWith(Parameters::new())
+    }
+
+    /// Runs a server of the project of `parameters` through the whole
+    /// script, ending it properly. Returns the messages of the server.
+    fn runWith(mut self, parameters: Parameters) -> Server {
+        self.request("shutdown", JsonValue::Null);
+        self.notify("exit", JsonValue::Null);
+        let mut output = vec![];
+        let shutdown = serve(Cursor::new(self.input), &mut output, parameters).unwrap();
+        assert!(shutdown);
+        let mut output = Cursor::new(output);
+        let mut messages = vec![];
+        while let Some(message) = readMessage(&mut output).unwrap() {
+            messages.push(message);
+        }
+        Server { messages }
+    }
+}
+
+/// What the server answered
+struct Server {
+    messages: Vec<JsonValue>,
+}
+
+impl Server {
+    fn response(&self, id: usize) -> &JsonValue {
+        let response = self
+            .messages
+            .iter()
+            .find(|message| message["id"] == id)
+            .unwrap();
+        assert!(response["error"].is_null(), "{}", response.pretty(2));
+        &response["result"]
+    }
+
+    /// Diagnostics published for `path`, in order
+    fn diagnostics(&self, path: &str) -> Vec<&JsonValue> {
+        self.messages
+            .iter()
+            .filter(|message| {
+                message["method"] == "textDocument/publishDiagnostics"
+                    && message["params"]["uri"] == pathToUri(path)
+            })
+            .map(|message| &message["params"]["diagnostics"])
+            .collect()
+    }
+}
+
+fn position(value: &JsonValue) -> (usize, usize) {
+    (
+        value["line"].as_usize().unwrap(),
+        value["character"].as_usize().unwrap(),
+    )
+}
+
+#[test]
+fn uriAndPositions() {
+    assert_eq!(pathToUri("/a b/c++.cpp"), "file:///a%20b/c%2B%2B.cpp");
+    assert_eq!(
+        uriToPath("file:///a%20b/c%2B%2B.cpp").as_deref(),
+        Some("/a b/c++.cpp")
+    );
+    assert_eq!(uriToPath("untitled:1"), None);
+
+    let content = "ab\n€x\n𝄞y";
+    assert_eq!(offsetToPosition(content, 3), (1, 0));
+    assert_eq!(offsetToPosition(content, 6), (1, 1));
+    assert_eq!(offsetToPosition(content, 12), (2, 2));
+    assert_eq!(offsetToPosition(content, 13), (2, 3));
+    assert_eq!(positionToOffset(content, 1, 1), 6);
+    assert_eq!(positionToOffset(content, 2, 2), 12);
+    assert_eq!(positionToOffset(content, 0, 10), 2);
+    assert_eq!(positionToOffset(content, 5, 0), content.len());
+}
+
+#[test]
+fn publishesDiagnostics() {
+    let path = "/lsp/publishesDiagnostics/a.cpp";
+    let mut client = Client::default();
+    client.request("initialize", object! {capabilities: object! {}});
+    client.open(path, "namespace a {}\nusing namespace nope;\n");
+    client.change(path, "namespace a {}\nusing namespace a;\n");
+    let server = client.run();
+
+    let capabilities = &server.response(1)["capabilities"];
+    assert_eq!(capabilities["textDocumentSync"]["change"], 1);
+    assert_eq!(capabilities["definitionProvider"], true);
+
+    let diagnostics = server.diagnostics(path);
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].len(), 1);
+    let diagnostic = &diagnostics[0][0];
+    assert_eq!(diagnostic["severity"], 1);
+    assert_eq!(position(&diagnostic["range"]["start"]), (1, 0));
+    assert_eq!(position(&diagnostic["range"]["end"]), (1, 21));
+    assert!(diagnostic["message"]
+        .as_str()
+        .unwrap()
+        .contains("unable to resolve"));
+    // Fixing the error clears it
+    assert!(diagnostics[1].is_empty());
+}
+
+#[test]
+fn navigatesNames() {
+    let path = "/lsp/navigatesNames/a.cpp";
+    let mut client = Client::default();
+    client.request("initialize", object! {capabilities: object! {}});
+    client.open(
+        path,
+        "namespace a {\ninline namespace b {}\n__rustycpp__(enum E);\n}\nnamespace c {\nusing namespace a::b;\n}\nnamespace a {}\n",
+    );
+    let symbols = client.document("textDocument/documentSymbol", path);
+    let nested = client.at("textDocument/definition", path, 5, 16);
+    let name = client.at("textDocument/definition", path, 5, 19);
+    let extension = client.at("textDocument/definition", path, 7, 10);
+    let nothing = client.at("textDocument/definition", path, 5, 2);
+    let hover = client.at("textDocument/hover", path, 5, 19);
+    let server = client.run();
+
+    assert!(server
+        .diagnostics(path)
+        .iter()
+        .all(|diags| diags.is_empty()));
+
+    let symbols = server.response(symbols);
+    let summary = |symbol: &JsonValue| {
+        format!(
+            "{} {} {:?}",
+            symbol["name"],
+            symbol["kind"],
+            position(&symbol["selectionRange"]["start"])
+        )
+    };
+    assert_eq!(
+        symbols.members().map(summary).collect::<Vec<_>>(),
+        ["a 3 (0, 10)", "c 3 (4, 10)", "a 3 (7, 10)"]
+    );
+    assert_eq!(
+        symbols[0]["children"]
+            .members()
+            .map(summary)
+            .collect::<Vec<_>>(),
+        ["b 3 (1, 17)", "E 10 (2, 13)"]
+    );
+    assert_eq!(symbols[0]["children"][0]["detail"], "inline");
+
+    let definition = |id| {
+        let location = server.response(id);
+        assert_eq!(location["uri"], pathToUri(path));
+        position(&location["range"]["start"])
+    };
+    assert_eq!(definition(nested), (0, 10));
+    assert_eq!(definition(name), (1, 17));
+    assert_eq!(definition(extension), (0, 10));
+    assert!(server.response(nothing).is_null());
+
+    let hover = server.response(hover);
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(text.contains("inline namespace a::b"), "{text}");
+    assert!(text.contains(&format!("{path}:2:18")), "{text}");
+    assert_eq!(position(&hover["range"]["start"]), (5, 19));
+}
+
+#[test]
+fn foldsConditionals() {
+    let path = "/lsp/foldsConditionals/a.cpp";
+    let mut client = Client::default();
+    client.request("initialize", object! {capabilities: object! {}});
+    client.open(
+        path,
+        "#if 1\nnamespace a {}\nnamespace b {}\n#else\nnamespace z {}\n#endif\n#ifdef X\n#endif\n",
+    );
+    let folding = client.document("textDocument/foldingRange", path);
+    // The files read by the previous compilation are read again
+    client.change(path, "#if 1\nnamespace a {}\n#endif\n");
+    let changed = client.document("textDocument/foldingRange", path);
+    let server = client.run();
+
+    let ranges = |id| {
+        server
+            .response(id)
+            .members()
+            .map(|range| {
+                (
+                    range["startLine"].as_usize().unwrap(),
+                    range["endLine"].as_usize().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ranges(folding), [(0, 2), (3, 4)]);
+    assert_eq!(ranges(changed), [(0, 1)]);
+}
+
+#[test]
+fn unknownRequests() {
+    let mut client = Client::default();
+    let id = client.request("workspace/unknown", JsonValue::Null);
+    client.notify("$/unknownNotification", JsonValue::Null);
+    let server = client.run();
+    let response = server
+        .messages
+        .iter()
+        .find(|message| message["id"] == id)
+        .unwrap();
+    assert_eq!(response["error"]["code"], -32601);
+}
+
+#[test]
+fn keepsBuildOutputs() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-lsp")
+        .join("keepsBuildOutputs");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = "/lsp/keepsBuildOutputs/a.cpp";
+    let mut client = Client::default();
+    client.open(path, "export module a;\nexport namespace a1 {}\n");
+    let mut parameters = Parameters::new();
+    parameters.bmiDir = dir.join("bmi").to_str().unwrap().to_string();
+    parameters.timingCacheFile = Some(dir.join("timings.json").to_str().unwrap().to_string());
+    let server = client.runWith(parameters);
+
+    // The unsaved documents don't replace the outputs of the project
+    assert_eq!(server.diagnostics(path).len(), 1);
+    assert!(!dir.exists());
+}
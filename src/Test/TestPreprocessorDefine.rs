@@ -2,13 +2,17 @@ use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use crate::Preprocessor::MacroTrace::{MacroTraceStep, MacroTracer};
 use crate::Preprocessor::Preprocessor;
 use crate::Preprocessor::Pretoken::PreToken;
 use crate::Utils::CompilerState::CompilerState;
 use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::{InMemoryFileSystem, OverlayFileSystem, RealFileSystem};
 use crate::Utils::Parameters::Parameters;
 use crate::Utils::StateCompileUnit::StateCompileUnit;
-use crate::Utils::Structs::{CompileMsg, FileTokPos};
+use crate::Utils::Structs::{
+    CompileError, CompileMsg, CompileMsgImpl, CompileMsgKind, FileTokPos, SourceRange,
+};
 use test_log::test;
 
 fn generateFileMap(files: &[(&'static str, &'static str)]) -> (CompilerState, u64) {
@@ -23,19 +27,23 @@ fn generateFileMap(files: &[(&'static str, &'static str)]) -> (CompilerState, u6
             .to_string(),
     );
 
-    for (filePath, _) in files {
-        params.translationUnits.push((*filePath).to_string());
+    let fileSystem = InMemoryFileSystem::new();
+    for (filePath, fileContents) in files {
+        let path = Path::new("/").join(filePath).to_str().unwrap().to_string();
+        fileSystem.addFile(&path, fileContents.to_string());
+        params.translationUnits.push(path);
     }
 
     let parameters = Arc::new(params);
-    let fileMap = Arc::new(Mutex::new(FileMap::new(parameters.clone())));
+    let fileSystem = OverlayFileSystem::new(vec![Arc::new(fileSystem), Arc::new(RealFileSystem)]);
+    let fileMap = Arc::new(Mutex::new(FileMap::new_with_fs(
+        parameters.clone(),
+        Arc::new(fileSystem),
+    )));
     let mut compileUnits = HashMap::new();
-    for (i, (filePath, fileContents)) in files.iter().enumerate() {
-        fileMap
-            .lock()
-            .unwrap()
-            .addTestFile((*filePath).to_string(), fileContents);
-        compileUnits.insert(i as u64 + 1, StateCompileUnit::new());
+    for path in &parameters.translationUnits {
+        let tu = fileMap.lock().unwrap().getAddFile(path);
+        compileUnits.insert(tu, StateCompileUnit::new());
     }
 
     (
@@ -46,6 +54,12 @@ fn generateFileMap(files: &[(&'static str, &'static str)]) -> (CompilerState, u6
             translationUnitsFiles: Arc::new((1..2).collect::<HashSet<_>>()),
             moduleHeaderUnitsFiles: Arc::new(HashSet::new()),
             foundErrors: Arc::default(),
+            tokenCache: Arc::default(),
+            bmiCache: Arc::default(),
+            preprocessorCallbacks: vec![],
+            macroTracer: None,
+            timeTrace: None,
+            conditionalReports: None,
         },
         1,
     )
@@ -67,6 +81,37 @@ fn getToksPreprocessed(files: &[(&'static str, &'static str)]) -> Vec<PreToken>
     .collect::<Vec<PreToken>>()
 }
 
+fn getFileToksPreprocessedNoWs(
+    files: &[(&'static str, &'static str)],
+) -> (CompilerState, Vec<FileTokPos<PreToken>>) {
+    let f = generateFileMap(files);
+    let toks = Preprocessor::new(f.clone())
+        .filter_map(Result::ok)
+        .filter(|x| {
+            !matches!(
+                x.tokPos.tok,
+                PreToken::Whitespace(_)
+                    | PreToken::Newline
+                    | PreToken::ValidNop
+                    | PreToken::EnableMacro(_)
+                    | PreToken::DisableMacro(_)
+            )
+        })
+        .collect::<Vec<_>>();
+    (f.0, toks)
+}
+
+fn traceMacros(files: &[(&'static str, &'static str)], request: &str) -> Vec<MacroTraceStep> {
+    let mut f = generateFileMap(files);
+    let tu = f.1;
+    let tracer = Arc::new(MacroTracer::fromRequest(request, &f.0.compileFiles).unwrap());
+    f.0.macroTracer = Some(tracer.clone());
+    for tok in Preprocessor::new(f) {
+        tok.unwrap();
+    }
+    tracer.take(tu).into_iter().map(|x| x.step).collect()
+}
+
 fn getErrsPreprocessed(files: &[(&'static str, &'static str)]) -> Vec<CompileMsg> {
     let prep = Preprocessor::new(generateFileMap(files));
     prep.filter_map(Result::err).collect::<Vec<CompileMsg>>()
@@ -795,3 +840,258 @@ fn mandatoryDefinedMacros() {
         assert!(!res.is_empty());
     }
 }
+
+#[test]
+fn expansionLocationOfObjectMacro() {
+    let (_state, toks) = getFileToksPreprocessedNoWs(&[("test", "#define E e\nE\n")]);
+    assert_eq!(toks.len(), 1);
+    let loc = toks[0].expansion.as_ref().unwrap().loc();
+    assert_eq!(loc.macroName.as_ref(), "E");
+    assert_eq!(loc.definedAt.as_ref().unwrap().start, 8);
+    assert_eq!(loc.expandedAt.start, 12);
+    assert!(loc.parent.is_none());
+    assert_eq!(toks[0].tokPos.start, 10);
+}
+
+#[test]
+fn expansionLocationChain() {
+    let (_state, toks) =
+        getFileToksPreprocessedNoWs(&[("test", "#define B b\n#define A(x) B x\nA(c)\n")]);
+    assert_eq!(toks.len(), 2);
+    let names = toks[0]
+        .expansion
+        .as_ref()
+        .unwrap()
+        .chain()
+        .map(|loc| loc.macroName.as_ref().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["B", "A"]);
+    // Arguments are also in the expansion, but are spelled in the invocation
+    let names = toks[1]
+        .expansion
+        .as_ref()
+        .unwrap()
+        .chain()
+        .map(|loc| loc.macroName.as_ref().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["A"]);
+    assert_eq!(toks[1].tokPos.start, 31);
+}
+
+#[test]
+fn expansionNotesInDiagnostics() {
+    let (state, toks) = getFileToksPreprocessedNoWs(&[(
+        "test",
+        "#define ASSERT(x) CHECK(x)\n#define CHECK(x) x\nASSERT(a)\n",
+    )]);
+    assert_eq!(toks.len(), 1);
+    let err = CompileError::fromPreTo("test error", &toks[0]);
+    let msg = err.to_string(&state.compileFiles);
+    log::debug!("{msg}");
+    assert!(msg.contains("in expansion of macro CHECK defined at /test:2:9"));
+    assert!(msg.contains("in expansion of macro ASSERT defined at /test:1:9"));
+    assert!(
+        msg.find("macro CHECK").unwrap() < msg.find("macro ASSERT").unwrap(),
+        "innermost expansion must be reported first"
+    );
+}
+
+#[test]
+fn expansionOutlivesTheCompilation() {
+    let (state, toks) = getFileToksPreprocessedNoWs(&[("test", "#define N n\nN\n")]);
+    let err = CompileError::fromPreTo("test error", &toks[0]);
+    drop((state, toks));
+    let expansion = err.expansion().unwrap();
+    assert_eq!(expansion.loc().macroName.as_ref(), "N");
+    assert_eq!(expansion.chain().count(), 1);
+}
+
+#[test]
+fn expansionNotesInRangeDiagnostics() {
+    let (state, toks) = getFileToksPreprocessedNoWs(&[("test", "#define N a b\nN\n")]);
+    assert_eq!(toks.len(), 2);
+    let range = SourceRange::newDoubleTok(&toks[0], &toks[1]);
+    let err = CompileError::fromSourceRange("test error", &range);
+    let msg = err.to_string(&state.compileFiles);
+    log::debug!("{msg}");
+    assert!(msg.contains("in expansion of macro N defined at /test:1:9"));
+}
+
+#[test]
+fn traceMacroByName() {
+    let steps = traceMacros(
+        &[(
+            "test",
+            "#define STR(x) #x\n#define CAT(a, b) a ## b\n#define F(x) STR(x) CAT(x, 1)\n#define V v\nF(V)\nV\n",
+        )],
+        "F",
+    );
+    log::debug!("{steps:#?}");
+    assert!(matches!(
+        &steps[0],
+        MacroTraceStep::Invocation { macroName, args: Some(args), rescan: false }
+            if macroName == "F" && args == &vec!["V".to_string()]
+    ));
+    assert!(steps.iter().any(|step| matches!(
+        step,
+        MacroTraceStep::ArgumentPreExpansion { macroName, param, from, to }
+            if macroName == "F" && param == "x" && from == "V" && to == "v"
+    )));
+    assert!(steps.iter().any(|step| matches!(
+        step,
+        MacroTraceStep::Invocation { macroName, rescan: true, .. } if macroName == "STR"
+    )));
+    assert!(steps.iter().any(|step| matches!(
+        step,
+        MacroTraceStep::Stringification { result, .. } if result == "\"v\""
+    )));
+    assert!(steps.iter().any(|step| matches!(
+        step,
+        MacroTraceStep::Concatenation { left, right, result, .. }
+            if left == "v" && right == "1" && result == "v1"
+    )));
+    // The V in the last line is not part of the requested expansion
+    assert_eq!(
+        steps
+            .iter()
+            .filter(|step| matches!(
+                step,
+                MacroTraceStep::Invocation { macroName, rescan: false, .. } if macroName == "V"
+            ))
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn traceMacroByLine() {
+    let steps = traceMacros(
+        &[("test", "#define R R\n#define F() f\nF R\nF()\n")],
+        "/test:3",
+    );
+    log::debug!("{steps:#?}");
+    assert!(matches!(
+        &steps[0],
+        MacroTraceStep::NotInvoked { macroName } if macroName == "F"
+    ));
+    assert!(matches!(
+        &steps[1],
+        MacroTraceStep::Invocation { macroName, args: None, rescan: false } if macroName == "R"
+    ));
+    assert!(steps.iter().any(|step| matches!(
+        step,
+        MacroTraceStep::PaintedBlue { macroName } if macroName == "R"
+    )));
+    assert!(!steps.iter().any(|step| matches!(
+        step,
+        MacroTraceStep::Invocation { macroName, .. } if macroName == "F"
+    )));
+}
+
+#[test]
+fn identicalRedefinitionIsAllowed() {
+    let errs = getErrsPreprocessed(&[(
+        "test",
+        r"#define A(x) x   + /* one */ 1
+#define A(x) x + 1
+",
+    )]);
+    assert!(errs.is_empty(), "{errs:?}");
+}
+
+#[test]
+fn differentRedefinitionWarns() {
+    let errs = getErrsPreprocessed(&[(
+        "test",
+        r"#define A 1
+#define A 2
+#define F(x) x
+#define F(y) y
+#define G(x) x+1
+#define G(x) x + 1
+",
+    )]);
+    let msgs = errs
+        .iter()
+        .map(|err| (err.severity(), err.msg()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        msgs,
+        [
+            (
+                CompileMsgKind::Warning,
+                "Macro A redefined with a different definition"
+            ),
+            (CompileMsgKind::Notice, "Previous definition is here"),
+            (
+                CompileMsgKind::Warning,
+                "Macro F redefined with a different definition"
+            ),
+            (CompileMsgKind::Notice, "Previous definition is here"),
+            (
+                CompileMsgKind::Warning,
+                "Macro G redefined with a different definition"
+            ),
+            (CompileMsgKind::Notice, "Previous definition is here"),
+        ]
+    );
+}
+
+#[test]
+fn reservedMacroNamesWarn() {
+    let errs = getErrsPreprocessed(&[(
+        "test",
+        r"#define __FILE__ file
+#undef __cplusplus
+#define defined 1
+#define _MY_GUARD
+",
+    )]);
+    let msgs = errs.iter().map(CompileMsg::msg).collect::<Vec<_>>();
+    assert_eq!(
+        msgs,
+        [
+            "Defining the reserved macro name __FILE__ is undefined behavior",
+            "Undefining the reserved macro name __cplusplus is undefined behavior",
+            "Defining the reserved macro name defined is undefined behavior",
+        ]
+    );
+}
+
+#[test]
+fn unusedMacrosWarn() {
+    let mut f = generateFileMap(&[(
+        "test",
+        r#"#define USED 1
+#define TESTED
+#define UNUSED
+#define REDEF 1
+#define REDEF 1
+#define F(x) x
+#define G USED
+#ifdef TESTED
+#endif
+G F(1)
+#define UNDEF
+#undef UNDEF
+#include "includeTree/b.h"
+"#,
+    )]);
+    f.0.parameters = Arc::new(Parameters {
+        warnUnusedMacros: true,
+        ..(*f.0.parameters).clone()
+    });
+    let errs = Preprocessor::new(f)
+        .filter_map(Result::err)
+        .collect::<Vec<_>>();
+    let msgs = errs.iter().map(CompileMsg::msg).collect::<Vec<_>>();
+    assert_eq!(
+        msgs,
+        [
+            "Macro REDEF is never used",
+            "Macro UNDEF is never used",
+            "Macro UNUSED is never used",
+            "Macro REDEF is never used",
+        ]
+    );
+}
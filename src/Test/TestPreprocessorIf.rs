@@ -2,13 +2,15 @@ use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use crate::Preprocessor::ConditionalReport::ConditionalReport;
 use crate::Preprocessor::Preprocessor;
 use crate::Preprocessor::Pretoken::PreToken;
 use crate::Utils::CompilerState::CompilerState;
 use crate::Utils::FileMap::FileMap;
+use crate::Utils::FileSystem::{InMemoryFileSystem, OverlayFileSystem, RealFileSystem};
 use crate::Utils::Parameters::Parameters;
 use crate::Utils::StateCompileUnit::StateCompileUnit;
-use crate::Utils::Structs::CompileMsg;
+use crate::Utils::Structs::{CompileMsg, CompileMsgKind};
 
 use test_log::test;
 
@@ -24,19 +26,23 @@ fn generateFileMap(files: &[(&'static str, String)]) -> (CompilerState, u64) {
             .to_string(),
     );
 
-    for (filePath, _) in files {
-        params.translationUnits.push((*filePath).to_string());
+    let fileSystem = InMemoryFileSystem::new();
+    for (filePath, fileContents) in files {
+        let path = Path::new("/").join(filePath).to_str().unwrap().to_string();
+        fileSystem.addFile(&path, fileContents.clone());
+        params.translationUnits.push(path);
     }
 
     let parameters = Arc::new(params);
-    let fileMap = Arc::new(Mutex::new(FileMap::new(parameters.clone())));
+    let fileSystem = OverlayFileSystem::new(vec![Arc::new(fileSystem), Arc::new(RealFileSystem)]);
+    let fileMap = Arc::new(Mutex::new(FileMap::new_with_fs(
+        parameters.clone(),
+        Arc::new(fileSystem),
+    )));
     let mut compileUnits = HashMap::new();
-    for (i, (filePath, fileContents)) in files.iter().enumerate() {
-        fileMap
-            .lock()
-            .unwrap()
-            .addTestFile((*filePath).to_string(), fileContents);
-        compileUnits.insert(i as u64 + 1, StateCompileUnit::new());
+    for path in &parameters.translationUnits {
+        let tu = fileMap.lock().unwrap().getAddFile(path);
+        compileUnits.insert(tu, StateCompileUnit::new());
     }
 
     (
@@ -47,6 +53,12 @@ fn generateFileMap(files: &[(&'static str, String)]) -> (CompilerState, u64) {
             translationUnitsFiles: Arc::new((1..2).collect::<HashSet<_>>()),
             moduleHeaderUnitsFiles: Arc::new(HashSet::new()),
             foundErrors: Arc::default(),
+            tokenCache: Arc::default(),
+            bmiCache: Arc::default(),
+            preprocessorCallbacks: vec![],
+            macroTracer: None,
+            timeTrace: None,
+            conditionalReports: None,
         },
         1,
     )
@@ -57,6 +69,14 @@ fn getToksPreprocessed(files: &[(&'static str, String)]) -> Vec<Result<PreToken,
     prep.map(|x| x.map(|x| x.tokPos.tok)).collect::<Vec<_>>()
 }
 
+fn getConditionalReport(files: &[(&'static str, String)]) -> ConditionalReport {
+    let mut prep = Preprocessor::new(generateFileMap(files)).withConditionalReport();
+    for tok in &mut prep {
+        tok.unwrap();
+    }
+    prep.takeConditionalReport().unwrap()
+}
+
 fn getToksPreprocessedNoWs(files: &[(&'static str, String)]) -> Vec<Result<PreToken, CompileMsg>> {
     let mut res = getToksPreprocessed(files);
     res.retain(|x| {
@@ -88,6 +108,23 @@ fn checkForCorrectEvalOfIfClause(string: &'static str) {
     assert!(res, "The expression does not yield a trueish value");
 }
 
+fn checkForCorrectEvalOfIfClauseWithWarning(string: &'static str, warning: &str) {
+    let info = &[("test", string.to_string() + "\nSUCCESS\n#endif")];
+    let tokens = getToksPreprocessedNoWs(info);
+    assert!(tokens
+        .iter()
+        .any(|x| matches!(x, Ok(PreToken::Ident(val)) if val == "SUCCESS")));
+    let msgs = tokens
+        .into_iter()
+        .filter_map(Result::err)
+        .collect::<Vec<_>>();
+    assert!(!msgs.is_empty());
+    for msg in msgs {
+        assert_eq!(msg.severity(), CompileMsgKind::Warning);
+        assert!(msg.msg().contains(warning));
+    }
+}
+
 fn checkForAnyEvalOfIfClause(string: &'static str) {
     let info = &[(
         "test",
@@ -188,21 +225,23 @@ fn checkDefined4() {
 
 #[test]
 fn checkDefined5() {
-    checkForCorrectEvalOfIfClause(
+    checkForCorrectEvalOfIfClauseWithWarning(
         r##"
         #define L defined(L)
         #if L
 "##,
+        "defined generated by a macro expansion",
     );
 }
 
 #[test]
 fn checkDefined6() {
-    checkForCorrectEvalOfIfClause(
+    checkForCorrectEvalOfIfClauseWithWarning(
         r##"
         #define L(defined) defined(L)
         #if L(defined)
 "##,
+        "defined generated by a macro expansion",
     );
 }
 
@@ -268,3 +307,57 @@ fn checkValidOp() {
 "##,
     );
 }
+
+#[test]
+fn conditionalReportBranches() {
+    let content = "#define A\n#if 0\nno\n#elif defined(A)\nyes\n#else\nno\n#endif\n";
+    let report = getConditionalReport(&[("test", content.to_string())]);
+    let groups = report.groups();
+    assert_eq!(groups.len(), 1);
+    assert!(groups[0].active);
+    let summary = groups[0]
+        .branches
+        .iter()
+        .map(|b| (b.kind.as_str(), b.condition.as_str(), b.evaluated, b.taken))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            ("if", "0", Some(false), false),
+            ("elif", "defined(A)", Some(true), true),
+            ("else", "", None, false),
+        ]
+    );
+    let skipped = report
+        .skippedRanges()
+        .iter()
+        .map(|range| &content[range.start..range.end])
+        .collect::<Vec<_>>();
+    assert_eq!(skipped, vec!["no\n", "no\n"]);
+    let endif = groups[0].endif.as_ref().unwrap();
+    assert_eq!(&content[endif.start..endif.end], "#endif");
+}
+
+#[test]
+fn conditionalReportNestedInactive() {
+    let content = "#ifdef A\n#ifndef B\nx\n#else\ny\n#endif\n#endif\n#ifndef A\nz\n";
+    let report = getConditionalReport(&[("test", content.to_string())]);
+    let groups = report.groups();
+    assert_eq!(groups.len(), 3);
+    assert!(groups[0].active);
+    assert!(!groups[0].branches[0].taken);
+    assert!(!groups[1].active);
+    assert_eq!(groups[1].branches.len(), 2);
+    assert!(groups[1].branches.iter().all(|b| b.evaluated.is_none()));
+    // Unterminated groups extend until the end of the file
+    assert!(groups[2].branches[0].taken);
+    assert!(groups[2].endif.is_none());
+    assert_eq!(groups[2].branches[0].body.end, content.len());
+    // Nested inactive regions are already covered by the outer one
+    let skipped = report
+        .skippedRanges()
+        .iter()
+        .map(|range| &content[range.start..range.end])
+        .collect::<Vec<_>>();
+    assert_eq!(skipped, vec!["#ifndef B\nx\n#else\ny\n#endif\n"]);
+}
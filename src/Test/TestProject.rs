@@ -1,13 +1,29 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use ::function_name::named;
+use strum::IntoEnumIterator;
 use test_log::test;
 
 use crate::{
     Ast::Common::AstTu,
+    Ast::Type::{Builtin::BuiltinTypeKind, QualType, QualTypeFlags},
     Compiler::Compiler,
+    ModuleTree::CriticalPath::{CostModel, CriticalPathStep, TimingCache},
+    ModuleTree::RebuildPlan::FilesEntered,
+    ModuleTree::Structs::ModuleDeclaration,
+    Preprocessor::Callbacks::PreprocessorCallbacks,
+    Sema::Bmi::{bmiPath, sourceHash, Bmi, BMI_FORMAT_VERSION},
+    Sema::{Scope::Scope, TypeDict::TypeDict},
+    Utils::StringRef::ToStringRef,
+    Utils::TimeTrace::TraceStage,
     Utils::{
         CompilerState::CompilerState,
+        FileMap::FileMap,
         Parameters::Parameters,
         Structs::{CompileMsg, CompileMsgKind},
     },
@@ -35,10 +51,7 @@ macro_rules! testUnsuccessfulProject {
     }};
 }
 
-fn testProject(
-    funcName: &str,
-    file: &str,
-) -> Vec<(HashMap<String, AstTu>, Vec<CompileMsg>, CompilerState)> {
+fn projectParameters(funcName: &str, file: &str) -> Parameters {
     let dirTest = std::path::Path::new(file)
         .canonicalize()
         .unwrap()
@@ -55,6 +68,23 @@ fn testProject(
     parameters
         .includeDirs
         .push(dirTest.to_str().unwrap().to_string());
+    for dir in &mut parameters.moduleSearchDirs {
+        *dir = dirTest.join(&dir).to_str().unwrap().to_string();
+    }
+    parameters.bmiDir = std::env::temp_dir()
+        .join("rustycpp-test-bmi")
+        .join(funcName)
+        .to_str()
+        .unwrap()
+        .to_string();
+    parameters
+}
+
+fn testProject(
+    funcName: &str,
+    file: &str,
+) -> Vec<(HashMap<String, AstTu>, Vec<CompileMsg>, CompilerState)> {
+    let mut parameters = projectParameters(funcName, file);
     let compute = move |parameters| {
         let mut tmpRes = (HashMap::new(), Vec::new());
         let stateCompiler = Compiler::new(parameters).parsed_tree_test(&mut tmpRes);
@@ -148,6 +178,764 @@ fn headerModuleErr1() {
     testUnsuccessfulProject!()
         .into_iter()
         .for_each(|(_, e, s)| {
+            assert!(e.iter().any(|e| e.msg().contains("foo.hpp imports ")
+                && e.msg().contains("bar.hpp imports ")
+                && e.msg().contains(".hpp:1:2)")));
             checkErrors(e, &s, &[e!(1, "foo.hpp", true), e!(1, "bar.hpp", true)]);
         });
 }
+
+#[test]
+#[named]
+fn includeTranslation() {
+    for (_, compilerState) in testSuccessfulProject!() {
+        let main = *compilerState.translationUnitsFiles.iter().next().unwrap();
+        let header = *compilerState.moduleHeaderUnitsFiles.iter().next().unwrap();
+        let node = compilerState.compileUnits[&main]
+            .moduleKind
+            .lock()
+            .unwrap()
+            .clone();
+        assert!(node
+            .dependsOn
+            .iter()
+            .any(|dep| dep.0 == ModuleDeclaration::ModuleHeaderUnit(header)));
+    }
+}
+
+#[test]
+#[named]
+fn includeTranslationEndsImport() {
+    // The translated include is followed by a declaration
+    testSuccessfulProject!();
+}
+
+#[test]
+#[named]
+fn includeTranslationOff() {
+    let mut compiler = Compiler::new(projectParameters(function_name!(), file!()));
+    let filesEntered = Arc::new(FilesEntered::default());
+    compiler.addPreprocessorCallbacks(filesEntered.clone());
+    let mut result = (HashMap::new(), Vec::new());
+    let compilerState = compiler.parsed_tree_test(&mut result);
+    assertErrors(&result.1, &compilerState);
+
+    let main = *compilerState.translationUnitsFiles.iter().next().unwrap();
+    let header = *compilerState.moduleHeaderUnitsFiles.iter().next().unwrap();
+    assert!(filesEntered.take(main).contains(&header));
+    let node = compilerState.compileUnits[&main]
+        .moduleKind
+        .lock()
+        .unwrap()
+        .clone();
+    assert!(!node
+        .dependsOn
+        .iter()
+        .any(|dep| matches!(dep.0, ModuleDeclaration::ModuleHeaderUnit(_))));
+}
+
+#[test]
+#[named]
+fn scanDependencies() {
+    let _ = testSuccessfulProject!();
+    let mut compiler = Compiler::new(projectParameters(function_name!(), file!()));
+    let tree = compiler.scanDependencies().unwrap();
+    let main = tree
+        .childModules
+        .values()
+        .chain(tree.roots.values())
+        .find(|node| node.module.0.to_string() == "export module main")
+        .unwrap();
+    assert_eq!(
+        main.dependsOn
+            .iter()
+            .map(|dep| dep.0.to_string())
+            .collect::<Vec<_>>(),
+        ["export module bar"]
+    );
+}
+
+#[test]
+#[named]
+fn moduleDiscovery() {
+    for (_, compilerState) in testSuccessfulProject!() {
+        let mut compileFiles = compilerState.compileFiles.lock().unwrap();
+        let mut files = compilerState
+            .translationUnitsFiles
+            .iter()
+            .map(|tu| {
+                Path::new(compileFiles.getOpenedFile(*tu).path())
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "base.cpp",
+                "format.cppm",
+                "main.cpp",
+                "util-detail.cppm",
+                "util.cppm"
+            ]
+        );
+    }
+}
+
+#[test]
+#[named]
+fn moduleNotFound() {
+    testUnsuccessfulProject!()
+        .into_iter()
+        .for_each(|(_, e, s)| {
+            assert!(e.iter().all(|e| e.msg() == "Module missing not found"));
+            checkErrors(e, &s, &[e!(2, "main.cpp")]);
+        });
+}
+
+#[test]
+#[named]
+fn moduleCycle() {
+    testUnsuccessfulProject!()
+        .into_iter()
+        .for_each(|(_, e, s)| {
+            assert_eq!(e.len(), 1);
+            let msg = e[0].msg();
+            assert!(msg.starts_with("Module dependency cycle: a imports b (at "));
+            assert!(msg.contains("a.cpp:2:1) -> b imports c (at "));
+            assert!(msg.contains("b.cpp:2:1) -> c imports a (at "));
+            assert!(msg.ends_with("c.cpp:2:1)"));
+            checkErrors(e, &s, &[e!(2, "a.cpp")]);
+        });
+}
+
+#[test]
+#[named]
+fn partitionCycle() {
+    testUnsuccessfulProject!()
+        .into_iter()
+        .for_each(|(_, e, s)| {
+            assert_eq!(e.len(), 1);
+            let msg = e[0].msg();
+            assert!(msg.starts_with("Cycle between the partitions of module m: m:p1 imports m:p2"));
+            assert!(msg.contains("p1.cpp:2:1) -> m:p2 imports m:p1 (at "));
+            checkErrors(e, &s, &[e!(2, "p1.cpp")]);
+        });
+}
+
+#[test]
+#[named]
+fn moduleUnitErrors() {
+    testUnsuccessfulProject!()
+        .into_iter()
+        .for_each(|(_, e, s)| {
+            checkErrors(
+                e,
+                &s,
+                &[
+                    e!(1, "a2.cpp"),
+                    e!(3, "g.cpp"),
+                    e!(2, "hi.cpp"),
+                    e!(2, "q.cpp"),
+                ],
+            );
+        });
+}
+
+#[test]
+#[named]
+fn modulePartitionErrors() {
+    testUnsuccessfulProject!()
+        .into_iter()
+        .for_each(|(_, e, s)| {
+            checkErrors(
+                e,
+                &s,
+                &[
+                    e!(1, "cp2.cpp"),
+                    e!(1, "d.cpp"),
+                    e!(1, "eq.cpp"),
+                    e!(2, "f.cpp"),
+                ],
+            );
+        });
+}
+
+#[test]
+#[named]
+fn modulePartitions() {
+    let mut compiler = Compiler::new(projectParameters(function_name!(), file!()));
+    let tree = compiler.scanDependencies().unwrap();
+    let primary = &tree.childModules[&ModuleDeclaration::ExportPrimary("m".to_StringRef())];
+    let mut dependencies = primary
+        .dependsOn
+        .iter()
+        .map(|dep| dep.0.to_string())
+        .collect::<Vec<_>>();
+    dependencies.sort();
+    assert_eq!(dependencies, ["export module m:a", "module m:impl"]);
+}
+
+#[test]
+#[named]
+fn parsesModulePartition() {
+    let _ = testSuccessfulProject!();
+}
+
+#[test]
+#[named]
+fn moduleImports() {
+    let _ = testSuccessfulProject!();
+    let parameters = projectParameters(function_name!(), file!());
+    let bmi = Bmi::read(&bmiPath(&parameters.bmiDir, "b")).unwrap();
+    assert_eq!(bmi.module, "b");
+    let source = std::fs::read_to_string(&bmi.source).unwrap();
+    assert_eq!(bmi.sourceHash, sourceHash(&source));
+    assert_eq!(
+        bmi.imports
+            .iter()
+            .map(|import| (import.module.as_str(), import.exported))
+            .collect::<Vec<_>>(),
+        [("a", true), ("b:impl", false)]
+    );
+}
+
+#[test]
+fn bmiDirNextToFileList() {
+    let list = Path::new(file!())
+        .canonicalize()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .join("testProject")
+        .join("moduleImports")
+        .join("compile_list.json");
+    let parameters = Parameters::new_file(list.to_str().unwrap()).unwrap();
+    assert_eq!(
+        Path::new(&parameters.bmiDir),
+        list.parent().unwrap().join(".rustycpp").join("bmi")
+    );
+}
+
+#[test]
+#[named]
+fn bmiOtherVersion() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-bmi")
+        .join(function_name!());
+    let path = bmiPath(dir.to_str().unwrap(), "old");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(
+        &path,
+        json::object! {version: BMI_FORMAT_VERSION + 1}.dump(),
+    )
+    .unwrap();
+    assert!(Bmi::read(&path).is_err_and(|err| err.contains("format version")));
+}
+
+#[test]
+#[named]
+fn bmiBuiltinTypesByName() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-bmi")
+        .join(function_name!());
+    let path = bmiPath(dir.to_str().unwrap(), "types");
+    let alloc: &'static bumpalo::Bump = Box::leak(Box::default());
+    let newTypeDict = || {
+        let mut typeDict = TypeDict::new(alloc);
+        BuiltinTypeKind::iter().for_each(|ty| typeDict.addBuiltinType(ty));
+        typeDict
+    };
+    let mut typeDict = newTypeDict();
+    let uint = typeDict.getBuiltinType(BuiltinTypeKind::UInt);
+    typeDict.getPtrType(QualType::new(uint.into(), QualTypeFlags::CONST));
+    let mut fileMap = FileMap::new(Arc::new(Parameters::new()));
+    Bmi::new(
+        "types".to_string(),
+        String::new(),
+        String::new(),
+        vec![],
+        &Scope::new_root(),
+        &typeDict,
+        &mut fileMap,
+    )
+    .write(&path)
+    .unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(content.contains("\"builtin\": \"UInt\""));
+
+    let load = |fileMap: &mut FileMap| {
+        let mut typeDict = newTypeDict();
+        Bmi::read(&path).unwrap().load(
+            &Scope::new_root(),
+            &mut typeDict,
+            alloc,
+            fileMap,
+            "types".to_StringRef(),
+            false,
+        );
+        typeDict
+            .pointerTypes()
+            .map(|ty| ty.getPointee().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(load(&mut fileMap), ["const unsigned int"]);
+    std::fs::write(&path, content.replace("\"UInt\"", "\"Unsigned\"")).unwrap();
+    assert!(load(&mut fileMap).is_empty());
+}
+
+#[test]
+#[named]
+fn exportErrors() {
+    testUnsuccessfulProject!()
+        .into_iter()
+        .for_each(|(_, e, s)| {
+            assert!(e.iter().any(|e| e.msg().contains("can't be nested")));
+            checkErrors(e, &s, &[e!(3, "e.cpp"), e!(2, "g.cpp")]);
+        });
+}
+
+#[test]
+#[named]
+fn moduleVisibility() {
+    testUnsuccessfulProject!()
+        .into_iter()
+        .for_each(|(_, e, s)| {
+            assert!(e.iter().any(|e| e
+                .msg()
+                .contains("Declaration of hidden is not exported by module a")));
+            assert!(e.iter().any(|e| e
+                .msg()
+                .contains("Declaration of Internal is not exported by module a")));
+            assert!(e.iter().any(|e| e
+                .msg()
+                .contains("Declaration of gmf is in the global module fragment of module a")));
+            checkErrors(
+                e,
+                &s,
+                &[e!(3, "main.cpp"), e!(4, "main.cpp"), e!(5, "main.cpp")],
+            );
+        });
+}
+
+#[test]
+#[named]
+fn rebuildPlan() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-rebuild")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write("a.h", "#define A 1\n");
+    write("a.cpp", "module;\n#include \"a.h\"\nexport module a;\n");
+    write("b.cpp", "import a;\n");
+    write("c.cpp", "int c;\n");
+    write("d.cpp", "#include \"a.h\"\n");
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let parameters = || {
+        let mut parameters = Parameters::new();
+        parameters.translationUnits = ["a.cpp", "b.cpp", "c.cpp", "d.cpp"].map(path).to_vec();
+        parameters.includeDirs = vec![dir.to_str().unwrap().to_string()];
+        parameters.rebuildStateFile = path("state.json");
+        parameters
+    };
+    let plan = |changed: &[&str]| {
+        let changed = changed.iter().map(|file| path(file)).collect::<Vec<_>>();
+        Compiler::new(parameters()).rebuildPlan(&changed).unwrap()
+    };
+    let confirm = || Compiler::new(parameters()).confirmRebuild().unwrap();
+    let sorted = |mut files: Vec<String>| {
+        files.sort_unstable();
+        files
+    };
+    let paths = |files: &[&str]| files.iter().map(|file| path(file)).collect::<Vec<_>>();
+
+    // Without a previous scan, everything is rebuilt
+    let first = plan(&[]);
+    assert_eq!(
+        sorted(first.rebuild.clone()),
+        paths(&["a.cpp", "b.cpp", "c.cpp", "d.cpp"])
+    );
+    assert_eq!(
+        first.rescanned,
+        paths(&["a.cpp", "b.cpp", "c.cpp", "d.cpp"])
+    );
+    let position = |file: &str| first.rebuild.iter().position(|tu| *tu == path(file));
+    assert!(position("a.cpp") < position("b.cpp"));
+
+    // Until the rebuild is confirmed, it is planned again
+    let pending = plan(&[]);
+    assert_eq!(sorted(pending.rebuild), sorted(first.rebuild.clone()));
+    assert!(pending.rescanned.is_empty());
+
+    // Nothing changed
+    confirm();
+    let unchanged = plan(&[]);
+    assert!(unchanged.rebuild.is_empty());
+    assert!(unchanged.rescanned.is_empty());
+
+    // A header in the list, with the same contents: its includers and their
+    // importers are rebuilt, without scanning anything
+    let header = plan(&["a.h"]);
+    assert_eq!(header.rebuild.first(), Some(&path("a.cpp")));
+    assert_eq!(sorted(header.rebuild), paths(&["a.cpp", "b.cpp", "d.cpp"]));
+    assert!(header.rescanned.is_empty());
+    confirm();
+
+    // Files whose contents changed are found without the list
+    write("c.cpp", "int c2;\n");
+    let source = plan(&[]);
+    assert_eq!(source.rebuild, paths(&["c.cpp"]));
+    assert_eq!(source.rescanned, paths(&["c.cpp"]));
+    confirm();
+
+    write("a.h", "#define A 2\n");
+    let header = plan(&[]);
+    assert_eq!(sorted(header.rebuild), paths(&["a.cpp", "b.cpp", "d.cpp"]));
+    assert_eq!(header.rescanned, paths(&["a.cpp", "d.cpp"]));
+
+    // A failed rebuild leaves them pending, even if something else changes
+    write("c.cpp", "int c3;\n");
+    let failed = plan(&[]);
+    assert_eq!(
+        sorted(failed.rebuild),
+        paths(&["a.cpp", "b.cpp", "c.cpp", "d.cpp"])
+    );
+    assert_eq!(failed.rescanned, paths(&["c.cpp"]));
+}
+
+#[test]
+#[named]
+fn rebuildPlanRestoresDiscoveredModules() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-rebuild")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    std::fs::write(dir.join("main.cpp"), "import m;\n").unwrap();
+    std::fs::write(dir.join("lib").join("m.cpp"), "export module m;\n").unwrap();
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let plan = || {
+        let mut parameters = Parameters::new();
+        parameters.translationUnits = vec![path("main.cpp")];
+        parameters.includeDirs = vec![dir.to_str().unwrap().to_string()];
+        parameters.moduleSearchDirs = vec![path("lib")];
+        parameters.rebuildStateFile = path("state.json");
+        let mut compiler = Compiler::new(parameters);
+        let filesEntered = Arc::new(FilesEntered::default());
+        compiler.addPreprocessorCallbacks(filesEntered.clone());
+        let plan = compiler.rebuildPlan(&[]).unwrap();
+        let module = compiler
+            .fileMap()
+            .lock()
+            .unwrap()
+            .getPath(&path("lib/m.cpp"))
+            .unwrap();
+        (plan, filesEntered.take(module))
+    };
+
+    let (first, scanned) = plan();
+    assert_eq!(first.rebuild, [path("lib/m.cpp"), path("main.cpp")]);
+    assert!(!scanned.is_empty());
+
+    // The module found in the search directories is not scanned again
+    let (second, scanned) = plan();
+    assert_eq!(second.rebuild, first.rebuild);
+    assert!(second.rescanned.is_empty());
+    assert!(scanned.is_empty());
+}
+
+/// Crashes the lexing of the translation units
+#[derive(Debug, Default)]
+struct CrashWhileLexing;
+
+impl PreprocessorCallbacks for CrashWhileLexing {
+    fn fileEntered(&self, _tu: u64, _file: u64) {
+        panic!("crash test");
+    }
+}
+
+#[test]
+#[named]
+fn crashesAreReported() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-crash")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write("a.cpp", "export module a;\n");
+    write("b.cpp", "import a;\n");
+    write("c.cpp", "int c;\n");
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let compiler = || {
+        let mut parameters = Parameters::new();
+        parameters.translationUnits = ["a.cpp", "b.cpp", "c.cpp"].map(path).to_vec();
+        parameters.bmiDir = path("bmi");
+        Compiler::new(parameters)
+    };
+    let crashed = |errors: &[CompileMsg], stage: &str| {
+        let mut crashed = errors
+            .iter()
+            .filter_map(|err| {
+                let crash = format!("The compiler crashed while {stage} ");
+                let path = err.msg().strip_prefix(&crash)?.split(':').next()?;
+                Some(path.to_string())
+            })
+            .collect::<Vec<_>>();
+        crashed.sort();
+        crashed
+    };
+
+    // The translation units that crash are reported instead of waited for
+    let mut lexing = compiler();
+    lexing.addPreprocessorCallbacks(Arc::new(CrashWhileLexing));
+    let (_, errors) = lexing.parsedTrees();
+    assert_eq!(
+        crashed(&errors, "lexing"),
+        ["a.cpp", "b.cpp", "c.cpp"].map(path)
+    );
+
+    // The importers of the ones that crash are not parsed
+    let (_, errors) = compiler().recompile(&HashSet::new(), |_, _, _| panic!("crash test"));
+    assert_eq!(crashed(&errors, "parsing"), ["a.cpp", "c.cpp"].map(path));
+}
+
+#[test]
+#[named]
+fn criticalPath() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-critical-path")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write(
+        "a.cpp",
+        "export module a;\nexport namespace a1 {}\nexport namespace a2 {}\n",
+    );
+    write(
+        "b.cpp",
+        "export module b;\nimport a;\nexport namespace b1 {}\n",
+    );
+    write("c.cpp", "import b;\nnamespace c1 {}\n");
+    write("d.cpp", "namespace d {}\n");
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let report = || {
+        let mut parameters = Parameters::new();
+        parameters.translationUnits = ["a.cpp", "b.cpp", "c.cpp", "d.cpp"].map(path).to_vec();
+        parameters.bmiDir = path("bmi");
+        parameters.timingCacheFile = Some(path("timings.json"));
+        Compiler::new(parameters).criticalPath().unwrap()
+    };
+    let paths = |steps: &[CriticalPathStep]| {
+        steps
+            .iter()
+            .map(|step| step.path.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // Without a timing cache, the costs are estimated from the sizes
+    let first = report();
+    assert_eq!(
+        paths(&first.predicted),
+        ["a.cpp", "b.cpp", "c.cpp"].map(path).to_vec()
+    );
+    let actual = paths(&first.actual);
+    assert!(!actual.is_empty());
+    let position = |file: &str| actual.iter().position(|tu| *tu == path(file));
+    assert!(position("a.cpp").is_none() || position("a.cpp") < position("b.cpp"));
+
+    // The costs measured are kept in the timing cache, and used to estimate
+    // the next compilation
+    let cache = TimingCache::read(Path::new(&path("timings.json"))).unwrap();
+    assert_eq!(cache.units.len(), 4);
+    for step in &first.predicted {
+        assert_eq!(cache.units[&step.path].micros, step.micros);
+    }
+    let second = report();
+    for step in &second.predicted {
+        assert_eq!(step.estimatedMicros, cache.units[&step.path].micros);
+    }
+}
+
+#[test]
+#[named]
+fn remainingCostFollowsLexing() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-critical-path")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write("a.cpp", "export module a;\n");
+    write("b.cpp", "import a;\n");
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let mut parameters = Parameters::new();
+    parameters.translationUnits = ["a.cpp", "b.cpp"].map(path).to_vec();
+    let mut compiler = Compiler::new(parameters);
+    let tree = compiler.scanDependencies().unwrap();
+    let mut fileMap = compiler.fileMap().lock().unwrap();
+    let [a, b] = ["a.cpp", "b.cpp"].map(|file| fileMap.getPath(&path(file)).unwrap());
+    let costs = CostModel::new(&tree, &TimingCache::default(), &mut fileMap);
+    drop(fileMap);
+
+    assert_eq!(
+        costs.remainingCost(a),
+        costs.estimate(a) + costs.estimate(b)
+    );
+    assert_eq!(costs.remainingCost(b), costs.estimate(b));
+    costs.lexed(b, 1000, Duration::ZERO);
+    assert_eq!(costs.remainingCost(a), costs.estimate(a) + 1000);
+    assert_eq!(costs.remainingCost(b), 1000);
+}
+
+#[test]
+#[named]
+fn timeTrace() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-time-trace")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write("h1.h", "#define H1 1\n");
+    write("h2.h", "#include \"h1.h\"\n#define H2 2\n");
+    write(
+        "a.cpp",
+        "#include \"h2.h\"\nexport module a;\nexport namespace a {}\n",
+    );
+    write("b.cpp", "#include \"h1.h\"\nimport a;\nnamespace b {}\n");
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let mut parameters = Parameters::new();
+    parameters.translationUnits = ["a.cpp", "b.cpp"].map(path).to_vec();
+    parameters.includeDirs = vec![dir.to_str().unwrap().to_string()];
+    parameters.bmiDir = path("bmi");
+    let mut compiler = Compiler::new(parameters);
+    let timeTrace = compiler.enableTimeTrace();
+    let mut result = (HashMap::new(), vec![]);
+    let compilerState = compiler.parsed_tree_test(&mut result);
+    assertErrors(&result.1, &compilerState);
+    let fileMap = compilerState.compileFiles;
+    let events = timeTrace.events();
+    let filePath = |file: u64| fileMap.lock().unwrap().getOpenedFile(file).path().clone();
+
+    // Every translation unit is lexed and parsed once, without a scan
+    for tu in ["a.cpp", "b.cpp"] {
+        for (stage, times) in [
+            (TraceStage::Scan, 0),
+            (TraceStage::Lex, 1),
+            (TraceStage::Parse, 1),
+        ] {
+            let count = events
+                .iter()
+                .filter(|event| event.stage == stage && filePath(event.file) == path(tu))
+                .count();
+            assert_eq!(count, times, "{tu} {stage:?}");
+        }
+    }
+    // The headers are nested in the lexing of their translation units
+    let includes = events
+        .iter()
+        .filter(|event| event.stage == TraceStage::Include)
+        .collect::<Vec<_>>();
+    assert_eq!(includes.len(), 3);
+    for include in includes {
+        let lex = events
+            .iter()
+            .find(|event| event.stage == TraceStage::Lex && event.tu == include.tu)
+            .unwrap();
+        assert!(lex.start <= include.start);
+        assert!(include.start + include.duration <= lex.start + lex.duration);
+        assert_eq!(include.thread, lex.thread);
+    }
+    let headers = timeTrace.headerTimes();
+    assert_eq!(headers.len(), 2);
+    let h1 = headers
+        .iter()
+        .find(|header| filePath(header.file) == path("h1.h"))
+        .unwrap();
+    assert_eq!(h1.includes, 2);
+
+    let trace = timeTrace.toChromeTrace(&fileMap);
+    let traceEvents = trace["traceEvents"].members().collect::<Vec<_>>();
+    assert_eq!(
+        traceEvents
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .count(),
+        events.len()
+    );
+    assert!(traceEvents
+        .iter()
+        .any(|event| event["name"] == "Parse" && event["args"]["detail"] == path("b.cpp")));
+
+    let summary = timeTrace.summaryToText(&fileMap, 10);
+    assert!(summary.contains(&path("a.cpp")));
+    assert!(summary.contains(&path("h2.h")));
+}
+
+#[test]
+#[named]
+fn stageOutputs() {
+    let dir = std::env::temp_dir()
+        .join("rustycpp-test-stage-outputs")
+        .join(function_name!());
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+    write(
+        "b.cpp",
+        "export module b;\nexport namespace a { namespace inner {} }\nnamespace c {}\n",
+    );
+    write("a.cpp", "import b;\nnamespace a {}\n");
+
+    let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+    let mut parameters = Parameters::new();
+    parameters.translationUnits = ["b.cpp", "a.cpp"].map(path).to_vec();
+    parameters.bmiDir = path("bmi");
+    let read = |file: &str| std::fs::read_to_string(dir.join("out").join(file)).unwrap();
+
+    let mut compiler = Compiler::new(parameters.clone());
+    compiler.setOutputDir(Some(dir.join("out")));
+    compiler.print_scan().unwrap();
+    assert_eq!(read("b.cpp.deps"), "export module b\n");
+    assert_eq!(
+        read("a.cpp.deps"),
+        "global module\ndepends on: export module b\n"
+    );
+
+    let mut compiler = Compiler::new(parameters.clone());
+    compiler.setOutputDir(Some(dir.join("out")));
+    compiler.print_ast(Some("a")).unwrap();
+    let ast = read("b.cpp.ast");
+    assert!(ast.starts_with("AstDeclNamespaceStruct\n├─ name: a\n"));
+    assert!(ast.contains("name: inner"));
+    assert!(!ast.contains("name: c"));
+    assert!(!ast.contains('\u{1b}'));
+    assert!(read("a.cpp.ast").contains("name: a"));
+
+    // The same output in every run
+    let mut compiler = Compiler::new(parameters);
+    compiler.setOutputDir(Some(dir.join("out")));
+    compiler.print_ast(Some("a")).unwrap();
+    assert_eq!(read("b.cpp.ast"), ast);
+}
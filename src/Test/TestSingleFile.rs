@@ -51,6 +51,12 @@ fn testSingleFile(
     let fileTest = fileTest.to_str().unwrap().to_string();
     let mut params = Parameters::new();
     params.translationUnits.push(fileTest);
+    params.bmiDir = std::env::temp_dir()
+        .join("rustycpp-test-bmi")
+        .join(funcName)
+        .to_str()
+        .unwrap()
+        .to_string();
     let mut tmpRes = (HashMap::new(), Vec::new());
     let stateCompiler = Compiler::new(params).parsed_tree_test(&mut tmpRes);
     let (ast, errors) = tmpRes;
@@ -130,6 +136,13 @@ fn parsesModule() {
     let _ = testSuccessfulFile!();
 }
 
+#[test]
+#[named]
+fn parsesModulePrivateInPartition() {
+    let (_, e, s) = testUnsuccessfulFile!();
+    checkErrors(e, &s, &[e!(2)]);
+}
+
 #[test]
 #[named]
 fn parsesModuleError1() {
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use crate::Preprocessor::Minimize::minimize;
+use crate::Preprocessor::Pretoken::{PreToken, PreprocessingOperator};
+use crate::Preprocessor::TokenCache::TokenCache;
+use crate::Utils::Structs::{CompileFile, TokPos};
+
+use test_log::test;
+
+fn significant(toks: &[TokPos<PreToken>]) -> Vec<PreToken> {
+    toks.iter()
+        .map(|tok| tok.tok.clone())
+        .filter(|tok| !matches!(tok, PreToken::Whitespace(_) | PreToken::Newline))
+        .collect()
+}
+
+#[test]
+fn sharedBetweenUsers() {
+    let cache = TokenCache::default();
+    let file = CompileFile::new("a.h".to_string(), "int a;\n");
+    let first = cache.get(1, &file);
+    let second = cache.get(1, &CompileFile::new("a.h".to_string(), "int a;\n"));
+    assert!(Arc::ptr_eq(&first, &second));
+}
+
+#[test]
+fn invalidatedByContent() {
+    let cache = TokenCache::default();
+    let first = cache.get(1, &CompileFile::new("a.h".to_string(), "int a;\n"));
+    let second = cache.get(1, &CompileFile::new("a.h".to_string(), "b c;\n"));
+    assert!(!Arc::ptr_eq(&first, &second));
+    assert_eq!(
+        significant(second.tokens()),
+        vec![
+            PreToken::Ident("b".to_string()),
+            PreToken::Ident("c".to_string()),
+            PreToken::OperatorPunctuator(";"),
+        ]
+    );
+}
+
+#[test]
+fn headerNamesOnlyAfterInclude() {
+    let cache = TokenCache::default();
+    let lexed = cache.get(
+        1,
+        &CompileFile::new(
+            "a.h".to_string(),
+            "# include <a b.h>\n#include MACRO\nbool c = a<b>c;\n",
+        ),
+    );
+    let toks = significant(lexed.tokens());
+    assert!(toks.contains(&PreToken::HeaderName("<a b.h>".to_string())));
+    assert_eq!(
+        toks.iter()
+            .filter(|tok| matches!(tok, PreToken::HeaderName(_)))
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn directiveSkeleton() {
+    let cache = TokenCache::default();
+    let lexed = cache.get(
+        1,
+        &CompileFile::new(
+            "a.h".to_string(),
+            "#if 0\nint a # b;\n  #endif\nexport module m;\nint c;\n",
+        ),
+    );
+    let toks = lexed.tokens();
+    let first = lexed.nextDirective(0);
+    assert_eq!(first, 0);
+    let second = lexed.nextDirective(first + 1);
+    assert_eq!(toks[second].tok.to_str(), "#");
+    assert_eq!(toks[second + 1].tok.to_str(), "endif");
+    let third = lexed.nextDirective(second + 1);
+    assert_eq!(toks[third].tok.to_str(), "export");
+    assert_eq!(lexed.nextDirective(third + 1), toks.len());
+}
+
+#[test]
+fn minimizeKeepsOnlyDirectives() {
+    let content = r#"#include "a.h" /* spans
+lines */ int notKept;
+export module m;
+export int f();
+export
+module notAModule;
+  import <b.h>;
+int importer = 1'000;
+const char *s = R"x(
+#define NOT_A_DIRECTIVE
+)x";
+/* #define ALSO_NOT
+*/ #define NOT_AT_LINE_START
+#define CONTINUED \
+    1
+// #define COMMENTED
+"#;
+    let minimized = minimize(content);
+    assert_eq!(minimized.len(), content.len());
+    assert_eq!(minimized.lines().count(), content.lines().count());
+    let kept = minimized
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kept,
+        [
+            r#"#include "a.h" /* spans"#,
+            "lines */ int notKept;",
+            "export module m;",
+            "export",
+            "module notAModule;",
+            "import <b.h>;",
+            "#define CONTINUED \\",
+            "1",
+        ]
+    );
+}
+
+#[test]
+fn minimizingCacheKeepsOffsets() {
+    let content = "int a;\n#define A 1\n";
+    let cache = TokenCache::new_minimizing();
+    let lexed = cache.get(1, &CompileFile::new("a.h".to_string(), content));
+    let define = lexed
+        .tokens()
+        .iter()
+        .find(|tok| tok.tok == PreToken::Ident("define".to_string()))
+        .unwrap();
+    assert_eq!(&content[define.start..define.end], "define");
+    assert_eq!(
+        significant(lexed.tokens()),
+        vec![
+            PreToken::PreprocessingOperator(PreprocessingOperator::Hash),
+            PreToken::Ident("define".to_string()),
+            PreToken::Ident("A".to_string()),
+            PreToken::PPNumber("1".to_string()),
+        ]
+    );
+}
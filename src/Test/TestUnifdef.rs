@@ -0,0 +1,59 @@
+use crate::Preprocessor::Unifdef::{Unifdef, UnifdefConfig};
+
+use test_log::test;
+
+fn unifdef(defines: &[&str], undefines: &[&str], content: &str) -> String {
+    let config = UnifdefConfig::new(
+        &defines.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        &undefines
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+    );
+    Unifdef::run(&config, content)
+}
+
+#[test]
+fn resolvesKnownConditions() {
+    let res = unifdef(
+        &["WIN32"],
+        &["LINUX"],
+        "#ifdef WIN32\nwin();\n#else\nother();\n#endif\n#if defined(LINUX) || 0\nlinux();\n#endif\nend();\n",
+    );
+    assert_eq!(res, "win();\nend();\n");
+}
+
+#[test]
+fn leavesUnknownConditionsUntouched() {
+    let content = "#include <a.h> // comment\n#if UNKNOWN /* why */\nA MACRO_USE(x)\n#elif 0\nB\n#endif\n#if 0\nC\n#endif\n";
+    assert_eq!(unifdef(&["WIN32"], &[], content), content);
+}
+
+#[test]
+fn mixedKnownAndUnknownBranches() {
+    let res = unifdef(
+        &["VERSION=3"],
+        &["OLD"],
+        "#if defined(OLD)\nold\n#elif UNKNOWN\nunknown\n#elif VERSION > 2\nnew\n#else\nfallback\n#endif\n",
+    );
+    assert_eq!(res, "#if UNKNOWN\nunknown\n#else\nnew\n#endif\n");
+}
+
+#[test]
+fn nestedGroupsInDroppedRegions() {
+    let res = unifdef(
+        &[],
+        &["A"],
+        "#ifdef A\n#if UNKNOWN\nx\n#else\ny\n#endif\n#endif\n#ifndef A\n  #  if UNKNOWN\nz\n  #  endif\n#endif",
+    );
+    assert_eq!(res, "  #  if UNKNOWN\nz\n  #  endif\n");
+}
+
+#[test]
+fn macroValuesUsingUnknownIdentifiers() {
+    let content = "#if FOO == 1\nfoo\n#endif\n";
+    assert_eq!(unifdef(&["FOO=BAR"], &[], content), content);
+    assert_eq!(unifdef(&["FOO=BAR", "BAR=1"], &[], content), "foo\n");
+    assert_eq!(unifdef(&["FOO=BAR"], &["BAR"], content), "");
+    assert_eq!(unifdef(&["FOO=FOO"], &[], content), content);
+}
@@ -0,0 +1,4 @@
+#pragma once
+#include "amalgamate/xmacro.h"
+#include "amalgamate/lib.h"
+int detail();
@@ -0,0 +1,8 @@
+// The library
+#ifndef LIB_H
+#define LIB_H
+#include "amalgamate/detail.h"
+#include <vector>
+#include "amalgamate/detail.h"
+int lib();
+#endif
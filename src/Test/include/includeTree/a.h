@@ -0,0 +1,5 @@
+#ifndef INCLUDE_TREE_A_H
+#define INCLUDE_TREE_A_H
+#include "includeTree/b.h"
+int a;
+#endif
@@ -0,0 +1,4 @@
+#ifndef INCLUDE_TREE_B_H
+#define INCLUDE_TREE_B_H
+int b1, b2;
+#endif
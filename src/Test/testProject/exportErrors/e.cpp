@@ -0,0 +1,4 @@
+export module e;
+export {
+	export namespace x {}
+}
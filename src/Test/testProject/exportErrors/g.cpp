@@ -0,0 +1,2 @@
+namespace y {}
+export namespace z {}
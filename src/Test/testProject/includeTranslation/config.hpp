@@ -0,0 +1 @@
+#define CONFIG_VALUE 1
@@ -0,0 +1,4 @@
+#include "config.hpp"
+#if !CONFIG_VALUE
+#error "The macros of the header unit were not imported"
+#endif
@@ -0,0 +1,2 @@
+#include "config.hpp"
+namespace afterImport {}
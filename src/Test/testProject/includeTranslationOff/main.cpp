@@ -0,0 +1,4 @@
+#include "config.hpp"
+#if !CONFIG_VALUE
+#error "The header was not included"
+#endif
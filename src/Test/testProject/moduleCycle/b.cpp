@@ -0,0 +1,3 @@
+export module b;
+import c;
+import d;
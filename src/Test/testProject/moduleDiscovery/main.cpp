@@ -0,0 +1,2 @@
+import fmt;
+import util;
@@ -0,0 +1 @@
+module util:detail;
@@ -0,0 +1,3 @@
+export module util;
+import :detail;
+import base;
@@ -0,0 +1,8 @@
+export module a;
+export namespace [[rustycpp::tagDecl(1)]] n {
+	[[rustycpp::tagDecl(2)]]
+	__rustycpp__(enum E);
+}
+namespace hidden {
+	__rustycpp__(enum H);
+}
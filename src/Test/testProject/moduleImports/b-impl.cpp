@@ -0,0 +1,5 @@
+module b:impl;
+namespace inner {
+	[[rustycpp::tagDecl(4)]]
+	__rustycpp__(enum G);
+}
@@ -0,0 +1,8 @@
+export module b;
+export import a;
+import :impl;
+[[rustycpp::checkSymbolMatchTag(4, inner::G)]];
+export namespace n {
+	[[rustycpp::tagDecl(3)]]
+	__rustycpp__(enum F);
+}
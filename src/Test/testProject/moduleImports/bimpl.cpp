@@ -0,0 +1,4 @@
+module b;
+[[rustycpp::checkSymbolMatchTag(4, inner::G)]];
+[[rustycpp::checkSymbolMatchTag(2, n::E)]];
+[[rustycpp::checkSymbolMatchTag(3, n::F)]];
@@ -0,0 +1,5 @@
+import b;
+[[rustycpp::checkSymbolMatchTag(1, n)]];
+[[rustycpp::checkSymbolMatchTag(2, n::E)]];
+[[rustycpp::checkSymbolMatchTag(3, n::F)]];
+[[rustycpp::checkSymbolMatchTag(false, inner::G)]];
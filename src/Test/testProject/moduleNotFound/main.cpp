@@ -0,0 +1,2 @@
+import present;
+import missing;
@@ -0,0 +1 @@
+module c:p;
@@ -0,0 +1,2 @@
+export module e;
+import :q;
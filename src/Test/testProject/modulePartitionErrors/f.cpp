@@ -0,0 +1,2 @@
+export module f;
+export import :impl;
@@ -0,0 +1 @@
+module f:impl;
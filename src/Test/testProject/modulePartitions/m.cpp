@@ -0,0 +1,3 @@
+export module m;
+export import :a;
+import :impl;
@@ -0,0 +1,2 @@
+export module m:a;
+export import :b;
@@ -0,0 +1,3 @@
+export module g;
+module :private;
+module :private;
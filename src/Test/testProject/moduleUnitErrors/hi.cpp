@@ -0,0 +1,2 @@
+module h;
+export import h;
@@ -0,0 +1,2 @@
+export module q:part;
+module :private;
@@ -0,0 +1,3 @@
+module a:part;
+[[rustycpp::tagDecl(4)]]
+__rustycpp__(enum P);
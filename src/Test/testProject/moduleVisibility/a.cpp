@@ -0,0 +1,17 @@
+module;
+namespace gmf {
+	__rustycpp__(enum G);
+}
+export module a;
+import :part;
+export namespace n {
+	[[rustycpp::tagDecl(1)]]
+	__rustycpp__(enum E);
+}
+namespace hidden {
+	[[rustycpp::tagDecl(2)]]
+	__rustycpp__(enum H);
+}
+[[rustycpp::tagDecl(3)]]
+__rustycpp__(enum Internal);
+[[rustycpp::checkSymbolMatchTag(4, P)]];
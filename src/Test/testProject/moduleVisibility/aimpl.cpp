@@ -0,0 +1,5 @@
+module a;
+[[rustycpp::checkSymbolMatchTag(1, n::E)]];
+[[rustycpp::checkSymbolMatchTag(2, hidden::H)]];
+[[rustycpp::checkSymbolMatchTag(3, Internal)]];
+[[rustycpp::checkSymbolMatchTag(4, P)]];
@@ -0,0 +1,6 @@
+import a;
+[[rustycpp::checkSymbolMatchTag(1, n::E)]];
+[[rustycpp::checkSymbolMatchTag(false, hidden::H)]];
+[[rustycpp::checkSymbolMatchTag(false, Internal)]];
+[[rustycpp::checkSymbolMatchTag(false, gmf::G)]];
+[[rustycpp::checkSymbolMatchTag(false, P)]];
@@ -0,0 +1,2 @@
+module;
+export module foo.foo:bar.bar [[foo::bar]] alignas(invalid);
@@ -0,0 +1,2 @@
+export module foo.foo;
+export import :bar.bar;
@@ -0,0 +1,2 @@
+export module m;
+import :p1;
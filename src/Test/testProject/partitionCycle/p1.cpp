@@ -0,0 +1,2 @@
+module m:p1;
+import :p2;
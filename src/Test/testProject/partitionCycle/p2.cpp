@@ -0,0 +1,2 @@
+module m:p2;
+import :p1;
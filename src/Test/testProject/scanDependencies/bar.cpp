@@ -0,0 +1 @@
+export module bar;
@@ -0,0 +1 @@
+#define USE_BAR 1
@@ -0,0 +1,6 @@
+module;
+#include "config.hpp"
+export module main;
+#if USE_BAR
+import bar;
+#endif
@@ -1,4 +1,3 @@
 module;
-export module foo.foo:bar.bar [[foo::bar]] alignas(invalid);
+export module foo.foo [[foo::bar]] alignas(invalid);
 module :private;
-
@@ -0,0 +1,2 @@
+export module foo.foo:bar.bar;
+module :private;
@@ -6,10 +6,13 @@ pub mod Structs;
 pub mod CompilerState;
 pub mod DebugNode;
 pub mod FileMap;
+pub mod FileSystem;
 pub mod FoldingContainer;
+pub mod MacroExpansionRef;
 pub mod ModuleHeaderAtomicLexingList;
 pub mod NomLike;
 pub mod Parameters;
 pub mod StateCompileUnit;
 pub mod StringRef;
+pub mod TimeTrace;
 pub mod UnsafeAllocator;
@@ -6,10 +6,16 @@ use std::{
 };
 
 use crate::Compiler::TranslationUnit;
+use crate::Preprocessor::Callbacks::PreprocessorCallbacks;
+use crate::Preprocessor::ConditionalReport::ConditionalReport;
+use crate::Preprocessor::MacroTrace::MacroTracer;
+use crate::Preprocessor::TokenCache::TokenCache;
+use crate::Sema::Bmi::BmiCache;
 
 use super::FileMap::FileMap;
 use super::Parameters::Parameters;
 use super::StateCompileUnit::StateCompileUnit;
+use super::TimeTrace::TimeTrace;
 
 /// State of the compiler
 #[derive(Debug, Clone)]
@@ -24,5 +30,19 @@ pub struct CompilerState {
     pub moduleHeaderUnitsFiles: Arc<HashSet<u64>>,
     /// State of the compilation units
     pub compileUnits: Arc<HashMap<TranslationUnit, StateCompileUnit>>,
+    /// Have errors been found?
     pub foundErrors: Arc<AtomicBool>,
+    /// Pre-lexed files, shared by all the translation units
+    pub tokenCache: Arc<TokenCache>,
+    /// BMIs of the modules imported or compiled
+    pub bmiCache: Arc<BmiCache>,
+    /// Observers of the preprocessing
+    pub preprocessorCallbacks: Vec<Arc<dyn PreprocessorCallbacks>>,
+    /// If present, records the macro expansions requested by the user
+    pub macroTracer: Option<Arc<MacroTracer>>,
+    /// If present, records how long each stage takes
+    pub timeTrace: Option<Arc<TimeTrace>>,
+    /// If present, records the conditional structure of each translation
+    /// unit preprocessed
+    pub conditionalReports: Option<Arc<Mutex<HashMap<TranslationUnit, ConditionalReport>>>>,
 }
@@ -1,5 +1,6 @@
 use colored::Colorize;
 
+/// Printable tree of a node of the AST
 #[derive(Clone, Eq)]
 pub struct DebugNode {
     nameColorless: String,
@@ -23,6 +24,7 @@ impl std::fmt::Debug for DebugNode {
 }
 
 impl DebugNode {
+    /// Node without children
     pub fn new(name: String) -> Self {
         let mut result = String::new();
         Self::colorizedTag(&mut result, &name);
@@ -33,11 +35,15 @@ impl DebugNode {
         }
     }
 
+    /// Adds a child
+    #[must_use]
     pub fn add_child(mut self, child: Self) -> Self {
         self.children.push(child);
         self
     }
 
+    /// Adds children
+    #[must_use]
     pub fn add_children(mut self, children: Vec<Self>) -> Self {
         self.children.extend(children);
         self
@@ -60,14 +66,24 @@ impl DebugNode {
         colored::control::unset_override();
     }
 
-    fn to_string_rec(&self, depth: &mut Vec<bool>, isLast: bool, result: &mut String) {
+    fn to_string_rec(
+        &self,
+        depth: &mut Vec<bool>,
+        isLast: bool,
+        colored: bool,
+        result: &mut String,
+    ) {
         if !depth.is_empty() {
             for last in &depth[1..depth.len()] {
                 result.push_str(if *last { "   " } else { "│  " });
             }
             result.push_str(if isLast { "╰─ " } else { "├─ " });
         };
-        result.push_str(&self.name);
+        result.push_str(if colored {
+            &self.name
+        } else {
+            &self.nameColorless
+        });
         result.push('\n');
 
         if self.children.is_empty() {
@@ -77,7 +93,7 @@ impl DebugNode {
         for (i, child) in self.children.iter().enumerate() {
             depth.push(isLast);
             let isLast = i == self.children.len() - 1;
-            child.to_string_rec(depth, isLast, result);
+            child.to_string_rec(depth, isLast, colored, result);
             depth.pop();
         }
     }
@@ -92,16 +108,46 @@ impl DebugNode {
             + 1 // nl
     }
 
+    /// The children
     pub const fn getChilds(&self) -> &Vec<Self> {
         &self.children
     }
+
+    /// The tree without colors, for files and pipes
+    pub fn to_string_colorless(&self) -> String {
+        let mut result = String::new();
+        result.reserve(self.to_string_mem(0));
+        self.to_string_rec(&mut vec![], true, false, &mut result);
+        result.pop();
+        result
+    }
+
+    /// The outermost nodes of declarations named `name`: the ones with a
+    /// `name: <name>` child
+    pub fn findNamed(&self, name: &str) -> Vec<&Self> {
+        let label = format!("name: {name}");
+        let mut found = vec![];
+        let mut pending = vec![self];
+        while let Some(node) = pending.pop() {
+            if node
+                .children
+                .iter()
+                .any(|child| child.nameColorless == label)
+            {
+                found.push(node);
+            } else {
+                pending.extend(node.children.iter().rev());
+            }
+        }
+        found
+    }
 }
 
 impl ToString for DebugNode {
     fn to_string(&self) -> String {
         let mut result = String::new();
         result.reserve(self.to_string_mem(0));
-        self.to_string_rec(&mut vec![], true, &mut result);
+        self.to_string_rec(&mut vec![], true, true, &mut result);
         result.pop();
         result
     }
@@ -2,16 +2,17 @@
 #![allow(clippy::verbose_file_reads, clippy::cast_possible_truncation)]
 
 use std::path::{Path, PathBuf};
-use std::{collections::HashMap, fs::File, fs::OpenOptions, io::Read, sync::Arc};
+use std::{collections::HashMap, sync::Arc};
 
 use crate::Utils::Structs::CompileFile;
 
+use super::FileSystem::{FileSystem, RealFileSystem};
 use super::Parameters::Parameters;
 
 #[derive(Debug)]
 enum Either {
     CompileFile(Arc<CompileFile>),
-    NotReadFile(Option<File>),
+    NotReadFile,
 }
 
 #[derive(Debug)]
@@ -19,6 +20,8 @@ enum Either {
 pub struct FileMap {
     /// Parameters of the compilation
     params: Arc<Parameters>,
+    /// Where the files are read from
+    fileSystem: Arc<dyn FileSystem>,
     /// Files opened
     files: Vec<Either>,
     /// Resolved paths
@@ -28,10 +31,16 @@ pub struct FileMap {
 }
 
 impl<'a> FileMap {
-    /// New file map.
+    /// New file map, of the files on disk.
     pub fn new(params: Arc<Parameters>) -> Self {
+        Self::new_with_fs(params, Arc::new(RealFileSystem))
+    }
+
+    /// New file map, of the files of `fileSystem`.
+    pub fn new_with_fs(params: Arc<Parameters>, fileSystem: Arc<dyn FileSystem>) -> Self {
         let mut me = Self {
             params,
+            fileSystem,
             files: vec![],
             resolvedPaths: HashMap::new(),
             reverseResolved: HashMap::new(),
@@ -44,12 +53,25 @@ impl<'a> FileMap {
         me
     }
 
-    fn internalReadFile(&mut self, path: u64, mut file: &File) -> Arc<CompileFile> {
-        let mut filecontents: String = String::new();
+    /// Where the files are read from
+    pub fn fileSystem(&self) -> &Arc<dyn FileSystem> {
+        &self.fileSystem
+    }
+
+    /// Canonical path of `path`, or `path` itself if there is no file there.
+    pub fn canonicalPath(&self, path: &str) -> String {
+        self.fileSystem.canonicalize(Path::new(path)).map_or_else(
+            |_| path.to_string(),
+            |path| path.to_string_lossy().to_string(),
+        )
+    }
+
+    fn internalReadFile(&mut self, path: u64) -> Arc<CompileFile> {
         let pathStr = self.reverseResolved.get(&path).unwrap();
-        if let Err(err) = file.read_to_string(&mut filecontents) {
-            panic!("Error reading {pathStr}. Error: {err}");
-        }
+        let filecontents = match self.fileSystem.read(Path::new(pathStr)) {
+            Ok(filecontents) => filecontents,
+            Err(err) => panic!("Error reading {pathStr}. Error: {err}"),
+        };
 
         let res = Arc::new(CompileFile::new(pathStr.clone(), &filecontents));
         *self.files.get_mut(path as usize).unwrap() = Either::CompileFile(res.clone());
@@ -60,19 +82,60 @@ impl<'a> FileMap {
     pub fn getOpenedFile(&mut self, path: u64) -> Arc<CompileFile> {
         match self.files.get_mut(path as usize) {
             Some(Either::CompileFile(v)) => v.clone(),
-            Some(Either::NotReadFile(file)) => {
-                let fileRef = file.take().unwrap();
-                self.internalReadFile(path, &fileRef)
-            }
+            Some(Either::NotReadFile) => self.internalReadFile(path),
             _ => panic!("File not found in visited files: {path}"),
         }
     }
 
+    /// Reads again the files already read, replacing the ones whose contents
+    /// changed. Returns the paths of those. Fails if a file can no longer be
+    /// read.
+    pub fn refresh(&mut self) -> Result<Vec<String>, String> {
+        let mut changed = vec![];
+        for file in self.files.iter_mut().skip(1) {
+            let Either::CompileFile(compileFile) = file else {
+                continue;
+            };
+            let path = compileFile.path().clone();
+            let content = self
+                .fileSystem
+                .read(Path::new(&path))
+                .map_err(|err| format!("Error reading {path}. Error: {err}"))?;
+            let refreshed = CompileFile::new(path.clone(), &content);
+            if refreshed.content() != compileFile.content() {
+                *file = Either::CompileFile(Arc::new(refreshed));
+                changed.push(path);
+            }
+        }
+        Ok(changed)
+    }
+
     /// Get file. If not present, open it. On error, crash.
     pub fn getAddFile(&'a mut self, path: &str) -> u64 {
         self.getPath(path).unwrap()
     }
 
+    /// Resolve a path only through the include directories, not the system
+    /// ones. Used to know if a header belongs to the project.
+    pub fn getLocalPath(&mut self, pathStr: &str) -> Option<u64> {
+        let candidates = self
+            .params
+            .includeDirs
+            .iter()
+            .map(|dir| Path::new(dir).join(pathStr))
+            .collect::<Vec<_>>();
+        self.getFirstPath(candidates)
+    }
+
+    /// Resolve the first of `candidates` that exists.
+    pub fn getFirstPath(&mut self, candidates: impl IntoIterator<Item = PathBuf>) -> Option<u64> {
+        let found = candidates
+            .into_iter()
+            .find(|path| self.fileSystem.exists(path))?;
+        let found = self.fileSystem.canonicalize(&found).ok()?;
+        self.getPath(found.to_str()?).ok()
+    }
+
     /// Can it access the file? Does not need to be previously opened.
     pub fn hasFileAccess(&mut self, path: &str) -> bool {
         let absolutePath = self.getPath(path);
@@ -86,57 +149,45 @@ impl<'a> FileMap {
             let filename = std::path::Path::new(absolutePath);
             if !filename.extension().map_or(false, |ext| {
                 ext.eq_ignore_ascii_case("cpp")
+                    || ext.eq_ignore_ascii_case("cppm")
+                    || ext.eq_ignore_ascii_case("ixx")
+                    || ext.eq_ignore_ascii_case("mpp")
                     || ext.eq_ignore_ascii_case("hpp")
                     || ext.eq_ignore_ascii_case("h")
             }) {
                 log::error!("Unsuported file type: {}", absolutePath);
             }
-            let file: File = match OpenOptions::new().read(true).open(absolutePath) {
-                Ok(it) => it,
-                Err(err) => {
-                    return Err(err.to_string());
-                }
-            };
+            if !self.fileSystem.exists(filename) {
+                return Err(format!("Could not find file: {absolutePath}"));
+            }
             let pos = self.files.len() as u64;
-            self.files.push(Either::NotReadFile(Some(file)));
+            self.files.push(Either::NotReadFile);
             Ok(pos)
         }
     }
 
-    /// Add a fake test file. Intened for testing.
-    #[cfg(test)]
-    pub fn addTestFile(&mut self, path: String, content: &str) {
-        self.resolvedPaths
-            .insert(path.clone(), self.files.len() as u64);
-        self.reverseResolved
-            .insert(self.files.len() as u64, path.clone());
-        self.files
-            .push(Either::CompileFile(Arc::new(CompileFile::new(
-                path, content,
-            ))));
-    }
-
-    fn findBestPath(params: &Arc<Parameters>, pathStr: &str) -> Result<String, String> {
+    fn findBestPath(&self, pathStr: &str) -> Result<String, String> {
         let res: Result<PathBuf, String> = (|| {
             let path = Path::new(&pathStr).to_path_buf();
-            if path.is_absolute() && path.exists() {
+            if path.is_absolute() && self.fileSystem.exists(&path) {
                 return Ok(path);
             }
-            for dir in &params.includeDirs {
+            for dir in &self.params.includeDirs {
                 let resultingPath = Path::new(dir).join(&path);
-                if resultingPath.exists() {
+                if self.fileSystem.exists(&resultingPath) {
                     return Ok(resultingPath);
                 }
             }
-            for dir in &params.includeSystemDirs {
+            for dir in &self.params.includeSystemDirs {
                 let resultingPath = Path::new(dir).join(&path);
-                if resultingPath.exists() {
+                if self.fileSystem.exists(&resultingPath) {
                     return Ok(resultingPath);
                 }
             }
             Err(format!("Could not find file: {pathStr}"))
         })();
-        res.map(|path| path.canonicalize().unwrap().to_str().unwrap().to_string())
+        res.and_then(|path| self.fileSystem.canonicalize(&path))
+            .map(|path| path.to_str().unwrap().to_string())
     }
 
     /// Resolve a path. On error, return error.
@@ -144,7 +195,7 @@ impl<'a> FileMap {
         if let Some(v) = self.resolvedPaths.get(pathStr) {
             Ok(*v)
         } else {
-            let canonical = Self::findBestPath(&self.params, pathStr)?;
+            let canonical = self.findBestPath(pathStr)?;
             if let Some(v) = self.resolvedPaths.get(&canonical) {
                 let v = *v;
                 self.resolvedPaths.insert(pathStr.to_string(), v);
@@ -0,0 +1,154 @@
+//! File systems the compiler reads its files from.
+//!
+//! The [`FileMap`](super::FileMap::FileMap) reads every file through a
+//! [`FileSystem`]: the disk by default, but tools embedding the compiler can
+//! give it files that are not saved yet (like the buffers of an editor) with
+//! an [`InMemoryFileSystem`], layered over the disk with an
+//! [`OverlayFileSystem`].
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Source of the files of the compilation
+pub trait FileSystem: Debug + Send + Sync {
+    /// Contents of the file at `path`
+    fn read(&self, path: &Path) -> Result<String, String>;
+
+    /// Is there a file at `path`?
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Absolute path of the file at `path`, without `.`, `..` or links. Fails
+    /// if there is no file there.
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, String>;
+}
+
+/// The files on disk
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read(&self, path: &Path) -> Result<String, String> {
+        std::fs::read_to_string(path).map_err(|err| err.to_string())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, String> {
+        path.canonicalize().map_err(|err| err.to_string())
+    }
+}
+
+/// Files kept in memory. Relative paths are relative to the root, and there
+/// are no links, so a path is canonicalized by resolving its `.` and `..`.
+#[derive(Debug, Default)]
+pub struct InMemoryFileSystem {
+    /// Contents of each file, by canonical path
+    files: RwLock<HashMap<PathBuf, String>>,
+}
+
+impl InMemoryFileSystem {
+    /// Empty file system
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Absolute path, without `.` or `..`
+    fn normalize(path: &Path) -> PathBuf {
+        let mut normalized = PathBuf::from("/");
+        for component in path.components() {
+            match component {
+                Component::Prefix(prefix) => normalized.push(prefix.as_os_str()),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                Component::Normal(name) => normalized.push(name),
+            }
+        }
+        normalized
+    }
+
+    /// Adds the file at `path`, or replaces its contents
+    pub fn addFile(&self, path: impl AsRef<Path>, content: impl Into<String>) {
+        self.files
+            .write()
+            .unwrap()
+            .insert(Self::normalize(path.as_ref()), content.into());
+    }
+
+    /// Removes the file at `path`. Returns if it existed.
+    pub fn removeFile(&self, path: impl AsRef<Path>) -> bool {
+        self.files
+            .write()
+            .unwrap()
+            .remove(&Self::normalize(path.as_ref()))
+            .is_some()
+    }
+}
+
+impl FileSystem for InMemoryFileSystem {
+    fn read(&self, path: &Path) -> Result<String, String> {
+        self.files
+            .read()
+            .unwrap()
+            .get(&Self::normalize(path))
+            .cloned()
+            .ok_or_else(|| format!("No such file: {}", path.display()))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files
+            .read()
+            .unwrap()
+            .contains_key(&Self::normalize(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, String> {
+        let normalized = Self::normalize(path);
+        if self.files.read().unwrap().contains_key(&normalized) {
+            Ok(normalized)
+        } else {
+            Err(format!("No such file: {}", path.display()))
+        }
+    }
+}
+
+/// Layers of file systems. A file is taken from the first layer that has it,
+/// so the upper layers hide the files of the lower ones.
+#[derive(Debug)]
+pub struct OverlayFileSystem {
+    /// The layers, the upper one first
+    layers: Vec<Arc<dyn FileSystem>>,
+}
+
+impl OverlayFileSystem {
+    /// File system of the `layers`, the upper one first
+    pub fn new(layers: Vec<Arc<dyn FileSystem>>) -> Self {
+        Self { layers }
+    }
+
+    /// Upper layer that has the file at `path`
+    fn layerOf(&self, path: &Path) -> Result<&Arc<dyn FileSystem>, String> {
+        self.layers
+            .iter()
+            .find(|layer| layer.exists(path))
+            .ok_or_else(|| format!("No such file: {}", path.display()))
+    }
+}
+
+impl FileSystem for OverlayFileSystem {
+    fn read(&self, path: &Path) -> Result<String, String> {
+        self.layerOf(path)?.read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.layers.iter().any(|layer| layer.exists(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, String> {
+        self.layerOf(path)?.canonicalize(path)
+    }
+}
@@ -0,0 +1,53 @@
+//! Expansion locations of tokens generated by a macro expansion.
+//!
+//! Every time a macro is expanded, a new [`MacroExpansionLoc`] is created.
+//! Tokens generated by that expansion reference it, so diagnostics on them can
+//! explain which macro invocation produced them. The references are shared
+//! pointers, so they can travel with [`FileTokPos`](super::Structs::FileTokPos)
+//! all the way into the AST and the diagnostics, and outlive the compilation.
+use std::sync::Arc;
+
+use super::{StringRef::StringRef, Structs::SourceRange};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Where and how a macro was expanded
+pub struct MacroExpansionLoc {
+    /// Name of the expanded macro
+    pub macroName: StringRef,
+    /// Where the macro was defined. None for builtin macros
+    pub definedAt: Option<SourceRange>,
+    /// Location of the macro name in the invocation
+    pub expandedAt: SourceRange,
+    /// If the invocation was itself generated by a macro expansion, that expansion
+    pub parent: Option<MacroExpansionRef>,
+}
+
+#[derive(Debug, Clone)]
+/// Reference to a [`MacroExpansionLoc`]. Cheap to clone around.
+pub struct MacroExpansionRef {
+    loc: Arc<MacroExpansionLoc>,
+}
+
+impl PartialEq for MacroExpansionRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.loc, &other.loc)
+    }
+}
+impl Eq for MacroExpansionRef {}
+
+impl MacroExpansionRef {
+    /// A new expansion location
+    pub fn new(loc: MacroExpansionLoc) -> Self {
+        Self { loc: Arc::new(loc) }
+    }
+
+    /// The expansion location
+    pub fn loc(&self) -> &MacroExpansionLoc {
+        &self.loc
+    }
+
+    /// Iterates from this expansion up to the outermost one
+    pub fn chain(&self) -> impl Iterator<Item = &MacroExpansionLoc> {
+        std::iter::successors(Some(self), |exp| exp.loc.parent.as_ref()).map(|exp| &*exp.loc)
+    }
+}
@@ -1,5 +1,5 @@
 //! Parsing of the input config file.
-use std::fs;
+use std::{collections::HashMap, fs, path::Path};
 
 use json::{parse, JsonValue};
 
@@ -14,25 +14,101 @@ pub struct Parameters {
     pub includeDirs: Vec<String>,
     /// System Include paths.
     pub includeSystemDirs: Vec<String>,
+    /// Number of threads. All the cores by default.
     pub threadNum: Option<usize>,
+    /// Warn about the macros defined in the translation units that are never
+    /// used, like `-Wunused-macros` of other compilers.
+    pub warnUnusedMacros: bool,
+    /// Translate the `#include` of the module header units into imports of
+    /// them ([cpp.include]/7). Off by default: they used to be always
+    /// translated, and now are included textually unless this is set.
+    pub translateIncludes: bool,
+    /// Interface file of each module, by module name (`name` or
+    /// `name:partition`). Imported modules found here don't need to be
+    /// listed in the translation units.
+    pub moduleMap: HashMap<String, String>,
+    /// Directories where the interfaces of the imported modules that are not
+    /// listed in the translation units are searched.
+    pub moduleSearchDirs: Vec<String>,
+    /// Directory where the binary module interfaces (BMIs) of the module
+    /// interface units are written, and read from when importing them.
+    /// `.rustycpp/bmi` next to the file list by default.
+    pub bmiDir: String,
+    /// State file of the incremental rebuild planner, with the result of the
+    /// previous dependency scan. `.rustycpp/rebuild-state.json` next to the
+    /// file list by default.
+    pub rebuildStateFile: String,
+    /// Cache with the time each translation unit took to compile, used to
+    /// estimate the critical path of the module tree. Not used if missing.
+    pub timingCacheFile: Option<String>,
+}
+
+/// Directory of the files kept between compilations, next to the file list
+const STATE_DIR: &str = ".rustycpp";
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parameters {
-    /// new Parameters.
-    pub const fn new() -> Self {
+    /// new Parameters. The files kept between compilations are in the
+    /// current directory.
+    pub fn new() -> Self {
+        Self::new_at(Path::new(""))
+    }
+
+    /// new Parameters, of a file list in `listDir`
+    fn new_at(listDir: &Path) -> Self {
+        let stateDir = listDir.join(STATE_DIR);
         Self {
             translationUnits: Vec::new(),
             includeDirs: Vec::new(),
             includeSystemDirs: Vec::new(),
             moduleHeaderUnits: Vec::new(),
             threadNum: None,
+            warnUnusedMacros: false,
+            translateIncludes: false,
+            moduleMap: HashMap::new(),
+            moduleSearchDirs: Vec::new(),
+            bmiDir: stateDir.join("bmi").to_string_lossy().to_string(),
+            rebuildStateFile: stateDir
+                .join("rebuild-state.json")
+                .to_string_lossy()
+                .to_string(),
+            timingCacheFile: None,
         }
     }
 
     /// Parses the config file, and returns the results.
     pub fn new_file(file: &str) -> Result<Self, String> {
         let contents = fs::read_to_string(file).map_err(|x| x.to_string())?;
-        Self::new().parse(&contents)
+        Self::new_str(
+            &contents,
+            Path::new(file).parent().unwrap_or_else(|| Path::new("")),
+        )
+    }
+
+    /// Parses the contents of a config file in `listDir`, and returns the
+    /// results.
+    pub fn new_str(contents: &str, listDir: &Path) -> Result<Self, String> {
+        Self::new_at(listDir).parse(contents)
+    }
+
+    /// Makes the relative directories and output files of the parameters
+    /// relative to `dir` instead of the current directory. The translation
+    /// units and the interfaces of the `moduleMap` are found through the
+    /// include directories, so they follow them.
+    pub fn relativeTo(&mut self, dir: &Path) {
+        let join = |path: &mut String| *path = dir.join(&*path).to_string_lossy().to_string();
+        self.includeDirs
+            .iter_mut()
+            .chain(&mut self.includeSystemDirs)
+            .chain(&mut self.moduleSearchDirs)
+            .chain([&mut self.bmiDir, &mut self.rebuildStateFile])
+            .chain(&mut self.timingCacheFile)
+            .for_each(join);
     }
 
     /// Parses the config file.
@@ -65,6 +141,59 @@ impl Parameters {
                             );
                         }
                     }
+                    "warnUnusedMacros" => {
+                        if let JsonValue::Boolean(warn) = value {
+                            self.warnUnusedMacros = *warn;
+                        } else {
+                            return Err(
+                                "Invalid JSON Paramater: warnUnusedMacros must be a boolean"
+                                    .to_string(),
+                            );
+                        }
+                    }
+                    "translateIncludes" => {
+                        if let JsonValue::Boolean(translate) = value {
+                            self.translateIncludes = *translate;
+                        } else {
+                            return Err(
+                                "Invalid JSON Paramater: translateIncludes must be a boolean"
+                                    .to_string(),
+                            );
+                        }
+                    }
+                    "moduleMap" => {
+                        self.moduleMap = Self::parseStringMap(value, "moduleMap")?;
+                    }
+                    "moduleSearchDirs" => {
+                        self.moduleSearchDirs = Self::parseStringArray(value, "moduleSearchDirs")?;
+                    }
+                    "bmiDir" => {
+                        if let Some(dir) = value.as_str() {
+                            self.bmiDir = dir.to_string();
+                        } else {
+                            return Err(
+                                "Invalid JSON Paramater: bmiDir must be a string".to_string()
+                            );
+                        }
+                    }
+                    "rebuildStateFile" => {
+                        if let Some(file) = value.as_str() {
+                            self.rebuildStateFile = file.to_string();
+                        } else {
+                            return Err(
+                                "Invalid JSON Paramater: rebuildStateFile must be a string"
+                                    .to_string(),
+                            );
+                        }
+                    }
+                    "timingCacheFile" => {
+                        if let Some(file) = value.as_str() {
+                            self.timingCacheFile = Some(file.to_string());
+                        } else {
+                            return Err("Invalid JSON Paramater: timingCacheFile must be a string"
+                                .to_string());
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -92,4 +221,21 @@ impl Parameters {
         }
         Ok(res)
     }
+
+    /// Parse an object of strings. uses the name for error reporting only.
+    fn parseStringMap(value: &JsonValue, name: &str) -> Result<HashMap<String, String>, String> {
+        let mut res = HashMap::new();
+        if let JsonValue::Object(obj) = value {
+            for (key, val) in obj.iter() {
+                if let Some(str) = val.as_str() {
+                    res.insert(key.to_string(), str.to_string());
+                } else {
+                    return Err(format!("Invalid value for {name}.{key}: {val:?}"));
+                }
+            }
+        } else {
+            return Err(format!("Invalid value for {name}: {value:?}"));
+        }
+        Ok(res)
+    }
 }
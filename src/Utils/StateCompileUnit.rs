@@ -12,11 +12,13 @@ use crate::{
     Grammars::DefineAst::DefineAst,
     Lex::Token::Token,
     ModuleTree::{self, Structs::ModuleOperator},
-    Utils::Structs::{CompileMsg, FileTokPos},
+    Utils::Structs::{CompileMsg, FileTokPos, SourceRange},
 };
 
+/// Stages of a compile unit, in the order they are done. Once a stage is
+/// done, the previous ones are too.
 #[atomic_enum]
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub enum StageCompileUnit {
     Start,
     Lexer,
@@ -49,6 +51,8 @@ pub struct StateCompileUnit {
     pub moduleOperations: Mutex<Option<Vec<ModuleOperator>>>,
     /// Blocked by an import header. This can happen when we're lexing a module header, and we are unable to continue due to another import.
     pub blockedByImportHeader: AtomicU64,
+    /// Location of the import of the header that blocks the lexing, if any.
+    pub blockedByImportHeaderAt: Mutex<Option<SourceRange>>,
 }
 
 impl StateCompileUnit {
@@ -64,6 +68,7 @@ impl StateCompileUnit {
             moduleOperationPositions: Mutex::new(Vec::new()),
             moduleOperations: Mutex::new(None),
             blockedByImportHeader: AtomicU64::new(0),
+            blockedByImportHeaderAt: Mutex::new(None),
         }
     }
 }
@@ -1,10 +1,12 @@
 //! A varitety of structs used throughout the compiler.
-use std::fmt::Debug;
+use std::fmt::{Debug, Write};
+use std::hash::{Hash, Hasher};
 use std::sync::{Arc, Mutex};
 
 use colored::Colorize;
 
 use super::FileMap::FileMap;
+use super::MacroExpansionRef::MacroExpansionRef;
 
 #[derive(Debug, Default, Eq)]
 /// A file to be compiled
@@ -62,6 +64,22 @@ impl CompileFile {
         return (part + 1, diff - self.newlines.get(part - 1).unwrap_or(&0));
     }
 
+    /// Get the start and end offsets of a line (1-based). The end offset is
+    /// the position of the newline, if any
+    pub fn getLineOffsets(&self, line: usize) -> Option<(usize, usize)> {
+        let start = match line {
+            0 => return None,
+            1 => 0,
+            _ => self.newlines.get(line - 2)? + 1,
+        };
+        let end = self
+            .newlines
+            .get(line - 1)
+            .copied()
+            .unwrap_or(self.content.len());
+        Some((start, end))
+    }
+
     /// Get the location of a position as a string
     pub fn getLocStr(&self, diff: Option<usize>) -> String {
         diff.map_or_else(
@@ -104,6 +122,8 @@ pub struct CompileMsg {
     file: u64,
     at: Option<usize>,
     atEnd: Option<usize>,
+    /// If the message is located at a token generated by a macro expansion
+    expansion: Option<MacroExpansionRef>,
 }
 
 impl CompileMsg {
@@ -118,11 +138,59 @@ impl CompileMsg {
         self.kind
     }
 
+    /// Text of the message
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
     /// Get the location of the message
     pub const fn loc(&self) -> (u64, Option<usize>, Option<usize>) {
         (self.file, self.at, self.atEnd)
     }
 
+    /// The message, located in the macro expansion `expansion`. For the
+    /// messages built from offsets instead of tokens.
+    #[must_use]
+    pub fn withExpansion(mut self, expansion: Option<MacroExpansionRef>) -> Self {
+        self.expansion = expansion;
+        self
+    }
+
+    /// Macro expansion the message is located in, if any
+    pub fn expansion(&self) -> Option<MacroExpansionRef> {
+        self.expansion.clone()
+    }
+
+    /// Notes explaining the macro expansions that generated the location of the message
+    pub fn expansionNotes(&self, fileMap: &Arc<Mutex<FileMap>>) -> String {
+        let mut notes = String::new();
+        let Some(ref expansion) = self.expansion else {
+            return notes;
+        };
+        let mut fileMap = fileMap.lock().unwrap();
+        for loc in expansion.chain() {
+            let expandedAt = fileMap
+                .getOpenedFile(loc.expandedAt.startfile)
+                .getLocStr(Some(loc.expandedAt.start));
+            let definedAt = loc.definedAt.as_ref().map_or_else(
+                || "<builtin>".to_string(),
+                |definedAt| {
+                    fileMap
+                        .getOpenedFile(definedAt.startfile)
+                        .getLocStr(Some(definedAt.start))
+                },
+            );
+            let _ = writeln!(
+                notes,
+                "{} at: {expandedAt}\nin expansion of macro {} defined at {definedAt}",
+                CompileMsgKind::Notice.to_string(),
+                loc.macroName
+            );
+        }
+        drop(fileMap);
+        notes
+    }
+
     /// Print the message
     pub fn print(&self, fileMap: &Arc<Mutex<FileMap>>) {
         match self.kind {
@@ -138,10 +206,11 @@ impl CompileMsg {
             format!("{}:\n{}\n", self.kind.to_string(), self.msg)
         } else {
             format!(
-                "{} at: {}\n{}\n",
+                "{} at: {}\n{}\n{}",
                 self.kind.to_string(),
                 self.errorLocStr(fileMap),
-                self.msg
+                self.msg,
+                self.expansionNotes(fileMap)
             )
         }
     }
@@ -155,6 +224,7 @@ pub trait CompileMsgImpl {
             at: None,
             atEnd: None,
             kind: Self::getKind(),
+            expansion: None,
         }
     }
 
@@ -165,6 +235,7 @@ pub trait CompileMsgImpl {
             at: None,
             atEnd: None,
             kind: Self::getKind(),
+            expansion: None,
         }
     }
 
@@ -178,6 +249,7 @@ pub trait CompileMsgImpl {
             at: Some(preToken.tokPos.start),
             atEnd: Some(preToken.tokPos.end),
             kind: Self::getKind(),
+            expansion: preToken.expansion.clone(),
         }
     }
 
@@ -188,6 +260,7 @@ pub trait CompileMsgImpl {
             at: Some(at),
             atEnd,
             kind: Self::getKind(),
+            expansion: None,
         }
     }
 
@@ -199,6 +272,7 @@ pub trait CompileMsgImpl {
                 at: Some(range.start),
                 atEnd: Some(range.end),
                 kind: Self::getKind(),
+                expansion: range.expansion.clone(),
             }
         } else {
             CompileMsg {
@@ -207,6 +281,7 @@ pub trait CompileMsgImpl {
                 at: Some(range.start),
                 atEnd: None,
                 kind: Self::getKind(),
+                expansion: range.expansion.clone(),
             }
         }
     }
@@ -268,14 +343,18 @@ pub struct FileTokPos<T: Clone + Debug> {
     pub file: u64,
     /// token + position
     pub tokPos: TokPos<T>,
+    /// The macro expansion that generated this token, if any
+    pub expansion: Option<MacroExpansionRef>,
 }
 
-impl<T: Copy + Debug> Copy for FileTokPos<T> {}
-
 impl<T: Clone + Debug> FileTokPos<T> {
     /// New token
     pub const fn new(file: u64, tok: TokPos<T>) -> Self {
-        Self { file, tokPos: tok }
+        Self {
+            file,
+            tokPos: tok,
+            expansion: None,
+        }
     }
 
     /// New meta token. It is not located anywhere
@@ -287,12 +366,13 @@ impl<T: Clone + Debug> FileTokPos<T> {
                 end: 0,
                 tok,
             },
+            expansion: None,
         }
     }
 
     /// New meta token. It copies its location from another token, even if it is
     /// not located anywhere. Allows for better diagnostics
-    pub const fn new_meta_c<U: Clone + Debug>(tok: T, other: &FileTokPos<U>) -> Self {
+    pub fn new_meta_c<U: Clone + Debug>(tok: T, other: &FileTokPos<U>) -> Self {
         Self {
             file: other.file,
             tokPos: TokPos {
@@ -300,6 +380,7 @@ impl<T: Clone + Debug> FileTokPos<T> {
                 end: other.tokPos.end,
                 tok,
             },
+            expansion: other.expansion.clone(),
         }
     }
 
@@ -309,30 +390,49 @@ impl<T: Clone + Debug> FileTokPos<T> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub struct SourceRange {
     pub startfile: u64,
     pub endfile: u64,
     pub start: usize,
     pub end: usize,
+    /// The macro expansion that generated the start of the range, if any. Not
+    /// compared: two ranges are equal if they span the same source.
+    pub expansion: Option<MacroExpansionRef>,
+}
+
+impl PartialEq for SourceRange {
+    fn eq(&self, other: &Self) -> bool {
+        (self.startfile, self.endfile, self.start, self.end)
+            == (other.startfile, other.endfile, other.start, other.end)
+    }
+}
+impl Eq for SourceRange {}
+
+impl Hash for SourceRange {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.startfile, self.endfile, self.start, self.end).hash(state);
+    }
 }
 
 impl SourceRange {
-    pub const fn newSingleTok<T: Clone + Debug>(tok: &FileTokPos<T>) -> Self {
+    pub fn newSingleTok<T: Clone + Debug>(tok: &FileTokPos<T>) -> Self {
         Self {
             startfile: tok.file,
             endfile: tok.file,
             start: tok.tokPos.start,
             end: tok.tokPos.end,
+            expansion: tok.expansion.clone(),
         }
     }
 
-    pub const fn newDoubleTok<T: Clone + Debug>(t1: &FileTokPos<T>, t2: &FileTokPos<T>) -> Self {
+    pub fn newDoubleTok<T: Clone + Debug>(t1: &FileTokPos<T>, t2: &FileTokPos<T>) -> Self {
         Self {
             startfile: t1.file,
             endfile: t2.file,
             start: t1.tokPos.start,
             end: t2.tokPos.end,
+            expansion: t1.expansion.clone(),
         }
     }
 }
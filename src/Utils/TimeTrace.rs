@@ -0,0 +1,365 @@
+//! Time spent in each stage of the compilation, by translation unit and by
+//! included header, like `-ftime-trace` of other compilers.
+//!
+//! The stages record a [`TraceEvent`] when they finish, and the headers are
+//! timed through the [`PreprocessorCallbacks`] events. The result can be
+//! written as a Chrome trace (viewable in Perfetto or `chrome://tracing`), or
+//! summarized as text.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+
+use json::{object, JsonValue};
+
+use crate::Preprocessor::Callbacks::PreprocessorCallbacks;
+
+use super::FileMap::FileMap;
+
+/// Stages timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TraceStage {
+    /// Lexing of the minimized file, to find its module dependencies
+    Scan,
+    /// Lexing of the file, including its preprocessing
+    Lex,
+    /// Parsing of the module directives found when lexing
+    ModuleDirectives,
+    /// Parsing of the tokens
+    Parse,
+    /// Preprocessing of a header (and the ones it includes) while scanning
+    ScanInclude,
+    /// Preprocessing and lexing of a header (and the ones it includes)
+    Include,
+}
+
+impl TraceStage {
+    /// Name in the trace
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Scan => "Scan",
+            Self::Lex => "Lex",
+            Self::ModuleDirectives => "Module directives",
+            Self::Parse => "Parse",
+            Self::ScanInclude => "Scan include",
+            Self::Include => "Include",
+        }
+    }
+}
+
+/// A stage done to a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// The stage
+    pub stage: TraceStage,
+    /// The translation unit
+    pub tu: u64,
+    /// The file: the header for the includes, the translation unit otherwise
+    pub file: u64,
+    /// Thread that did it, numbered from 0 in the order they were seen
+    pub thread: usize,
+    /// When it started, since the trace started
+    pub start: Duration,
+    /// How long it took
+    pub duration: Duration,
+    /// Time spent in the preprocessor, when lexing
+    pub preprocessing: Option<Duration>,
+}
+
+/// Total time spent on a translation unit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnitTime {
+    /// The translation unit
+    pub tu: u64,
+    /// Scanning its dependencies
+    pub scan: Duration,
+    /// Preprocessing it
+    pub preprocess: Duration,
+    /// Lexing it, without the preprocessing
+    pub lex: Duration,
+    /// Parsing its module directives
+    pub moduleDirectives: Duration,
+    /// Parsing it
+    pub parse: Duration,
+}
+
+impl UnitTime {
+    /// Time spent in all the stages
+    pub fn total(&self) -> Duration {
+        self.scan + self.preprocess + self.lex + self.moduleDirectives + self.parse
+    }
+}
+
+/// Total time spent preprocessing a header, in all the translation units
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderTime {
+    /// The header
+    pub file: u64,
+    /// Times it was entered
+    pub includes: usize,
+    /// Time spent preprocessing and lexing it and the ones it includes
+    pub time: Duration,
+}
+
+/// Records how long each stage takes
+#[derive(Debug)]
+pub struct TimeTrace {
+    /// When the trace started
+    start: Instant,
+    /// Events recorded
+    events: Mutex<Vec<TraceEvent>>,
+    /// Number of each thread seen
+    threads: Mutex<HashMap<ThreadId, usize>>,
+    /// Stage lexing each translation unit, to know which stage its headers
+    /// belong to
+    lexing: Mutex<HashMap<u64, TraceStage>>,
+    /// Files being preprocessed, by translation unit, innermost last, with
+    /// when they were entered
+    openFiles: Mutex<HashMap<u64, Vec<(u64, Instant)>>>,
+}
+
+impl TimeTrace {
+    /// Starts a trace
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            events: Mutex::default(),
+            threads: Mutex::default(),
+            lexing: Mutex::default(),
+            openFiles: Mutex::default(),
+        }
+    }
+
+    /// Number of the current thread
+    fn thread(&self) -> usize {
+        let mut threads = self.threads.lock().unwrap();
+        let next = threads.len();
+        let thread = *threads.entry(std::thread::current().id()).or_insert(next);
+        drop(threads);
+        thread
+    }
+
+    /// Records an event of `file` that started at `start` and ends now
+    fn push(&self, stage: TraceStage, tu: u64, file: u64, start: Instant) -> TraceEvent {
+        TraceEvent {
+            stage,
+            tu,
+            file,
+            thread: self.thread(),
+            start: start.duration_since(self.start),
+            duration: start.elapsed(),
+            preprocessing: None,
+        }
+    }
+
+    /// Records that `stage` was done to `tu`, from `start` until now
+    pub fn record(&self, stage: TraceStage, tu: u64, start: Instant) {
+        let event = self.push(stage, tu, tu, start);
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// `tu` starts to be lexed by `stage`, either [`TraceStage::Scan`] or
+    /// [`TraceStage::Lex`]
+    pub fn startLexing(&self, tu: u64, stage: TraceStage) {
+        self.lexing.lock().unwrap().insert(tu, stage);
+    }
+
+    /// Records that `tu` was lexed by `stage`, from `start` until now,
+    /// spending `preprocessing` in the preprocessor
+    pub fn recordLexing(
+        &self,
+        stage: TraceStage,
+        tu: u64,
+        start: Instant,
+        preprocessing: Duration,
+    ) {
+        let event = TraceEvent {
+            preprocessing: Some(preprocessing),
+            ..self.push(stage, tu, tu, start)
+        };
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// Events recorded, in the order they started
+    pub fn events(&self) -> Vec<TraceEvent> {
+        let mut events = self.events.lock().unwrap().clone();
+        events.sort_by_key(|event| (event.start, event.thread));
+        events
+    }
+
+    /// Time spent on each translation unit. The slowest ones first
+    pub fn unitTimes(&self) -> Vec<UnitTime> {
+        let mut units = HashMap::<u64, UnitTime>::new();
+        for event in self.events.lock().unwrap().iter() {
+            let unit = units.entry(event.tu).or_insert_with(|| UnitTime {
+                tu: event.tu,
+                ..UnitTime::default()
+            });
+            match event.stage {
+                TraceStage::Scan => unit.scan += event.duration,
+                TraceStage::Lex => {
+                    let preprocessing = event.preprocessing.unwrap_or_default();
+                    unit.preprocess += preprocessing;
+                    unit.lex += event.duration.saturating_sub(preprocessing);
+                }
+                TraceStage::ModuleDirectives => unit.moduleDirectives += event.duration,
+                TraceStage::Parse => unit.parse += event.duration,
+                TraceStage::ScanInclude | TraceStage::Include => {}
+            }
+        }
+        let mut units = units.into_values().collect::<Vec<_>>();
+        units.sort_by(|a, b| b.total().cmp(&a.total()).then(a.tu.cmp(&b.tu)));
+        units
+    }
+
+    /// Time spent on each header when lexing the translation units. The
+    /// slowest ones first
+    pub fn headerTimes(&self) -> Vec<HeaderTime> {
+        let mut headers = HashMap::<u64, HeaderTime>::new();
+        for event in self.events.lock().unwrap().iter() {
+            if event.stage == TraceStage::Include {
+                let header = headers.entry(event.file).or_insert(HeaderTime {
+                    file: event.file,
+                    includes: 0,
+                    time: Duration::ZERO,
+                });
+                header.includes += 1;
+                header.time += event.duration;
+            }
+        }
+        let mut headers = headers.into_values().collect::<Vec<_>>();
+        headers.sort_by(|a, b| b.time.cmp(&a.time).then(a.file.cmp(&b.file)));
+        headers
+    }
+
+    /// The events in the Chrome trace event format
+    pub fn toChromeTrace(&self, fileMap: &Arc<Mutex<FileMap>>) -> JsonValue {
+        let mut fileMap = fileMap.lock().unwrap();
+        let micros = |time: Duration| time.as_secs_f64() * 1_000_000.0;
+        let mut traceEvents = self
+            .events()
+            .into_iter()
+            .map(|event| {
+                let mut args = object! {
+                    detail: fileMap.getOpenedFile(event.file).path().as_str(),
+                };
+                if event.file != event.tu {
+                    args["tu"] = fileMap.getOpenedFile(event.tu).path().as_str().into();
+                }
+                if let Some(preprocessing) = event.preprocessing {
+                    args["preprocessing_us"] = micros(preprocessing).into();
+                }
+                object! {
+                    name: event.stage.name(),
+                    ph: "X",
+                    pid: 1,
+                    tid: event.thread,
+                    ts: micros(event.start),
+                    dur: micros(event.duration),
+                    args: args,
+                }
+            })
+            .collect::<Vec<_>>();
+        drop(fileMap);
+        let mut threads = self
+            .threads
+            .lock()
+            .unwrap()
+            .values()
+            .copied()
+            .collect::<Vec<_>>();
+        threads.sort_unstable();
+        traceEvents.extend(threads.into_iter().map(|thread| {
+            object! {
+                name: "thread_name",
+                ph: "M",
+                pid: 1,
+                tid: thread,
+                args: object! { name: format!("Worker {thread}") },
+            }
+        }));
+        object! {
+            traceEvents: traceEvents,
+            displayTimeUnit: "ms",
+        }
+    }
+
+    /// Time in milliseconds, for humans
+    fn millis(time: Duration) -> String {
+        format!("{:.3}ms", time.as_secs_f64() * 1000.0)
+    }
+
+    /// Readable tables of the `count` slowest translation units and headers
+    pub fn summaryToText(&self, fileMap: &Arc<Mutex<FileMap>>, count: usize) -> String {
+        let units = self.unitTimes();
+        let headers = self.headerTimes();
+        let mut fileMap = fileMap.lock().unwrap();
+        let mut text = format!(
+            "// slowest translation units\n{:>12} {:>12} {:>12} {:>12} {:>12} {:>12} translation unit\n",
+            "total", "scan", "preprocess", "lex", "modules", "parse"
+        );
+        for unit in units.iter().take(count) {
+            let _ = writeln!(
+                text,
+                "{:>12} {:>12} {:>12} {:>12} {:>12} {:>12} {}",
+                Self::millis(unit.total()),
+                Self::millis(unit.scan),
+                Self::millis(unit.preprocess),
+                Self::millis(unit.lex),
+                Self::millis(unit.moduleDirectives),
+                Self::millis(unit.parse),
+                fileMap.getOpenedFile(unit.tu).path()
+            );
+        }
+        let _ = writeln!(
+            text,
+            "// slowest headers\n{:>12} {:>9} header",
+            "time", "includes"
+        );
+        for header in headers.iter().take(count) {
+            let _ = writeln!(
+                text,
+                "{:>12} {:>9} {}",
+                Self::millis(header.time),
+                header.includes,
+                fileMap.getOpenedFile(header.file).path()
+            );
+        }
+        drop(fileMap);
+        text
+    }
+}
+
+impl PreprocessorCallbacks for TimeTrace {
+    fn fileEntered(&self, tu: u64, file: u64) {
+        self.openFiles
+            .lock()
+            .unwrap()
+            .entry(tu)
+            .or_default()
+            .push((file, Instant::now()));
+    }
+
+    fn fileExited(&self, tu: u64, _file: u64) {
+        let Some((file, start)) = self
+            .openFiles
+            .lock()
+            .unwrap()
+            .get_mut(&tu)
+            .and_then(Vec::pop)
+        else {
+            return;
+        };
+        // The translation unit itself is timed by the stage lexing it
+        if file == tu {
+            return;
+        }
+        let stage = match self.lexing.lock().unwrap().get(&tu) {
+            Some(TraceStage::Scan) => TraceStage::ScanInclude,
+            _ => TraceStage::Include,
+        };
+        let event = self.push(stage, tu, file, start);
+        self.events.lock().unwrap().push(event);
+    }
+}
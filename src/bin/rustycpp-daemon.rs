@@ -0,0 +1,81 @@
+//! Compile server of rustycpp, and its client.
+//!
+//! Please see the readme at [github](https://github.com/Destroyerrrocket/rustycpp)
+//! for more information.
+//!
+#![warn(
+    missing_docs,
+    clippy::all,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo,
+    clippy::verbose_file_reads,
+    clippy::unneeded_field_pattern,
+    clippy::unnecessary_self_imports,
+    clippy::string_to_string,
+    clippy::if_then_some_else_none,
+    clippy::empty_structs_with_brackets,
+    //clippy::missing_docs_in_private_items
+)]
+#![allow(
+    clippy::multiple_crate_versions,
+    clippy::module_name_repetitions,
+    non_snake_case
+)]
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+
+#[derive(Subcommand)]
+#[doc(hidden)]
+enum Command {
+    /// Run the compile server, until it's asked to stop
+    Serve,
+    /// Compile a project with the compile server, and print its diagnostics
+    Compile {
+        /// Filelist to compile
+        #[clap(short, long)]
+        files: String,
+    },
+    /// Stop the compile server
+    Stop,
+}
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+#[doc(hidden)]
+struct Args {
+    /// Socket of the compile server. `rustycpp/daemon.sock` in the
+    /// `$XDG_RUNTIME_DIR` by default. Required if it's not set.
+    #[clap(short, long)]
+    socket: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args = Args::parse();
+    let Some(socket) = args.socket.or_else(rustycpp::defaultSocket) else {
+        log::error!("XDG_RUNTIME_DIR is not set: please give the socket with --socket");
+        return ExitCode::FAILURE;
+    };
+    let result = match args.command {
+        Command::Serve => rustycpp::serveCompiles(&socket).map(|()| true),
+        Command::Compile { files } => {
+            rustycpp::requestCompile(&socket, &files, &mut std::io::stderr().lock())
+        }
+        Command::Stop => rustycpp::requestStop(&socket).map(|()| true),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            log::error!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
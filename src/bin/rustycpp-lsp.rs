@@ -0,0 +1,62 @@
+//! Language server of rustycpp, over stdio.
+//!
+//! Please see the readme at [github](https://github.com/Destroyerrrocket/rustycpp)
+//! for more information.
+//!
+#![warn(
+    missing_docs,
+    clippy::all,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo,
+    clippy::verbose_file_reads,
+    clippy::unneeded_field_pattern,
+    clippy::unnecessary_self_imports,
+    clippy::string_to_string,
+    clippy::if_then_some_else_none,
+    clippy::empty_structs_with_brackets,
+    //clippy::missing_docs_in_private_items
+)]
+#![allow(
+    clippy::multiple_crate_versions,
+    clippy::module_name_repetitions,
+    non_snake_case
+)]
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use rustycpp::Parameters;
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+#[doc(hidden)]
+struct Args {
+    /// Filelist of the project. Without it, only the open documents are
+    /// compiled, unless the editor gives one as the `files` initialization
+    /// option.
+    #[clap(short, long)]
+    files: Option<String>,
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args = Args::parse();
+    let parameters = match args.files.as_deref().map(Parameters::new_file) {
+        Some(Ok(parameters)) => parameters,
+        Some(Err(err)) => {
+            log::error!("Can't read the file list: {err}");
+            return ExitCode::FAILURE;
+        }
+        None => Parameters::new(),
+    };
+    let stdin = std::io::stdin();
+    match rustycpp::serve(stdin.lock(), std::io::stdout().lock(), parameters) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            log::error!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
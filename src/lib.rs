@@ -0,0 +1,66 @@
+//! A C++ compiler (on the works).
+//!
+//! Please see the readme at [github](https://github.com/Destroyerrrocket/rustycpp)
+//! for more information.
+//!
+//! The front end can be embedded: create a [`Compiler::Compiler`] with the
+//! [`Parameters`] of the project, reading the files from disk, or from any
+//! [`FileSystem`] with [`Compiler::Compiler::new_with_fs`]. To compile files
+//! not saved yet (like the buffers of an editor), put them in an
+//! [`InMemoryFileSystem`] layered over the disk with an [`OverlayFileSystem`].
+//! Then [`Compiler::Compiler::parsedTrees`] returns the [`AstTu`] of each
+//! translation unit, and the diagnostics found.
+//!
+//! The language server of the `rustycpp-lsp` binary is also available, with
+//! [`serve`], and so is the compile server of the `rustycpp-daemon` binary,
+//! with [`serveCompiles`] and [`requestCompile`].
+//!
+#![feature(const_refs_to_cell)]
+#![warn(
+    missing_docs,
+    clippy::all,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo,
+    clippy::verbose_file_reads,
+    clippy::unneeded_field_pattern,
+    clippy::unnecessary_self_imports,
+    clippy::string_to_string,
+    clippy::if_then_some_else_none,
+    clippy::empty_structs_with_brackets,
+    //clippy::missing_docs_in_private_items
+)]
+#![allow(
+    clippy::multiple_crate_versions,
+    clippy::module_name_repetitions,
+    clippy::must_use_candidate,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    non_snake_case,
+    dead_code
+)]
+
+mod Ast;
+pub mod Compiler;
+mod Daemon;
+mod Grammars;
+mod Lex;
+mod Lsp;
+mod ModuleTree;
+mod Parse;
+mod Preprocessor;
+mod Sema;
+mod Utils;
+
+mod Test;
+
+pub use Ast::Common::{AstTu, CommonAst};
+pub use Daemon::{defaultSocket, requestCompile, requestStop, serveCompiles};
+pub use Lsp::serve;
+pub use Preprocessor::Unifdef::UnifdefConfig;
+pub use Utils::CompilerState::CompilerState;
+pub use Utils::DebugNode::DebugNode;
+pub use Utils::FileMap::FileMap;
+pub use Utils::FileSystem::{FileSystem, InMemoryFileSystem, OverlayFileSystem, RealFileSystem};
+pub use Utils::Parameters::Parameters;
+pub use Utils::Structs::{CompileFile, CompileMsg, CompileMsgKind};
@@ -3,7 +3,6 @@
 //! Please see the readme at [github](https://github.com/Destroyerrrocket/rustycpp)
 //! for more information.
 //!
-#![feature(const_refs_to_cell)]
 #![warn(
     missing_docs,
     clippy::all,
@@ -21,26 +20,148 @@
 #![allow(
     clippy::multiple_crate_versions,
     clippy::module_name_repetitions,
-    non_snake_case,
-    dead_code
+    non_snake_case
 )]
 
-mod Ast;
-mod Compiler;
-mod Grammars;
-mod Lex;
-mod ModuleTree;
-mod Parse;
-mod Preprocessor;
-mod Sema;
-mod Utils;
-
-mod Test;
+use std::path::PathBuf;
 
 use clap::Parser;
-use Utils::CompilerState::CompilerState;
-use Utils::Parameters::Parameters;
-use Utils::Structs::CompileMsg;
+use rustycpp::Compiler::Compiler;
+use rustycpp::{CompileMsg, CompilerState, Parameters, UnifdefConfig};
+
+/// Stage after which the compilation stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+#[doc(hidden)]
+enum Stage {
+    Scan,
+    Preprocess,
+    Lex,
+    Parse,
+    Sema,
+}
+
+/// What the compiler does instead of compiling
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+#[doc(hidden)]
+enum Mode {
+    /// Print the module depenedency tree of the provided set of files.
+    DependencyTree,
+    /// Print the translation units that must be recompiled after the files
+    /// given with --changed changed, in a valid build order. The previous
+    /// scan is kept in the rebuildStateFile of the file list, and the plan is
+    /// repeated until the rebuild is confirmed with --mode=confirm-rebuild.
+    RebuildPlan,
+    /// Record that the translation units of the last rebuild plan were
+    /// rebuilt, so the next plans only include them if they change again.
+    ConfirmRebuild,
+    /// Compile, and print the critical path of the module tree predicted
+    /// before compiling, and the one found with the time each translation
+    /// unit took.
+    CriticalPath,
+    /// Print as JSON the conditional structure (#if/#elif/#else/#endif) of
+    /// each translation unit, with the regions that were skipped.
+    ConditionalReport,
+    /// Print the include hierarchy of each translation unit, and how much
+    /// each header costs to preprocess.
+    IncludeTree,
+    /// Print as JSON where every macro is defined, undefined, expanded and
+    /// tested, with the macros never used and the ones tested but never
+    /// defined.
+    MacroIndex,
+    /// Partially preprocess files, resolving only the #if groups that depend
+    /// on the macros given with --define and --undefine, and print the
+    /// resulting source to stdout.
+    Unifdef,
+    /// Bundle each translation unit with the project headers it includes
+    /// (found through includeDirs), and print the resulting source to stdout.
+    Amalgamate,
+}
+
+/// Format of the macro expansion explanation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+#[doc(hidden)]
+enum ExplainFormat {
+    Text,
+    Json,
+}
+
+/// Short forms of `--mode`, as a flag for each mode
+#[derive(clap::Args)]
+#[group(id = "modeFlags", multiple = false)]
+#[allow(clippy::struct_excessive_bools)]
+#[doc(hidden)]
+struct ModeFlags {
+    /// Same as --mode=dependency-tree
+    #[clap(long, conflicts_with = "mode")]
+    printDependencyTree: bool,
+    /// Same as --mode=rebuild-plan
+    #[clap(long, conflicts_with = "mode")]
+    planRebuild: bool,
+    /// Same as --mode=confirm-rebuild
+    #[clap(long, conflicts_with = "mode")]
+    confirmRebuild: bool,
+    /// Same as --mode=critical-path
+    #[clap(long, conflicts_with = "mode")]
+    criticalPath: bool,
+    /// Same as --mode=conditional-report
+    #[clap(long, conflicts_with = "mode")]
+    conditionalReport: bool,
+    /// Same as --mode=include-tree
+    #[clap(short = 'H', long, conflicts_with = "mode")]
+    includeTree: bool,
+    /// Same as --mode=macro-index
+    #[clap(long, conflicts_with = "mode")]
+    macroIndex: bool,
+    /// Same as --mode=unifdef
+    #[clap(long, conflicts_with = "mode")]
+    unifdef: bool,
+    /// Same as --mode=amalgamate
+    #[clap(long, conflicts_with = "mode")]
+    amalgamate: bool,
+}
+
+impl ModeFlags {
+    /// The mode of the flag given, if any
+    fn mode(&self) -> Option<Mode> {
+        [
+            (self.printDependencyTree, Mode::DependencyTree),
+            (self.planRebuild, Mode::RebuildPlan),
+            (self.confirmRebuild, Mode::ConfirmRebuild),
+            (self.criticalPath, Mode::CriticalPath),
+            (self.conditionalReport, Mode::ConditionalReport),
+            (self.includeTree, Mode::IncludeTree),
+            (self.macroIndex, Mode::MacroIndex),
+            (self.unifdef, Mode::Unifdef),
+            (self.amalgamate, Mode::Amalgamate),
+        ]
+        .into_iter()
+        .find_map(|(given, mode)| given.then_some(mode))
+    }
+}
+
+/// Short forms of `--stage`
+#[derive(clap::Args)]
+#[group(id = "stageFlags", multiple = false)]
+#[doc(hidden)]
+struct StageFlags {
+    /// Same as --stage=preprocess
+    #[clap(long, conflicts_with = "stage")]
+    preprocess: bool,
+    /// Same as --stage=lex
+    #[clap(long, conflicts_with = "stage")]
+    lexify: bool,
+}
+
+impl StageFlags {
+    /// The stage of the flag given, if any
+    const fn stage(&self) -> Option<Stage> {
+        match (self.preprocess, self.lexify) {
+            (true, _) => Some(Stage::Preprocess),
+            (_, true) => Some(Stage::Lex),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -50,17 +171,88 @@ struct Args {
     #[clap(short, long)]
     files: String,
 
-    /// Print the module depenedency tree of the provided set of files.
+    /// Instead of compiling, run this mode and print its result. Each mode
+    /// can also be given as a flag, like --include-tree (or -H).
+    #[clap(long, value_enum)]
+    mode: Option<Mode>,
+
+    #[clap(flatten)]
+    modeFlags: ModeFlags,
+
+    /// Stop after this stage, and print its result for each translation unit
+    /// in the order they were given: the modules they depend on (scan), the
+    /// preprocessed source (preprocess), the tokens (lex), the AST (parse) or
+    /// the diagnostics (sema). --preprocess and --lexify stand for the
+    /// preprocess and lex stages.
+    #[clap(long, value_enum)]
+    stage: Option<Stage>,
+
+    #[clap(flatten)]
+    stageFlags: StageFlags,
+
+    /// Print the AST of each translation unit. With a name, only the
+    /// declarations with that name.
+    #[clap(long, num_args = 0..=1, default_missing_value = "")]
+    dumpAst: Option<String>,
+
+    /// Write the result of each translation unit to a file in this directory,
+    /// named after the translation unit, instead of printing it.
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// File that changed, for --mode=rebuild-plan. Files whose contents
+    /// changed since the previous scan are found even if not given.
+    #[clap(long)]
+    changed: Vec<String>,
+
+    /// Time each stage of the compilation by translation unit and header, and
+    /// write the result to this file as a Chrome trace (viewable in Perfetto).
+    #[clap(long)]
+    timeTrace: Option<String>,
+
+    /// Time each stage of the compilation, and print to stderr the slowest
+    /// translation units and headers.
     #[clap(long, value_parser, default_value = "false")]
-    printDependencyTree: bool,
+    timeReport: bool,
 
-    /// Preprocess files and print the result to stdout.
+    /// Explain step by step the macro expansions of a line (path:line) or of
+    /// a macro (its name), and print them to stdout.
+    #[clap(long)]
+    explainMacro: Option<String>,
+
+    /// Format of the macro expansion explanation.
+    #[clap(long, value_enum, default_value = "text")]
+    explainFormat: ExplainFormat,
+
+    /// Macro known to be defined in the unifdef mode, as NAME or NAME=VALUE.
+    #[clap(short = 'D', long)]
+    define: Vec<String>,
+
+    /// Macro known to be undefined in the unifdef mode.
+    #[clap(short = 'U', long)]
+    undefine: Vec<String>,
+
+    /// Warn about the macros defined in the translation units that are never
+    /// used.
     #[clap(long, value_parser, default_value = "false")]
-    preprocess: bool,
+    warnUnusedMacros: bool,
 
-    /// Lexify files and print the result to stdout.
+    /// Translate the #include of the moduleHeaderUnits into imports of the
+    /// header units. Without it, they are included textually.
     #[clap(long, value_parser, default_value = "false")]
-    lexify: bool,
+    translateIncludes: bool,
+}
+
+impl Args {
+    /// The mode selected, by --mode or by its flag
+    fn mode(&self) -> Option<Mode> {
+        self.mode.or_else(|| self.modeFlags.mode())
+    }
+
+    /// The stage selected, by --stage or by its flag
+    fn stage(&self) -> Option<Stage> {
+        self.stage.or_else(|| self.stageFlags.stage())
+    }
 }
 
 /// Wrapper for main, to allow for the use of `?` in main
@@ -68,13 +260,53 @@ fn execCompiler(
     parameters: Parameters,
     args: &Args,
 ) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
-    let mut compiler = Compiler::Compiler::new(parameters);
-    if args.printDependencyTree {
-        compiler.print_dependency_tree()
-    } else if args.preprocess {
-        compiler.print_preprocessor()
-    } else if args.lexify {
-        compiler.print_lexer()
+    let mut compiler = Compiler::new(parameters);
+    compiler.setOutputDir(args.output.as_ref().map(PathBuf::from));
+    if args.timeTrace.is_some() || args.timeReport {
+        compiler.enableTimeTrace();
+    }
+    let result = execMode(&mut compiler, args);
+    compiler.print_time_trace(args.timeTrace.as_deref(), args.timeReport)?;
+    result
+}
+
+/// Runs the mode of the compiler selected
+fn execMode(compiler: &mut Compiler, args: &Args) -> Result<(), (CompilerState, Vec<CompileMsg>)> {
+    let astFilter = args.dumpAst.as_deref().filter(|name| !name.is_empty());
+    match args.stage() {
+        Some(Stage::Scan) => return compiler.print_scan(),
+        Some(Stage::Preprocess) => return compiler.print_preprocessor(),
+        Some(Stage::Lex) => return compiler.print_lexer(),
+        Some(Stage::Sema) if args.dumpAst.is_none() => return compiler.print_diagnostics(),
+        Some(Stage::Parse | Stage::Sema) => return compiler.print_ast(astFilter),
+        None if args.dumpAst.is_some() => return compiler.print_ast(astFilter),
+        None => {}
+    }
+    match args.mode() {
+        Some(Mode::DependencyTree) => return compiler.print_dependency_tree(),
+        Some(Mode::RebuildPlan) => return compiler.print_rebuild_plan(&args.changed),
+        Some(Mode::ConfirmRebuild) => {
+            for err in compiler.confirmRebuild().err().unwrap_or_default() {
+                err.print(compiler.fileMap());
+            }
+            return Ok(());
+        }
+        Some(Mode::CriticalPath) => return compiler.print_critical_path(),
+        Some(Mode::ConditionalReport) => return compiler.print_conditional_report(),
+        Some(Mode::IncludeTree) => return compiler.print_include_tree(),
+        Some(Mode::MacroIndex) => return compiler.print_macro_index(),
+        Some(Mode::Unifdef) => {
+            compiler.print_unifdef(&UnifdefConfig::new(&args.define, &args.undefine));
+            return Ok(());
+        }
+        Some(Mode::Amalgamate) => {
+            compiler.print_amalgamation();
+            return Ok(());
+        }
+        None => {}
+    }
+    if let Some(request) = &args.explainMacro {
+        compiler.print_macro_explanation(request, args.explainFormat == ExplainFormat::Json)
     } else {
         compiler.doTheThing()
     }
@@ -87,8 +319,19 @@ fn main() {
         log::error!("File list not specified!");
         return;
     }
+    if args.dumpAst.is_some()
+        && matches!(
+            args.stage(),
+            Some(Stage::Scan | Stage::Preprocess | Stage::Lex)
+        )
+    {
+        log::error!("--dump-ast needs the parse or sema stage");
+        return;
+    }
 
-    let parameters = Parameters::new_file(&args.files).unwrap();
+    let mut parameters = Parameters::new_file(&args.files).unwrap();
+    parameters.warnUnusedMacros |= args.warnUnusedMacros;
+    parameters.translateIncludes |= args.translateIncludes;
     if let Err((compilerState, errors)) = execCompiler(parameters, &args) {
         for err in errors {
             err.print(&compilerState.compileFiles);